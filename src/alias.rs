@@ -0,0 +1,329 @@
+use crate::bed::BedRecord;
+use crate::utils::raw;
+
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::path::Path;
+
+/// Contig naming conventions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Naming {
+    /// `chr1`, `chrM`, `chr1_KI270706v1_random`.
+    Ucsc,
+    /// `1`, `MT`, `KI270706.1`.
+    Ensembl,
+    /// `NC_000001.11`.
+    Refseq,
+}
+
+impl Naming {
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn from_column(name: &str) -> Option<Naming> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "ucsc" => Some(Naming::Ucsc),
+            "ensembl" => Some(Naming::Ensembl),
+            "refseq" => Some(Naming::Refseq),
+            _ => None,
+        }
+    }
+}
+
+/// Bundled RefSeq accessions of the primary chromosomes, by UCSC name.
+const HG38: [(&str, &str); 25] = [
+    ("chr1", "NC_000001.11"),
+    ("chr2", "NC_000002.12"),
+    ("chr3", "NC_000003.12"),
+    ("chr4", "NC_000004.12"),
+    ("chr5", "NC_000005.10"),
+    ("chr6", "NC_000006.12"),
+    ("chr7", "NC_000007.14"),
+    ("chr8", "NC_000008.11"),
+    ("chr9", "NC_000009.12"),
+    ("chr10", "NC_000010.11"),
+    ("chr11", "NC_000011.10"),
+    ("chr12", "NC_000012.12"),
+    ("chr13", "NC_000013.11"),
+    ("chr14", "NC_000014.9"),
+    ("chr15", "NC_000015.10"),
+    ("chr16", "NC_000016.10"),
+    ("chr17", "NC_000017.11"),
+    ("chr18", "NC_000018.10"),
+    ("chr19", "NC_000019.10"),
+    ("chr20", "NC_000020.11"),
+    ("chr21", "NC_000021.9"),
+    ("chr22", "NC_000022.11"),
+    ("chrX", "NC_000023.11"),
+    ("chrY", "NC_000024.10"),
+    ("chrM", "NC_012920.1"),
+];
+
+const MM39: [(&str, &str); 22] = [
+    ("chr1", "NC_000067.7"),
+    ("chr2", "NC_000068.8"),
+    ("chr3", "NC_000069.7"),
+    ("chr4", "NC_000070.7"),
+    ("chr5", "NC_000071.7"),
+    ("chr6", "NC_000072.7"),
+    ("chr7", "NC_000073.7"),
+    ("chr8", "NC_000074.7"),
+    ("chr9", "NC_000075.7"),
+    ("chr10", "NC_000076.7"),
+    ("chr11", "NC_000077.7"),
+    ("chr12", "NC_000078.7"),
+    ("chr13", "NC_000079.7"),
+    ("chr14", "NC_000080.7"),
+    ("chr15", "NC_000081.7"),
+    ("chr16", "NC_000082.7"),
+    ("chr17", "NC_000083.7"),
+    ("chr18", "NC_000084.7"),
+    ("chr19", "NC_000085.7"),
+    ("chrX", "NC_000086.8"),
+    ("chrY", "NC_000087.8"),
+    ("chrM", "NC_005089.1"),
+];
+
+/// Names of the same contigs under each [`Naming`], as in a UCSC
+/// chromAlias file.
+#[derive(Debug, Clone, Default)]
+pub struct ChromAlias {
+    /// Any known name -> contig.
+    names: HashMap<String, usize>,
+    contigs: Vec<[Option<String>; 3]>,
+}
+
+impl ChromAlias {
+    /// Loads a bundled table (`hg38`/`GRCh38`, `mm39`/`GRCm39`, primary
+    /// chromosomes only) or a chromAlias file.
+    pub fn load(source: &str) -> Result<ChromAlias, Box<dyn Error>> {
+        if let Some(table) = ChromAlias::builtin(source) {
+            return Ok(table);
+        }
+        if !Path::new(source).exists() {
+            return Err(format!(
+                "{:?} is neither a chromAlias file nor a bundled assembly (hg38, GRCh38, mm39, GRCm39)",
+                source
+            )
+            .into());
+        }
+        ChromAlias::parse(&raw(source)?)
+    }
+
+    pub fn builtin(assembly: &str) -> Option<ChromAlias> {
+        let pairs: &[(&str, &str)] = match assembly.to_ascii_lowercase().as_str() {
+            "hg38" | "grch38" => &HG38,
+            "mm39" | "grcm39" => &MM39,
+            _ => return None,
+        };
+        let mut table = ChromAlias::default();
+        for (ucsc, refseq) in pairs {
+            table.push([
+                Some(ucsc.to_string()),
+                Some(ensembl_name(ucsc)),
+                Some(refseq.to_string()),
+            ]);
+        }
+        Some(table)
+    }
+
+    /// Parses a chromAlias file, either the current layout with a
+    /// `# ucsc<TAB>assembly<TAB>ensembl<TAB>genbank<TAB>refseq` header and
+    /// one contig per row, or the legacy `alias<TAB>ucsc<TAB>sources` rows.
+    pub fn parse(contents: &str) -> Result<ChromAlias, Box<dyn Error>> {
+        let mut table = ChromAlias::default();
+        let header = contents.lines().find(|l| l.starts_with('#'));
+        let columns = header
+            .map(|h| {
+                h.trim_start_matches('#')
+                    .split('\t')
+                    .map(Naming::from_column)
+                    .collect::<Vec<_>>()
+            })
+            .filter(|c| c.iter().any(Option::is_some));
+
+        let rows = contents
+            .lines()
+            .filter(|l| !l.starts_with('#') && !l.trim().is_empty());
+        match columns {
+            Some(columns) => {
+                for row in rows {
+                    let mut names: [Option<String>; 3] = Default::default();
+                    for (naming, value) in columns.iter().zip(row.split('\t')) {
+                        if let (Some(naming), false) = (naming, value.trim().is_empty()) {
+                            names[naming.index()] = Some(value.trim().to_string());
+                        }
+                    }
+                    table.push(names);
+                }
+            }
+            None => {
+                let mut by_ucsc: HashMap<String, [Option<String>; 3]> = HashMap::new();
+                for row in rows {
+                    let fields = row.split('\t').collect::<Vec<_>>();
+                    if fields.len() < 3 {
+                        return Err(format!("invalid chromAlias line: {}", row).into());
+                    }
+                    let names = by_ucsc.entry(fields[1].to_string()).or_default();
+                    names[Naming::Ucsc.index()] = Some(fields[1].to_string());
+                    for source in fields[2].split(',').filter_map(Naming::from_column) {
+                        names[source.index()] = Some(fields[0].to_string());
+                    }
+                }
+                by_ucsc.into_values().for_each(|names| table.push(names));
+            }
+        }
+        if table.contigs.is_empty() {
+            return Err("chromAlias file lists no contigs".into());
+        }
+        Ok(table)
+    }
+
+    fn push(&mut self, names: [Option<String>; 3]) {
+        let i = self.contigs.len();
+        for name in names.iter().flatten() {
+            self.names.entry(name.clone()).or_insert(i);
+        }
+        self.contigs.push(names);
+    }
+
+    /// Name of `contig` (under any naming) in `target` naming.
+    pub fn translate(&self, contig: &str, target: Naming) -> Option<&str> {
+        let i = *self.names.get(contig)?;
+        self.contigs[i][target.index()].as_deref()
+    }
+}
+
+/// Ensembl name of a UCSC primary chromosome.
+fn ensembl_name(ucsc: &str) -> String {
+    match ucsc.strip_prefix("chr").unwrap_or(ucsc) {
+        "M" => "MT".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// UCSC <-> Ensembl renaming of primary chromosomes, used when no alias
+/// table is given.
+fn by_rule(contig: &str, target: Naming) -> Option<String> {
+    let primary = |name: &str| {
+        name.chars().all(|c| c.is_ascii_digit()) || ["X", "Y", "W", "Z", "M", "MT"].contains(&name)
+    };
+    match target {
+        Naming::Ensembl => contig
+            .strip_prefix("chr")
+            .filter(|name| primary(name))
+            .map(ensembl_name)
+            .or_else(|| primary(contig).then(|| contig.to_string())),
+        Naming::Ucsc if contig.starts_with("chr") => Some(contig.to_string()),
+        Naming::Ucsc => match contig {
+            "MT" => Some("chrM".to_string()),
+            name if primary(name) => Some(format!("chr{}", name)),
+            _ => None,
+        },
+        Naming::Refseq => None,
+    }
+}
+
+/// Renames the contigs of `records` to `target` naming, through `table`
+/// when given and the UCSC/Ensembl primary-chromosome rules otherwise.
+/// Returns the contigs that could not be translated, left unchanged.
+pub fn rename_contigs(
+    records: &mut [BedRecord],
+    table: Option<&ChromAlias>,
+    target: Naming,
+) -> BTreeSet<String> {
+    let mut cache: HashMap<String, Option<String>> = HashMap::new();
+    let mut unmapped = BTreeSet::new();
+    for record in records.iter_mut() {
+        let name = cache
+            .entry(record.chrom.clone())
+            .or_insert_with(|| match table {
+                Some(table) => table.translate(&record.chrom, target).map(str::to_string),
+                None => by_rule(&record.chrom, target),
+            });
+        match name {
+            Some(name) => record.chrom.clone_from(name),
+            None => {
+                unmapped.insert(record.chrom.clone());
+            }
+        }
+    }
+    unmapped
+}
+
+/// `--add-chr-prefix`/`--strip-chr-prefix`: the quick Ensembl <-> UCSC
+/// renaming of every contig, without an alias table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChrPrefix {
+    Add,
+    Strip,
+}
+
+impl ChrPrefix {
+    /// `contig` with its `chr` prefix added or stripped (`MT` and `chrM`
+    /// trade places), or None when it stays as is.
+    pub fn rename(self, contig: &str) -> Option<String> {
+        match self {
+            ChrPrefix::Add if contig == "MT" => Some("chrM".to_string()),
+            ChrPrefix::Add if !contig.starts_with("chr") => Some(format!("chr{}", contig)),
+            ChrPrefix::Add => None,
+            ChrPrefix::Strip if contig == "chrM" => Some("MT".to_string()),
+            ChrPrefix::Strip => contig
+                .strip_prefix("chr")
+                .filter(|name| !name.is_empty())
+                .map(str::to_string),
+        }
+    }
+}
+
+/// Renames the contig of every record with `prefix`.
+pub fn set_chr_prefix(records: &mut [BedRecord], prefix: ChrPrefix) {
+    for record in records.iter_mut() {
+        if let Some(name) = prefix.rename(&record.chrom) {
+            record.chrom = name;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translates_between_namings() {
+        let table = ChromAlias::parse(
+            "# ucsc\tassembly\tensembl\tgenbank\trefseq\n\
+             chr1\t1\t1\tCM000663.2\tNC_000001.11\n\
+             chr1_KI270706v1_random\tHSCHR1_RANDOM_CTG1\tKI270706.1\tKI270706.1\tNT_187361.1\n",
+        )
+        .unwrap();
+        assert_eq!(table.translate("1", Naming::Refseq), Some("NC_000001.11"));
+        assert_eq!(
+            table.translate("KI270706.1", Naming::Ucsc),
+            Some("chr1_KI270706v1_random")
+        );
+
+        let legacy = ChromAlias::parse("1\tchr1\tensembl\nNC_000001.11\tchr1\trefseq\n").unwrap();
+        assert_eq!(legacy.translate("NC_000001.11", Naming::Ensembl), Some("1"));
+
+        let hg38 = ChromAlias::builtin("GRCh38").unwrap();
+        assert_eq!(hg38.translate("MT", Naming::Ucsc), Some("chrM"));
+        assert_eq!(by_rule("chrM", Naming::Ensembl).as_deref(), Some("MT"));
+        assert_eq!(by_rule("chr1_KI270706v1_random", Naming::Ensembl), None);
+    }
+
+    #[test]
+    fn adds_and_strips_chr_prefix() {
+        assert_eq!(ChrPrefix::Add.rename("1").as_deref(), Some("chr1"));
+        assert_eq!(ChrPrefix::Add.rename("MT").as_deref(), Some("chrM"));
+        assert_eq!(
+            ChrPrefix::Add.rename("KI270706.1").as_deref(),
+            Some("chrKI270706.1")
+        );
+        assert_eq!(ChrPrefix::Add.rename("chrX"), None);
+        assert_eq!(ChrPrefix::Strip.rename("chrM").as_deref(), Some("MT"));
+        assert_eq!(ChrPrefix::Strip.rename("chr22").as_deref(), Some("22"));
+        assert_eq!(ChrPrefix::Strip.rename("22"), None);
+    }
+}
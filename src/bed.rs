@@ -1,103 +1,554 @@
+use crate::frame::{compute_frames, Frame};
+
 use std::cmp::{max, min};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// BED12 column names, for error messages.
+const COLUMNS: [&str; 12] = [
+    "chrom",
+    "chromStart",
+    "chromEnd",
+    "name",
+    "score",
+    "strand",
+    "thickStart",
+    "thickEnd",
+    "itemRgb",
+    "blockCount",
+    "blockSizes",
+    "blockStarts",
+];
+
+/// What is wrong with a BED line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// Fewer than 12 tab-separated fields (how many there are).
+    TooFewFields(usize),
+    /// Fewer than the 6 fields of a single-block line (see
+    /// [`BedRecord::parse_blocks`]).
+    TooFewBlockFields(usize),
+    InvalidUtf8,
+    /// A field or block value that is not an unsigned integer.
+    InvalidNumber(String),
+    /// blockSizes and blockStarts list different numbers of blocks.
+    BlockMismatch {
+        sizes: usize,
+        starts: usize,
+    },
+    NoBlocks,
+    /// blockCount differs from the number of blocks listed.
+    BlockCount {
+        declared: u32,
+        blocks: usize,
+    },
+    /// A value or block coordinate out of range.
+    Overflow,
+}
+
+/// A BED line that could not be parsed, with where the problem is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// 1-based column, when the problem lies in one.
+    pub column: Option<usize>,
+    /// Byte offset of the problem in the line.
+    pub offset: usize,
+    pub kind: ParseErrorKind,
+}
 
-#[derive(Debug, PartialEq)]
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let ParseErrorKind::TooFewFields(_) = self.kind {
+            return write!(
+                f,
+                "Bed line has less than 12 fields and cannot be parsed into a BedRecord"
+            );
+        }
+        if let ParseErrorKind::TooFewBlockFields(_) = self.kind {
+            return write!(f, "Bed line has less than 6 fields");
+        }
+        match self.column {
+            Some(column) => write!(
+                f,
+                "column {} ({}), byte {}: ",
+                column,
+                COLUMNS[column - 1],
+                self.offset
+            )?,
+            None => write!(f, "byte {}: ", self.offset)?,
+        }
+        match &self.kind {
+            ParseErrorKind::TooFewFields(_) | ParseErrorKind::TooFewBlockFields(_) => Ok(()),
+            ParseErrorKind::InvalidUtf8 => write!(f, "invalid UTF-8"),
+            ParseErrorKind::InvalidNumber(value) => write!(f, "invalid number {:?}", value),
+            ParseErrorKind::BlockMismatch { sizes, starts } => {
+                write!(f, "{} block sizes but {} block starts", sizes, starts)
+            }
+            ParseErrorKind::NoBlocks => write!(f, "no blocks"),
+            ParseErrorKind::BlockCount { declared, blocks } => {
+                write!(f, "blockCount {} but {} blocks", declared, blocks)
+            }
+            ParseErrorKind::Overflow => write!(f, "value out of range"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct BedRecord {
     pub chrom: String,
     pub tx_start: u32,
     pub tx_end: u32,
     pub name: String,
+    /// Column 5, when it is a number.
+    pub score: Option<f64>,
+    /// Column 5 as read when it is neither a number nor `.`, e.g. text.
+    pub score_label: Option<String>,
     pub strand: String,
     pub cds_start: u32,
     pub cds_end: u32,
-    pub exon_count: u16,
+    /// Column 10, checked against the number of blocks when parsed.
+    pub exon_count: u32,
     pub exon_start: Vec<u32>,
     pub exon_end: Vec<u32>,
+    /// Column 9 (itemRgb) as read, unless it is `0`, `.` or empty.
+    pub item_rgb: Option<String>,
+    /// Columns after the twelfth, e.g. the ID and description of bedDetail.
+    pub extra: Vec<String>,
+    /// Exon frames given with `--frames`, used instead of computing them.
+    pub frames: Option<Vec<i16>>,
+    /// Column-4 name as read, once [`rename`](BedRecord::rename) changed it.
+    pub original_name: Option<String>,
+}
+
+/// A column-5 score kept as text: anything but a number or `.`.
+fn score_label(field: &str) -> Option<String> {
+    (!matches!(field, "" | ".") && field.parse::<f64>().is_err()).then(|| field.to_string())
+}
+
+/// BED columns copied to transcript attributes with `--passthrough`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Passthrough {
+    /// Column 5 as `score`, numbers and text alike.
+    Score,
+    /// Column 9 as `item_rgb`, unless it is 0.
+    ItemRgb,
+}
+
+impl Passthrough {
+    pub fn key(self) -> &'static str {
+        match self {
+            Passthrough::Score => "score",
+            Passthrough::ItemRgb => "item_rgb",
+        }
+    }
+
+    /// Value of the column in `record`, if it has one.
+    pub fn value(self, record: &BedRecord) -> Option<String> {
+        match self {
+            Passthrough::Score => record
+                .score
+                .map(|score| score.to_string())
+                .or_else(|| record.score_label.clone()),
+            Passthrough::ItemRgb => record.item_rgb.clone(),
+        }
+    }
 }
 
 impl BedRecord {
     pub fn parse(line: &str) -> Result<BedRecord, String> {
-        let fields: Vec<&str> = line.split('\t').collect();
-        if fields.len() < 12 {
-            return Err(format!("Expected at least 12 fields, found {}", line));
-        }
+        BedRecord::parse_bytes(line.as_bytes()).map_err(|e| e.to_string())
+    }
 
-        let chrom = fields[0].to_string();
-        let name = fields[3].to_string();
-        let strand = fields[5].to_string();
+    /// Parses a BED12+ line without ever panicking: any malformed input
+    /// is reported as a [`ParseError`] with the column and byte offset
+    /// of the problem. A UTF-8 BOM and a trailing CR are ignored.
+    pub fn parse_bytes(line: &[u8]) -> Result<BedRecord, ParseError> {
+        // files saved on Windows: UTF-8 BOM and CRLF line endings
+        let base = if line.starts_with(b"\xef\xbb\xbf") {
+            3
+        } else {
+            0
+        };
+        let line = &line[base..];
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let line = std::str::from_utf8(line).map_err(|e| ParseError {
+            column: None,
+            offset: base + e.valid_up_to(),
+            kind: ParseErrorKind::InvalidUtf8,
+        })?;
 
-        let get = |field: &str| field.parse::<u32>().map_err(|_| "Cannot parse field");
-        let tx_start = get(fields[1])?;
-        let tx_end = get(fields[2])?;
-        let cds_start = get(fields[6])?;
-        let cds_end = get(fields[7])?;
-        let exon_count = get(fields[9])? as u16;
+        let mut fields = Vec::with_capacity(12);
+        let mut offset = base;
+        for field in line.split('\t') {
+            fields.push((offset, field));
+            offset += field.len() + 1;
+        }
+        if fields.len() < 12 {
+            return Err(ParseError {
+                column: None,
+                offset: base + line.len(),
+                kind: ParseErrorKind::TooFewFields(fields.len()),
+            });
+        }
 
-        let group = |field: &str| -> Result<Vec<u32>, &'static str> {
+        let error = |column: usize, offset: usize, kind| ParseError {
+            column: Some(column + 1),
+            offset,
+            kind,
+        };
+        let number = |column: usize| {
+            let (offset, field) = fields[column];
             field
-                .split(',')
-                .filter_map(|num| {
-                    if !num.is_empty() {
-                        Some(num.parse::<u32>().expect("Cannot parse number"))
-                    } else {
-                        None
-                    }
-                })
-                .map(|num| Ok(num))
-                .collect()
+                .parse::<u32>()
+                .map_err(|_| error(column, offset, ParseErrorKind::InvalidNumber(field.into())))
         };
+        let tx_start = number(1)?;
+        let tx_end = number(2)?;
+        let cds_start = number(6)?;
+        let cds_end = number(7)?;
+        let declared = number(9)?;
 
-        let exon_start = group(fields[11])?;
-        let exon_end = group(fields[10])?;
-
-        if exon_start.len() != exon_end.len() {
-            return Err("Exon start and end vectors have different lengths".to_string());
+        let blocks = |column: usize| {
+            let (mut offset, field) = fields[column];
+            let mut values = Vec::new();
+            for value in field.split(',') {
+                if !value.is_empty() {
+                    let n = value.parse::<u32>().map_err(|_| {
+                        error(column, offset, ParseErrorKind::InvalidNumber(value.into()))
+                    })?;
+                    values.push((offset, n));
+                }
+                offset += value.len() + 1;
+            }
+            Ok(values)
+        };
+        let sizes = blocks(10)?;
+        let starts = blocks(11)?;
+        if sizes.len() != starts.len() {
+            return Err(error(
+                11,
+                fields[11].0,
+                ParseErrorKind::BlockMismatch {
+                    sizes: sizes.len(),
+                    starts: starts.len(),
+                },
+            ));
+        }
+        if sizes.is_empty() {
+            return Err(error(10, fields[10].0, ParseErrorKind::NoBlocks));
+        }
+        let exon_count = u32::try_from(sizes.len())
+            .map_err(|_| error(10, fields[10].0, ParseErrorKind::Overflow))?;
+        if declared != exon_count {
+            return Err(error(
+                9,
+                fields[9].0,
+                ParseErrorKind::BlockCount {
+                    declared,
+                    blocks: sizes.len(),
+                },
+            ));
         }
 
-        let exon_starts: Vec<u32> = exon_start.iter().map(|&s| s + tx_start).collect();
-        let exon_ends: Vec<u32> = exon_end
-            .iter()
-            .enumerate()
-            .map(|(i, &s)| s + exon_starts[i])
-            .collect();
+        let mut exon_start = Vec::with_capacity(starts.len());
+        let mut exon_end = Vec::with_capacity(sizes.len());
+        for (&(start_offset, start), &(size_offset, size)) in starts.iter().zip(&sizes) {
+            let start = tx_start
+                .checked_add(start)
+                .ok_or_else(|| error(11, start_offset, ParseErrorKind::Overflow))?;
+            let end = start
+                .checked_add(size)
+                .ok_or_else(|| error(10, size_offset, ParseErrorKind::Overflow))?;
+            exon_start.push(start);
+            exon_end.push(end);
+        }
 
         Ok(BedRecord {
-            chrom: chrom.to_string(),
-            tx_start: tx_start,
-            tx_end: tx_end,
-            name: name.to_string(),
-            strand: strand.to_string(),
-            cds_start: cds_start,
-            cds_end: cds_end,
-            exon_count: exon_count,
-            exon_start: exon_starts,
-            exon_end: exon_ends,
+            chrom: fields[0].1.to_string(),
+            tx_start,
+            tx_end,
+            name: fields[3].1.to_string(),
+            score: fields[4].1.parse().ok(),
+            score_label: score_label(fields[4].1),
+            strand: fields[5].1.to_string(),
+            cds_start,
+            cds_end,
+            exon_count,
+            exon_start,
+            exon_end,
+            item_rgb: Some(fields[8].1)
+                .filter(|rgb| !matches!(*rgb, "" | "." | "0"))
+                .map(String::from),
+            extra: fields[12..].iter().map(|(_, f)| f.to_string()).collect(),
+            frames: None,
+            original_name: None,
         })
     }
 
-    pub fn get_frames(&self) -> Vec<i16> {
-        let mut exon_frames: Vec<i16> = vec![0; self.exon_count as usize];
-        let mut cds: u32 = 0;
+    /// Renames the record, remembering the name it was read with.
+    pub fn rename(&mut self, name: String) {
+        if name != self.name {
+            let old = std::mem::replace(&mut self.name, name);
+            self.original_name.get_or_insert(old);
+        }
+    }
+
+    /// Name in column 4 of the input, before any renaming.
+    pub fn original_name(&self) -> &str {
+        self.original_name.as_deref().unwrap_or(&self.name)
+    }
 
-        let exon_range = if self.strand == "+" {
-            (0..(self.exon_count as usize)).collect::<Vec<_>>()
+    /// Parses a BED12 line, or a BED6+ line as a single-block non-coding
+    /// record (e.g. `bedtools bamtobed -split` fragments).
+    pub fn parse_blocks(line: &str) -> Result<BedRecord, String> {
+        BedRecord::parse_blocks_bytes(line.as_bytes()).map_err(|e| e.to_string())
+    }
+
+    /// [`BedRecord::parse_blocks`] reporting a [`ParseError`], as
+    /// [`BedRecord::parse_bytes`] does.
+    pub fn parse_blocks_bytes(line: &[u8]) -> Result<BedRecord, ParseError> {
+        let base = if line.starts_with(b"\xef\xbb\xbf") {
+            3
         } else {
-            (0..(self.exon_count as usize)).rev().collect::<Vec<_>>()
+            0
+        };
+        let trimmed = &line[base..];
+        let trimmed = trimmed.strip_suffix(b"\r").unwrap_or(trimmed);
+        let trimmed = std::str::from_utf8(trimmed).map_err(|e| ParseError {
+            column: None,
+            offset: base + e.valid_up_to(),
+            kind: ParseErrorKind::InvalidUtf8,
+        })?;
+        let fields: Vec<&str> = trimmed.split('\t').collect();
+        if fields.len() >= 12 {
+            return BedRecord::parse_bytes(line);
+        }
+        if fields.len() < 6 {
+            return Err(ParseError {
+                column: None,
+                offset: base + trimmed.len(),
+                kind: ParseErrorKind::TooFewBlockFields(fields.len()),
+            });
+        }
+        let get = |column: usize| {
+            fields[column].parse::<u32>().map_err(|_| ParseError {
+                column: Some(column + 1),
+                offset: base + fields[..column].iter().map(|f| f.len() + 1).sum::<usize>(),
+                kind: ParseErrorKind::InvalidNumber(fields[column].to_string()),
+            })
         };
+        let (tx_start, tx_end) = (get(1)?, get(2)?);
+        Ok(BedRecord {
+            chrom: fields[0].to_string(),
+            tx_start,
+            tx_end,
+            name: fields[3].to_string(),
+            score: fields[4].parse().ok(),
+            score_label: score_label(fields[4]),
+            strand: fields[5].to_string(),
+            cds_start: tx_start,
+            cds_end: tx_start,
+            exon_count: 1,
+            exon_start: vec![tx_start],
+            exon_end: vec![tx_end],
+            item_rgb: None,
+            extra: Vec::new(),
+            frames: None,
+            original_name: None,
+        })
+    }
+
+    /// Exon blocks as (start, end) pairs, in genomic order.
+    pub fn exon_blocks(&self) -> Vec<(u32, u32)> {
+        self.exon_start
+            .iter()
+            .copied()
+            .zip(self.exon_end.iter().copied())
+            .collect()
+    }
+
+    /// Coding portions of each exon, in genomic order.
+    pub fn cds_blocks(&self) -> Vec<(u32, u32)> {
+        self.exon_start
+            .iter()
+            .zip(self.exon_end.iter())
+            .filter_map(|(&start, &end)| {
+                let start = max(start, self.cds_start);
+                let end = min(end, self.cds_end);
+                if start < end {
+                    Some((start, end))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
 
-        for exon in exon_range {
-            let cds_exon_start = max(self.exon_start[exon], self.cds_start);
-            let cds_exon_end = min(self.exon_end[exon], self.cds_end);
+    /// Exon frames in genomic order, -1 for non-coding exons: the ones
+    /// given with `--frames`, or computed from the CDS (see
+    /// [`compute_frames`]).
+    pub fn get_frames(&self) -> Vec<i16> {
+        match &self.frames {
+            Some(frames) => frames.clone(),
+            None => self.computed_frames(),
+        }
+    }
 
-            if cds_exon_start < cds_exon_end {
-                exon_frames[exon] = (cds % 3) as i16;
-                cds += cds_exon_end - cds_exon_start;
-            } else {
-                exon_frames[exon] = -1;
+    /// Exon frames computed from the CDS extents, whatever `--frames`
+    /// gives.
+    pub fn computed_frames(&self) -> Vec<i16> {
+        // anything but + has always been read right to left here
+        let strand = if self.strand == "+" { "+" } else { "-" };
+        let exons = self
+            .exon_blocks()
+            .into_iter()
+            .map(|(start, end)| (start as u64, end as u64))
+            .collect::<Vec<_>>();
+        compute_frames(&exons, (self.cds_start as u64, self.cds_end as u64), strand)
+            .into_iter()
+            .map(Frame::exon_frame)
+            .collect()
+    }
+}
+
+impl FromStr for BedRecord {
+    type Err = ParseError;
+
+    fn from_str(line: &str) -> Result<BedRecord, ParseError> {
+        BedRecord::parse_bytes(line.as_bytes())
+    }
+}
+
+/// The BED12 line of the record, extra columns included. A missing
+/// score or itemRgb is written as 0.
+impl fmt::Display for BedRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t",
+            self.chrom, self.tx_start, self.tx_end, self.name
+        )?;
+        match (self.score, &self.score_label) {
+            (Some(score), _) => write!(f, "{}", score)?,
+            (None, Some(label)) => f.write_str(label)?,
+            (None, None) => f.write_str("0")?,
+        }
+        write!(
+            f,
+            "\t{}\t{}\t{}\t{}\t{}\t",
+            self.strand,
+            self.cds_start,
+            self.cds_end,
+            self.item_rgb.as_deref().unwrap_or("0"),
+            self.exon_count
+        )?;
+        for (start, end) in self.exon_start.iter().zip(&self.exon_end) {
+            write!(f, "{},", end.wrapping_sub(*start))?;
+        }
+        f.write_str("\t")?;
+        for start in &self.exon_start {
+            write!(f, "{},", start.wrapping_sub(self.tx_start))?;
+        }
+        self.extra
+            .iter()
+            .try_for_each(|column| write!(f, "\t{}", column))
+    }
+}
+
+/// `--auto-name`: renames `records` to `PREFIX_000001`, `PREFIX_000002`,
+/// ... in input order, `first` being the number of records before them.
+pub fn auto_name(records: &mut [BedRecord], prefix: &str, first: usize) {
+    for (i, record) in records.iter_mut().enumerate() {
+        record.rename(format!("{}_{:06}", prefix, first + i + 1));
+    }
+}
+
+/// Reads a `transcript_id<TAB>frames` table, frames comma-separated in
+/// genomic order as in genePredExt `exonFrames` (e.g. `0,2,1,-1,`).
+pub fn read_frames(contents: &str) -> Result<HashMap<String, Vec<i16>>, String> {
+    let mut frames = HashMap::new();
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, values) = line
+            .split_once('\t')
+            .ok_or_else(|| format!("line {}: expected transcript_id<TAB>frames", i + 1))?;
+        let values = values
+            .trim()
+            .split(',')
+            .filter(|v| !v.is_empty())
+            .map(|v| match v.trim().parse::<i16>() {
+                Ok(frame @ -1..=2) => Ok(frame),
+                _ => Err(format!("line {}: invalid frame {:?}", i + 1, v)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        frames.insert(name.trim().to_string(), values);
+    }
+    Ok(frames)
+}
+
+/// Joins runs of consecutive records with the same name, chromosome and
+/// strand into one multi-exon record: blocks are sorted and overlapping
+/// ones merged, and the thick region spans those of the coding
+/// fragments. A name that reappears after a different one, or on another
+/// chromosome or strand (chimeric reads), starts a new record.
+pub fn stitch_by_name(records: Vec<BedRecord>) -> Vec<BedRecord> {
+    let mut stitched: Vec<BedRecord> = Vec::new();
+    let mut blocks: Vec<(u32, u32)> = Vec::new();
+    let flush = |record: &mut BedRecord, blocks: &mut Vec<(u32, u32)>| {
+        blocks.sort_unstable();
+        let mut merged: Vec<(u32, u32)> = Vec::with_capacity(blocks.len());
+        for &(start, end) in blocks.iter() {
+            match merged.last_mut() {
+                Some(last) if start < last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
             }
         }
+        record.exon_start = merged.iter().map(|b| b.0).collect();
+        record.exon_end = merged.iter().map(|b| b.1).collect();
+        record.exon_count = merged.len() as u32;
+        blocks.clear();
+    };
 
-        exon_frames
+    for record in records {
+        match stitched.last_mut() {
+            Some(last)
+                if last.name == record.name
+                    && last.chrom == record.chrom
+                    && last.strand == record.strand =>
+            {
+                if record.cds_start < record.cds_end {
+                    if last.cds_start < last.cds_end {
+                        last.cds_start = min(last.cds_start, record.cds_start);
+                        last.cds_end = max(last.cds_end, record.cds_end);
+                    } else {
+                        (last.cds_start, last.cds_end) = (record.cds_start, record.cds_end);
+                    }
+                }
+                last.tx_start = min(last.tx_start, record.tx_start);
+                last.tx_end = max(last.tx_end, record.tx_end);
+                blocks.extend(record.exon_blocks());
+            }
+            _ => {
+                if let Some(last) = stitched.last_mut() {
+                    flush(last, &mut blocks);
+                }
+                blocks.extend(record.exon_blocks());
+                stitched.push(record);
+            }
+        }
+    }
+    if let Some(last) = stitched.last_mut() {
+        flush(last, &mut blocks);
+    }
+    for record in stitched.iter_mut().filter(|r| r.cds_start >= r.cds_end) {
+        (record.cds_start, record.cds_end) = (record.tx_start, record.tx_start);
     }
+    stitched
 }
 
 #[cfg(test)]
@@ -130,6 +581,97 @@ mod tests {
         assert_eq!(record.get_frames(), vec![1, 0, 0, 0, 1, 0, 2, 1, 0]);
     }
 
+    #[test]
+    fn bom_and_crlf() {
+        let line = "\u{feff}chr15\t81000922\t81005788\tENST00000267984\t0\t+\t81002271\t81003360\t0\t1\t4866,\t0\r";
+        let record = BedRecord::parse(line).unwrap();
+
+        assert_eq!(record.chrom, "chr15");
+        assert_eq!(record.exon_start, vec![81000922]);
+    }
+
+    #[test]
+    fn bed_detail_columns() {
+        let line = "chr15\t81000922\t81005788\tENST00000267984\t0\t+\t81002271\t81003360\t0\t1\t4866,\t0,\tuc002bfx.2\tTMC3 transcript";
+        let record = BedRecord::parse(line).unwrap();
+
+        assert_eq!(record.extra, vec!["uc002bfx.2", "TMC3 transcript"]);
+    }
+
+    #[test]
+    fn round_trips_lines() {
+        let line = "chr11\t13934505\t13958243\tENST00000674667\t1000\t-\t13934505\t13958243\t0\t3\t224,217,49,\t0,1305,23689,\tuc001mlq.1";
+        let record = line.parse::<BedRecord>().unwrap();
+        assert_eq!(record.to_string(), line);
+        assert_eq!(record.to_string().parse::<BedRecord>(), Ok(record));
+        assert!("chr1\t0".parse::<BedRecord>().is_err());
+
+        let line =
+            line.replacen("\t1000\t", "\thigh\t", 1)
+                .replacen("\t0\t3\t", "\t255,0,0\t3\t", 1);
+        let record = line.parse::<BedRecord>().unwrap();
+        assert_eq!(
+            (record.score, record.score_label.as_deref()),
+            (None, Some("high"))
+        );
+        assert_eq!(
+            Passthrough::ItemRgb.value(&record).as_deref(),
+            Some("255,0,0")
+        );
+        assert_eq!(record.to_string(), line);
+        let dotted = line.replacen("\thigh\t", "\t.\t", 1);
+        assert_eq!(Passthrough::Score.value(&dotted.parse().unwrap()), None);
+    }
+
+    #[test]
+    fn rename_keeps_original_name() {
+        let line = "chr1\t0\t100\tENST1.2\t0\t+\t0\t0\t0\t1\t100,\t0,";
+        let mut record = BedRecord::parse(line).unwrap();
+        record.rename("ENST1.2".to_string());
+        assert_eq!(record.original_name, None);
+        record.rename("ENST1".to_string());
+        record.rename("ENST1_G2".to_string());
+        assert_eq!(record.name, "ENST1_G2");
+        assert_eq!(record.original_name(), "ENST1.2");
+
+        let anonymous = line.replace("ENST1.2", ".");
+        let mut records = vec![BedRecord::parse(&anonymous).unwrap(); 2];
+        auto_name(&mut records, "TX", 9);
+        assert_eq!(records[0].name, "TX_000010");
+        assert_eq!(records[1].name, "TX_000011");
+        assert_eq!(records[1].original_name(), ".");
+    }
+
+    #[test]
+    fn stitches_split_alignments() {
+        let fragments = [
+            "chr1\t500\t600\tr1\t60\t+",
+            "chr1\t100\t200\tr1\t60\t+",
+            "chr1\t100\t200\tr2\t60\t-",
+        ]
+        .iter()
+        .map(|l| BedRecord::parse_blocks(l).unwrap())
+        .collect::<Vec<_>>();
+        let stitched = stitch_by_name(fragments);
+
+        assert_eq!(stitched.len(), 2);
+        assert_eq!(stitched[0].exon_blocks(), vec![(100, 200), (500, 600)]);
+        assert_eq!((stitched[0].tx_start, stitched[0].tx_end), (100, 600));
+        assert_eq!(stitched[0].cds_start, stitched[0].cds_end);
+    }
+
+    #[test]
+    fn frames_override() {
+        let frames = read_frames("# id\tframes\nT1\t0,2,-1,\n").unwrap();
+        let line = "chr1\t0\t900\tT1\t0\t+\t0\t500\t0\t3\t100,100,100,\t0,400,800,";
+        let mut record = BedRecord::parse(line).unwrap();
+        assert_eq!(record.get_frames(), vec![0, 1, -1]);
+
+        record.frames = frames.get("T1").cloned();
+        assert_eq!(record.get_frames(), vec![0, 2, -1]);
+        assert!(read_frames("T1\t0,3\n").is_err());
+    }
+
     #[test]
     fn invalid_record() {
         let line =
@@ -145,6 +687,44 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_bytes_never_panics() {
+        let line = b"chr1\t100\t500\tT1\t0\t+\t100\t500\t0\t2\t100,9x,\t0,300,";
+        let error = BedRecord::parse_bytes(line).unwrap_err();
+        assert_eq!(
+            error,
+            ParseError {
+                column: Some(11),
+                offset: 36,
+                kind: ParseErrorKind::InvalidNumber("9x".to_string()),
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "column 11 (blockSizes), byte 36: invalid number \"9x\""
+        );
+
+        let overflow = b"chr1\t4294967000\t4294967295\tT1\t0\t+\t0\t0\t0\t1\t100,\t1000,";
+        let error = BedRecord::parse_bytes(overflow).unwrap_err();
+        assert_eq!(
+            (error.column, error.kind),
+            (Some(12), ParseErrorKind::Overflow)
+        );
+        let count = b"chr1\t100\t500\tT1\t0\t+\t100\t500\t0\t70000\t100,100,\t0,300,";
+        let error = BedRecord::parse_bytes(count).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "column 10 (blockCount), byte 30: blockCount 70000 but 2 blocks"
+        );
+        assert_eq!(
+            BedRecord::parse_bytes(b"chr1\t\xff").unwrap_err().kind,
+            ParseErrorKind::InvalidUtf8
+        );
+        for cut in 0..line.len() {
+            let _ = BedRecord::parse_bytes(&line[..cut]);
+        }
+    }
+
     #[test]
     fn empty_record() {
         let line = "";
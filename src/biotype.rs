@@ -0,0 +1,232 @@
+use crate::bed::BedRecord;
+use crate::lines::GtfRecord;
+
+use std::collections::HashMap;
+
+/// transcript_biotype of `--nmd-biotype` transcripts.
+pub const NMD_BIOTYPE: &str = "nonsense_mediated_decay";
+
+/// Thresholds of `--infer-biotypes`.
+#[derive(Debug, Clone, Copy)]
+pub struct BiotypeRules {
+    /// Shortest CDS, in nucleotides, of a protein_coding transcript.
+    pub min_cds: u32,
+    /// Shortest exonic length of a lncRNA.
+    pub min_lnc_length: u32,
+    /// Fewest exons of a lncRNA.
+    pub min_lnc_exons: u16,
+}
+
+impl BiotypeRules {
+    /// Biotype of a transcript: protein_coding with a long enough CDS,
+    /// lncRNA when long (and spliced) enough without one, and
+    /// processed_transcript otherwise.
+    pub fn transcript(&self, record: &BedRecord) -> &'static str {
+        let cds = record
+            .cds_blocks()
+            .iter()
+            .map(|(start, end)| end - start)
+            .sum::<u32>();
+        let length = record
+            .exon_blocks()
+            .iter()
+            .map(|(start, end)| end - start)
+            .sum::<u32>();
+        if cds > 0 && cds >= self.min_cds {
+            "protein_coding"
+        } else if length >= self.min_lnc_length
+            && record.exon_count >= u32::from(self.min_lnc_exons)
+        {
+            "lncRNA"
+        } else {
+            "processed_transcript"
+        }
+    }
+
+    /// Biotype of every gene, the most informative of its transcripts':
+    /// protein_coding, then lncRNA, then processed_transcript. Without
+    /// `isoforms` each transcript is its own gene.
+    pub fn genes(
+        &self,
+        records: &[BedRecord],
+        isoforms: &HashMap<String, String>,
+    ) -> HashMap<String, &'static str> {
+        let rank = |biotype: &str| match biotype {
+            "protein_coding" => 2,
+            "lncRNA" => 1,
+            _ => 0,
+        };
+        let mut genes: HashMap<String, &'static str> = HashMap::new();
+        for record in records {
+            let gene = isoforms.get(&record.name).unwrap_or(&record.name);
+            let biotype = self.transcript(record);
+            let entry = genes.entry(gene.clone()).or_insert(biotype);
+            if rank(biotype) > rank(entry) {
+                *entry = biotype;
+            }
+        }
+        genes
+    }
+}
+
+/// Column-2 values of `--source` and `--source-map`: a source per
+/// transcript_biotype or gene_biotype, and one for everything else.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    biotypes: HashMap<String, String>,
+    default: Option<String>,
+}
+
+impl SourceMap {
+    /// Reads a `biotype<TAB>source` table; blank and `#` lines are
+    /// skipped.
+    pub fn parse(contents: &str, default: Option<String>) -> Result<SourceMap, String> {
+        let mut biotypes = HashMap::new();
+        for (i, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split('\t').map(str::trim).collect::<Vec<_>>()[..] {
+                [biotype, source] if !biotype.is_empty() && !source.is_empty() => {
+                    biotypes.insert(biotype.to_string(), source.to_string());
+                }
+                _ => return Err(format!("line {}: expected biotype<TAB>source", i + 1)),
+            }
+        }
+        Ok(SourceMap { biotypes, default })
+    }
+
+    /// Only `default`, for runs without a `--source-map`.
+    pub fn new(default: Option<String>) -> SourceMap {
+        SourceMap {
+            biotypes: HashMap::new(),
+            default,
+        }
+    }
+
+    /// Sets the source of `lines`, the features of one transcript (or a
+    /// gene line), from the transcript_biotype, else the gene_biotype,
+    /// of the first; unmapped biotypes take the default.
+    pub fn apply(&self, lines: &mut [GtfRecord]) {
+        let Some(first) = lines.first() else {
+            return;
+        };
+        let source = ["transcript_biotype", "gene_biotype"]
+            .iter()
+            .find_map(|key| self.biotypes.get(first.attrs.get(key)?))
+            .or(self.default.as_ref());
+        if let Some(source) = source {
+            let source = source.clone();
+            lines
+                .iter_mut()
+                .for_each(|line| line.source = Some(source.clone()));
+        }
+    }
+}
+
+/// Whether the stop codon of `record` lies more than `distance` exonic
+/// bases upstream of its last exon-exon junction, the usual rule for
+/// nonsense-mediated decay.
+pub fn nmd_candidate(record: &BedRecord, distance: u32) -> bool {
+    if record.cds_start >= record.cds_end || record.exon_start.len() < 2 {
+        return false;
+    }
+    let (from, to) = if record.strand == "-" {
+        (record.exon_end[0], record.cds_start)
+    } else {
+        (
+            record.cds_end,
+            record.exon_start[record.exon_start.len() - 1],
+        )
+    };
+    let exonic = record
+        .exon_blocks()
+        .iter()
+        .map(|&(start, end)| end.min(to).saturating_sub(start.max(from)))
+        .sum::<u32>();
+    exonic > distance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_transcripts_and_genes() {
+        let rules = BiotypeRules {
+            min_cds: 300,
+            min_lnc_length: 200,
+            min_lnc_exons: 2,
+        };
+        let coding =
+            BedRecord::parse("chr1\t1000\t2000\tc1\t0\t+\t1100\t1900\t0\t2\t400,400,\t0,600,")
+                .unwrap();
+        let lnc =
+            BedRecord::parse("chr1\t1000\t2000\tn1\t0\t+\t1000\t1000\t0\t2\t200,200,\t0,800,")
+                .unwrap();
+        let short =
+            BedRecord::parse("chr1\t5000\t5150\tn2\t0\t-\t5000\t5000\t0\t1\t150,\t0,").unwrap();
+        assert_eq!(rules.transcript(&coding), "protein_coding");
+        assert_eq!(rules.transcript(&lnc), "lncRNA");
+        assert_eq!(rules.transcript(&short), "processed_transcript");
+
+        let isoforms = [("c1", "g1"), ("n1", "g1"), ("n2", "g2")]
+            .iter()
+            .map(|(t, g)| (t.to_string(), g.to_string()))
+            .collect();
+        let genes = rules.genes(&[coding, lnc, short], &isoforms);
+        assert_eq!(genes["g1"], "protein_coding");
+        assert_eq!(genes["g2"], "processed_transcript");
+
+        let nmd = BedRecord::parse(
+            "chr1\t1000\t2000\tt1\t0\t+\t1010\t1300\t0\t3\t400,100,100,\t0,600,900,",
+        )
+        .unwrap();
+        let minus = BedRecord::parse(
+            "chr1\t1000\t2000\tt2\t0\t-\t1300\t1900\t0\t3\t100,400,200,\t0,200,800,",
+        )
+        .unwrap();
+        assert!(nmd_candidate(&nmd, 50));
+        assert!(!nmd_candidate(&nmd, 200));
+        assert!(nmd_candidate(&minus, 50));
+        assert!(!nmd_candidate(&minus, 100));
+    }
+
+    #[test]
+    fn maps_biotypes_to_sources() {
+        let sources = SourceMap::parse(
+            "# biotype\tsource\nprotein_coding\tensembl\nnovel\tstringtie_novel\n",
+            Some("lab".to_string()),
+        )
+        .unwrap();
+        let line = |attrs: &[(&str, &str)]| {
+            let mut line: GtfRecord = "chr1\tbed2gtf\ttranscript\t1\t100\t.\t+\t.\tgene_id \"G1\";"
+                .parse()
+                .unwrap();
+            for (key, value) in attrs {
+                line.attrs.push(*key, *value);
+            }
+            line
+        };
+        let mut lines = vec![
+            line(&[
+                ("gene_biotype", "novel"),
+                ("transcript_biotype", "protein_coding"),
+            ]),
+            line(&[]),
+        ];
+        sources.apply(&mut lines);
+        assert_eq!(lines[1].source.as_deref(), Some("ensembl"));
+        let mut gene = [line(&[("gene_biotype", "novel")])];
+        sources.apply(&mut gene);
+        assert!(gene[0].to_string().starts_with("chr1\tstringtie_novel\t"));
+        let mut other = [line(&[("gene_biotype", "lncRNA")])];
+        sources.apply(&mut other);
+        assert_eq!(other[0].source.as_deref(), Some("lab"));
+
+        let mut untouched = [line(&[])];
+        SourceMap::default().apply(&mut untouched);
+        assert!(untouched[0].to_string().starts_with("chr1\tbed2gtf\t"));
+        assert!(SourceMap::parse("protein_coding\n", None).is_err());
+    }
+}
@@ -0,0 +1,363 @@
+use crate::bed::BedRecord;
+use crate::lines::GtfRecord;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// A broken domain invariant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// Transcript (or gene) the violation is about.
+    pub id: String,
+    /// Short name of the invariant, e.g. `cds_within_tx`.
+    pub rule: &'static str,
+    pub detail: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} ({})", self.id, self.rule, self.detail)
+    }
+}
+
+/// Checks the invariants of one BED12 record: a non-empty transcript
+/// span, blockCount matching the blocks, sorted non-overlapping exons
+/// spanning exactly the transcript, a CDS inside the transcript, and
+/// `--frames` exon frames that follow the CDS lengths.
+pub fn check_record(record: &BedRecord) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let mut fail = |rule, detail: String| {
+        violations.push(Violation {
+            id: record.name.clone(),
+            rule,
+            detail,
+        })
+    };
+
+    if record.tx_start >= record.tx_end {
+        fail(
+            "tx_bounds",
+            format!("start {} >= end {}", record.tx_start, record.tx_end),
+        );
+    }
+    if record.exon_count as usize != record.exon_start.len() {
+        fail(
+            "exon_count",
+            format!(
+                "blockCount {} but {} blocks",
+                record.exon_count,
+                record.exon_start.len()
+            ),
+        );
+    }
+    let exons = record.exon_blocks();
+    if let Some(w) = exons.windows(2).find(|w| w[0].1 > w[1].0) {
+        fail(
+            "exons_sorted",
+            format!(
+                "exon {}-{} overlaps or follows {}-{}",
+                w[1].0, w[1].1, w[0].0, w[0].1
+            ),
+        );
+    }
+    if let (Some(first), Some(last)) = (exons.first(), exons.last()) {
+        if first.0 != record.tx_start || last.1 != record.tx_end {
+            fail(
+                "exons_span_tx",
+                format!(
+                    "exons span {}-{}, transcript {}-{}",
+                    first.0, last.1, record.tx_start, record.tx_end
+                ),
+            );
+        }
+    }
+    if record.cds_start > record.cds_end
+        || (record.cds_start < record.cds_end
+            && (record.cds_start < record.tx_start || record.cds_end > record.tx_end))
+    {
+        fail(
+            "cds_within_tx",
+            format!(
+                "CDS {}-{} in transcript {}-{}",
+                record.cds_start, record.cds_end, record.tx_start, record.tx_end
+            ),
+        );
+    }
+    if let Some(frames) = &record.frames {
+        let cds = exons
+            .iter()
+            .zip(frames)
+            .map(|(&(start, end), &frame)| {
+                let (start, end) = (start.max(record.cds_start), end.min(record.cds_end));
+                (end.saturating_sub(start), frame)
+            })
+            .filter(|&(len, frame)| len > 0 && frame >= 0)
+            .collect::<Vec<_>>();
+        let ordered = if record.strand == "-" {
+            cds.into_iter().rev().collect::<Vec<_>>()
+        } else {
+            cds
+        };
+        if let Some(w) = ordered
+            .windows(2)
+            .find(|w| (w[0].1 as u32 + w[0].0) % 3 != w[1].1 as u32)
+        {
+            fail(
+                "frames",
+                format!(
+                    "frame {} after {} CDS bases in frame {}",
+                    w[1].1, w[0].0, w[0].1
+                ),
+            );
+        }
+    }
+    violations
+}
+
+/// Exons whose `--frames` frame differs from the one computed from the
+/// CDS extents (`--check-frames`), one `frame_disagreement` each.
+/// Exons are numbered 5' to 3' as in the output.
+pub fn frame_disagreements(record: &BedRecord) -> Vec<Violation> {
+    let Some(given) = &record.frames else {
+        return Vec::new();
+    };
+    let exons = record.exon_blocks();
+    let n = exons.len();
+    exons
+        .iter()
+        .zip(given.iter().zip(record.computed_frames()))
+        .enumerate()
+        .filter(|(_, (_, (given, computed)))| **given != *computed)
+        .map(|(i, (&(start, end), (given, computed)))| Violation {
+            id: record.name.clone(),
+            rule: "frame_disagreement",
+            detail: format!(
+                "exon {} ({}-{}): given {}, computed {}",
+                if record.strand == "-" { n - i } else { i + 1 },
+                start + 1,
+                end,
+                given,
+                computed
+            ),
+        })
+        .collect()
+}
+
+/// Flags exons shorter than `exon_lt` bp and introns shorter than
+/// `intron_lt` bp (`--warn-exon-lt`, `--warn-intron-lt`), as
+/// `micro_exon` and `tiny_intron`. Such blocks are rarely biology; they
+/// usually come from alignment artifacts.
+pub fn short_blocks(
+    record: &BedRecord,
+    exon_lt: Option<u32>,
+    intron_lt: Option<u32>,
+) -> Vec<Violation> {
+    let exons = record.exon_blocks();
+    let mut violations = Vec::new();
+    let mut flag = |rule, what, lengths: Vec<u32>, min| {
+        if !lengths.is_empty() {
+            let lengths = lengths.iter().map(u32::to_string).collect::<Vec<_>>();
+            violations.push(Violation {
+                id: record.name.clone(),
+                rule,
+                detail: format!(
+                    "{} {} shorter than {} bp ({} bp)",
+                    lengths.len(),
+                    what,
+                    min,
+                    lengths.join(", ")
+                ),
+            });
+        }
+    };
+    if let Some(min) = exon_lt {
+        let short = exons
+            .iter()
+            .map(|(start, end)| end.saturating_sub(*start))
+            .filter(|&len| len < min)
+            .collect();
+        flag("micro_exon", "exons", short, min);
+    }
+    if let Some(min) = intron_lt {
+        let short = exons
+            .windows(2)
+            .map(|w| w[1].0.saturating_sub(w[0].1))
+            .filter(|&len| len < min)
+            .collect();
+        flag("tiny_intron", "introns", short, min);
+    }
+    violations
+}
+
+/// Checks converted features: exons and CDS inside their transcript,
+/// transcripts inside their gene, exon_number running 1..n without gaps
+/// and CDS frames following the CDS lengths in transcription order.
+pub fn check_gtf_consistency(records: &[GtfRecord]) -> Vec<Violation> {
+    let mut genes: HashMap<&str, &GtfRecord> = HashMap::new();
+    let mut transcripts: BTreeMap<&str, Vec<&GtfRecord>> = BTreeMap::new();
+    for record in records {
+        match (record.feature.as_str(), record.attrs.get("transcript_id")) {
+            ("gene", _) => {
+                if let Some(gene) = record.attrs.get("gene_id") {
+                    genes.insert(gene, record);
+                }
+            }
+            (_, Some(tx)) => transcripts.entry(tx).or_default().push(record),
+            _ => {}
+        }
+    }
+
+    let mut violations = Vec::new();
+    for (tx, features) in &transcripts {
+        let mut fail = |rule, detail: String| {
+            violations.push(Violation {
+                id: tx.to_string(),
+                rule,
+                detail,
+            })
+        };
+        let Some(transcript) = features.iter().find(|r| r.feature == "transcript") else {
+            fail(
+                "transcript_line",
+                "features without a transcript line".into(),
+            );
+            continue;
+        };
+        let inside = |r: &GtfRecord| r.start >= transcript.start && r.end <= transcript.end;
+        for feature in features
+            .iter()
+            .filter(|r| matches!(r.feature.as_str(), "exon" | "CDS"))
+        {
+            if !inside(feature) {
+                fail(
+                    if feature.feature == "exon" {
+                        "exon_within_tx"
+                    } else {
+                        "cds_within_tx"
+                    },
+                    format!(
+                        "{} {}-{} in transcript {}-{}",
+                        feature.feature,
+                        feature.start,
+                        feature.end,
+                        transcript.start,
+                        transcript.end
+                    ),
+                );
+            }
+        }
+        let gene = transcript.attrs.get("gene_id").and_then(|g| genes.get(g));
+        if let Some(gene) = gene.filter(|g| g.start > transcript.start || g.end < transcript.end) {
+            fail(
+                "tx_within_gene",
+                format!(
+                    "transcript {}-{} in gene {}-{}",
+                    transcript.start, transcript.end, gene.start, gene.end
+                ),
+            );
+        }
+
+        let mut numbers = features
+            .iter()
+            .filter(|r| r.feature == "exon")
+            .filter_map(|r| r.attrs.get("exon_number")?.parse::<usize>().ok())
+            .collect::<Vec<_>>();
+        numbers.sort_unstable();
+        if numbers.iter().enumerate().any(|(i, &n)| n != i + 1) {
+            fail("exon_number", format!("exon numbers {:?}", numbers));
+        }
+
+        let mut cds = features
+            .iter()
+            .filter(|r| r.feature == "CDS")
+            .filter_map(|r| Some((r.start, r.end - r.start + 1, r.frame?)))
+            .collect::<Vec<_>>();
+        cds.sort_unstable();
+        if transcript.strand == "-" {
+            cds.reverse();
+        }
+        if let Some(w) = cds
+            .windows(2)
+            .find(|w| (w[0].2 as u32 + w[0].1) % 3 != w[1].2 as u32)
+        {
+            fail(
+                "cds_frames",
+                format!(
+                    "CDS at {} has frame {} after {} bases in frame {}",
+                    w[1].0, w[1].2, w[0].1, w[0].2
+                ),
+            );
+        }
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lines::Attributes;
+
+    #[test]
+    fn flags_broken_invariants() {
+        let line = "chr1\t0\t900\tT1\t0\t+\t50\t850\t0\t3\t100,100,100,\t0,400,800,";
+        let mut record = BedRecord::parse(line).unwrap();
+        assert!(check_record(&record).is_empty());
+        record.cds_end = 950;
+        record.frames = Some(vec![0, 1, 0]);
+        let rules = check_record(&record)
+            .iter()
+            .map(|v| v.rule)
+            .collect::<Vec<_>>();
+        assert_eq!(rules, ["cds_within_tx", "frames"]);
+        let disagreements = frame_disagreements(&record);
+        assert_eq!(disagreements.len(), 1);
+        assert_eq!(
+            disagreements[0].detail,
+            "exon 2 (401-500): given 1, computed 2"
+        );
+
+        let line = "chr1\t0\t900\tT1\t0\t+\t0\t0\t0\t3\t100,4,100,\t0,120,800,";
+        let record = BedRecord::parse(line).unwrap();
+        let flagged = short_blocks(&record, Some(6), Some(30));
+        assert_eq!(
+            flagged.iter().map(|v| v.rule).collect::<Vec<_>>(),
+            ["micro_exon", "tiny_intron"]
+        );
+        assert_eq!(flagged[1].detail, "1 introns shorter than 30 bp (20 bp)");
+        assert!(short_blocks(&record, Some(4), None).is_empty());
+
+        let feature = |kind: &str, start: u32, end: u32, frame: Option<u8>, exon: Option<&str>| {
+            let mut attrs = Attributes::new();
+            attrs.push("gene_id", "G1");
+            attrs.push("transcript_id", "T1");
+            if let Some(number) = exon {
+                attrs.push("exon_number", number);
+            }
+            GtfRecord {
+                chrom: "chr1".to_string(),
+                feature: kind.to_string(),
+                start,
+                end,
+                strand: "+".to_string(),
+                frame,
+                attrs,
+                source: None,
+            }
+        };
+        let mut records = vec![
+            feature("transcript", 1, 900, None, None),
+            feature("exon", 1, 100, None, Some("1")),
+            feature("exon", 401, 500, None, Some("2")),
+            feature("CDS", 51, 100, Some(0), Some("1")),
+            feature("CDS", 401, 500, Some(2), Some("2")),
+        ];
+        assert!(check_gtf_consistency(&records).is_empty());
+        records[4].frame = Some(0);
+        records.push(feature("exon", 801, 950, None, Some("4")));
+        let rules = check_gtf_consistency(&records)
+            .iter()
+            .map(|v| v.rule)
+            .collect::<Vec<_>>();
+        assert_eq!(rules, ["exon_within_tx", "exon_number", "cds_frames"]);
+    }
+}
@@ -1,6 +1,35 @@
-use clap::{self, ArgAction, Parser};
+use crate::alias::{ChrPrefix, Naming};
+use crate::bed::Passthrough;
+use crate::compat::Compat;
+use crate::dedup::DedupMode;
+use crate::deps::UCSC_TOOLS;
+use crate::filter::{parse_regex, Head, RecordFilter, StrandFilter};
+use crate::fix::{CleanMode, FixMode};
+use crate::format::{
+    infer_codec, infer_input, infer_output, Codec, Dialect, Format, InputFormat, OutputSpec,
+};
+use crate::formatter::GtfFlavor;
+use crate::genes::{AutoGene, GeneIds, GroupBy, StrandConflict};
+use crate::genetic_code::CodeAssignment;
+use crate::gz::parse_level;
+use crate::isoforms::{IsoformsOrder, OnConflict};
+use crate::lines::{
+    parse_promoters, AttrStyle, AttrSyntax, ExonNumbering, ExonOrder, OutputOrder, PhaseStyle,
+    Promoters, DEFAULT_ATTR_ORDER,
+};
+use crate::meta::DuplicateNames;
+use crate::report::ProgressHook;
+use crate::signals::CancelToken;
+use crate::sort::{ChromOrder, Unlisted};
+use crate::spill::parse_size;
+use crate::utils::{parse_header_line, parse_header_value, read_header_file};
+
+use clap::{self, ArgAction, Args, CommandFactory, Parser, Subcommand};
+use flate2::Compression;
 use num_cpus;
-use std::path::PathBuf;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
 #[derive(Parser, Debug)]
@@ -8,40 +37,74 @@ use thiserror::Error;
     name = "bed2gtf",
     version = env!("CARGO_PKG_VERSION"),
     author = "Alejandro Gonzales-Irribarren <jose.gonzalesdezavala1@unmsm.edu.pe>",
-    about = "A fast and memory efficient BED to GTF converter"
+    about = "A fast and memory efficient BED to GTF converter",
+    subcommand_negates_reqs = true
 )]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Arguments as given on the command line, kept for `--report`.
+    #[clap(skip)]
+    pub command_line: Vec<String>,
+
     #[clap(
         short = 'b',
         long,
-        help = "Path to BED file",
+        help = "Path, http(s)/ftp URL or s3:// / gs:// URI to BED file",
         value_name = "BED",
-        required = true
+        required_unless_present = "version_json"
     )]
-    pub bed: PathBuf,
+    pub bed: Option<PathBuf>,
 
     #[clap(
         short = 'o',
         long,
-        help = "Path to output file",
+        help = "Path or s3:// / gs:// URI to output file [.gtf/.gff3, plus .gz/.zst], or - for stdout",
         value_name = "OUTPUT",
-        required = true
+        required_unless_present = "version_json"
     )]
-    pub output: PathBuf,
+    pub output: Option<PathBuf>,
 
     #[clap(
         short = 't',
         long,
-        help = "Number of threads",
+        help = "Number of threads; 0 or auto sizes the pool from the CPUs and the input size",
         value_name = "THREADS",
-        default_value_t = num_cpus::get()
+        default_value_t = num_cpus::get(),
+        value_parser = parse_threads,
+        global = true
     )]
     pub threads: usize,
 
+    #[clap(
+        long = "parse-threads",
+        help = "Threads parsing the input BED [default: --threads]",
+        value_name = "THREADS",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub parse_threads: Option<usize>,
+
+    #[clap(
+        long = "compress-threads",
+        help = "Threads compressing gzip output, in their own pool [default: share --threads]",
+        value_name = "THREADS",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub compress_threads: Option<usize>,
+
+    #[clap(
+        long = "chunk-size",
+        help = "Records per parallel work unit when parsing and converting [default: rayon's adaptive splitting]; larger units cut contention on many-core machines",
+        value_name = "RECORDS",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub chunk_size: Option<usize>,
+
     #[clap(
         short,
         long = "gz",
-        help = "Compress output file",
+        help = "Gzip the output even if its name does not end in .gz",
         value_name = "FLAG",
         default_missing_value("true"),
         default_value("false"),
@@ -51,6 +114,265 @@ pub struct Cli {
     )]
     pub gz: bool,
 
+    /// Compression of the output, from its name and --gz; set by
+    /// [`Cli::resolve`].
+    #[clap(skip)]
+    pub codec: Codec,
+
+    /// Output format implied by the name the binary was run as (see
+    /// [`invocation_format`]), used when neither `--output-format` nor
+    /// the output name tell.
+    #[clap(skip)]
+    pub invoked_format: Option<Format>,
+
+    /// Stops the conversion when cancelled (see
+    /// [`ConversionJob::with_cancel_token`](crate::convert::ConversionJob::with_cancel_token)).
+    #[clap(skip)]
+    pub cancel: CancelToken,
+
+    /// Receives stage and record progress (see
+    /// [`ConversionJob::with_progress`](crate::convert::ConversionJob::with_progress)).
+    #[clap(skip)]
+    pub progress: ProgressHook,
+
+    /// Text converted in place of the `--bed` and `--isoforms` files,
+    /// whose names are then only placeholders (see
+    /// [`convert_str`](crate::convert::convert_str)).
+    #[clap(skip)]
+    pub inline: Option<InlineInput>,
+
+    #[clap(
+        long = "input-format",
+        help = "Input format, for names without a .bed/.bed.gz extension (pipes, .bed12)",
+        value_name = "FORMAT",
+        value_enum
+    )]
+    pub input_format: Option<InputFormat>,
+
+    #[clap(
+        long = "profile",
+        help = "Print wall time, CPU time and allocations per conversion stage to stderr",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set
+    )]
+    pub profile: bool,
+
+    #[clap(
+        long = "no-summary",
+        help = "Do not print the per-chromosome summary table to stderr after converting",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set
+    )]
+    pub no_summary: bool,
+
+    #[clap(
+        long = "verify",
+        help = "Check the domain invariants of every BED record and of the converted features, and every output line against the GTF/GFF3 grammar, and fail if any is broken",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+        conflicts_with_all = ["assume_sorted", "max_mem"],
+    )]
+    pub verify: bool,
+
+    #[clap(
+        long = "lenient",
+        help = "Skip BED lines that cannot be parsed and transcripts that cannot be converted, with a warning, instead of stopping",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub lenient: bool,
+
+    #[clap(
+        long = "max-errors",
+        help = "Stop once more than this many records were skipped (malformed, failed, --fix=skip, --clean=no-exons) or repaired (--fix, --clean, --merge-exons)",
+        value_name = "N"
+    )]
+    pub max_errors: Option<usize>,
+
+    #[clap(
+        long = "max-error-rate",
+        help = "Stop once more than this fraction of the BED records were skipped or repaired (see --max-errors)",
+        value_name = "FRACTION",
+        value_parser = parse_fraction
+    )]
+    pub max_error_rate: Option<f64>,
+
+    #[clap(
+        long = "bed-detail",
+        help = "Input is UCSC bedDetail (BED12+2): columns 13 and 14 become db_xref and description",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub bed_detail: bool,
+
+    #[clap(
+        long = "passthrough",
+        help = "Comma-separated BED columns copied to transcript attributes: score (column 5, numbers or text) and item-rgb (column 9, as item_rgb); either may be '.', text or floats without failing the parse",
+        value_name = "COLUMNS",
+        value_enum,
+        value_delimiter = ','
+    )]
+    pub passthrough: Vec<Passthrough>,
+
+    #[clap(
+        long = "dialect",
+        help = "How to read BED12 columns: ucsc, minimap2 for --junc-bed/paftools alignments (non-coding, one transcript per alignment), or transdecoder ORF BEDs",
+        value_name = "DIALECT",
+        value_enum,
+        default_value_t = Dialect::Ucsc
+    )]
+    pub dialect: Dialect,
+
+    #[clap(
+        long = "stitch-by-name",
+        help = "Join consecutive same-name records (e.g. bamtobed -split fragments, BED6 allowed) into one transcript",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub stitch_by_name: bool,
+
+    #[clap(
+        long = "frames",
+        help = "TSV of transcript_id and comma-separated exon frames (genePredExt exonFrames) used instead of computed frames",
+        value_name = "TSV"
+    )]
+    pub frames: Option<PathBuf>,
+
+    #[clap(
+        long = "check-frames",
+        help = "Recompute the frames of every --frames transcript from its CDS extents and report, per exon, where the given frames disagree",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+        requires = "frames",
+    )]
+    pub check_frames: bool,
+
+    #[clap(
+        long = "output-format",
+        help = "Output format [default: from the output name, GTF for stdout]",
+        value_name = "FORMAT",
+        value_enum
+    )]
+    pub output_format: Option<Format>,
+
+    #[clap(
+        long = "gff3-noncoding-type",
+        help = "GFF3 feature type of transcripts without a CDS (coding ones are mRNA), e.g. ncRNA, lnc_RNA or transcript",
+        value_name = "TYPE",
+        default_value = "ncRNA"
+    )]
+    pub gff3_noncoding_type: String,
+
+    #[clap(
+        long = "gtf-flavor",
+        help = "Dialect of GTF outputs: gtf2.2, or ucsc for the lines of UCSC genePredToGtf (no genes; gene_id, transcript_id, exon_number, exon_id and gene_name only)",
+        value_name = "FLAVOR",
+        value_enum,
+        default_value_t = GtfFlavor::Gtf22,
+        conflicts_with = "merge_into"
+    )]
+    pub gtf_flavor: GtfFlavor,
+
+    #[clap(
+        long = "also-write",
+        help = "Additional output [.gtf/.gff3, plus .gz/.zst] written from the same conversion (repeatable)",
+        value_name = "OUTPUT",
+        conflicts_with_all = ["assume_sorted", "max_mem"]
+    )]
+    pub also_write: Vec<PathBuf>,
+
+    #[clap(
+        long = "merge-into",
+        help = "Existing GTF [.gtf/.gtf.gz] the converted transcripts are added to: identical transcripts are dropped, gene extents updated and the merged annotation written sorted to the output",
+        value_name = "GTF",
+        conflicts_with_all = ["assume_sorted", "max_mem", "keep_order", "also_write"]
+    )]
+    pub merge_into: Option<PathBuf>,
+
+    /// `--also-write` outputs with their format and compression, set by
+    /// [`Cli::resolve`].
+    #[clap(skip)]
+    pub extra_outputs: Vec<OutputSpec>,
+
+    #[clap(
+        long = "gz-level",
+        help = "Gzip level of compressed outputs [1-9, fast, best]",
+        value_name = "LEVEL",
+        default_value = "6",
+        value_parser = parse_level,
+        global = true
+    )]
+    pub gz_level: Compression,
+
+    #[clap(
+        long = "version-json",
+        help = "Print version, git commit, build features and the output header as JSON, then exit",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set
+    )]
+    pub version_json: bool,
+
+    #[clap(
+        long = "no-banner",
+        help = "Do not print the startup banner",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+        global = true
+    )]
+    pub no_banner: bool,
+
+    #[clap(
+        long = "log-file",
+        help = "Also append all log messages, timestamped and including debug ones, to this file",
+        value_name = "LOG",
+        global = true
+    )]
+    pub log_file: Option<PathBuf>,
+
+    #[clap(
+        long = "tmpdir",
+        help = "Directory for temporary files (--max-mem spills, verify-against-ucsc), e.g. node-local scratch [default: $TMPDIR or /tmp]",
+        value_name = "DIR",
+        global = true
+    )]
+    pub tmpdir: Option<PathBuf>,
+
     #[arg(
         short,
         long = "no-gene",
@@ -61,69 +383,1541 @@ pub struct Cli {
         num_args(0..=1),
         require_equals(true),
         action = ArgAction::Set,
-        conflicts_with = "isoforms",
+        conflicts_with_all = ["isoforms", "infer_genes", "group_by"],
     )]
     pub no_gene: bool,
 
     #[clap(
         short = 'i',
         long,
-        help = "Path to isoforms file [gene -> transcript1, transcript2, ...]",
+        help = "Path or http(s)/ftp URL to isoforms file [gene -> transcript1, transcript2, ...], or a UCSC kgXref, refLink, knownIsoforms or knownToEnsembl table with its #header",
         value_name = "ISOFORMS",
-        required_unless_present = "no_gene",
+        required_unless_present_any = [
+            "no_gene", "infer_genes", "auto_gene", "group_by", "biomart", "version_json"
+        ],
         default_value = None,
     )]
     pub isoforms: Option<PathBuf>,
-}
 
-#[derive(Debug, Error)]
-pub enum CliError {
-    #[error("Invalid input: {0}")]
-    InvalidInput(String),
-    #[error("IO error: {0}")]
-    IoError(#[from] std::io::Error),
-}
+    #[clap(
+        long = "audit-isoforms",
+        help = "Before converting, report transcripts missing from either the BED or the isoforms file, and stop if any BED transcript is unlisted",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+        requires = "isoforms",
+    )]
+    pub audit_isoforms: bool,
 
-impl Cli {
-    pub fn check(&self) -> Result<(), CliError> {
-        self.validate_args()
-    }
+    #[clap(
+        long = "isoforms-order",
+        help = "Column order of the isoforms file",
+        value_name = "ORDER",
+        value_enum,
+        default_value_t = IsoformsOrder::Auto
+    )]
+    pub isoforms_order: IsoformsOrder,
 
-    fn validate_args(&self) -> Result<(), CliError> {
-        validate(&self.bed)?;
+    #[clap(
+        long = "on-conflict",
+        help = "What to do with a transcript listed under several genes in the isoforms file [duplicate/secondary for readthrough loci]",
+        value_name = "POLICY",
+        value_enum,
+        default_value_t = OnConflict::Error
+    )]
+    pub on_conflict: OnConflict,
 
-        match self.bed.extension() {
-            Some(ext) if ext == "bed" || ext == "gz" => (),
-            _ => {
-                return Err(CliError::InvalidInput(format!(
-                    "file {:?} is not a BED file",
-                    self.bed
-                )))
-            }
-        }
+    #[clap(
+        long = "infer-genes",
+        help = "Cluster transcripts into genes by exonic overlap instead of reading an isoforms file",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+        conflicts_with = "isoforms",
+    )]
+    pub infer_genes: bool,
 
-        if !self.no_gene {
-            let isoforms = self.isoforms.as_ref().unwrap();
-            validate(isoforms)?;
-        }
+    #[clap(
+        long = "gene-ids",
+        help = "IDs of --infer-genes (or --group-by overlap) genes: numbered in genomic order, or hashed from chrom/strand/extent so unchanged loci keep their ID across inputs",
+        value_name = "SCHEME",
+        value_enum,
+        default_value_t = GeneIds::Numbered
+    )]
+    pub gene_ids: GeneIds,
 
-        match self.output.extension() {
-            Some(ext) if ext == "gtf" => (),
-            _ => {
-                return Err(CliError::InvalidInput(format!(
-                    "file {:?} is not a GTF file",
-                    self.bed
-                )))
-            }
-        }
+    #[clap(
+        long = "auto-gene",
+        help = "Write gene lines without an isoforms file, deriving gene_id from the transcript name: identity, strip-version or regex:<pattern> (first capture group)",
+        value_name = "RULE",
+        value_parser = AutoGene::parse,
+        conflicts_with_all = ["isoforms", "infer_genes", "no_gene"]
+    )]
+    pub auto_gene: Option<AutoGene>,
 
-        Ok(())
-    }
-}
+    #[clap(
+        long = "group-by",
+        help = "Group transcripts into genes without an isoforms file: overlap (same-strand exonic overlap, as --infer-genes), delimiter=<sep> (first field of compound names like G1;G1.1;Q7KRI2) or regex=<pattern> (first capture group of the name)",
+        value_name = "RULE",
+        value_parser = GroupBy::parse,
+        conflicts_with_all = ["isoforms", "biomart", "infer_genes", "auto_gene", "no_gene"]
+    )]
+    pub group_by: Option<GroupBy>,
 
-fn validate(arg: &PathBuf) -> Result<(), CliError> {
-    if !arg.exists() {
-        return Err(CliError::InvalidInput(format!("{:?} does not exist", arg)));
+    #[clap(
+        long = "split-loci",
+        help = "Split genes whose transcripts are more than DISTANCE bp apart (or on different chromosomes/strands) into GENE_1, GENE_2, ...",
+        value_name = "DISTANCE"
+    )]
+    pub split_loci: Option<u32>,
+
+    #[clap(
+        long = "strand-conflict",
+        help = "Genes whose transcripts lie on more than one strand: first keeps the strand of the first transcript seen, majority takes the strand of most transcripts, error stops, split makes GENE_plus and GENE_minus; all are listed in the warnings",
+        value_name = "POLICY",
+        value_enum,
+        default_value_t = StrandConflict::First
+    )]
+    pub strand_conflict: StrandConflict,
+
+    #[clap(
+        long,
+        help = "Emit intron features between consecutive exons",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub introns: bool,
+
+    #[clap(
+        long,
+        help = "Emit five_prime_utr/three_prime_utr features for the exon parts outside the CDS of coding transcripts",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub utrs: bool,
+
+    #[clap(
+        long = "add-promoters",
+        help = "Emit a promoter feature per transcript, UP bp upstream of its TSS to DOWN bp (default 0) downstream, strand-aware and clipped at the contig ends of --chrom-sizes",
+        value_name = "UP[,DOWN]",
+        value_parser = parse_promoters
+    )]
+    pub add_promoters: Option<Promoters>,
+
+    #[clap(
+        long = "no-codons",
+        help = "Do not write start_codon/stop_codon lines; CDS bounds and cds_start_NF/cds_end_NF tags are unchanged",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub no_codons: bool,
+
+    #[clap(
+        long = "exon-only",
+        help = "Write only transcript and exon lines (plus genes unless --no-gene), skipping all CDS work; enough for Salmon/kallisto references and splicing tools",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+        conflicts_with_all = ["introns", "utrs", "tag_cds_incomplete", "flag_nmd"],
+    )]
+    pub exon_only: bool,
+
+    #[clap(
+        long = "gene-level",
+        help = "Write only gene lines (extents and metadata), no transcripts or exons; for featureCounts in gene mode or browser gene tracks",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+        conflicts_with_all = [
+            "no_gene", "exon_only", "introns", "flatten", "assume_sorted", "two_pass", "max_mem"
+        ],
+    )]
+    pub gene_level: bool,
+
+    #[clap(
+        long,
+        help = "Emit DEXSeq-style exonic_part features: the flattened union of each gene's exons",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub flatten: bool,
+
+    #[clap(
+        long = "exon-frames",
+        help = "Add a genePredExt-style exon_frame attribute (-1 for non-coding exons) to exon lines",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub exon_frames: bool,
+
+    #[clap(
+        long = "unique-exon-ids",
+        help = "Give identical exons of different isoforms one exon_id (hash of chrom:start-end:strand) instead of <transcript>.<n>",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub unique_exon_ids: bool,
+
+    #[clap(
+        long = "exon-numbering",
+        help = "Number exons and introns 5'->3' along the transcript or by genomic position",
+        value_name = "SCHEME",
+        value_enum,
+        default_value_t = ExonNumbering::Transcriptional
+    )]
+    pub exon_numbering: ExonNumbering,
+
+    #[clap(
+        long = "exon-order",
+        help = "Order of exon/CDS lines within a transcript: by genomic position or 5'->3' (descending on -, as in Ensembl GTFs; features are then grouped by gene and transcript)",
+        value_name = "ORDER",
+        value_enum,
+        default_value_t = ExonOrder::Genomic,
+        conflicts_with = "max_mem"
+    )]
+    pub exon_order: ExonOrder,
+
+    #[clap(
+        long = "order",
+        help = "Order of the output lines: coords (chromosome and start), or by-id (gene_id, then transcript_id in byte order, then feature) for diff and join against ID-sorted tables",
+        value_name = "ORDER",
+        value_enum,
+        default_value_t = OutputOrder::Coords
+    )]
+    pub order: OutputOrder,
+
+    #[clap(
+        long,
+        help = "Collapse duplicated transcripts, recording merged IDs in a merged_from attribute",
+        value_name = "MODE",
+        value_enum
+    )]
+    pub dedup: Option<DedupMode>,
+
+    #[clap(
+        long,
+        help = "Repairs: swap or skip records with chromStart > chromEnd or thickStart > thickEnd instead of stopping; trim-cds trims CDS lengths to a multiple of 3; span-to-blocks or blocks-to-span align chromStart/chromEnd and the exon blocks when they disagree",
+        value_name = "MODE",
+        value_enum,
+        value_delimiter = ','
+    )]
+    pub fix: Vec<FixMode>,
+
+    #[clap(
+        long,
+        help = "gffread-style cleanups: no-exons drops transcripts without a non-empty exon, zero-length removes empty exon blocks, merge-cds merges touching or overlapping blocks whose CDS frames agree",
+        value_name = "MODE",
+        value_enum,
+        value_delimiter = ','
+    )]
+    pub clean: Vec<CleanMode>,
+
+    #[clap(
+        long = "merge-exons",
+        help = "Merge exons separated by at most GAP bp (default 0: touching or overlapping) within each transcript, recomputing exon counts and frames",
+        value_name = "GAP",
+        num_args(0..=1),
+        require_equals(true),
+        default_missing_value("0")
+    )]
+    pub merge_exons: Option<u32>,
+
+    #[clap(
+        long = "warn-exon-lt",
+        help = "Warn about transcripts with exons shorter than BP bp, usually alignment artifacts",
+        value_name = "BP"
+    )]
+    pub warn_exon_lt: Option<u32>,
+
+    #[clap(
+        long = "warn-intron-lt",
+        help = "Warn about transcripts with introns shorter than BP bp, usually alignment artifacts",
+        value_name = "BP"
+    )]
+    pub warn_intron_lt: Option<u32>,
+
+    #[clap(
+        long = "tag-cds-incomplete",
+        help = "Tag transcripts whose CDS length is not a multiple of 3 with cds_incomplete",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set
+    )]
+    pub tag_cds_incomplete: bool,
+
+    #[clap(
+        long = "tx-meta",
+        help = "Path to a transcript metadata TSV with a header row; columns are added as attributes on transcript lines",
+        value_name = "TSV"
+    )]
+    pub tx_meta: Option<PathBuf>,
+
+    #[clap(
+        long = "gene-meta",
+        help = "Path to a gene metadata TSV keyed by gene_id (e.g. gene_name, gene_biotype, description); columns are added to gene and transcript lines",
+        value_name = "TSV"
+    )]
+    pub gene_meta: Option<PathBuf>,
+
+    #[clap(
+        long = "biomart",
+        help = "Path to an Ensembl BioMart export (TSV with its header: Gene stable ID, Transcript stable ID and e.g. Gene name, Gene type, Transcript type) used as the isoforms file and as transcript and gene metadata",
+        value_name = "TSV",
+        conflicts_with_all = ["isoforms", "tx_meta", "gene_meta"]
+    )]
+    pub biomart: Option<PathBuf>,
+
+    #[clap(
+        long = "duplicate-gene-names",
+        help = "What to do with genes of --gene-meta sharing a gene_name: keep them (reported as warnings) or uniquify them to NAME_1, NAME_2, ... in genomic order (Cell Ranger rejects duplicates)",
+        value_name = "POLICY",
+        value_enum,
+        default_value_t = DuplicateNames::Keep,
+        requires = "gene_meta"
+    )]
+    pub duplicate_gene_names: DuplicateNames,
+
+    #[clap(
+        long = "gene-bed",
+        visible_alias = "genes",
+        help = "BED6+ of curated gene extents named by gene_id; gene lines take these coordinates instead of the span of their transcripts, which come from the main BED through the isoforms map",
+        value_name = "BED",
+        conflicts_with = "no_gene"
+    )]
+    pub gene_bed: Option<PathBuf>,
+
+    #[clap(
+        long = "keep-absent-genes",
+        help = "Write gene lines, at their --gene-bed extent and tagged no_transcripts, for genes of the isoforms file with none of their transcripts in the BED",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+        requires = "gene_bed"
+    )]
+    pub keep_absent_genes: bool,
+
+    #[clap(
+        long = "infer-biotypes",
+        help = "Add gene_biotype/transcript_biotype guessed from CDS presence, CDS length and exon count, unless given by --gene-meta/--tx-meta",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub infer_biotypes: bool,
+
+    #[clap(
+        long = "min-cds-length",
+        help = "Shortest CDS (nt) of a protein_coding transcript for --infer-biotypes",
+        value_name = "NT",
+        default_value_t = 300
+    )]
+    pub min_cds_length: u32,
+
+    #[clap(
+        long = "min-lnc-length",
+        help = "Shortest exonic length (nt) of a lncRNA for --infer-biotypes; shorter non-coding transcripts are processed_transcript",
+        value_name = "NT",
+        default_value_t = 200
+    )]
+    pub min_lnc_length: u32,
+
+    #[clap(
+        long = "min-lnc-exons",
+        help = "Fewest exons of a lncRNA for --infer-biotypes",
+        value_name = "N",
+        default_value_t = 1
+    )]
+    pub min_lnc_exons: u16,
+
+    #[clap(
+        long = "flag-nmd",
+        help = "Tag NMD_candidate on transcripts whose stop codon lies more than --nmd-distance nt upstream of the last exon-exon junction",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub flag_nmd: bool,
+
+    #[clap(
+        long = "nmd-distance",
+        help = "Stop codon to last junction distance (nt) beyond which --flag-nmd tags a transcript",
+        value_name = "NT",
+        default_value_t = 50
+    )]
+    pub nmd_distance: u32,
+
+    #[clap(
+        long = "nmd-biotype",
+        help = "Set transcript_biotype to nonsense_mediated_decay on transcripts tagged by --flag-nmd",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+        requires = "flag_nmd",
+    )]
+    pub nmd_biotype: bool,
+
+    #[clap(
+        long = "source",
+        help = "Column-2 source of the features --source-map does not cover [default: bed2gtf]",
+        value_name = "NAME",
+        value_parser = parse_source
+    )]
+    pub source: Option<String>,
+
+    #[clap(
+        long = "source-map",
+        help = "Path to a biotype<TAB>source TSV setting column 2 per transcript_biotype (else gene_biotype), e.g. protein_coding<TAB>ensembl",
+        value_name = "TSV"
+    )]
+    pub source_map: Option<PathBuf>,
+
+    #[clap(
+        long = "keep-order",
+        help = "Write transcripts in BED input order, children grouped under each transcript, without sorting",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub keep_order: bool,
+
+    #[clap(
+        long = "assume-sorted",
+        help = "Input BED is coordinate-sorted: stream output per transcript instead of sorting all features",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+        conflicts_with_all = ["keep_order", "flatten", "circular", "compat"]
+    )]
+    pub assume_sorted: bool,
+
+    #[clap(
+        long = "max-mem",
+        help = "Memory budget (e.g. 8G, 512M); sorted features spill to temporary files beyond it. With --two-pass, the BED lines are sorted on disk first, so input in any order streams",
+        value_name = "SIZE",
+        value_parser = parse_size,
+        conflicts_with_all = ["keep_order", "flatten", "compat", "assume_sorted"]
+    )]
+    pub max_mem: Option<u64>,
+
+    #[clap(
+        long = "two-pass",
+        help = "Read the BED twice, first for gene extents only, then converting one chromosome at a time, so memory follows the largest chromosome; chromosomes must come in --chrom-order blocks unless --max-mem sorts the input first",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+        conflicts_with_all = [
+            "keep_order", "assume_sorted", "flatten", "dedup", "infer_genes",
+            "stitch_by_name", "merge_into", "also_write", "verify", "compat", "circular",
+            "write_sequences", "rescan_start", "gene_bed", "infer_biotypes", "nmd_biotype",
+            "tx_meta", "gene_meta", "bed_detail", "keep_original_name", "frames",
+            "target_naming", "audit_isoforms", "cds_report", "tss_out", "tes_out",
+            "junctions_out", "biomart"
+        ]
+    )]
+    pub two_pass: bool,
+
+    #[clap(
+        long = "warnings-out",
+        help = "Write every non-fatal issue (line, id, code, action) to a TSV",
+        value_name = "TSV"
+    )]
+    pub warnings_out: Option<PathBuf>,
+
+    #[clap(
+        long = "rejects",
+        help = "Write every skipped input line (malformed with --lenient, or dropped by --fix=skip, --clean=no-exons or a failed conversion) with a trailing reason column, to repair and reconvert",
+        value_name = "BED"
+    )]
+    pub rejects: Option<PathBuf>,
+
+    #[clap(
+        long = "report",
+        help = "Write a JSON summary of the run (counts, warnings, timings, memory, options)",
+        value_name = "JSON"
+    )]
+    pub report: Option<PathBuf>,
+
+    #[clap(
+        long = "metrics-out",
+        help = "Write Prometheus textfile metrics of the run (records converted, warnings per code, duration, peak memory)",
+        value_name = "PROM"
+    )]
+    pub metrics_out: Option<PathBuf>,
+
+    #[clap(
+        long = "manifest",
+        help = "Write a JSON provenance record of the run (input and output checksums, effective options, version, host, times)",
+        value_name = "JSON"
+    )]
+    pub manifest: Option<PathBuf>,
+
+    #[clap(
+        long = "cds-report",
+        help = "Write a TSV with the CDS and 5'/3' UTR lengths, coding exons and start/stop completeness of every transcript",
+        value_name = "TSV"
+    )]
+    pub cds_report: Option<PathBuf>,
+
+    #[clap(
+        long = "tss-out",
+        help = "Write the transcription start site of every transcript as 1-bp BED, collapsed per gene (score: transcripts sharing the site, column 7: their ids)",
+        value_name = "BED"
+    )]
+    pub tss_out: Option<PathBuf>,
+
+    #[clap(
+        long = "tes-out",
+        help = "Write the transcription end site of every transcript as 1-bp BED, collapsed per gene like --tss-out",
+        value_name = "BED"
+    )]
+    pub tes_out: Option<PathBuf>,
+
+    #[clap(
+        long = "junctions-out",
+        help = "Write every distinct intron in STAR --sjdbFileChrStartEnd layout (chrom, 1-based start and end, strand), plus its transcripts, genes and, with --fasta, donor/acceptor motif",
+        value_name = "TAB"
+    )]
+    pub junctions_out: Option<PathBuf>,
+
+    #[clap(
+        long = "header-line",
+        help = "Extra header line, e.g. '#!genome-build GRCh38.p14' (repeatable)",
+        value_name = "LINE",
+        value_parser = parse_header_line
+    )]
+    pub header_line: Vec<String>,
+
+    #[clap(
+        long = "genome-build",
+        help = "Genome build (e.g. GRCh38), written as a '#!genome-build' header line and to --report",
+        value_name = "BUILD",
+        value_parser = parse_header_value
+    )]
+    pub genome_build: Option<String>,
+
+    #[clap(
+        long = "assembly-accession",
+        help = "Assembly accession (e.g. GCA_000001405.15), written as a '#!genome-build-accession' header line and to --report",
+        value_name = "ACCESSION",
+        value_parser = parse_header_value
+    )]
+    pub assembly_accession: Option<String>,
+
+    #[clap(
+        long = "header-file",
+        help = "File with extra '#' header lines, written after --header-line",
+        value_name = "FILE"
+    )]
+    pub header_file: Option<PathBuf>,
+
+    #[clap(
+        long = "chroms",
+        help = "Comma-separated chromosomes to convert; records elsewhere are dropped when read",
+        value_name = "CHROMS",
+        value_delimiter = ','
+    )]
+    pub chroms: Vec<String>,
+
+    #[clap(
+        long = "exclude-chroms-regex",
+        help = "Drop records on chromosomes matching this pattern when read (e.g. '_(alt|random)$')",
+        value_name = "PATTERN",
+        value_parser = parse_regex
+    )]
+    pub exclude_chroms_regex: Option<Regex>,
+
+    #[clap(
+        long = "min-score",
+        help = "Drop records whose score (column 5) is below this, or not a number, when read",
+        value_name = "SCORE"
+    )]
+    pub min_score: Option<f64>,
+
+    #[clap(
+        long = "strand",
+        help = "Convert only the records on this strand; genes span only the transcripts kept",
+        value_name = "STRAND",
+        value_enum,
+        default_value_t = StrandFilter::Both,
+        allow_hyphen_values = true
+    )]
+    pub strand: StrandFilter,
+
+    #[clap(
+        long = "head",
+        help = "Convert only the first N records (after the filters above), for a quick preview of the output options",
+        value_name = "N"
+    )]
+    pub head: Option<usize>,
+
+    #[clap(
+        long = "head-per-chrom",
+        help = "Make --head keep the first N records of every chromosome",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+        requires = "head"
+    )]
+    pub head_per_chrom: bool,
+
+    #[clap(
+        long = "target-naming",
+        help = "Rename contigs to UCSC (chr1), Ensembl (1) or RefSeq (NC_000001.11) names",
+        value_name = "NAMING",
+        value_enum
+    )]
+    pub target_naming: Option<Naming>,
+
+    #[clap(
+        long = "add-chr-prefix",
+        help = "Prefix every contig without one with 'chr' (MT becomes chrM), for Ensembl BEDs on UCSC references",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+        conflicts_with_all = ["strip_chr_prefix", "target_naming"],
+    )]
+    pub add_chr_prefix: bool,
+
+    #[clap(
+        long = "strip-chr-prefix",
+        help = "Remove the 'chr' prefix of every contig (chrM becomes MT), for UCSC BEDs on Ensembl references",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+        conflicts_with = "target_naming",
+    )]
+    pub strip_chr_prefix: bool,
+
+    #[clap(
+        long = "chrom-alias",
+        help = "UCSC chromAlias file, or a bundled assembly (hg38, GRCh38, mm39, GRCm39), used by --target-naming [default: chr prefix rules]",
+        value_name = "FILE|ASSEMBLY",
+        requires = "target_naming"
+    )]
+    pub chrom_alias: Option<String>,
+
+    #[clap(
+        long = "id-prefix",
+        help = "Prefix added to every gene_id, transcript_id and exon_id",
+        value_name = "PREFIX"
+    )]
+    pub id_prefix: Option<String>,
+
+    #[clap(
+        long = "id-suffix",
+        help = "Suffix added to every gene_id, transcript_id and exon_id",
+        value_name = "SUFFIX"
+    )]
+    pub id_suffix: Option<String>,
+
+    #[clap(
+        long = "auto-name",
+        help = "Name transcripts PREFIX_000001, PREFIX_000002, ... in input order, for BEDs whose names are missing (.) or repeated",
+        value_name = "PREFIX"
+    )]
+    pub auto_name: Option<String>,
+
+    #[clap(
+        long = "strip-versions",
+        help = "Remove trailing .N versions from transcript and gene IDs, in the BED and the isoforms file",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub strip_versions: bool,
+
+    #[clap(
+        long = "keep-original-name",
+        help = "Add the untouched BED name (column 4) as original_name to transcripts, to trace IDs rewritten by --auto-name, --strip-versions, --dialect or --on-conflict duplicate",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+    )]
+    pub keep_original_name: bool,
+
+    #[clap(
+        long = "chrom-sizes",
+        help = "Path to a chrom.sizes file with contig lengths",
+        value_name = "SIZES"
+    )]
+    pub chrom_sizes: Option<PathBuf>,
+
+    #[clap(
+        long,
+        help = "Comma-separated circular contigs; features past the contig end are split at the origin",
+        value_name = "CONTIGS",
+        value_delimiter = ',',
+        requires = "chrom_sizes"
+    )]
+    pub circular: Vec<String>,
+
+    #[clap(
+        long,
+        help = "Path to genome FASTA [.fa/.fa.gz] used to validate start/stop codons",
+        value_name = "FASTA"
+    )]
+    pub fasta: Option<PathBuf>,
+
+    #[clap(
+        long = "alt-starts",
+        help = "Accept CTG/GTG/TTG as valid start codons",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+        requires = "fasta",
+    )]
+    pub alt_starts: bool,
+
+    #[clap(
+        long = "genetic-code",
+        help = "NCBI translation table for codon checks and proteins, for all chromosomes (2) or one (chrM:2); repeatable",
+        value_name = "[CHROM:]TABLE",
+        requires = "fasta"
+    )]
+    pub genetic_code: Vec<CodeAssignment>,
+
+    #[clap(
+        long = "rescan-start",
+        help = "Move the CDS start of non-ATG transcripts to the first in-frame downstream ATG",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set,
+        requires = "fasta",
+    )]
+    pub rescan_start: bool,
+
+    #[clap(
+        long = "write-sequences",
+        help = "Write PREFIX.transcripts.fa, PREFIX.cds.fa and PREFIX.proteins.fa (requires --fasta)",
+        value_name = "PREFIX",
+        requires = "fasta"
+    )]
+    pub write_sequences: Option<String>,
+
+    #[clap(
+        long,
+        help = "Enforce the requirements of a downstream tool",
+        value_name = "TOOL",
+        value_enum
+    )]
+    pub compat: Option<Compat>,
+
+    #[clap(
+        long = "chrom-order",
+        help = "Chromosome sort order [natural, lexicographic, karyotypic, primary-first (alt/patch/unplaced contigs last), file:PATH]",
+        value_name = "ORDER",
+        default_value = "natural",
+        global = true
+    )]
+    pub chrom_order: ChromOrder,
+
+    #[clap(
+        long = "unlisted-contigs",
+        help = "Where contigs missing from a --chrom-order file go",
+        value_name = "POLICY",
+        value_enum,
+        default_value_t = Unlisted::Last,
+        global = true
+    )]
+    pub unlisted_contigs: Unlisted,
+
+    #[clap(
+        long = "phase-style",
+        help = "Convention for the frame/phase column",
+        value_name = "STYLE",
+        value_enum,
+        default_value_t = PhaseStyle::Gtf
+    )]
+    pub phase_style: PhaseStyle,
+
+    #[clap(
+        long = "attr-order",
+        help = "Comma-separated attribute keys written first, in this order; other attributes follow",
+        value_name = "KEYS",
+        value_delimiter = ',',
+        default_values_t = DEFAULT_ATTR_ORDER.map(String::from)
+    )]
+    pub attr_order: Vec<String>,
+
+    #[clap(
+        long = "attr-style",
+        help = "Comma-separated GTF attribute syntax tweaks for strict readers: no-space drops the space between attributes, no-trailing-semicolon the ';' after the last one, bare-numbers leaves integer values such as exon_number unquoted",
+        value_name = "STYLE",
+        value_enum,
+        value_delimiter = ','
+    )]
+    pub attr_style: Vec<AttrSyntax>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// bgzip and tabix-index a GTF/GFF, sorting it first if needed
+    Index(IndexArgs),
+    /// Compare two annotations (GTF or BED) transcript by transcript
+    Compare(CompareArgs),
+    /// Sort a GTF/GFF by chromosome (see --chrom-order) and start
+    Sort(SortArgs),
+    /// Download the UCSC binaries used by verification into the cache
+    FetchDeps(FetchDepsArgs),
+    /// Convert with bed2gtf and UCSC bedToGenePred+genePredToGtf and diff the results
+    VerifyAgainstUcsc(VerifyArgs),
+    /// Convert embedded fixtures and compare them with their expected GTFs
+    Selftest,
+}
+
+#[derive(Args, Debug)]
+pub struct IndexArgs {
+    #[clap(help = "Path to GTF/GFF file [.gtf/.gtf.gz]", value_name = "GTF")]
+    pub gtf: PathBuf,
+
+    #[clap(
+        short = 'o',
+        long,
+        help = "Path to bgzipped output [default: GTF, plus .gz if uncompressed]",
+        value_name = "OUTPUT"
+    )]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct CompareArgs {
+    #[clap(
+        help = "Reference annotation [.gtf/.bed, optionally .gz]",
+        value_name = "REFERENCE"
+    )]
+    pub reference: PathBuf,
+
+    #[clap(
+        help = "Query annotation [.gtf/.bed, optionally .gz]",
+        value_name = "QUERY"
+    )]
+    pub query: PathBuf,
+
+    #[clap(
+        short = 'o',
+        long,
+        help = "Path to TSV report",
+        value_name = "OUTPUT",
+        required = true
+    )]
+    pub output: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct SortArgs {
+    #[clap(help = "Path to GTF/GFF file [.gtf/.gtf.gz]", value_name = "GTF")]
+    pub gtf: PathBuf,
+
+    #[clap(
+        short = 'o',
+        long,
+        help = "Path to sorted output [.gz to bgzip]",
+        value_name = "OUTPUT",
+        required = true
+    )]
+    pub output: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct FetchDepsArgs {
+    #[clap(
+        help = "Tools to fetch [default: bedToGenePred genePredToGtf]",
+        value_name = "TOOL"
+    )]
+    pub tools: Vec<String>,
+
+    #[clap(
+        long = "cache-dir",
+        help = "Where binaries are kept [default: $BED2GTF_CACHE or ~/.cache/bed2gtf]",
+        value_name = "DIR"
+    )]
+    pub cache_dir: Option<PathBuf>,
+
+    #[clap(
+        long = "sha256",
+        help = "Expected digest of a tool, as TOOL=HEX (repeatable)",
+        value_name = "TOOL=HEX",
+        value_parser = parse_key_val
+    )]
+    pub sha256: Vec<(String, String)>,
+
+    #[clap(
+        long = "ucsc-release",
+        help = "Pin UCSC binaries to this release (e.g. 469) instead of the latest build; cached apart",
+        value_name = "N"
+    )]
+    pub ucsc_release: Option<u32>,
+
+    #[clap(
+        long = "offline",
+        help = "Never download UCSC binaries; fail with instructions when one is not cached [also $BED2GTF_OFFLINE]",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set
+    )]
+    pub offline: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    #[clap(short = 'b', long, help = "Path to BED file", value_name = "BED")]
+    pub bed: PathBuf,
+
+    #[clap(
+        short = 'i',
+        long,
+        help = "Path to isoforms file; without it gene_id is the transcript name",
+        value_name = "ISOFORMS"
+    )]
+    pub isoforms: Option<PathBuf>,
+
+    #[clap(
+        short = 'o',
+        long,
+        help = "Path to TSV with the differing lines [< bed2gtf only, > UCSC only]",
+        value_name = "OUTPUT"
+    )]
+    pub output: Option<PathBuf>,
+
+    #[clap(
+        long = "cache-dir",
+        help = "Where UCSC binaries are looked up and downloaded [default: $BED2GTF_CACHE or ~/.cache/bed2gtf]",
+        value_name = "DIR"
+    )]
+    pub cache_dir: Option<PathBuf>,
+
+    #[clap(
+        long = "ucsc-release",
+        help = "Pin UCSC binaries to this release (e.g. 469) instead of the latest build; cached apart",
+        value_name = "N"
+    )]
+    pub ucsc_release: Option<u32>,
+
+    #[clap(
+        long = "offline",
+        help = "Never download UCSC binaries; fail with instructions when one is not cached [also $BED2GTF_OFFLINE]",
+        value_name = "FLAG",
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set
+    )]
+    pub offline: bool,
+
+    #[clap(
+        long,
+        help = "Kill a UCSC tool that runs longer than SECS",
+        value_name = "SECS"
+    )]
+    pub timeout: Option<u64>,
+}
+
+/// Output format asked for by the name the binary was run as, e.g. a
+/// `bed2gff3` symlink or wrapper. genePred is not an output format, so
+/// `bed2genepred` is refused rather than silently writing GTF.
+pub fn invocation_format(program: &Path) -> Result<Option<Format>, String> {
+    let name = program
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match name.as_str() {
+        "bed2gff3" | "bed2gff" => Ok(Some(Format::Gff3)),
+        "bed2genepred" => Err(format!(
+            "run as {}, but genePred is not an output format; use bed2gtf or bed2gff3",
+            program.display()
+        )),
+        _ => Ok(None),
+    }
+}
+
+fn parse_key_val(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got {:?}", s))
+}
+
+/// Parses a source column value: no tabs or newlines, not empty.
+fn parse_source(s: &str) -> Result<String, String> {
+    match s.trim() {
+        "" => Err("the source cannot be empty".to_string()),
+        s if s.contains(['\t', '\n']) => Err(format!("invalid source {:?}", s)),
+        s => Ok(s.to_string()),
+    }
+}
+
+/// Parses a fraction between 0 and 1.
+fn parse_fraction(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(f) if (0.0..=1.0).contains(&f) => Ok(f),
+        _ => Err(format!("expected a fraction between 0 and 1, got {:?}", s)),
+    }
+}
+
+/// Parses a thread count; `auto` is the same as 0.
+fn parse_threads(s: &str) -> Result<usize, String> {
+    match s {
+        "auto" => Ok(0),
+        _ => s
+            .parse()
+            .map_err(|_| format!("expected a thread count or auto, got {:?}", s)),
+    }
+}
+
+/// Input bytes worth one more thread; parsing and conversion of less than
+/// this do not pay for the extra worker.
+const BYTES_PER_THREAD: u64 = 8 * 1024 * 1024;
+
+/// Thread count for `--threads auto`: one per [`BYTES_PER_THREAD`] of
+/// input (compressed inputs count four times their size), capped by the
+/// available parallelism. Inputs of unknown size get every CPU.
+fn auto_threads(input: Option<&PathBuf>) -> usize {
+    let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let size = input
+        .and_then(|path| std::fs::metadata(path).ok())
+        .filter(|m| m.is_file())
+        .map(|m| m.len());
+    match (size, input) {
+        (Some(size), Some(path)) => {
+            let factor = match infer_codec(path) {
+                Codec::None => 1,
+                _ => 4,
+            };
+            ((size * factor).div_ceil(BYTES_PER_THREAD) as usize).clamp(1, cpus)
+        }
+        _ => cpus,
+    }
+}
+
+/// In-memory BED and isoforms text of a [`Cli`].
+#[derive(Debug, Clone, Default)]
+pub struct InlineInput {
+    pub bed: String,
+    pub isoforms: Option<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum CliError {
+    #[error("Invalid input: {0}")]
+    InvalidInput(String),
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+impl Cli {
+    pub fn check(&self) -> Result<(), CliError> {
+        match &self.command {
+            Some(Command::Index(args)) => validate(&args.gtf),
+            Some(Command::Sort(args)) => validate(&args.gtf),
+            Some(Command::VerifyAgainstUcsc(args)) => {
+                validate(&args.bed)?;
+                match &args.isoforms {
+                    Some(isoforms) => validate(isoforms),
+                    None => Ok(()),
+                }
+            }
+            Some(Command::FetchDeps(args)) => match args
+                .tools
+                .iter()
+                .chain(args.sha256.iter().map(|(tool, _)| tool))
+                .find(|t| !UCSC_TOOLS.contains(&t.as_str()))
+            {
+                Some(tool) => Err(CliError::InvalidInput(format!(
+                    "unknown tool {:?} [{}]",
+                    tool,
+                    UCSC_TOOLS.join(", ")
+                ))),
+                None => Ok(()),
+            },
+            Some(Command::Compare(args)) => {
+                validate(&args.reference)?;
+                validate(&args.query)
+            }
+            Some(Command::Selftest) => Ok(()),
+            None => self.validate_args(),
+        }
+    }
+
+    /// Settles options that depend on others: the thread count
+    /// (`--threads 0`), `--group-by` as `--infer-genes` or
+    /// `--auto-gene`, `--biomart` as isoforms, the input format, the
+    /// format and compression of `-o` and of every `--also-write`
+    /// output (rejecting combinations those formats cannot write),
+    /// compat presets, the header lines (provenance, `--header-line`,
+    /// `--header-file`) and the placement of contigs missing from a
+    /// `--chrom-order` file.
+    pub fn resolve(&mut self) -> Result<(), String> {
+        if self.threads == 0 {
+            self.threads = match &self.command {
+                None => auto_threads(Some(self.bed())),
+                Some(_) => auto_threads(None),
+            };
+        }
+        if self.command.is_none() {
+            match self.group_by.clone() {
+                Some(GroupBy::Overlap) => self.infer_genes = true,
+                Some(GroupBy::Name(rule)) => self.auto_gene = Some(rule),
+                None => {}
+            }
+            if self.gene_ids != GeneIds::Numbered && !self.infer_genes {
+                return Err("--gene-ids needs --infer-genes or --group-by overlap".to_string());
+            }
+            if self.isoforms.is_none() {
+                self.isoforms = self.biomart.clone();
+            }
+            if self.input_format.is_none() {
+                self.input_format = Some(match crate::utils::is_pipe(self.bed()) {
+                    true => InputFormat::Bed,
+                    false => infer_input(self.bed())?,
+                });
+            }
+            let output = self.output().clone();
+            let (format, codec) = if crate::utils::is_stdout(&output) {
+                (self.invoked_format.unwrap_or_default(), Codec::None)
+            } else {
+                infer_output(&output).or_else(|e| {
+                    self.output_format
+                        .or(self.invoked_format)
+                        .map(|f| (f, infer_codec(&output)))
+                        .ok_or(e)
+                })?
+            };
+            self.output_format = Some(self.output_format.unwrap_or(format));
+            self.codec = match (codec, self.gz) {
+                (Codec::Zstd, true) => {
+                    return Err(format!("--gz conflicts with zstd output {:?}", output))
+                }
+                (Codec::None, true) => Codec::Gzip,
+                (codec, _) => codec,
+            };
+            self.extra_outputs = self
+                .also_write
+                .iter()
+                .map(|path| {
+                    let (format, codec) = infer_output(path)?;
+                    Ok(OutputSpec {
+                        path: path.clone(),
+                        format,
+                        codec,
+                    })
+                })
+                .collect::<Result<_, String>>()?;
+            if let Some(dup) = self.also_write.iter().find(|p| **p == output) {
+                return Err(format!("--also-write {:?} is the main output", dup));
+            }
+            let formats = std::iter::once(self.format())
+                .chain(self.extra_outputs.iter().map(|o| o.format))
+                .collect::<Vec<_>>();
+            // GFF3 would read exons sharing an ID as one discontinuous feature
+            if self.unique_exon_ids && formats.contains(&Format::Gff3) {
+                return Err("--unique-exon-ids writes GTF only".to_string());
+            }
+            if self.merge_into.is_some() && self.format() != Format::Gtf {
+                return Err("--merge-into writes GTF only".to_string());
+            }
+            if self.compat.is_some() && formats.contains(&Format::Gff3) {
+                return Err("--compat profiles write GTF only".to_string());
+            }
+        }
+        if let Some(compat) = self.compat {
+            compat.check_inputs(
+                self.isoforms.is_some() || self.infer_genes || self.auto_gene.is_some(),
+            )?;
+            if self.format() != Format::Gtf {
+                return Err("--compat profiles write GTF only".to_string());
+            }
+            if compat.needs_index() {
+                self.codec = match self.codec {
+                    Codec::Zstd => {
+                        return Err("tabix indexing needs gzip, not zstd, output".to_string())
+                    }
+                    _ => Codec::Gzip,
+                };
+            }
+        }
+        let mut header = self.provenance_header();
+        header.append(&mut self.header_line);
+        self.header_line = header;
+        if let Some(path) = &self.header_file {
+            let lines = read_header_file(path)?;
+            self.header_line.extend(lines);
+        }
+        let unlisted = self.unlisted_contigs;
+        self.chrom_order.set_unlisted(unlisted);
+        Ok(())
+    }
+
+    /// Whether the main output goes to standard output (`-o -`).
+    pub fn writes_stdout(&self) -> bool {
+        let output = match &self.command {
+            Some(Command::Compare(args)) => Some(&args.output),
+            Some(Command::Sort(args)) => Some(&args.output),
+            Some(Command::VerifyAgainstUcsc(args)) => args.output.as_ref(),
+            Some(_) => None,
+            None => self.output.as_ref(),
+        };
+        output.is_some_and(crate::utils::is_stdout)
+    }
+
+    /// Output format; resolved from the output name by [`Cli::resolve`].
+    pub fn format(&self) -> Format {
+        self.output_format.unwrap_or_default()
+    }
+
+    /// The `-o` output with its resolved format and compression.
+    pub fn main_output(&self) -> OutputSpec {
+        OutputSpec {
+            path: self.output().clone(),
+            format: self.format(),
+            codec: self.codec,
+        }
+    }
+
+    /// Attribute serialization of GTF2.2 output (`--attr-style`).
+    pub fn attr_style(&self) -> AttrStyle {
+        AttrStyle::new(&self.attr_style)
+    }
+
+    /// `--add-chr-prefix` or `--strip-chr-prefix`.
+    pub fn chr_prefix(&self) -> Option<ChrPrefix> {
+        if self.add_chr_prefix {
+            Some(ChrPrefix::Add)
+        } else if self.strip_chr_prefix {
+            Some(ChrPrefix::Strip)
+        } else {
+            None
+        }
+    }
+
+    /// The `#!genome-build` and `#!genome-build-accession` lines of
+    /// `--genome-build` and `--assembly-accession`, written before the
+    /// `--header-line`s.
+    pub fn provenance_header(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(build) = &self.genome_build {
+            lines.push(format!("#!genome-build {}", build));
+        }
+        if let Some(accession) = &self.assembly_accession {
+            lines.push(format!("#!genome-build-accession {}", accession));
+        }
+        lines
+    }
+
+    /// The records `--chroms`, `--exclude-chroms-regex`, `--min-score` and
+    /// `--strand` keep.
+    pub fn record_filter(&self) -> RecordFilter {
+        RecordFilter {
+            chroms: self.chroms.iter().cloned().collect(),
+            exclude_chroms: self.exclude_chroms_regex.clone(),
+            min_score: self.min_score,
+            strand: self.strand,
+        }
+    }
+
+    /// `--head`, when given.
+    pub fn head(&self) -> Option<Head> {
+        self.head.map(|n| Head::new(n, self.head_per_chrom))
+    }
+
+    /// What `--fix` does with records whose start is past their end.
+    pub fn inverted_fix(&self) -> Option<FixMode> {
+        self.fix
+            .iter()
+            .copied()
+            .find(|mode| matches!(mode, FixMode::Swap | FixMode::Skip))
+    }
+
+    /// The first of `--fix=span-to-blocks`/`blocks-to-span`.
+    pub fn span_fix(&self) -> Option<FixMode> {
+        self.fix
+            .iter()
+            .copied()
+            .find(|mode| matches!(mode, FixMode::SpanToBlocks | FixMode::BlocksToSpan))
+    }
+
+    /// Value of every option of this run, defaults included, read back
+    /// from [`Cli::command_line`]; empty when that does not parse.
+    pub fn effective_options(&self) -> BTreeMap<String, Vec<String>> {
+        let Ok(matches) = Cli::command().try_get_matches_from(&self.command_line) else {
+            return BTreeMap::new();
+        };
+        Cli::command()
+            .get_arguments()
+            .filter_map(|arg| {
+                let id = arg.get_id().as_str();
+                let values = matches.get_raw(id)?;
+                let values = values.map(|v| v.to_string_lossy().into_owned());
+                Some((id.to_string(), values.collect()))
+            })
+            .collect()
+    }
+
+    /// Where temporary files go: `--tmpdir`, else the system default
+    /// (`$TMPDIR` on Unix).
+    pub fn tmpdir(&self) -> PathBuf {
+        self.tmpdir.clone().unwrap_or_else(std::env::temp_dir)
+    }
+
+    /// Every output of a conversion: `-o` first, then `--also-write`.
+    pub fn outputs(&self) -> Vec<OutputSpec> {
+        let mut outputs = vec![self.main_output()];
+        outputs.extend(self.extra_outputs.iter().cloned());
+        outputs
+    }
+
+    /// Input BED path; always present when no subcommand is given.
+    pub fn bed(&self) -> &PathBuf {
+        self.bed.as_ref().expect("--bed is required")
+    }
+
+    /// Output path; always present when no subcommand is given.
+    pub fn output(&self) -> &PathBuf {
+        self.output.as_ref().expect("--output is required")
+    }
+
+    fn validate_args(&self) -> Result<(), CliError> {
+        let (bed, output) = (self.bed(), self.output());
+        if self.inline.is_none() {
+            validate(bed)?;
+        }
+
+        if self.fix.contains(&FixMode::Swap) && self.fix.contains(&FixMode::Skip) {
+            return Err(CliError::InvalidInput(
+                "--fix takes either swap or skip".to_string(),
+            ));
+        }
+
+        // resolve() turns --group-by into --infer-genes or --auto-gene,
+        // so their conflicts must hold for Cli values built by hand too
+        if self.group_by.is_some() {
+            let other = if self.isoforms.is_some() || self.biomart.is_some() {
+                Some("--isoforms or --biomart")
+            } else if self.infer_genes || self.auto_gene.is_some() {
+                Some("--infer-genes or --auto-gene")
+            } else if self.no_gene {
+                Some("--no-gene")
+            } else {
+                None
+            };
+            if let Some(other) = other {
+                return Err(CliError::InvalidInput(format!(
+                    "--group-by cannot be used with {}",
+                    other
+                )));
+            }
+        }
+
+        if self.two_pass {
+            let unsupported = if crate::utils::is_pipe(bed) && self.max_mem.is_none() {
+                Some("a pipe, which cannot be read twice without --max-mem")
+            } else if self.on_conflict.keeps_all() {
+                Some("--on-conflict duplicate/secondary")
+            } else if self.dialect != Dialect::Ucsc {
+                Some("--dialect")
+            } else if self.exon_order == ExonOrder::Transcription {
+                Some("--exon-order transcription")
+            } else if matches!(self.group_by, Some(GroupBy::Overlap)) {
+                Some("--group-by overlap")
+            } else if self.max_mem.is_some() && self.head.is_some() {
+                Some("--head with --max-mem, which sorts before --head picks records")
+            } else if self.max_mem.is_some() && self.auto_name.is_some() {
+                Some("--auto-name with --max-mem, which sorts the input before it is numbered")
+            } else {
+                None
+            };
+            if let Some(what) = unsupported {
+                return Err(CliError::InvalidInput(format!(
+                    "--two-pass does not support {}",
+                    what
+                )));
+            }
+        }
+
+        if self.order == OutputOrder::ById {
+            let unsupported = if self.keep_order {
+                Some("--keep-order")
+            } else if self.assume_sorted || self.max_mem.is_some() || self.two_pass {
+                Some("--assume-sorted, --max-mem or --two-pass, which write in coordinate order")
+            } else if self.merge_into.is_some() {
+                Some("--merge-into")
+            } else if self.compat.is_some_and(|c| c.needs_index()) {
+                Some("a tabix-indexed --compat")
+            } else {
+                None
+            };
+            if let Some(what) = unsupported {
+                return Err(CliError::InvalidInput(format!(
+                    "--order by-id does not support {}",
+                    what
+                )));
+            }
+        }
+
+        if self.input_format.is_none() && !crate::utils::is_pipe(bed) {
+            infer_input(bed).map_err(CliError::InvalidInput)?;
+        }
+
+        let inline_isoforms = self.inline.as_ref().is_some_and(|i| i.isoforms.is_some());
+        if let (Some(isoforms), false) = (&self.isoforms, inline_isoforms) {
+            validate(isoforms)?;
+        }
+
+        if let Some(base) = &self.merge_into {
+            validate(base)?;
+        }
+
+        if let Some(meta) = &self.tx_meta {
+            validate(meta)?;
+        }
+
+        if let Some(meta) = &self.gene_meta {
+            validate(meta)?;
+        }
+
+        if let Some(map) = &self.source_map {
+            validate(map)?;
+        }
+
+        if let Some(biomart) = &self.biomart {
+            validate(biomart)?;
+        }
+
+        if self.target_naming == Some(Naming::Refseq) && self.chrom_alias.is_none() {
+            return Err(CliError::InvalidInput(
+                "--target-naming refseq needs --chrom-alias (a chromAlias file or hg38/mm39)"
+                    .to_string(),
+            ));
+        }
+
+        if let Some(fasta) = &self.fasta {
+            validate(fasta)?;
+        }
+
+        if let Some(sizes) = &self.chrom_sizes {
+            validate(sizes)?;
+        }
+
+        if crate::utils::is_object_url(output) {
+            validate(output)?;
+        }
+        let remote = crate::utils::is_object_url(output) || crate::utils::is_stdout(output);
+        if remote && self.compat.is_some_and(|c| c.needs_index()) {
+            return Err(CliError::InvalidInput(format!(
+                "{:?} cannot be tabix-indexed, write to a local file instead",
+                output
+            )));
+        }
+
+        if !crate::utils::is_stdout(output) && self.output_format.is_none() {
+            infer_output(output).map_err(CliError::InvalidInput)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn validate(arg: &PathBuf) -> Result<(), CliError> {
+    if crate::utils::is_object_url(arg) && !cfg!(feature = "cloud") {
+        return Err(CliError::InvalidInput(format!(
+            "{:?} requires bed2gtf built with the `cloud` feature",
+            arg
+        )));
+    }
+
+    if crate::utils::is_url(arg) || crate::utils::is_object_url(arg) {
+        return Ok(());
+    }
+
+    if !arg.exists() {
+        return Err(CliError::InvalidInput(format!("{:?} does not exist", arg)));
+    }
+
+    if crate::utils::is_pipe(arg) {
+        return Ok(());
     }
 
     if !arg.is_file() {
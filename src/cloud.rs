@@ -0,0 +1,156 @@
+use bytes::Bytes;
+use futures::stream::{BoxStream, StreamExt};
+use object_store::aws::AmazonS3Builder;
+use object_store::buffered::BufWriter as ObjectBufWriter;
+use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::path::Path as ObjectPath;
+use object_store::ObjectStore;
+use tokio::io::AsyncWriteExt;
+use tokio::runtime::{Builder, Runtime};
+
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+/// Parts are flushed to the store once this many bytes are buffered.
+const PART_SIZE: usize = 10 * 1024 * 1024;
+
+/// Builds a store for an `s3://bucket/key` or `gs://bucket/key` URI.
+/// Credentials and region are taken from the usual `AWS_*` /
+/// `GOOGLE_*` environment variables.
+fn store(url: &str) -> io::Result<(Arc<dyn ObjectStore>, ObjectPath)> {
+    let (scheme, rest) = url
+        .split_once("://")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, url.to_string()))?;
+    let key = match rest.split_once('/') {
+        Some((_, key)) if !key.is_empty() => key,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} has no object key", url),
+            ))
+        }
+    };
+
+    let store: Arc<dyn ObjectStore> = match scheme {
+        "s3" => Arc::new(
+            AmazonS3Builder::from_env()
+                .with_url(url)
+                .build()
+                .map_err(to_io)?,
+        ),
+        "gs" => Arc::new(
+            GoogleCloudStorageBuilder::from_env()
+                .with_url(url)
+                .build()
+                .map_err(to_io)?,
+        ),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported object store scheme: {}", scheme),
+            ))
+        }
+    };
+
+    let path = ObjectPath::parse(key).map_err(to_io)?;
+    Ok((store, path))
+}
+
+fn runtime() -> io::Result<Runtime> {
+    Builder::new_current_thread().enable_all().build()
+}
+
+fn to_io<E: std::error::Error + Send + Sync + 'static>(e: E) -> io::Error {
+    match e.to_string() {
+        s if s.contains("not found") || s.contains("NotFound") => {
+            io::Error::new(io::ErrorKind::NotFound, s)
+        }
+        _ => io::Error::other(e),
+    }
+}
+
+/// Streams an object as a blocking reader.
+pub fn open_object(url: &str) -> io::Result<Box<dyn Read + Send>> {
+    let rt = runtime()?;
+    let (store, path) = store(url)?;
+    let result = rt.block_on(store.get(&path)).map_err(to_io)?;
+
+    Ok(Box::new(ObjectReader {
+        rt,
+        stream: result.into_stream(),
+        chunk: Bytes::new(),
+    }))
+}
+
+struct ObjectReader {
+    rt: Runtime,
+    stream: BoxStream<'static, object_store::Result<Bytes>>,
+    chunk: Bytes,
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.rt.block_on(self.stream.next()) {
+                Some(chunk) => self.chunk = chunk.map_err(to_io)?,
+                None => return Ok(0),
+            }
+        }
+
+        let n = buf.len().min(self.chunk.len());
+        buf[..n].copy_from_slice(&self.chunk.split_to(n));
+        Ok(n)
+    }
+}
+
+/// Blocking writer that uploads to an object store. Output larger than
+/// [`PART_SIZE`] goes through a multipart upload; the object only
+/// becomes visible once [`ObjectWriter::finish`] (or drop) completes it.
+pub struct ObjectWriter {
+    rt: Runtime,
+    inner: Option<ObjectBufWriter>,
+    url: String,
+}
+
+impl ObjectWriter {
+    pub fn create(url: &str) -> io::Result<ObjectWriter> {
+        let rt = runtime()?;
+        let (store, path) = store(url)?;
+        Ok(ObjectWriter {
+            rt,
+            inner: Some(ObjectBufWriter::with_capacity(store, path, PART_SIZE)),
+            url: url.to_string(),
+        })
+    }
+
+    /// Completes the upload. Calling it more than once is a no-op.
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self.inner.take() {
+            Some(mut inner) => self.rt.block_on(inner.shutdown()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Write for ObjectWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.inner.as_mut() {
+            Some(inner) => self.rt.block_on(inner.write(buf)),
+            None => Err(io::Error::other("write after finish")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // parts are uploaded as the buffer fills; a flush must not
+        // complete the upload
+        Ok(())
+    }
+}
+
+impl Drop for ObjectWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            log::error!("Failed to upload {}: {}", self.url, e);
+        }
+    }
+}
@@ -1,129 +1,311 @@
 use crate::bed::BedRecord;
 use std::cmp::{max, min};
 
-#[derive(Debug, Clone)]
+/// A start or stop codon: up to three pieces in genomic order, as a
+/// codon can be split by introns, even across an exon of one or two
+/// coding bases.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct Codon {
-    pub start: u32,
-    pub end: u32,
-    pub index: u32,
-    pub start2: u32,
-    pub end2: u32,
+    /// `(start, end, exon index)` of every piece, 0-based half-open.
+    pub pieces: Vec<(u32, u32, u32)>,
 }
 
 impl Codon {
     pub fn new() -> Codon {
-        Codon {
-            start: 0,
-            end: 0,
-            index: 0,
-            start2: 0,
-            end2: 0,
+        Codon::default()
+    }
+
+    /// Genomic start of the first piece.
+    pub fn start(&self) -> u32 {
+        self.pieces.first().map_or(0, |p| p.0)
+    }
+
+    /// Genomic end of the last piece.
+    pub fn end(&self) -> u32 {
+        self.pieces.last().map_or(0, |p| p.1)
+    }
+
+    pub fn len(&self) -> u32 {
+        self.pieces.iter().map(|(start, end, _)| end - start).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pieces.is_empty()
+    }
+}
+
+/// Coding range of exon `i`, clipped to the CDS.
+fn cds_range(record: &BedRecord, i: usize) -> (u32, u32) {
+    (
+        max(record.exon_start[i], record.cds_start),
+        min(record.exon_end[i], record.cds_end),
+    )
+}
+
+/// Frame after `bases` more coding bases than `frame` (non-negative).
+/// Both are reduced mod 3 first, so long exons cannot overflow it.
+fn frame_past(frame: i16, bases: u32) -> u32 {
+    (frame as u32 % 3 + bases % 3) % 3
+}
+
+/// Takes codon bases from the coding exons `indices`, walking away from
+/// the CDS end they start at (`forward` from its left end). `None` when
+/// the CDS runs out before 3 bases.
+fn gather<I: Iterator<Item = usize>>(
+    record: &BedRecord,
+    indices: I,
+    forward: bool,
+) -> Option<Codon> {
+    let mut pieces = Vec::with_capacity(2);
+    let mut need = 3;
+    for i in indices {
+        let (start, end) = cds_range(record, i);
+        if start >= end {
+            break;
+        }
+        let take = min(need, end - start);
+        pieces.push(match forward {
+            true => (start, start + take, i as u32),
+            false => (end - take, end, i as u32),
+        });
+        need -= take;
+        if need == 0 {
+            if !forward {
+                pieces.reverse();
+            }
+            return Some(Codon { pieces });
         }
     }
+    None
 }
 
+/// First codon in genomic order (start codon on +, stop codon on -).
+/// `None` when the record is non-coding, its CDS does not begin on a
+/// codon boundary there (frame from `--frames`) or is shorter than a
+/// codon; such codons cannot be resolved and the transcript is tagged
+/// incomplete.
 pub fn first_codon(record: &BedRecord) -> Option<Codon> {
     let exon_frames = record.get_frames();
-    record
-        .exon_start
-        .iter()
-        .zip(record.exon_end.iter())
-        .enumerate()
-        .find_map(|(mut index, (&start, &end))| {
-            let frame = exon_frames.get(index)?;
-            let mut codon = Codon::new();
-
-            if *frame < 0 {
-                return Some(codon);
-            }
+    let index = exon_frames.iter().position(|&f| f >= 0)?;
 
-            let cds_start = max(start, record.cds_start);
-            let cds_end = min(end, record.cds_end);
-
-            let frame = if record.strand == "+" {
-                *frame
-            } else {
-                (*frame + (cds_end - cds_start) as i16) % 3
-            };
-
-            if frame == 0 {
-                codon.start = cds_start;
-                codon.end = cds_start + 3;
-                codon.index = index as u32;
-                let diff = cds_end - cds_start;
-
-                if diff >= 3 {
-                    Some(codon)
-                } else {
-                    index += 1;
-                    if index >= exon_frames.len() {
-                        Some(codon)
-                    } else {
-                        let need = 3 - diff;
-                        if diff < need {
-                            Some(codon)
-                        } else {
-                            codon.start2 = cds_start;
-                            codon.end2 = cds_start + need;
-                            Some(codon)
-                        }
-                    }
-                }
-            } else {
-                Some(Codon::new())
-            }
-        })
+    let (cds_start, cds_end) = cds_range(record, index);
+    let frame = if record.strand == "+" {
+        exon_frames[index] as u32 % 3
+    } else {
+        frame_past(exon_frames[index], cds_end - cds_start)
+    };
+    if frame != 0 {
+        return None;
+    }
+    gather(record, index..exon_frames.len(), true)
 }
 
+/// Last codon in genomic order (stop codon on +, start codon on -); see
+/// [`first_codon`].
 pub fn last_codon(record: &BedRecord) -> Option<Codon> {
     let exon_frames = record.get_frames();
-    record
-        .exon_start
-        .iter()
-        .zip(record.exon_end.iter())
-        .enumerate()
-        .rev() // Reverse the iterator to start from the last exon
-        .find_map(|(mut index, (&start, &end))| {
-            let mut codon = Codon::new();
-            let frame = exon_frames.get(index)?;
-            let cds_start = max(start, record.cds_start);
-            let cds_end = min(end, record.cds_end);
-
-            let frame = if record.strand == "+" {
-                (*frame + (cds_end - cds_start) as i16) % 3
-            } else {
-                *frame
-            };
-
-            if frame == 0 {
-                codon.start = max(cds_start, cds_end - 3); // Find the last 3 bases of the CDS
-                codon.end = cds_end;
-                codon.index = index as u32;
-                let diff = cds_end - cds_start;
-
-                if diff >= 3 {
-                    Some(codon)
-                } else {
-                    index += 1;
-                    if index >= exon_frames.len() {
-                        Some(codon)
-                    } else {
-                        let need = 3 - diff;
-                        if diff < need {
-                            Some(codon)
-                        } else {
-                            codon.start2 = cds_start;
-                            codon.end2 = cds_start + need;
-                            Some(codon)
-                        }
-                    }
-                }
-            } else {
-                Some(Codon::new())
-            }
-        })
+    let index = exon_frames.iter().rposition(|&f| f >= 0)?;
+
+    let (cds_start, cds_end) = cds_range(record, index);
+    let frame = if record.strand == "+" {
+        frame_past(exon_frames[index], cds_end - cds_start)
+    } else {
+        exon_frames[index] as u32 % 3
+    };
+    if frame != 0 {
+        return None;
+    }
+    gather(record, (0..=index).rev(), false)
 }
 
 pub fn codon_complete(codon: &Codon) -> bool {
-    ((codon.end - codon.start) + (codon.end2 - codon.start2)) == 3
+    codon.len() == 3
+}
+
+/// Where the start or stop codon of a [`BedRecord`] lies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodonPlacement {
+    /// The record is non-coding (thickStart == thickEnd).
+    NoCds,
+    /// That end of the CDS holds no whole codon: it is off the codon
+    /// boundary (`--frames`), the CDS is shorter than 3 bases, or the
+    /// blocks disagree with blockCount. Converted transcripts are tagged
+    /// cds_start_NF/cds_end_NF.
+    Incomplete,
+    /// Three bases inside one exon.
+    Contiguous(Codon),
+    /// Three bases split by introns over two or three exons.
+    Split(Codon),
+}
+
+impl CodonPlacement {
+    fn of(record: &BedRecord, codon: fn(&BedRecord) -> Option<Codon>) -> CodonPlacement {
+        let blocks = record.exon_start.len();
+        if record.cds_start >= record.cds_end {
+            return CodonPlacement::NoCds;
+        }
+        if blocks != record.exon_end.len()
+            || blocks != record.exon_count as usize
+            || record.frames.as_ref().is_some_and(|f| f.len() != blocks)
+        {
+            return CodonPlacement::Incomplete;
+        }
+        match codon(record) {
+            Some(codon) if codon.pieces.len() == 1 => CodonPlacement::Contiguous(codon),
+            Some(codon) => CodonPlacement::Split(codon),
+            None => CodonPlacement::Incomplete,
+        }
+    }
+
+    /// The codon, when whole.
+    pub fn codon(&self) -> Option<&Codon> {
+        match self {
+            CodonPlacement::Contiguous(codon) | CodonPlacement::Split(codon) => Some(codon),
+            _ => None,
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.codon().is_some()
+    }
+}
+
+impl BedRecord {
+    /// The start codon: at thickStart on the + strand, ending at thickEnd
+    /// on -. Never panics, whatever the record holds.
+    pub fn start_codon(&self) -> CodonPlacement {
+        match self.strand.as_str() {
+            "-" => CodonPlacement::of(self, last_codon),
+            _ => CodonPlacement::of(self, first_codon),
+        }
+    }
+
+    /// The stop codon, the last three bases of the CDS in transcription
+    /// order (thickEnd includes it, as in UCSC tables); see
+    /// [`BedRecord::start_codon`].
+    pub fn stop_codon(&self) -> CodonPlacement {
+        match self.strand.as_str() {
+            "-" => CodonPlacement::of(self, first_codon),
+            _ => CodonPlacement::of(self, last_codon),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_start_codon_plus() {
+        let line = "chr1\t100\t300\tT\t0\t+\t100\t300\t0\t2\t2,100,\t0,100,";
+        let record = BedRecord::parse(line).unwrap();
+
+        let start = first_codon(&record).unwrap();
+        assert_eq!(start.pieces, [(100, 102, 0), (200, 201, 1)]);
+        assert!(codon_complete(&start));
+
+        let stop = last_codon(&record).unwrap();
+        assert_eq!(stop.pieces, [(297, 300, 1)]);
+    }
+
+    #[test]
+    fn split_start_codon_minus() {
+        let line = "chr1\t100\t300\tT\t0\t-\t100\t300\t0\t2\t100,2,\t0,198,";
+        let record = BedRecord::parse(line).unwrap();
+
+        let start = last_codon(&record).unwrap();
+        assert_eq!(start.pieces, [(199, 200, 0), (298, 300, 1)]);
+        assert!(codon_complete(&start));
+
+        let stop = first_codon(&record).unwrap();
+        assert_eq!(stop.pieces, [(100, 103, 0)]);
+    }
+
+    #[test]
+    fn utr_only_first_exon() {
+        let line = "chr1\t100\t400\tT\t0\t+\t250\t400\t0\t2\t50,150,\t0,150,";
+        let record = BedRecord::parse(line).unwrap();
+
+        let start = first_codon(&record).unwrap();
+        assert_eq!(start.pieces, [(250, 253, 1)]);
+    }
+
+    #[test]
+    fn codon_across_three_exons() {
+        // 1-base and 1-base terminal CDS exons, then the rest of the codon
+        let line = "chr1\t100\t400\tT\t0\t+\t100\t400\t0\t3\t1,1,100,\t0,50,200,";
+        let record = BedRecord::parse(line).unwrap();
+        let start = first_codon(&record).unwrap();
+        assert_eq!(start.pieces, [(100, 101, 0), (150, 151, 1), (300, 301, 2)]);
+        assert_eq!((start.start(), start.end()), (100, 301));
+
+        let line = "chr1\t100\t400\tT\t0\t-\t100\t400\t0\t3\t100,1,1,\t0,150,299,";
+        let record = BedRecord::parse(line).unwrap();
+        let start = last_codon(&record).unwrap();
+        assert_eq!(start.pieces, [(199, 200, 0), (250, 251, 1), (399, 400, 2)]);
+
+        // a CDS shorter than a codon has no resolvable start or stop
+        let line = "chr1\t100\t400\tT\t0\t+\t150\t152\t0\t1\t300,\t0,";
+        let record = BedRecord::parse(line).unwrap();
+        assert_eq!(first_codon(&record), None);
+        assert_eq!(last_codon(&record), None);
+        assert!(!codon_complete(&last_codon(&record).unwrap_or_default()));
+    }
+
+    #[test]
+    fn places_start_and_stop_codons() {
+        let plus = "chr1\t100\t300\tT\t0\t+\t100\t300\t0\t2\t2,100,\t0,100,";
+        let record = BedRecord::parse(plus).unwrap();
+        let start = record.start_codon();
+        assert!(matches!(&start, CodonPlacement::Split(c) if c.pieces.len() == 2));
+        assert_eq!(
+            record.stop_codon(),
+            CodonPlacement::Contiguous(Codon {
+                pieces: vec![(297, 300, 1)]
+            })
+        );
+
+        let minus = "chr1\t100\t300\tT\t0\t-\t100\t300\t0\t2\t100,2,\t0,198,";
+        let record = BedRecord::parse(minus).unwrap();
+        assert_eq!(record.start_codon().codon().unwrap().start(), 199);
+        assert_eq!(record.stop_codon().codon().unwrap().start(), 100);
+
+        let mut record = BedRecord::parse(plus).unwrap();
+        record.cds_end = record.cds_start;
+        assert_eq!(record.start_codon(), CodonPlacement::NoCds);
+        record.cds_end = record.cds_start + 2;
+        assert_eq!(record.stop_codon(), CodonPlacement::Incomplete);
+        record.cds_end = 300;
+        record.frames = Some(vec![1, 0]);
+        assert!(!record.start_codon().is_complete());
+        // blockCount claiming a block that is not there
+        record.frames = None;
+        record.exon_count = 3;
+        assert_eq!(record.start_codon(), CodonPlacement::Incomplete);
+    }
+
+    #[test]
+    fn long_coding_exons() {
+        // exons past i16::MAX bases, where the frame sum used to overflow
+        let line = "chr1\t0\t40002\tTXL\t0\t-\t0\t40002\t0\t2\t32766,2,\t0,40000,";
+        let record = BedRecord::parse(line).unwrap();
+        assert_eq!(record.stop_codon(), CodonPlacement::Incomplete);
+
+        let line = "chr1\t0\t40002\tTXL\t0\t-\t0\t40002\t0\t2\t32769,3,\t0,39999,";
+        let record = BedRecord::parse(line).unwrap();
+        assert_eq!(record.stop_codon().codon().unwrap().pieces, [(0, 3, 0)]);
+        assert_eq!(record.start_codon().codon().unwrap().start(), 39999);
+
+        let line = "chr1\t0\t40002\tTXL\t0\t+\t0\t40002\t0\t2\t3,32769,\t0,7233,";
+        let record = BedRecord::parse(line).unwrap();
+        assert_eq!(record.start_codon().codon().unwrap().pieces, [(0, 3, 0)]);
+        assert_eq!(
+            record.stop_codon().codon().unwrap().pieces,
+            [(39999, 40002, 1)]
+        );
+
+        let line = "chr1\t0\t40002\tTXL\t0\t+\t0\t40002\t0\t2\t2,32767,\t0,7235,";
+        let record = BedRecord::parse(line).unwrap();
+        assert_eq!(record.stop_codon().codon().unwrap().start(), 39999);
+    }
 }
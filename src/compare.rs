@@ -0,0 +1,313 @@
+use crate::bed::BedRecord;
+use crate::utils::{parallel_parse, raw, with_gz};
+
+use natord::compare;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+/// Outcome of matching a reference transcript against the query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum MatchClass {
+    /// Same exons (and coding span, when both sides have one).
+    Identical,
+    /// Same intron chain but different ends or coding span, or same
+    /// transcript_id with a different structure.
+    Modified,
+    /// Reference transcript with no counterpart in the query.
+    Missing,
+    /// Query transcript with no counterpart in the reference.
+    Novel,
+}
+
+impl fmt::Display for MatchClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            MatchClass::Identical => "identical",
+            MatchClass::Modified => "modified",
+            MatchClass::Missing => "missing",
+            MatchClass::Novel => "novel",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Transcript model reduced to what the comparison needs. Exons are
+/// 0-based half-open and sorted; `coding` spans CDS plus stop codon.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Model {
+    pub chrom: String,
+    pub strand: String,
+    pub gene: String,
+    pub transcript: String,
+    pub exons: Vec<(u32, u32)>,
+    pub coding: Option<(u32, u32)>,
+}
+
+impl Model {
+    fn from_bed(record: &BedRecord) -> Model {
+        Model {
+            chrom: record.chrom.clone(),
+            strand: record.strand.clone(),
+            gene: record.name.clone(),
+            transcript: record.name.clone(),
+            exons: record.exon_blocks(),
+            coding: (record.cds_start < record.cds_end)
+                .then_some((record.cds_start, record.cds_end)),
+        }
+    }
+
+    fn introns(&self) -> Vec<(u32, u32)> {
+        self.exons.windows(2).map(|w| (w[0].1, w[1].0)).collect()
+    }
+
+    fn span(&self) -> (u32, u32) {
+        (self.exons[0].0, self.exons[self.exons.len() - 1].1)
+    }
+}
+
+/// One line of the comparison report.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub gene: String,
+    pub reference: Option<String>,
+    pub query: Option<String>,
+    pub class: MatchClass,
+}
+
+/// Reads transcript models from a BED (`.bed`/`.bed.gz`) or GTF
+/// (`.gtf`/`.gtf.gz`) file. BED records are their own gene.
+pub fn read_models<P: AsRef<Path>>(path: P) -> Result<Vec<Model>, Box<dyn Error>> {
+    let path = path.as_ref();
+    let name = path.to_string_lossy();
+    let contents = if name.ends_with(".gz") {
+        with_gz(path)?
+    } else {
+        raw(path)?
+    };
+
+    let stem = name.trim_end_matches(".gz");
+    if stem.ends_with(".bed") {
+        return Ok(parallel_parse(&contents)?
+            .iter()
+            .map(Model::from_bed)
+            .collect());
+    }
+    parse_gtf_models(&contents)
+}
+
+/// Groups GTF exon/CDS/stop_codon lines into transcript models.
+pub fn parse_gtf_models(contents: &str) -> Result<Vec<Model>, Box<dyn Error>> {
+    let mut models: Vec<Model> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields = line.split('\t').collect::<Vec<_>>();
+        if fields.len() < 9 {
+            return Err(format!("line {}: expected 9 GTF columns", i + 1).into());
+        }
+        let feature = fields[2];
+        if !matches!(feature, "exon" | "CDS" | "stop_codon") {
+            continue;
+        }
+
+        let start = fields[3]
+            .parse::<u32>()
+            .map_err(|_| format!("line {}: invalid start {}", i + 1, fields[3]))?;
+        let end = fields[4]
+            .parse::<u32>()
+            .map_err(|_| format!("line {}: invalid end {}", i + 1, fields[4]))?;
+        let attrs = parse_attributes(fields[8]);
+        let transcript = match attrs.get("transcript_id") {
+            Some(tx) => tx.to_string(),
+            None => continue,
+        };
+
+        let n = *index.entry(transcript.clone()).or_insert_with(|| {
+            models.push(Model {
+                chrom: fields[0].to_string(),
+                strand: fields[6].to_string(),
+                gene: attrs
+                    .get("gene_id")
+                    .copied()
+                    .unwrap_or(&transcript)
+                    .to_string(),
+                transcript,
+                exons: Vec::new(),
+                coding: None,
+            });
+            models.len() - 1
+        });
+
+        let model = &mut models[n];
+        let (start, end) = (start.saturating_sub(1), end);
+        if feature == "exon" {
+            model.exons.push((start, end));
+        } else {
+            model.coding = Some(match model.coding {
+                Some((s, e)) => (s.min(start), e.max(end)),
+                None => (start, end),
+            });
+        }
+    }
+
+    models.retain(|m| !m.exons.is_empty());
+    for model in &mut models {
+        model.exons.sort_unstable();
+    }
+    Ok(models)
+}
+
+/// Minimal `key "value";` attribute parser; first value wins.
+pub fn parse_attributes(s: &str) -> HashMap<&str, &str> {
+    let mut attrs = HashMap::new();
+    for pair in s.split(';') {
+        if let Some((key, value)) = pair.trim().split_once(' ') {
+            attrs.entry(key).or_insert(value.trim().trim_matches('"'));
+        }
+    }
+    attrs
+}
+
+type ChainKey = (String, String, Vec<(u32, u32)>);
+
+fn chain_key(model: &Model) -> ChainKey {
+    (model.chrom.clone(), model.strand.clone(), model.introns())
+}
+
+/// Matches every reference transcript against the query by intron
+/// chain (single-exon transcripts match on overlap) and classifies it;
+/// unmatched query transcripts are reported as novel. Results are
+/// grouped by gene.
+pub fn compare_models(reference: &[Model], query: &[Model]) -> Vec<Comparison> {
+    let mut by_chain: HashMap<ChainKey, Vec<usize>> = HashMap::new();
+    let mut by_id: HashMap<&str, usize> = HashMap::new();
+    for (i, model) in query.iter().enumerate() {
+        by_chain.entry(chain_key(model)).or_default().push(i);
+        by_id.insert(&model.transcript, i);
+    }
+
+    let same_coding = |a: &Model, b: &Model| match (a.coding, b.coding) {
+        (Some(x), Some(y)) => x == y,
+        (None, None) => true,
+        // coding on one side only
+        _ => false,
+    };
+    let overlaps = |a: &Model, b: &Model| {
+        let ((s1, e1), (s2, e2)) = (a.span(), b.span());
+        s1 < e2 && s2 < e1
+    };
+
+    let mut used = HashSet::new();
+    let mut out = Vec::with_capacity(reference.len());
+    for model in reference {
+        let mut candidates = by_chain
+            .get(&chain_key(model))
+            .map(|v| v.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .copied()
+            .filter(|&i| model.exons.len() > 1 || overlaps(model, &query[i]))
+            .collect::<Vec<_>>();
+        // unclaimed query models first, then the same transcript_id
+        candidates.sort_by_key(|&i| (used.contains(&i), query[i].transcript != model.transcript));
+
+        let is_identical =
+            |i: usize| query[i].exons == model.exons && same_coding(model, &query[i]);
+        let unclaimed = candidates.iter().copied().filter(|i| !used.contains(i));
+        let (hit, class) = match unclaimed.clone().find(|&i| is_identical(i)) {
+            Some(i) => (Some(i), MatchClass::Identical),
+            None => match unclaimed
+                .chain(candidates.iter().copied())
+                .next()
+                .or_else(|| by_id.get(model.transcript.as_str()).copied())
+            {
+                Some(i) if is_identical(i) => (Some(i), MatchClass::Identical),
+                Some(i) => (Some(i), MatchClass::Modified),
+                None => (None, MatchClass::Missing),
+            },
+        };
+
+        if let Some(i) = hit {
+            used.insert(i);
+        }
+        out.push(Comparison {
+            gene: model.gene.clone(),
+            reference: Some(model.transcript.clone()),
+            query: hit.map(|i| query[i].transcript.clone()),
+            class,
+        });
+    }
+
+    for (i, model) in query.iter().enumerate() {
+        if !used.contains(&i) {
+            out.push(Comparison {
+                gene: model.gene.clone(),
+                reference: None,
+                query: Some(model.transcript.clone()),
+                class: MatchClass::Novel,
+            });
+        }
+    }
+
+    out.sort_by(|a, b| {
+        compare(&a.gene, &b.gene)
+            .then(a.class.cmp(&b.class))
+            .then(a.reference.cmp(&b.reference))
+            .then(a.query.cmp(&b.query))
+    });
+    out
+}
+
+/// Number of transcripts per class.
+pub fn summarize(results: &[Comparison]) -> BTreeMap<MatchClass, usize> {
+    let mut counts = BTreeMap::new();
+    for result in results {
+        *counts.entry(result.class).or_insert(0) += 1;
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_by_intron_chain() {
+        let reference = parse_gtf_models(
+            "chr1\ts\texon\t1\t100\t.\t+\t.\tgene_id \"G\"; transcript_id \"A\";\n\
+             chr1\ts\texon\t201\t300\t.\t+\t.\tgene_id \"G\"; transcript_id \"A\";\n\
+             chr1\ts\texon\t1\t100\t.\t+\t.\tgene_id \"G\"; transcript_id \"B\";\n\
+             chr1\ts\texon\t201\t300\t.\t+\t.\tgene_id \"G\"; transcript_id \"B\";\n\
+             chr1\ts\texon\t1001\t1100\t.\t+\t.\tgene_id \"H\"; transcript_id \"C\";\n",
+        )
+        .unwrap();
+        let query = parse_gtf_models(
+            "chr1\ts\texon\t1\t100\t.\t+\t.\tgene_id \"G\"; transcript_id \"A\";\n\
+             chr1\ts\texon\t201\t300\t.\t+\t.\tgene_id \"G\"; transcript_id \"A\";\n\
+             chr1\ts\texon\t11\t100\t.\t+\t.\tgene_id \"G\"; transcript_id \"B\";\n\
+             chr1\ts\texon\t201\t300\t.\t+\t.\tgene_id \"G\"; transcript_id \"B\";\n\
+             chr1\ts\texon\t5001\t5100\t.\t+\t.\tgene_id \"N\"; transcript_id \"D\";\n",
+        )
+        .unwrap();
+
+        let results = compare_models(&reference, &query);
+        let class = |tx: &str| {
+            results
+                .iter()
+                .find(|r| r.reference.as_deref() == Some(tx) || r.query.as_deref() == Some(tx))
+                .unwrap()
+                .class
+        };
+
+        assert_eq!(class("A"), MatchClass::Identical);
+        assert_eq!(class("B"), MatchClass::Modified);
+        assert_eq!(class("C"), MatchClass::Missing);
+        assert_eq!(class("D"), MatchClass::Novel);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::lines::GtfRecord;
+
+use std::collections::{HashMap, HashSet};
+
+/// Attributes Cell Ranger reads from every line of a gene.
+const CELLRANGER_ATTRS: [&str; 2] = ["gene_name", "gene_biotype"];
+
+/// Presets enforcing what a downstream consumer needs from the GTF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Compat {
+    /// gene_name and gene_biotype on every line (cellranger mkref).
+    Cellranger,
+    /// Every transcript carries exons (STAR/STARsolo --sjdbGTFfile).
+    Starsolo,
+    /// Exons grouped under real gene_ids (featureCounts -g gene_id).
+    Featurecounts,
+    /// Sorted, bgzipped and tabix-indexed output (VEP --gtf).
+    Vep,
+}
+
+impl Compat {
+    /// Checks the run configuration before any work is done.
+    pub fn check_inputs(&self, has_genes: bool) -> Result<(), String> {
+        match self {
+            Compat::Featurecounts if !has_genes => Err(
+                "featurecounts profile needs gene-level records: provide --isoforms or \
+                 --infer-genes instead of --no-gene"
+                    .to_string(),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Brings the converted records in line with the profile, or
+    /// explains why the input cannot satisfy it.
+    pub fn apply(&self, records: &mut [GtfRecord]) -> Result<(), String> {
+        match self {
+            Compat::Cellranger => {
+                propagate_gene_attrs(records, &CELLRANGER_ATTRS);
+                let missing = records
+                    .iter()
+                    .filter(|r| CELLRANGER_ATTRS.iter().any(|k| r.attrs.get(k).is_none()))
+                    .filter_map(|r| r.attrs.get("gene_id"))
+                    .collect::<HashSet<_>>();
+                if !missing.is_empty() {
+                    return Err(format!(
+                        "cellranger profile requires gene_name and gene_biotype on every gene; \
+                         {} genes lack them (e.g. {}). Add gene_name/gene_biotype columns to \
+                         --gene-meta or --tx-meta",
+                        missing.len(),
+                        missing.iter().next().unwrap()
+                    ));
+                }
+                Ok(())
+            }
+            Compat::Starsolo => {
+                let with_exons = records
+                    .iter()
+                    .filter(|r| r.feature == "exon")
+                    .filter_map(|r| r.attrs.get("transcript_id"))
+                    .collect::<HashSet<_>>();
+                let empty = records
+                    .iter()
+                    .filter(|r| r.feature == "transcript")
+                    .filter_map(|r| r.attrs.get("transcript_id"))
+                    .filter(|tx| !with_exons.contains(tx))
+                    .count();
+                if empty > 0 {
+                    return Err(format!(
+                        "starsolo profile requires exons for every transcript; {} transcripts \
+                         have none",
+                        empty
+                    ));
+                }
+                Ok(())
+            }
+            Compat::Featurecounts | Compat::Vep => Ok(()),
+        }
+    }
+
+    /// Whether the output must be bgzipped and tabix-indexed.
+    pub fn needs_index(&self) -> bool {
+        matches!(self, Compat::Vep)
+    }
+}
+
+/// Copies `keys` from each gene's first transcript line onto every line
+/// of that gene that lacks them.
+pub fn propagate_gene_attrs(records: &mut [GtfRecord], keys: &[&str]) {
+    let mut by_gene: HashMap<String, Vec<(String, String)>> = HashMap::new();
+    for record in records.iter().filter(|r| r.feature == "transcript") {
+        if let Some(gene) = record.attrs.get("gene_id") {
+            by_gene.entry(gene.to_string()).or_insert_with(|| {
+                keys.iter()
+                    .filter_map(|k| record.attrs.get(k).map(|v| (k.to_string(), v.to_string())))
+                    .collect()
+            });
+        }
+    }
+
+    for record in records.iter_mut() {
+        let values = match record.attrs.get("gene_id").and_then(|g| by_gene.get(g)) {
+            Some(values) => values,
+            None => continue,
+        };
+        for (key, value) in values {
+            if record.attrs.get(key).is_none() {
+                record.attrs.push(key.as_str(), value.as_str());
+            }
+        }
+    }
+}
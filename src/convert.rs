@@ -0,0 +1,2936 @@
+use crate::alias::{rename_contigs, set_chr_prefix, ChromAlias};
+use crate::bed::{auto_name, read_frames, stitch_by_name, BedRecord};
+use crate::biotype::{nmd_candidate, BiotypeRules, SourceMap, NMD_BIOTYPE};
+use crate::check::{
+    check_gtf_consistency, check_record, frame_disagreements, short_blocks, Violation,
+};
+use crate::cli::{Cli, InlineInput};
+use crate::codon::*;
+use crate::coverage::write_cds_report;
+use crate::dedup::dedup;
+use crate::error::Bed2GtfError;
+use crate::fasta::{check_codons, rescan_start, write_sequences, Genome, START_CODONS};
+use crate::fix::{
+    blocks_span_tx, cds_length, drop_empty_blocks, fix_inverted, fix_span, has_exons, inverted,
+    merge_close_blocks, merge_touching_blocks, trim_cds, CleanMode, FixMode,
+};
+use crate::format::{Codec, Format, InputFormat, OutputSpec};
+use crate::formatter::{FeatureFormatter, Gff3Formatter, GtfFlavor, GtfFormatter, UcscFormatter};
+use crate::genes::{
+    apply_gene_bounds, auto_genes, flatten_genes, infer_genes, mixed_strand_genes,
+    placeholder_genes, read_gene_bed, split_distant_loci, split_strands, strand_gene,
+    StrandConflict, NO_TRANSCRIPTS,
+};
+use crate::genetic_code::genetic_code;
+use crate::gz::ParGzWriter;
+use crate::index::index_gtf;
+use crate::isoforms::{read_isoforms, Conflict, IsoformsAudit, OnConflict};
+use crate::junctions::write_junctions;
+use crate::lines::*;
+use crate::manifest::Manifest;
+use crate::merge::merge_annotations;
+use crate::meta::{DuplicateNames, MetaTable};
+use crate::pipeline::{for_each_chunk, BackgroundWriter};
+use crate::report::{ProgressHook, Report};
+use crate::signals::{CancelToken, PartialOutput};
+use crate::sink::{AnnotationSink, FeatureWriter};
+use crate::sites::{write_sites, SiteKind};
+use crate::sort::read_text;
+use crate::spill::{approx_size, Spill};
+use crate::utils::*;
+use crate::warnings::Warnings;
+use clap::Parser;
+
+use crate::par::prelude::*;
+use log::warn;
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// BED records converted per thread between writes in `--assume-sorted`.
+const STREAM_CHUNK: usize = 1024;
+/// Bytes of BED text read per chunk in `--two-pass`.
+const TWO_PASS_CHUNK_BYTES: usize = 8 * 1024 * 1024;
+
+/// Runs a full BED-to-GTF conversion as configured by `args`, which must
+/// have passed [`Cli::check`]. Parallel stages run on the current rayon
+/// pool.
+pub fn convert(args: &Cli) -> Result<(), Bed2GtfError> {
+    run(args, None)
+}
+
+/// [`convert`] sending the converted features to `sink` instead of the
+/// `-o`/`--also-write` files; side outputs (`--report`,
+/// `--write-sequences`, ...) are still written. `--max-mem` (unless with
+/// `--two-pass`) and `--merge-into` work on rendered text and cannot
+/// feed a sink.
+pub fn convert_into(args: &Cli, sink: &mut dyn AnnotationSink) -> Result<(), Bed2GtfError> {
+    if (args.max_mem.is_some() && !args.two_pass) || args.merge_into.is_some() {
+        return Err(Bed2GtfError::Validation(
+            "--max-mem (without --two-pass) and --merge-into cannot write to an annotation sink"
+                .to_string(),
+        ));
+    }
+    run(args, Some(sink))
+}
+
+/// BED files above this size get a hint about the low-memory modes.
+const LARGE_INPUT: u64 = 1 << 30;
+
+fn run(args: &Cli, custom: Option<&mut dyn AnnotationSink>) -> Result<(), Bed2GtfError> {
+    let mut report = Report::new(args);
+    let genome = load_genome(args)?;
+    if args.two_pass {
+        return two_pass(args, genome, report, custom);
+    }
+    if args.max_mem.is_none() && !args.assume_sorted && args.inline.is_none() {
+        let size = std::fs::metadata(args.bed()).map_or(0, |meta| meta.len());
+        if size > LARGE_INPUT {
+            log::info!(
+                "{} is {} MB and will be held in memory; --two-pass (with --max-mem for unsorted input) bounds it",
+                args.bed().display(),
+                size >> 20
+            );
+        }
+    }
+
+    let (bed, warnings) = read_bed(args, &mut report)?;
+    let (bed, merged) = fix_bed(bed, args, genome.as_ref(), &warnings, &mut report)?;
+    let (
+        bed,
+        GeneMap {
+            mut imap,
+            secondary,
+            absent_genes,
+        },
+    ) = assign_genes(bed, args, &warnings)?;
+    if let (Some(prefix), Some(genome)) = (&args.write_sequences, &genome) {
+        write_sequences(&bed, genome, &imap, prefix, &args.genetic_code)?;
+        log::info!(
+            "Sequences written to {}.{{transcripts,cds,proteins}}.fa",
+            prefix
+        );
+    }
+    let (tx_meta, gene_meta) = load_meta(args, &bed, &imap, &warnings)?;
+    let gene_attrs = |line: &GtfRecord| {
+        gene_meta
+            .as_ref()
+            .and_then(|m| m.attributes(line.attrs.get("gene_id")?))
+    };
+
+    let gene_track = custom_par_parse(&bed)
+        .map_err(|_| format!("Error parsing BED file {}", args.bed().display()))?;
+
+    let strands = resolve_strands(&mut imap, &gene_track, args.strand_conflict, &warnings)?;
+    if let Some(distance) = args.split_loci {
+        let affected = split_distant_loci(&mut imap, &gene_track, distance);
+        if !affected.is_empty() {
+            warn!(
+                "{} genes span multiple loci and were split: {}",
+                affected.len(),
+                affected
+                    .iter()
+                    .map(|(gene, n)| format!("{} ({} loci)", gene, n))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            for (gene, n) in &affected {
+                warnings.push(gene, "gene_split", format!("split into {} loci", n));
+            }
+        }
+    }
+
+    let rules = BiotypeRules {
+        min_cds: args.min_cds_length,
+        min_lnc_length: args.min_lnc_length,
+        min_lnc_exons: args.min_lnc_exons,
+    };
+    let biotypes = args.infer_biotypes.then(|| rules.genes(&bed, &imap));
+
+    let contig_sizes = promoter_bounds(args)?;
+    let sources = source_map(args)?;
+    let to_lines = |record: &BedRecord| {
+        let mut lines = to_gtf(record, &imap, genome.as_ref(), args, &warnings)?;
+        add_promoter(&mut lines, record, args, &contig_sizes);
+        if let Some(attrs) = gene_attrs(&lines[0]) {
+            lines[0].attrs.extend(&attrs);
+        }
+        if let Some(attrs) = tx_meta.as_ref().and_then(|m| m.attributes(&record.name)) {
+            lines[0].attrs.extend(&attrs);
+        }
+        if args.bed_detail {
+            for (key, value) in ["db_xref", "description"].iter().zip(&record.extra) {
+                if !value.trim().is_empty() {
+                    lines[0].attrs.push(*key, value.trim());
+                }
+            }
+        }
+        if let Some(genes) = &biotypes {
+            let gene = lines[0]
+                .attrs
+                .get("gene_id")
+                .and_then(|g| genes.get(g).copied());
+            for (key, value) in [
+                ("gene_biotype", gene),
+                ("transcript_biotype", Some(rules.transcript(record))),
+            ] {
+                if let (true, Some(value)) = (lines[0].attrs.get(key).is_none(), value) {
+                    lines[0].attrs.push(key, value);
+                }
+            }
+        }
+        if args.nmd_biotype && nmd_candidate(record, args.nmd_distance) {
+            let transcript = &mut lines[0].attrs;
+            match transcript.get("transcript_biotype") {
+                Some(_) => transcript.update("transcript_biotype", |_| NMD_BIOTYPE.to_string()),
+                None => transcript.push("transcript_biotype", NMD_BIOTYPE),
+            }
+        }
+        if args.keep_original_name {
+            lines[0].attrs.push("original_name", record.original_name());
+        }
+        if let Some(names) = merged.get(&record.name) {
+            lines[0].attrs.push("merged_from", names.join(","));
+        }
+        if let Some(genes) = secondary.get(&record.name) {
+            if args.on_conflict == OnConflict::Secondary {
+                lines[0].attrs.push("secondary_gene_ids", genes.join(","));
+            }
+        }
+        sources.apply(&mut lines);
+        Ok(lines)
+    };
+
+    write_tables(args, &bed, &imap, genome.as_ref())?;
+    report.stage("prepare");
+    args.cancel.check()?;
+
+    let mut genes = combine_maps_par(&imap, &gene_track);
+    set_strands(&mut genes, &strands);
+    apply_gene_bed(&mut genes, args, &absent_genes, &warnings)?;
+    for gene in &mut genes {
+        if let Some(attrs) = gene_attrs(gene) {
+            gene.attrs.extend(&attrs);
+        }
+        if let Some(genes) = &biotypes {
+            let biotype = gene
+                .attrs
+                .get("gene_id")
+                .and_then(|g| genes.get(g).copied());
+            if let (true, Some(biotype)) = (gene.attrs.get("gene_biotype").is_none(), biotype) {
+                gene.attrs.push("gene_biotype", biotype);
+            }
+        }
+        sources.apply(std::slice::from_mut(gene));
+    }
+    report.stage("genes");
+    args.cancel.check()?;
+
+    if args.assume_sorted {
+        stream_sorted(&bed, genes, to_lines, args, &warnings, &mut report, custom)?
+            .record(&warnings, &mut report);
+        report.stage("stream");
+        return finish(args, &warnings, report);
+    }
+
+    if let Some(budget) = args.max_mem {
+        spill_sorted(&bed, genes, to_lines, args, &warnings, &mut report, budget)?
+            .record(&warnings, &mut report);
+        report.stage("spill");
+        return finish(args, &warnings, report);
+    }
+
+    if args.verify {
+        let violations = bed.par_iter().flat_map(check_record).collect::<Vec<_>>();
+        verified("BED record", &violations)?;
+    }
+
+    // --gene-level converts no transcript at all
+    let transcripts: &[BedRecord] = if args.gene_level { &[] } else { &bed };
+    let results = convert_transcripts(transcripts, to_lines, args, &warnings, &mut report)?;
+    report.stage("convert");
+    let mut extra = if args.flatten {
+        flatten_genes(&bed, &imap)
+    } else {
+        Vec::new()
+    };
+    extra
+        .iter_mut()
+        .for_each(|line| sources.apply(std::slice::from_mut(line)));
+
+    let blocks = lay_out(args, genes, results, extra, bed.len())?;
+    report.stage("layout");
+    args.cancel.check()?;
+
+    write_blocks(args, &blocks, custom, &mut report)?;
+    report.stage("write");
+
+    finish(args, &warnings, report)
+}
+
+/// Reads the BED input, stitching fragments with `--stitch-by-name`, and
+/// applies the record filters, `--head` and the renaming options.
+/// Malformed lines stop the run unless `--lenient`, which keeps them as
+/// warnings and rejects.
+fn read_bed(args: &Cli, report: &mut Report) -> Result<(Vec<BedRecord>, Warnings), Bed2GtfError> {
+    let parse = || -> Result<(Vec<BedRecord>, Vec<Rejected>), Box<dyn Error>> {
+        Ok(match (args.input_format, &args.inline) {
+            (Some(_), _) if args.stitch_by_name => {
+                let (fragments, rejected) =
+                    parse_blocks_reader(open_input(args)?, args.lenient, args.chunk_size)?;
+                let total = fragments.len();
+                let bed = stitch_by_name(fragments);
+                log::info!(
+                    "Stitched {} BED records into {} transcripts",
+                    total,
+                    bed.len()
+                );
+                (bed, rejected)
+            }
+            (_, Some(inline)) => {
+                parallel_parse_reader(inline.bed.as_bytes(), args.lenient, args.chunk_size)?
+            }
+            (Some(InputFormat::BedGz), None) => {
+                parse_gz(args.bed(), args.lenient, args.chunk_size)?
+            }
+            (Some(InputFormat::Bed), None) => {
+                parse_plain(args.bed(), args.lenient, args.chunk_size)?
+            }
+            (None, None) => return Err("Not a .BED/.BED.GZ. Wrong file format!".into()),
+        })
+    };
+    let (bed, rejected) = match args.parse_threads {
+        Some(threads) => {
+            let pool = crate::par::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .map_err(|e| e.to_string())?;
+            pool.install(|| parse().map_err(|e| e.to_string()))
+        }
+        None => parse().map_err(|e| e.to_string()),
+    }
+    .map_err(|message| Bed2GtfError::Parse {
+        path: args.bed().clone(),
+        message,
+    })?;
+
+    let (mut bed, mut rejected) = (bed, rejected);
+    report.bed_records = bed.len() + rejected.len();
+    report.stage("read");
+    args.cancel.check()?;
+    args.dialect.normalize(&mut bed);
+    if let Some(prefix) = &args.auto_name {
+        auto_name(&mut bed, prefix, 0);
+    }
+    let filtered = args.record_filter().apply(&mut bed, &mut rejected);
+    if filtered > 0 {
+        log::info!(
+            "Dropped {} records filtered out by --chroms, --exclude-chroms-regex, --min-score or --strand",
+            filtered
+        );
+    }
+    let previewed = args.head().map_or(0, |mut head| head.apply(&mut bed));
+    if previewed > 0 {
+        log::info!("Left out {} records past --head", previewed);
+    }
+    report.skipped_records = filtered + previewed + rejected.len();
+    if let Some(prefix) = args.chr_prefix() {
+        set_chr_prefix(&mut bed, prefix);
+    }
+    if args.strip_versions {
+        bed.par_iter_mut()
+            .for_each(|record| record.rename(strip_version(&record.name).to_string()));
+    }
+    let warnings = Warnings::new(&bed);
+    if let Some(first) = rejected.first() {
+        warn!(
+            "{} malformed BED lines skipped (first at {})",
+            rejected.len(),
+            first
+        );
+        for line in &rejected {
+            warnings.push(
+                &format!("line {}", line.line_number),
+                "invalid_record",
+                format!("skipped: {}", line.error),
+            );
+            warnings.reject(&line.line, &line.error);
+        }
+    }
+
+    Ok((bed, warnings))
+}
+
+/// `--dedup` representatives -> the transcripts merged into them.
+type Merged = HashMap<String, Vec<String>>;
+
+/// Repairs and adjusts the records read: `--fix`, `--target-naming`,
+/// `--frames`, `--dedup` and `--rescan-start`. Returns them with the
+/// transcripts each `--dedup` representative was merged from.
+fn fix_bed(
+    bed: Vec<BedRecord>,
+    args: &Cli,
+    genome: Option<&Genome>,
+    warnings: &Warnings,
+    report: &mut Report,
+) -> Result<(Vec<BedRecord>, Merged), Bed2GtfError> {
+    let mut bed = fix_records(bed, args, warnings, report)?;
+    error_budget(args, warnings, report, 0)?;
+
+    if let Some(target) = args.target_naming {
+        let table = match &args.chrom_alias {
+            Some(source) => Some(
+                ChromAlias::load(source)
+                    .map_err(|e| format!("Error reading chromAlias {}: {}", source, e))?,
+            ),
+            None => None,
+        };
+        let unmapped = rename_contigs(&mut bed, table.as_ref(), target);
+        if !unmapped.is_empty() {
+            warn!(
+                "{} contigs have no {:?} name and were kept as is: {}",
+                unmapped.len(),
+                target,
+                unmapped.iter().cloned().collect::<Vec<_>>().join(", ")
+            );
+            for contig in &unmapped {
+                warnings.push(contig, "contig_unmapped", "kept contig name");
+            }
+        }
+    }
+
+    if args.strip_versions {
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+        for record in &bed {
+            *seen.entry(&record.name).or_default() += 1;
+        }
+        let collisions = seen
+            .values()
+            .filter(|&&n| n > 1)
+            .map(|n| n - 1)
+            .sum::<usize>();
+        if collisions > 0 {
+            warn!(
+                "{} transcript names collide after stripping versions",
+                collisions
+            );
+            for (name, n) in seen.iter().filter(|(_, &n)| n > 1) {
+                warnings.push(
+                    name,
+                    "version_collision",
+                    format!("kept {} transcripts with this name", n),
+                );
+            }
+        }
+    }
+
+    if let Some(path) = &args.frames {
+        let mut table = read_frames(&raw(path)?)
+            .map_err(|e| format!("Error reading frames file {}: {}", path.display(), e))?;
+        if args.strip_versions {
+            table = table
+                .into_iter()
+                .map(|(name, frames)| (strip_version(&name).to_string(), frames))
+                .collect();
+        }
+        let mut applied = 0;
+        for record in bed.iter_mut() {
+            let Some(frames) = table.get(&record.name) else {
+                continue;
+            };
+            if frames.len() != record.exon_count as usize {
+                warn!(
+                    "{} has {} exons but {} frames; computing its frames instead",
+                    record.name,
+                    record.exon_count,
+                    frames.len()
+                );
+                warnings.push(&record.name, "frames_mismatch", "computed frames");
+                continue;
+            }
+            record.frames = Some(frames.clone());
+            applied += 1;
+        }
+        log::info!("Using given frames for {} transcripts", applied);
+        if args.check_frames {
+            let disagreements = bed
+                .par_iter()
+                .flat_map_iter(frame_disagreements)
+                .collect::<Vec<_>>();
+            for violation in &disagreements {
+                warnings.push(&violation.id, violation.rule, violation.detail.as_str());
+            }
+            match disagreements.len() {
+                0 => log::info!(
+                    "Given frames agree with the CDS of all {} transcripts",
+                    applied
+                ),
+                n => warn!(
+                    "{} exons in {} transcripts have frames that disagree with their CDS: {}",
+                    n,
+                    disagreements
+                        .iter()
+                        .map(|v| &v.id)
+                        .collect::<HashSet<_>>()
+                        .len(),
+                    disagreements
+                        .iter()
+                        .take(5)
+                        .map(|v| v.to_string())
+                        .collect::<Vec<_>>()
+                        .join("; ")
+                ),
+            }
+        }
+    }
+
+    let (bed, merged) = match args.dedup {
+        Some(mode) => {
+            let total = bed.len();
+            let (bed, merged) = dedup(bed, mode);
+            report.skipped_records += total - bed.len();
+            for (kept, names) in &merged {
+                for name in names.iter().filter(|n| *n != kept) {
+                    warnings.push(name, "duplicate_merged", format!("merged into {}", kept));
+                }
+            }
+            log::info!(
+                "Collapsed {} duplicated transcripts into {} representatives",
+                total - bed.len(),
+                merged.len()
+            );
+            (bed, merged)
+        }
+        None => (bed, HashMap::new()),
+    };
+
+    let bed = match (genome, args.rescan_start) {
+        (Some(genome), true) => {
+            let mut bed = bed;
+            let moved = bed
+                .par_iter_mut()
+                .filter_map(|record| {
+                    let pos = rescan_start(record, genome)?;
+                    warnings.push(
+                        &record.name,
+                        "start_rescanned",
+                        format!("moved CDS start to in-frame ATG at {}", pos),
+                    );
+                    Some(pos)
+                })
+                .count();
+            log::info!(
+                "Moved the CDS start of {} non-ATG transcripts to an in-frame ATG",
+                moved
+            );
+            bed
+        }
+        _ => bed,
+    };
+
+    Ok((bed, merged))
+}
+
+/// Genes of the transcripts, with what the isoforms file adds to them.
+struct GeneMap {
+    /// Gene of every transcript.
+    imap: HashMap<String, String>,
+    /// Transcripts listed under more than one gene -> the genes after
+    /// the first.
+    secondary: HashMap<String, Vec<String>>,
+    /// Genes of the isoforms file none of whose transcripts are in the
+    /// BED.
+    absent_genes: Vec<String>,
+}
+
+/// Maps every transcript to its gene, from the isoforms file,
+/// `--auto-gene` or `--infer-genes`; with `--on-conflict duplicate`,
+/// transcripts listed under several genes are copied into each.
+fn assign_genes(
+    bed: Vec<BedRecord>,
+    args: &Cli,
+    warnings: &Warnings,
+) -> Result<(Vec<BedRecord>, GeneMap), Bed2GtfError> {
+    let mut secondary: HashMap<String, Vec<String>> = HashMap::new();
+    let mut absent_genes = Vec::new();
+    let mut imap = if let Some(isoforms) = &args.isoforms {
+        let isf = read_isoforms_text(args, isoforms)
+            .map_err(|e| Bed2GtfError::io(format!("Error reading {}", isoforms.display()), e))?;
+        let names = bed.iter().map(|r| r.name.as_str()).collect::<HashSet<_>>();
+        let (imap, conflicts) = read_isoforms(
+            &isf,
+            &names,
+            args.isoforms_order,
+            args.strip_versions,
+            args.on_conflict,
+        )
+        .map_err(|e| Bed2GtfError::Isoform(format!("{}: {}", isoforms.display(), e)))?;
+        for conflict in conflicts {
+            let others = conflict.genes[1..]
+                .iter()
+                .map(|(g, _)| g.clone())
+                .collect::<Vec<_>>();
+            let action = conflict_action(&conflict, args.on_conflict);
+            warnings.push(&conflict.transcript, "isoform_conflict", action);
+            if args.on_conflict.keeps_all() {
+                secondary.insert(conflict.transcript, others);
+            }
+        }
+        let audit = IsoformsAudit::new(&names, &imap);
+        if args.audit_isoforms {
+            if !audit.unlisted.is_empty() {
+                return Err(Bed2GtfError::Isoform(format!(
+                    "{}: {}",
+                    isoforms.display(),
+                    audit
+                )));
+            }
+            log::info!("Isoforms audit: {}", audit);
+        }
+        if !audit.absent.is_empty() {
+            warn!("{}", audit.absent_summary());
+        }
+        absent_genes = audit.absent_genes;
+        imap
+    } else if let Some(rule) = &args.auto_gene {
+        let (imap, unmatched) = auto_genes(&bed, rule);
+        if !unmatched.is_empty() {
+            warn!(
+                "{} transcript names do not match the --auto-gene pattern and are their own gene",
+                unmatched.len()
+            );
+            for name in &unmatched {
+                warnings.push(
+                    name,
+                    "gene_unmatched",
+                    "used the transcript name as gene_id",
+                );
+            }
+        }
+        imap
+    } else if args.infer_genes {
+        let imap = infer_genes(&bed, args.gene_ids);
+        log::info!(
+            "Inferred {} genes from exonic overlap",
+            imap.values().collect::<HashSet<_>>().len()
+        );
+        imap
+    } else {
+        HashMap::new()
+    };
+
+    let bed = if args.on_conflict == OnConflict::Duplicate && !secondary.is_empty() {
+        let mut bed = bed;
+        let copies = bed
+            .iter()
+            .filter_map(|record| Some((record, secondary.get(&record.name)?)))
+            .flat_map(|(record, genes)| {
+                genes.iter().map(move |gene| {
+                    let mut copy = record.clone();
+                    copy.rename(format!("{}_{}", record.name, gene));
+                    (copy, gene)
+                })
+            })
+            .collect::<Vec<_>>();
+        log::info!(
+            "Copied {} transcripts listed under several genes",
+            copies.len()
+        );
+        for (copy, gene) in copies {
+            imap.insert(copy.name.clone(), gene.clone());
+            bed.push(copy);
+        }
+        bed
+    } else {
+        bed
+    };
+
+    Ok((
+        bed,
+        GeneMap {
+            imap,
+            secondary,
+            absent_genes,
+        },
+    ))
+}
+
+/// Tables written next to the annotation: `--cds-report`, `--tss-out`,
+/// `--tes-out` and `--junctions-out`.
+fn write_tables(
+    args: &Cli,
+    bed: &[BedRecord],
+    imap: &HashMap<String, String>,
+    genome: Option<&Genome>,
+) -> Result<(), Bed2GtfError> {
+    if let Some(path) = &args.cds_report {
+        write_cds_report(path, bed, imap)
+            .map_err(|e| format!("Error writing {}: {}", path.display(), e))?;
+        log::info!("CDS report written to {}", path.display());
+    }
+    for (path, kind, what) in [
+        (&args.tss_out, SiteKind::Start, "TSS"),
+        (&args.tes_out, SiteKind::End, "TES"),
+    ] {
+        if let Some(path) = path {
+            write_sites(path, bed, imap, kind)
+                .map_err(|e| format!("Error writing {}: {}", path.display(), e))?;
+            log::info!("{}s written to {}", what, path.display());
+        }
+    }
+    if let Some(path) = &args.junctions_out {
+        let n = write_junctions(path, bed, imap, genome)
+            .map_err(|e| format!("Error writing {}: {}", path.display(), e))?;
+        log::info!("{} splice junctions written to {}", n, path.display());
+    }
+
+    Ok(())
+}
+
+/// The `--tx-meta` and `--gene-meta` tables, or those of `--biomart`;
+/// gene names are made unique as `--duplicate-gene-names` says.
+fn load_meta(
+    args: &Cli,
+    bed: &[BedRecord],
+    imap: &HashMap<String, String>,
+    warnings: &Warnings,
+) -> Result<(Option<MetaTable>, Option<MetaTable>), Bed2GtfError> {
+    let (biomart_transcripts, biomart_genes) = match &args.biomart {
+        Some(path) => {
+            let (transcripts, genes) = raw(path)
+                .and_then(|contents| MetaTable::from_biomart(&contents))
+                .map_err(|e| format!("Error reading BioMart file {}: {}", path.display(), e))?;
+            log::info!(
+                "Loaded BioMart metadata for {} transcripts and {} genes",
+                transcripts.len(),
+                genes.len()
+            );
+            (Some(transcripts), Some(genes))
+        }
+        None => (None, None),
+    };
+
+    let tx_meta = match &args.tx_meta {
+        Some(path) => {
+            let table = MetaTable::from_path(path, "transcript_id")
+                .map_err(|e| format!("Error reading metadata file {}: {}", path.display(), e))?;
+            log::info!("Loaded metadata for {} transcripts", table.len());
+            Some(table)
+        }
+        None => biomart_transcripts,
+    };
+
+    let gene_meta = match &args.gene_meta {
+        Some(path) => {
+            let table = MetaTable::from_path(path, "gene_id")
+                .map_err(|e| format!("Error reading metadata file {}: {}", path.display(), e))?;
+            log::info!("Loaded metadata for {} genes", table.len());
+            Some(table)
+        }
+        None => biomart_genes,
+    }
+    .map(|mut table| {
+        unique_gene_names(&mut table, bed, imap, args, warnings);
+        table
+    });
+
+    Ok((tx_meta, gene_meta))
+}
+
+/// `--gene-bed`: moves the gene lines onto their curated extents and,
+/// with `--keep-absent-genes`, adds those of `absent_genes` it places.
+fn apply_gene_bed(
+    genes: &mut Vec<GtfRecord>,
+    args: &Cli,
+    absent_genes: &[String],
+    warnings: &Warnings,
+) -> Result<(), Bed2GtfError> {
+    let Some(path) = &args.gene_bed else {
+        return Ok(());
+    };
+    let mut bounds = read_gene_bed(&raw(path)?)
+        .map_err(|e| format!("Error reading gene BED {}: {}", path.display(), e))?;
+    if args.strip_versions {
+        bounds = bounds
+            .into_iter()
+            .map(|(id, bound)| (strip_version(&id).to_string(), bound))
+            .collect();
+    }
+    let (moved, mismatched, outside) = apply_gene_bounds(genes, &bounds);
+    log::info!(
+        "Took the extents of {} genes from {}",
+        moved,
+        path.display()
+    );
+    if !mismatched.is_empty() {
+        warn!(
+            "{} genes lie on another chromosome or strand than in {} and keep their transcript span",
+            mismatched.len(),
+            path.display()
+        );
+        for gene in &mismatched {
+            warnings.push(gene, "gene_bed_mismatch", "kept transcript span");
+        }
+    }
+    if !outside.is_empty() {
+        warn!(
+            "{} genes have transcripts outside their extent in {}",
+            outside.len(),
+            path.display()
+        );
+        for gene in &outside {
+            warnings.push(gene, "gene_bed_outside", "used curated extent");
+        }
+    }
+    if args.keep_absent_genes {
+        let (placed, missing) = placeholder_genes(absent_genes, &bounds);
+        log::info!(
+            "Wrote {} gene lines without transcripts from {}",
+            placed.len(),
+            path.display()
+        );
+        if !missing.is_empty() {
+            warn!(
+                "{} genes without transcripts in the BED have no extent in {} and are left out",
+                missing.len(),
+                path.display()
+            );
+        }
+        genes.extend(placed);
+    }
+    Ok(())
+}
+
+/// Converts `transcripts` with `convert`, a chunk at a time so that
+/// cancellation, progress and the error budget are checked as it goes.
+/// Failed transcripts stop the run unless `--lenient`, which sets them
+/// aside as warnings.
+fn convert_transcripts<F>(
+    transcripts: &[BedRecord],
+    convert: F,
+    args: &Cli,
+    warnings: &Warnings,
+    report: &mut Report,
+) -> Result<Vec<Vec<GtfRecord>>, Bed2GtfError>
+where
+    F: Fn(&BedRecord) -> Result<Vec<GtfRecord>, Bed2GtfError> + Sync,
+{
+    let mut failures = Failures::default();
+    let mut results = Vec::with_capacity(transcripts.len());
+    let mut done = 0;
+    for records in transcripts.chunks(stream_chunk(args)) {
+        args.cancel.check()?;
+        results.extend(
+            failures.sift(
+                records,
+                records
+                    .par_iter()
+                    .with_min_len(args.chunk_size.unwrap_or(1))
+                    .map(&convert)
+                    .collect(),
+            ),
+        );
+        error_budget(args, warnings, report, failures.errors.len())?;
+        done += records.len();
+        report.progress(done, Some(transcripts.len()));
+    }
+    failures.check(args.lenient)?;
+    failures.record(warnings, report);
+    Ok(results)
+}
+
+/// Lays the gene lines, converted transcripts and `extra` features out
+/// in output order (`--keep-order`, `--exon-order`, `--order`), checking
+/// them with `--verify` and applying `--circular`, the ID affixes and
+/// `--compat`. `records` is the number of BED records behind them.
+fn lay_out(
+    args: &Cli,
+    genes: Vec<GtfRecord>,
+    results: Vec<Vec<GtfRecord>>,
+    extra: Vec<GtfRecord>,
+    records: usize,
+) -> Result<Vec<GtfRecord>, Bed2GtfError> {
+    // transcription order needs each transcript's children kept together
+    let grouped = args.keep_order || args.exon_order == ExonOrder::Transcription;
+    let mut blocks = if grouped {
+        let mut results = results;
+        if !args.keep_order {
+            args.chrom_order
+                .check(results.iter().map(|t| t[0].chrom.as_str()))?;
+            gene_order(&mut results, &genes, &args.chrom_order);
+        }
+        input_order(genes, results, extra, args.exon_order)
+    } else {
+        let mut blocks = genes;
+        blocks.extend(results.into_iter().flatten());
+        blocks.extend(extra);
+        blocks
+    };
+
+    if args.verify {
+        verified("converted feature", &check_gtf_consistency(&blocks))?;
+        log::info!("Verified {} BED records and their features", records);
+    }
+
+    if !args.circular.is_empty() {
+        let sizes = read_chrom_sizes(args.chrom_sizes.as_ref().unwrap())?;
+        let circular = args.circular.iter().cloned().collect::<HashSet<_>>();
+        blocks = wrap_circular(blocks, &sizes, &circular);
+    }
+
+    if args.id_prefix.is_some() || args.id_suffix.is_some() {
+        add_id_affixes(
+            &mut blocks,
+            args.id_prefix.as_deref().unwrap_or_default(),
+            args.id_suffix.as_deref().unwrap_or_default(),
+        );
+    }
+
+    if let Some(compat) = args.compat {
+        compat.apply(&mut blocks)?;
+    }
+
+    if args.order == OutputOrder::ById {
+        sort_by_id(&mut blocks, args.exon_order);
+    } else if !grouped {
+        let order = &args.chrom_order;
+        order.check(blocks.iter().map(|b| b.chrom.as_str()))?;
+        order.sort_by_coords(&mut blocks, |b| (&b.chrom, b.start));
+    }
+
+    Ok(blocks)
+}
+
+/// Writes the laid-out `blocks` to `custom`, to `--merge-into`, or to
+/// `-o` and every `--also-write` output (indexed for `--compat` presets
+/// that need it), counting them in `report`. Outputs are only kept once
+/// all are written.
+fn write_blocks(
+    args: &Cli,
+    blocks: &[GtfRecord],
+    custom: Option<&mut dyn AnnotationSink>,
+    report: &mut Report,
+) -> Result<(), Bed2GtfError> {
+    let to_files = custom.is_none();
+    let partials = match (custom, &args.merge_into) {
+        (Some(sink), _) => {
+            sink.write_records(blocks)?;
+            sink.flush()?;
+            Vec::new()
+        }
+        (None, Some(base)) => vec![merge_output(base, blocks, args)?],
+        // every output is rendered from the same laid-out records
+        (None, None) => args
+            .outputs()
+            .iter()
+            .map(|output| {
+                let partial = PartialOutput::new(&output.path);
+                let error = |e| format!("Error writing {}: {}", output.path.display(), e);
+                let mut file = open_writer(output, args)?;
+                let mut sink = open_sink(&mut file, output, args)?;
+                sink.write_records(blocks).map_err(error)?;
+                sink.flush()?;
+                drop(sink);
+                file.finish().map_err(error)?;
+                Ok(partial)
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?,
+    };
+    blocks.iter().for_each(|entry| report.count(entry));
+
+    if to_files && args.compat.is_some_and(|c| c.needs_index()) {
+        index_gtf(args.output(), Some(args.output()), args.gz_level)?;
+        log::info!("Index written to {}.tbi", args.output().display());
+    }
+    partials.into_iter().for_each(PartialOutput::keep);
+    Ok(())
+}
+
+fn load_genome(args: &Cli) -> Result<Option<Genome>, Bed2GtfError> {
+    let Some(fasta) = &args.fasta else {
+        return Ok(None);
+    };
+    let genome = Genome::from_path(fasta)
+        .map_err(|e| format!("Error reading FASTA file {}: {}", fasta.display(), e))?;
+    log::info!("Loaded {} sequences from {}", genome.len(), fasta.display());
+    Ok(Some(genome))
+}
+
+/// The isoforms table at `path`, or the one given in memory (see
+/// [`convert_str`]).
+fn read_isoforms_text(args: &Cli, path: &PathBuf) -> std::io::Result<String> {
+    match args.inline.as_ref().and_then(|i| i.isoforms.clone()) {
+        Some(text) => Ok(text),
+        None => reader(path),
+    }
+}
+
+/// The BED input, inline or from `-b`.
+fn open_input(args: &Cli) -> Result<Box<dyn std::io::BufRead + Send>, Bed2GtfError> {
+    match &args.inline {
+        Some(inline) => Ok(Box::new(std::io::Cursor::new(
+            inline.bed.clone().into_bytes(),
+        ))),
+        None => open_bed(args.bed())
+            .map_err(|e| Bed2GtfError::io(format!("Error reading {}", args.bed().display()), e)),
+    }
+}
+
+/// `--two-pass --max-mem`: sorts the raw BED lines by `--chrom-order`
+/// and start, spilling runs of at most half of `budget`, and merges them
+/// into one file of the returned spill, so that the two passes can read
+/// input in any order. Each line ends in a field with its input line
+/// number, so that rejected records keep it (see
+/// [`parse_numbered_lines`]).
+fn sort_bed(args: &Cli, budget: u64) -> Result<(Spill, PathBuf), Bed2GtfError> {
+    let order = &args.chrom_order;
+    let mut spill = Spill::bed(&args.tmpdir())?;
+    let (mut lines, mut held, mut number) = (Vec::new(), 0, 0);
+    for_each_chunk(open_input(args)?, TWO_PASS_CHUNK_BYTES, |chunk| {
+        args.cancel.check()?;
+        for line in chunk.lines() {
+            number += 1;
+            let line = format!("{}\t{}", line, number);
+            held += line.len() + std::mem::size_of::<String>();
+            lines.push(line);
+            if held as u64 > budget / 2 {
+                spill.write_lines(&mut lines, order)?;
+                lines.clear();
+                held = 0;
+            }
+        }
+        Ok(())
+    })?;
+    if !lines.is_empty() {
+        spill.write_lines(&mut lines, order)?;
+    }
+    drop(lines);
+
+    let path = spill.path("sorted.bed");
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(&path)?);
+    spill.merge(order, &mut writer)?;
+    writer.flush()?;
+    log::info!(
+        "Sorted {} in {} runs for --two-pass",
+        args.bed().display(),
+        spill.runs()
+    );
+    Ok((spill, path))
+}
+
+/// Streams the BED input chunk by chunk, handing `f` the records of each
+/// (names without versions with `--strip-versions`) and its malformed
+/// lines; these stop the run unless `--lenient`. Records dropped by
+/// [`Cli::record_filter`] or past `--head` never reach `f`; returns how
+/// many there were.
+fn for_each_bed_chunk<F>(args: &Cli, sorted: Option<&Path>, mut f: F) -> Result<usize, Bed2GtfError>
+where
+    F: FnMut(Vec<BedRecord>, Vec<Rejected>) -> Result<(), Box<dyn Error>>,
+{
+    let input = match sorted {
+        Some(path) => open_bed(path)
+            .map_err(|e| Bed2GtfError::io(format!("Error reading {}", path.display()), e))?,
+        None => open_input(args)?,
+    };
+    let filter = args.record_filter();
+    let mut head = args.head();
+    let (mut line, mut named, mut filtered) = (1, 0, 0);
+    for_each_chunk(input, TWO_PASS_CHUNK_BYTES, |chunk| {
+        args.cancel.check()?;
+        let (mut records, mut rejected) = match sorted {
+            Some(_) => parse_numbered_lines(chunk, args.chunk_size),
+            None => parse_lines(chunk, line, args.chunk_size),
+        };
+        line += chunk.lines().count();
+        if let Some(prefix) = &args.auto_name {
+            auto_name(&mut records, prefix, named);
+            named += records.len();
+        }
+        filtered += filter.apply(&mut records, &mut rejected);
+        if let Some(head) = &mut head {
+            filtered += head.apply(&mut records);
+        }
+        if let Some(prefix) = args.chr_prefix() {
+            set_chr_prefix(&mut records, prefix);
+        }
+        if let (false, Some(first)) = (args.lenient, rejected.first()) {
+            return Err(Bed2GtfError::Parse {
+                path: args.bed().clone(),
+                message: first.to_string(),
+            }
+            .into());
+        }
+        if args.strip_versions {
+            records
+                .par_iter_mut()
+                .for_each(|record| record.rename(strip_version(&record.name).to_string()));
+        }
+        f(records, rejected)
+    })
+    .map_err(Bed2GtfError::from)?;
+    Ok(filtered)
+}
+
+/// `--two-pass`: a first pass over the BED keeps only the span of every
+/// transcript, enough for the gene lines; a second one converts and
+/// writes one chromosome at a time. Neither all records nor all features
+/// are ever held, but each chromosome must come in one block of the
+/// input, in `--chrom-order`, unless `--max-mem` sorts it first.
+fn two_pass(
+    args: &Cli,
+    genome: Option<Genome>,
+    mut report: Report,
+    custom: Option<&mut dyn AnnotationSink>,
+) -> Result<(), Bed2GtfError> {
+    let order = &args.chrom_order;
+    let sorted = match args.max_mem {
+        Some(budget) => {
+            let sorted = sort_bed(args, budget)?;
+            report.stage("sort");
+            Some(sorted)
+        }
+        None => None,
+    };
+    let sorted = sorted.as_ref().map(|(_, path)| path.as_path());
+    let mut track = GeneTrack::new();
+    let mut chroms: Vec<String> = Vec::new();
+    let mut auto = HashMap::new();
+    let mut total = 0;
+    for_each_bed_chunk(args, sorted, |mut records, _| {
+        total += records.len();
+        if let Some(mode) = args.inverted_fix() {
+            records = fix_inverted(records, mode).0;
+        }
+        for record in &records {
+            if chroms.last() != Some(&record.chrom) {
+                if chroms.contains(&record.chrom) {
+                    return Err(format!(
+                        "{} is not grouped by chromosome ({} appears again at {}); \
+                         sort it, add --max-mem or drop --two-pass",
+                        args.bed().display(),
+                        record.chrom,
+                        record.name
+                    )
+                    .into());
+                }
+                chroms.push(record.chrom.clone());
+            }
+            track.entry(record.name.clone()).or_insert_with(|| {
+                (
+                    record.chrom.clone(),
+                    record.tx_start,
+                    record.tx_end,
+                    record.strand.clone(),
+                )
+            });
+        }
+        if let Some(rule) = &args.auto_gene {
+            auto.extend(auto_genes(&records, rule).0);
+        }
+        Ok(())
+    })?;
+    order.check(chroms.iter().map(|c| c.as_str()))?;
+    if let Some(w) = chroms
+        .windows(2)
+        .find(|w| order.compare(&w[0], &w[1]).is_gt())
+    {
+        return Err(format!(
+            "{} has {} before {}, against --chrom-order; sort it, add --max-mem or drop --two-pass",
+            args.bed().display(),
+            w[0],
+            w[1]
+        )
+        .into());
+    }
+
+    let warnings = Warnings::default();
+    let mut imap = if let Some(isoforms) = &args.isoforms {
+        let contents = read_isoforms_text(args, isoforms)
+            .map_err(|e| Bed2GtfError::io(format!("Error reading {}", isoforms.display()), e))?;
+        let names = track.keys().map(|n| n.as_str()).collect::<HashSet<_>>();
+        let (imap, conflicts) = read_isoforms(
+            &contents,
+            &names,
+            args.isoforms_order,
+            args.strip_versions,
+            args.on_conflict,
+        )
+        .map_err(|e| Bed2GtfError::Isoform(format!("{}: {}", isoforms.display(), e)))?;
+        for conflict in &conflicts {
+            let action = conflict_action(conflict, args.on_conflict);
+            warnings.push(&conflict.transcript, "isoform_conflict", action);
+        }
+        let audit = IsoformsAudit::new(&names, &imap);
+        if !audit.absent.is_empty() {
+            warn!("{}", audit.absent_summary());
+        }
+        imap
+    } else {
+        auto
+    };
+    let strands = resolve_strands(&mut imap, &track, args.strand_conflict, &warnings)?;
+    if let Some(distance) = args.split_loci {
+        for (gene, n) in split_distant_loci(&mut imap, &track, distance) {
+            warnings.push(&gene, "gene_split", format!("split into {} loci", n));
+        }
+    }
+    let mut genes: HashMap<String, Vec<GtfRecord>> = HashMap::new();
+    let sources = source_map(args)?;
+    let mut lines = combine_maps_par(&imap, &track);
+    set_strands(&mut lines, &strands);
+    for mut gene in lines {
+        sources.apply(std::slice::from_mut(&mut gene));
+        genes.entry(gene.chrom.clone()).or_default().push(gene);
+    }
+    drop(track);
+    report.stage("extents");
+    args.cancel.check()?;
+
+    let contig_sizes = promoter_bounds(args)?;
+    let mut file = None;
+    let (mut sink, partial) = main_sink(args, custom, &mut file)?;
+    let (prefix, suffix) = (
+        args.id_prefix.as_deref().unwrap_or_default(),
+        args.id_suffix.as_deref().unwrap_or_default(),
+    );
+    let (mut failures, mut done) = (Failures::default(), 0);
+    let mut write_chrom = |bed: Vec<BedRecord>, report: &mut Report| {
+        let Some(chrom) = bed.first().map(|r| r.chrom.clone()) else {
+            return Ok(());
+        };
+        done += bed.len();
+        let bed = fix_records(bed, args, &warnings, report)?;
+        let converted = failures.sift(
+            &bed,
+            bed.par_iter()
+                .with_min_len(args.chunk_size.unwrap_or(1))
+                .map(|record| {
+                    let mut lines = to_gtf(record, &imap, genome.as_ref(), args, &warnings)?;
+                    add_promoter(&mut lines, record, args, &contig_sizes);
+                    sources.apply(&mut lines);
+                    Ok(lines)
+                })
+                .collect(),
+        );
+        error_budget(args, &warnings, report, failures.errors.len())?;
+        let mut blocks = genes.remove(&chrom).unwrap_or_default();
+        blocks.extend(converted.into_iter().flatten());
+        add_id_affixes(&mut blocks, prefix, suffix);
+        blocks.par_sort_unstable_by(|a, b| a.start.cmp(&b.start));
+        sink.write_records(&blocks)?;
+        blocks.iter().for_each(|r| report.count(r));
+        report.progress(done, Some(total));
+        Ok::<_, Box<dyn Error>>(())
+    };
+
+    let mut batch: Vec<BedRecord> = Vec::new();
+    let filtered = for_each_bed_chunk(args, sorted, |records, rejected| {
+        report.bed_records += records.len() + rejected.len();
+        report.skipped_records += rejected.len();
+        for line in &rejected {
+            warnings.push(
+                &format!("line {}", line.line_number),
+                "invalid_record",
+                format!("skipped: {}", line.error),
+            );
+            warnings.reject(&line.line, &line.error);
+        }
+        for record in records {
+            if batch.last().is_some_and(|last| last.chrom != record.chrom) {
+                write_chrom(std::mem::take(&mut batch), &mut report)?;
+            }
+            batch.push(record);
+        }
+        Ok(())
+    })?;
+    if filtered > 0 {
+        log::info!(
+            "Dropped {} records filtered out by --chroms, --exclude-chroms-regex, --min-score, --strand or --head",
+            filtered
+        );
+    }
+    report.bed_records += filtered;
+    report.skipped_records += filtered;
+    write_chrom(batch, &mut report)?;
+    failures.check(args.lenient)?;
+    sink.flush()?;
+    drop(sink);
+    if let Some(file) = file {
+        file.finish()?;
+    }
+    partial.into_iter().for_each(PartialOutput::keep);
+    failures.record(&warnings, &mut report);
+    report.stage("convert");
+
+    finish(args, &warnings, report)
+}
+
+/// Contig lengths bounding `--add-promoters`, from `--chrom-sizes`.
+fn promoter_bounds(args: &Cli) -> Result<HashMap<String, u32>, Box<dyn Error>> {
+    match (&args.add_promoters, &args.chrom_sizes) {
+        (Some(_), Some(path)) => read_chrom_sizes(path),
+        _ => Ok(HashMap::new()),
+    }
+}
+
+/// The column-2 sources of `--source` and `--source-map`.
+fn source_map(args: &Cli) -> Result<SourceMap, Box<dyn Error>> {
+    match &args.source_map {
+        Some(path) => Ok(SourceMap::parse(&raw(path)?, args.source.clone())
+            .map_err(|e| format!("Error reading source map {}: {}", path.display(), e))?),
+        None => Ok(SourceMap::new(args.source.clone())),
+    }
+}
+
+/// `--add-promoters`: appends the promoter of `record` to its `lines`.
+fn add_promoter(
+    lines: &mut Vec<GtfRecord>,
+    record: &BedRecord,
+    args: &Cli,
+    contig_sizes: &HashMap<String, u32>,
+) {
+    if let Some(promoters) = args.add_promoters {
+        let gene = lines[0]
+            .attrs
+            .get("gene_id")
+            .unwrap_or_default()
+            .to_string();
+        let contig_len = contig_sizes.get(&record.chrom).copied();
+        write_promoter(record, &gene, promoters, contig_len, lines);
+    }
+}
+
+/// Transcripts that could not be converted, kept with their errors so
+/// that a run reports every one of them rather than the first.
+#[derive(Debug, Default)]
+struct Failures {
+    errors: Vec<(String, Bed2GtfError)>,
+    /// The failed records, for `--rejects`.
+    records: Vec<BedRecord>,
+}
+
+impl Failures {
+    /// The converted transcripts of `records`, setting the failed ones
+    /// aside.
+    fn sift(
+        &mut self,
+        records: &[BedRecord],
+        results: Vec<Result<Vec<GtfRecord>, Bed2GtfError>>,
+    ) -> Vec<Vec<GtfRecord>> {
+        records
+            .iter()
+            .zip(results)
+            .filter_map(|(record, result)| {
+                result
+                    .map_err(|error| {
+                        self.errors.push((record.name.clone(), error));
+                        self.records.push(record.clone());
+                    })
+                    .ok()
+            })
+            .collect()
+    }
+
+    /// The error policy: any failure stops the run, with all of them,
+    /// unless `--lenient` skips them.
+    fn check(&mut self, lenient: bool) -> Result<(), Bed2GtfError> {
+        if lenient || self.errors.is_empty() {
+            return Ok(());
+        }
+        Err(Bed2GtfError::Conversion(std::mem::take(&mut self.errors)))
+    }
+
+    /// Records the skipped transcripts as warnings.
+    fn record(self, warnings: &Warnings, report: &mut Report) {
+        if self.errors.is_empty() {
+            return;
+        }
+        warn!(
+            "Skipped {} transcripts that could not be converted: {}",
+            self.errors.len(),
+            self.errors
+                .iter()
+                .map(|(id, _)| id.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        report.skipped_records += self.errors.len();
+        for ((id, error), record) in self.errors.into_iter().zip(&self.records) {
+            warnings.reject_record(record, &error);
+            warnings.push(&id, "conversion_failed", format!("skipped: {}", error));
+        }
+    }
+}
+
+/// `--max-errors`/`--max-error-rate`: stops the run once more BED
+/// records were skipped or repaired than allowed, `pending` being the
+/// failed conversions not yet recorded in `warnings`.
+fn error_budget(
+    args: &Cli,
+    warnings: &Warnings,
+    report: &Report,
+    pending: usize,
+) -> Result<(), Bed2GtfError> {
+    let errors = warnings.rejects_len() + report.fixed_records + pending;
+    let over = match (args.max_errors, args.max_error_rate) {
+        (Some(max), _) if errors > max => format!("--max-errors {}", max),
+        (_, Some(rate)) if errors as f64 > rate * report.bed_records as f64 => {
+            format!("--max-error-rate {}", rate)
+        }
+        _ => return Ok(()),
+    };
+    Err(Bed2GtfError::Validation(format!(
+        "{} of {} BED records skipped or repaired, more than {} allows",
+        errors, report.bed_records, over
+    )))
+}
+
+/// What `policy` did with a transcript listed under several genes.
+fn conflict_action(conflict: &Conflict, policy: OnConflict) -> String {
+    let (primary, others) = conflict.genes.split_first().expect("conflicts have genes");
+    let others = others.iter().map(|(g, _)| g.as_str()).collect::<Vec<_>>();
+    match policy {
+        OnConflict::Last => format!("kept {}", conflict.genes[conflict.genes.len() - 1].0),
+        OnConflict::Skip => "left out of the isoforms map".to_string(),
+        OnConflict::Duplicate => format!(
+            "copied under {}",
+            others
+                .iter()
+                .map(|g| format!("{} as {}_{}", g, conflict.transcript, g))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        OnConflict::Secondary => format!(
+            "kept {}, secondary_gene_ids {}",
+            primary.0,
+            others.join(",")
+        ),
+        _ => format!("kept {}", primary.0),
+    }
+}
+
+/// Applies `--fix` to records with start > end (an error without it) and
+/// reports, tags or trims CDS lengths that are not a multiple of 3.
+fn fix_records(
+    mut bed: Vec<BedRecord>,
+    args: &Cli,
+    warnings: &Warnings,
+    report: &mut Report,
+) -> Result<Vec<BedRecord>, Bed2GtfError> {
+    if let Some(first) = bed.iter().find(|r| inverted(r)) {
+        let Some(mode) = args.inverted_fix() else {
+            return Err(Bed2GtfError::Validation(format!(
+                "{} has start > end (chromStart {}, chromEnd {}, thickStart {}, thickEnd {}); \
+                 use --fix=swap or --fix=skip to convert anyway",
+                first.name, first.tx_start, first.tx_end, first.cds_start, first.cds_end
+            )));
+        };
+        if mode == FixMode::Skip {
+            bed.iter()
+                .filter(|r| inverted(r))
+                .for_each(|r| warnings.reject_record(r, "start > end"));
+        }
+        let total = bed.len();
+        let fixed;
+        (bed, fixed) = fix_inverted(bed, mode);
+        report.skipped_records += total - bed.len();
+        let action = match mode {
+            FixMode::Skip => "skipped",
+            _ => {
+                report.fixed_records += fixed.len();
+                "start and end swapped"
+            }
+        };
+        for name in &fixed {
+            warnings.push(name, "inverted_coordinates", action);
+        }
+        warn!("{} records with start > end: {}", fixed.len(), action);
+    }
+
+    if args.clean.contains(&CleanMode::NoExons) {
+        let total = bed.len();
+        bed.retain(|record| {
+            let keep = has_exons(record);
+            if !keep {
+                warnings.push(&record.name, "no_exons", "skipped");
+                warnings.reject_record(record, "no exons");
+            }
+            keep
+        });
+        if bed.len() < total {
+            report.skipped_records += total - bed.len();
+            warn!("{} transcripts without exons skipped", total - bed.len());
+        }
+    }
+    let cleanups = [
+        (
+            CleanMode::ZeroLength,
+            drop_empty_blocks as fn(&mut BedRecord) -> usize,
+            "zero_length_exon",
+            "zero-length exon blocks removed",
+        ),
+        (
+            CleanMode::MergeCds,
+            merge_touching_blocks,
+            "merged_blocks",
+            "touching exon blocks merged",
+        ),
+    ];
+    for (mode, clean, code, what) in cleanups {
+        if !args.clean.contains(&mode) {
+            continue;
+        }
+        let cleaned = bed
+            .par_iter_mut()
+            .map(|record| {
+                let n = clean(record);
+                if n > 0 {
+                    warnings.push(&record.name, code, format!("{} {}", n, what));
+                }
+                n
+            })
+            .collect::<Vec<_>>();
+        report.fixed_records += cleaned.iter().filter(|&&n| n > 0).count();
+        let blocks = cleaned.iter().sum::<usize>();
+        if blocks > 0 {
+            warn!("{} {}", blocks, what);
+        }
+    }
+
+    if let Some(gap) = args.merge_exons {
+        let merged = bed
+            .par_iter_mut()
+            .map(|record| {
+                let n = merge_close_blocks(record, gap);
+                if n > 0 {
+                    let action = format!("{} exons merged across gaps of <= {} bp", n, gap);
+                    warnings.push(&record.name, "merged_exons", action);
+                }
+                n
+            })
+            .collect::<Vec<_>>();
+        report.fixed_records += merged.iter().filter(|&&n| n > 0).count();
+        let blocks = merged.iter().sum::<usize>();
+        if blocks > 0 {
+            warn!(
+                "{} exons merged into their neighbour (--merge-exons)",
+                blocks
+            );
+        }
+    }
+
+    if args.warn_exon_lt.is_some() || args.warn_intron_lt.is_some() {
+        let flagged = bed
+            .par_iter()
+            .flat_map_iter(|record| short_blocks(record, args.warn_exon_lt, args.warn_intron_lt))
+            .collect::<Vec<_>>();
+        for violation in &flagged {
+            warnings.push(&violation.id, violation.rule, violation.detail.as_str());
+        }
+        if !flagged.is_empty() {
+            warn!(
+                "{} transcripts with suspiciously short exons or introns \
+                 (--warn-exon-lt, --warn-intron-lt)",
+                flagged.iter().map(|v| &v.id).collect::<HashSet<_>>().len()
+            );
+        }
+    }
+
+    let span = args.span_fix();
+    let misaligned = bed
+        .par_iter_mut()
+        .filter(|record| !blocks_span_tx(record))
+        .map(|record| {
+            let action = match span {
+                Some(mode) => {
+                    let (start, end) = (record.tx_start, record.tx_end);
+                    fix_span(record, mode);
+                    if (start, end) == (record.tx_start, record.tx_end) {
+                        "first and last exons moved to the transcript span"
+                    } else {
+                        "transcript span moved to the exons"
+                    }
+                }
+                None => "reported",
+            };
+            warnings.push(&record.name, "exons_span_tx", action);
+        })
+        .count();
+    if span.is_some() {
+        report.fixed_records += misaligned;
+    }
+    if misaligned > 0 {
+        warn!(
+            "{} transcripts have exons not spanning chromStart-chromEnd{}",
+            misaligned,
+            if span.is_some() {
+                "; fixed"
+            } else {
+                "; use --fix=span-to-blocks or --fix=blocks-to-span"
+            }
+        );
+    }
+
+    let trim = args.fix.contains(&FixMode::TrimCds);
+    let incomplete = bed
+        .par_iter_mut()
+        .filter(|record| !cds_length(record).is_multiple_of(3))
+        .map(|record| {
+            let action = if trim {
+                format!(
+                    "trimmed {} bases from the 3' end of the CDS",
+                    trim_cds(record)
+                )
+            } else if args.tag_cds_incomplete {
+                "tagged cds_incomplete".to_string()
+            } else {
+                "reported".to_string()
+            };
+            warnings.push(&record.name, "cds_incomplete", action);
+        })
+        .count();
+    if trim {
+        report.fixed_records += incomplete;
+    }
+    if incomplete > 0 {
+        warn!(
+            "{} transcripts have a CDS length that is not a multiple of 3{}",
+            incomplete,
+            if trim { "; trimmed" } else { "" }
+        );
+    }
+    Ok(bed)
+}
+
+/// Reports genes of the output sharing a gene_name in `table` and, with
+/// `--duplicate-gene-names uniquify`, numbers them in genomic order.
+fn unique_gene_names(
+    table: &mut MetaTable,
+    bed: &[BedRecord],
+    imap: &HashMap<String, String>,
+    args: &Cli,
+    warnings: &Warnings,
+) {
+    let mut first: HashMap<&str, (&str, u32)> = HashMap::new();
+    for record in bed {
+        let gene = imap.get(&record.name).unwrap_or(&record.name);
+        let pos = first
+            .entry(gene)
+            .or_insert((&record.chrom, record.tx_start));
+        if args
+            .chrom_order
+            .cmp_coords((&record.chrom, record.tx_start), *pos)
+            .is_lt()
+        {
+            *pos = (&record.chrom, record.tx_start);
+        }
+    }
+    let mut genes = first.into_iter().collect::<Vec<_>>();
+    genes.sort_by(|a, b| args.chrom_order.cmp_coords(a.1, b.1).then(a.0.cmp(b.0)));
+    let ids = genes.iter().map(|(gene, _)| *gene).collect::<Vec<_>>();
+
+    let duplicates = table.duplicates("gene_name", &ids);
+    if duplicates.is_empty() {
+        return;
+    }
+    let uniquify = args.duplicate_gene_names == DuplicateNames::Uniquify;
+    warn!(
+        "{} gene_names are shared by several genes{}",
+        duplicates.len(),
+        if uniquify {
+            "; numbered"
+        } else {
+            " (see --duplicate-gene-names)"
+        }
+    );
+    for (name, genes) in &duplicates {
+        for (n, gene) in genes.iter().enumerate() {
+            let action = match uniquify {
+                true => format!("gene_name {} written as {}_{}", name, name, n + 1),
+                false => format!("gene_name {} kept", name),
+            };
+            warnings.push(gene, "duplicate_gene_name", action);
+        }
+    }
+    if uniquify {
+        table.uniquify("gene_name", &duplicates);
+    }
+}
+
+/// BED records converted between writes when streaming: [`STREAM_CHUNK`]
+/// per thread, or a `--chunk-size` work unit per thread if larger.
+fn stream_chunk(args: &Cli) -> usize {
+    STREAM_CHUNK.max(args.chunk_size.unwrap_or(0)) * crate::par::current_num_threads().max(1)
+}
+
+/// `--verify`: fails on broken invariants, listing the first few.
+fn verified(what: &str, violations: &[Violation]) -> Result<(), Bed2GtfError> {
+    if violations.is_empty() {
+        return Ok(());
+    }
+    for violation in violations.iter().take(10) {
+        log::error!("{}", violation);
+    }
+    Err(Bed2GtfError::Validation(format!(
+        "{} {} invariants broken",
+        violations.len(),
+        what
+    )))
+}
+
+/// Logs the feature summary and writes the optional `--warnings-out` and
+/// `--report` side outputs.
+fn finish(args: &Cli, warnings: &Warnings, report: Report) -> Result<(), Bed2GtfError> {
+    let started = report.started_at();
+    log::info!("{}", report.summary());
+    if !args.no_summary {
+        eprint!("{}", report.table(warnings, &args.chrom_order));
+    }
+    if args.profile {
+        eprint!("{}", report.profile_table());
+    }
+    if let Some(path) = &args.warnings_out {
+        warnings.write_tsv(path)?;
+        log::info!("{} warnings written to {}", warnings.len(), path.display());
+    }
+    if let Some(path) = &args.rejects {
+        warnings.write_rejects(path)?;
+        log::info!(
+            "{} skipped input lines written to {}",
+            warnings.rejects_len(),
+            path.display()
+        );
+    }
+    if let Some(path) = &args.metrics_out {
+        report.write_metrics(path, warnings)?;
+        log::info!("Metrics written to {}", path.display());
+    }
+    if let Some(path) = &args.report {
+        report.write(path, warnings)?;
+        log::info!("Report written to {}", path.display());
+    }
+    if let Some(path) = &args.manifest {
+        Manifest::new(args, started).write(path)?;
+        log::info!("Manifest written to {}", path.display());
+    }
+    Ok(())
+}
+
+/// `--merge-into`: adds the converted `records` to the base annotation
+/// and writes the merged result to the output.
+fn merge_output(
+    base: &Path,
+    records: &[GtfRecord],
+    args: &Cli,
+) -> Result<PartialOutput, Box<dyn Error>> {
+    let mut new = Vec::new();
+    text_sink(&mut new, args, Format::Gtf, None).write_records(records)?;
+    let (merged, stats) = merge_annotations(
+        &read_text(base)?,
+        &String::from_utf8(new)?,
+        &args.chrom_order,
+    )
+    .map_err(|e| format!("Error merging into {}: {}", base.display(), e))?;
+    log::info!(
+        "Merged into {}: {} transcripts added, {} already present, {} genes extended",
+        base.display(),
+        stats.added,
+        stats.duplicates,
+        stats.extended
+    );
+
+    let output = args.main_output();
+    let partial = PartialOutput::new(&output.path);
+    let mut writer = open_writer(&output, args)?;
+    writer.write_all(merged.as_bytes())?;
+    writer.finish()?;
+    Ok(partial)
+}
+
+/// GFF3 requires its version pragma on the very first line.
+fn header<W: Write + ?Sized>(writer: &mut W, args: &Cli, format: Format) {
+    if format == Format::Gff3 {
+        let _ = writeln!(writer, "##gff-version 3");
+    }
+    comments(writer, &args.header_line);
+}
+
+/// An output file behind its compressor, as [`open_writer`] opens it.
+/// The last compressed block and the last write of the file are only
+/// done by [`OutputFile::finish`], which must succeed before the output
+/// is kept.
+enum OutputFile {
+    Plain(BackgroundWriter),
+    Gzip(ParGzWriter<BackgroundWriter>),
+    Zstd(zstd::Encoder<'static, BackgroundWriter>),
+}
+
+impl OutputFile {
+    fn finish(self) -> io::Result<()> {
+        match self {
+            OutputFile::Plain(file) => file.finish(),
+            OutputFile::Gzip(writer) => writer.finish()?.finish(),
+            OutputFile::Zstd(encoder) => encoder.finish()?.finish(),
+        }
+    }
+
+    fn inner(&mut self) -> &mut dyn Write {
+        match self {
+            OutputFile::Plain(file) => file,
+            OutputFile::Gzip(writer) => writer,
+            OutputFile::Zstd(encoder) => encoder,
+        }
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner().flush()
+    }
+}
+
+/// Opens the output behind its compressor. The file itself is written by
+/// a [`BackgroundWriter`], so storage latency overlaps with conversion
+/// and compression.
+fn open_writer(output: &OutputSpec, args: &Cli) -> Result<OutputFile, String> {
+    let file = create_output(&output.path)
+        .map_err(|e| format!("Error creating {}: {}", output.path.display(), e))?;
+    let file = BackgroundWriter::new(file);
+    Ok(match output.codec {
+        Codec::None => OutputFile::Plain(file),
+        Codec::Gzip => {
+            let writer = ParGzWriter::new(file, args.gz_level);
+            match args.compress_threads {
+                Some(threads) => {
+                    let pool = crate::par::ThreadPoolBuilder::new()
+                        .num_threads(threads)
+                        .build()
+                        .map_err(|e| e.to_string())?;
+                    OutputFile::Gzip(writer.with_pool(Arc::new(pool)))
+                }
+                None => OutputFile::Gzip(writer),
+            }
+        }
+        Codec::Zstd => OutputFile::Zstd(
+            zstd::Encoder::new(file, 0)
+                .map_err(|e| format!("Error creating {}: {}", output.path.display(), e))?,
+        ),
+    })
+}
+
+/// The text writer of `format`, set up as `args` asks; with `check`,
+/// lines are checked against the grammar from that line on.
+fn text_sink<'a, W: Write + 'a>(
+    writer: W,
+    args: &Cli,
+    format: Format,
+    check: Option<usize>,
+) -> Box<dyn AnnotationSink + 'a> {
+    fn boxed<'a, W: Write + 'a, F: FeatureFormatter + 'a>(
+        writer: W,
+        formatter: F,
+        check: Option<usize>,
+    ) -> Box<dyn AnnotationSink + 'a> {
+        let writer = FeatureWriter::with_formatter(writer, formatter);
+        match check {
+            Some(first) => Box::new(writer.with_grammar_check(first)),
+            None => Box::new(writer),
+        }
+    }
+    let phase = args.phase_style;
+    match (format, args.gtf_flavor) {
+        (Format::Gtf, GtfFlavor::Gtf22) => {
+            let formatter = GtfFormatter {
+                attr_order: args.attr_order.clone(),
+                attr_style: args.attr_style(),
+                phase,
+            };
+            boxed(writer, formatter, check)
+        }
+        (Format::Gtf, GtfFlavor::Ucsc) => boxed(writer, UcscFormatter { phase }, check),
+        (Format::Gff3, _) => {
+            let formatter = Gff3Formatter {
+                noncoding_type: args.gff3_noncoding_type.clone(),
+                gene_parents: !args.no_gene,
+                phase,
+            };
+            boxed(writer, formatter, check)
+        }
+    }
+}
+
+/// Writes the header of `output` to `file`; features go through the
+/// returned sink, which checks every line with `--verify`.
+fn open_sink<'a>(
+    file: &'a mut OutputFile,
+    output: &OutputSpec,
+    args: &Cli,
+) -> Result<Box<dyn AnnotationSink + 'a>, String> {
+    let mut head = Vec::new();
+    header(&mut head, args, output.format);
+    file.write_all(&head)
+        .map_err(|e| format!("Error writing {}: {}", output.path.display(), e))?;
+    let first = head.iter().filter(|&&b| b == b'\n').count() + 1;
+    Ok(text_sink(
+        file,
+        args,
+        output.format,
+        args.verify.then_some(first),
+    ))
+}
+
+/// Where the streaming paths write: the caller's sink, or the `-o` file,
+/// opened into `file`, along with the guard removing it if the
+/// conversion fails.
+fn main_sink<'a, 's: 'a, 'f: 'a>(
+    args: &Cli,
+    custom: Option<&'s mut dyn AnnotationSink>,
+    file: &'f mut Option<OutputFile>,
+) -> Result<(Box<dyn AnnotationSink + 'a>, Option<PartialOutput>), String> {
+    match custom {
+        Some(sink) => Ok((Box::new(sink), None)),
+        None => {
+            let output = args.main_output();
+            let partial = PartialOutput::new(&output.path);
+            let file = file.insert(open_writer(&output, args)?);
+            Ok((open_sink(file, &output, args)?, Some(partial)))
+        }
+    }
+}
+
+/// Applies `--strand-conflict` to the genes with transcripts on more
+/// than one strand, listing each in the warnings. Returns the strand of
+/// the gene lines [`StrandConflict::Majority`] sets, for [`set_strands`].
+fn resolve_strands(
+    isoforms: &mut HashMap<String, String>,
+    gene_track: &GeneTrack,
+    policy: StrandConflict,
+    warnings: &Warnings,
+) -> Result<HashMap<String, String>, Bed2GtfError> {
+    let mixed = mixed_strand_genes(isoforms, gene_track);
+    if mixed.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let counts = |strands: &[(String, usize)]| {
+        strands
+            .iter()
+            .map(|(strand, n)| format!("{} {}", n, strand))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let listed = mixed
+        .iter()
+        .map(|(gene, strands)| format!("{} ({})", gene, counts(strands)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    if policy == StrandConflict::Error {
+        return Err(Bed2GtfError::Validation(format!(
+            "{} genes have transcripts on more than one strand (see --strand-conflict): {}",
+            mixed.len(),
+            listed
+        )));
+    }
+    warn!(
+        "{} genes have transcripts on more than one strand: {}",
+        mixed.len(),
+        listed
+    );
+
+    let mut strands = HashMap::new();
+    for (gene, counts) in &mixed {
+        let action = match policy {
+            StrandConflict::Majority => {
+                // most common first, ties in strand order: + before -
+                let majority = &counts[0].0;
+                strands.insert(gene.clone(), majority.clone());
+                format!("gene on {}, the strand of most transcripts", majority)
+            }
+            StrandConflict::Split => format!(
+                "split into {}",
+                counts
+                    .iter()
+                    .map(|(strand, _)| strand_gene(gene, strand))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            _ => "gene on the strand of the first transcript seen".to_string(),
+        };
+        warnings.push(gene, "strand_conflict", action);
+    }
+    if policy == StrandConflict::Split {
+        let genes = mixed.iter().map(|(gene, _)| gene.as_str()).collect();
+        split_strands(isoforms, gene_track, &genes);
+    }
+    Ok(strands)
+}
+
+/// Puts the gene lines named in `strands` on their strand.
+fn set_strands(genes: &mut [GtfRecord], strands: &HashMap<String, String>) {
+    if strands.is_empty() {
+        return;
+    }
+    for gene in genes {
+        if let Some(strand) = gene.attrs.get("gene_id").and_then(|id| strands.get(id)) {
+            gene.strand = strand.clone();
+        }
+    }
+}
+
+/// Fast path for coordinate-sorted input: converts the BED in chunks and
+/// writes each transcript as soon as its chunk is done, preceded by its
+/// gene line the first time the gene is seen. Nothing is sorted globally;
+/// the input order is checked in a single pass first.
+fn stream_sorted<F>(
+    bed: &[BedRecord],
+    genes: Vec<GtfRecord>,
+    convert: F,
+    args: &Cli,
+    warnings: &Warnings,
+    report: &mut Report,
+    custom: Option<&mut dyn AnnotationSink>,
+) -> Result<Failures, Box<dyn Error>>
+where
+    F: Fn(&BedRecord) -> Result<Vec<GtfRecord>, Bed2GtfError> + Sync,
+{
+    let order = &args.chrom_order;
+    if let Some(i) = bed.windows(2).position(|w| {
+        order.cmp_coords((&w[0].chrom, w[0].tx_start), (&w[1].chrom, w[1].tx_start))
+            == std::cmp::Ordering::Greater
+    }) {
+        return Err(format!(
+            "{} is not sorted ({} comes after {}); drop --assume-sorted",
+            args.bed().display(),
+            bed[i + 1].name,
+            bed[i].name
+        )
+        .into());
+    }
+    order.check(bed.iter().map(|r| r.chrom.as_str()))?;
+
+    // genes without transcripts (--keep-absent-genes) are written in
+    // between, in order: sorted backwards, the next one is last
+    let (mut lone, genes): (Vec<_>, Vec<_>) = genes
+        .into_iter()
+        .partition(|g| g.attrs.get("tag") == Some(NO_TRANSCRIPTS));
+    lone.sort_by(|a, b| order.cmp_coords((&b.chrom, b.start), (&a.chrom, a.start)));
+    let mut genes = genes
+        .into_iter()
+        .map(|g| (g.attrs.get("gene_id").unwrap_or_default().to_string(), g))
+        .collect::<HashMap<_, _>>();
+    let (prefix, suffix) = (
+        args.id_prefix.as_deref().unwrap_or_default(),
+        args.id_suffix.as_deref().unwrap_or_default(),
+    );
+    let write_gene = |sink: &mut dyn AnnotationSink, report: &mut Report, mut gene: GtfRecord| {
+        add_id_affixes(std::slice::from_mut(&mut gene), prefix, suffix);
+        report.count(&gene);
+        sink.write_gene(&gene)
+    };
+
+    let mut file = None;
+    let (mut sink, partial) = main_sink(args, custom, &mut file)?;
+    let mut failures = Failures::default();
+    let (chunk, mut done) = (stream_chunk(args), 0);
+    for records in bed.chunks(chunk) {
+        args.cancel.check()?;
+        done += records.len();
+        let converted = failures.sift(
+            records,
+            records
+                .par_iter()
+                .with_min_len(args.chunk_size.unwrap_or(1))
+                .map(&convert)
+                .collect(),
+        );
+        error_budget(args, warnings, report, failures.errors.len())?;
+        for mut lines in converted {
+            let at = (lines[0].chrom.as_str(), lines[0].start);
+            while let Some(gene) =
+                lone.pop_if(|g| order.cmp_coords((&g.chrom, g.start), at).is_le())
+            {
+                write_gene(sink.as_mut(), report, gene)?;
+            }
+            let gene = lines[0].attrs.get("gene_id").unwrap_or_default();
+            if let Some(gene) = genes.remove(gene) {
+                write_gene(sink.as_mut(), report, gene)?;
+            }
+            // the transcript line stays first
+            let strand = lines[0].strand.clone();
+            args.exon_order.sort(&mut lines[1..], &strand);
+            add_id_affixes(&mut lines, prefix, suffix);
+            let coding = lines.iter().any(|r| r.feature == "CDS");
+            sink.write_transcript(&lines[0], coding)?;
+            for entry in &lines[1..] {
+                sink.write_feature(entry)?;
+            }
+            lines.iter().for_each(|entry| report.count(entry));
+        }
+        report.progress(done, Some(bed.len()));
+    }
+    while let Some(gene) = lone.pop() {
+        write_gene(sink.as_mut(), report, gene)?;
+    }
+    failures.check(args.lenient)?;
+    sink.flush()?;
+    drop(sink);
+    if let Some(file) = file {
+        file.finish()?;
+    }
+    partial.into_iter().for_each(PartialOutput::keep);
+    Ok(failures)
+}
+
+/// Bounded-memory path for `--max-mem`: converts the BED in chunks and,
+/// whenever the features held (or the process's resident memory) would
+/// exceed the budget, sorts them and spills them to a temporary run. The
+/// runs are merged into the output at the end. Nothing is spilled when
+/// everything fits.
+fn spill_sorted<F>(
+    bed: &[BedRecord],
+    genes: Vec<GtfRecord>,
+    convert: F,
+    args: &Cli,
+    warnings: &Warnings,
+    report: &mut Report,
+    budget: u64,
+) -> Result<Failures, Box<dyn Error>>
+where
+    F: Fn(&BedRecord) -> Result<Vec<GtfRecord>, Bed2GtfError> + Sync,
+{
+    let order = &args.chrom_order;
+    order.check(bed.iter().map(|r| r.chrom.as_str()))?;
+    let (prefix, suffix) = (
+        args.id_prefix.as_deref().unwrap_or_default(),
+        args.id_suffix.as_deref().unwrap_or_default(),
+    );
+    let circular = if args.circular.is_empty() {
+        None
+    } else {
+        let sizes = read_chrom_sizes(args.chrom_sizes.as_ref().unwrap())?;
+        Some((sizes, args.circular.iter().cloned().collect::<HashSet<_>>()))
+    };
+    let layout = |mut records: Vec<GtfRecord>| {
+        if let Some((sizes, names)) = &circular {
+            records = wrap_circular(records, sizes, names);
+        }
+        add_id_affixes(&mut records, prefix, suffix);
+        records
+    };
+    let over_budget = |held: usize| {
+        held as u64 > budget / 2
+            || current_mem_usage_mb().is_some_and(|mb| mb * 1024.0 * 1024.0 > budget as f64 * 0.8)
+    };
+    let output = args.main_output();
+    let render = |writer: &mut dyn Write, records: &[GtfRecord]| {
+        text_sink(writer, args, output.format, None).write_records(records)
+    };
+
+    let mut spill = Spill::new(&args.tmpdir())?;
+    let mut blocks = layout(genes);
+    blocks.iter().for_each(|r| report.count(r));
+    let mut held = blocks.iter().map(approx_size).sum::<usize>();
+    let mut failures = Failures::default();
+    let (chunk, mut done) = (stream_chunk(args), 0);
+    for records in bed.chunks(chunk) {
+        args.cancel.check()?;
+        done += records.len();
+        let converted = failures.sift(
+            records,
+            records
+                .par_iter()
+                .with_min_len(args.chunk_size.unwrap_or(1))
+                .map(&convert)
+                .collect(),
+        );
+        error_budget(args, warnings, report, failures.errors.len())?;
+        let converted = layout(converted.into_iter().flatten().collect());
+        converted.iter().for_each(|r| report.count(r));
+        held += converted.iter().map(approx_size).sum::<usize>();
+        blocks.extend(converted);
+        if over_budget(held) {
+            spill.write_run(&mut blocks, order, render)?;
+            blocks.clear();
+            held = 0;
+        }
+        report.progress(done, Some(bed.len()));
+    }
+
+    failures.check(args.lenient)?;
+    let partial = PartialOutput::new(&output.path);
+    let mut writer = open_writer(&output, args)?;
+    header(&mut writer, args, output.format);
+    if spill.runs() == 0 {
+        order.sort_by_coords(&mut blocks, |b| (&b.chrom, b.start));
+        text_sink(&mut writer, args, output.format, None).write_records(&blocks)?;
+    } else {
+        if !blocks.is_empty() {
+            spill.write_run(&mut blocks, order, render)?;
+        }
+        log::info!(
+            "Memory budget exceeded; merging {} sorted runs from disk",
+            spill.runs()
+        );
+        spill.merge(order, &mut writer)?;
+    }
+    writer.finish()?;
+    partial.keep();
+    Ok(failures)
+}
+
+pub fn to_gtf(
+    bedline: &BedRecord,
+    isoforms: &HashMap<String, String>,
+    genome: Option<&Genome>,
+    args: &Cli,
+    warnings: &Warnings,
+) -> Result<Vec<GtfRecord>, Bed2GtfError> {
+    let mut result: Vec<GtfRecord> = Vec::new();
+
+    let gene = if !isoforms.is_empty() {
+        match isoforms.get(&bedline.name) {
+            Some(g) => g,
+            None => {
+                return Err(Bed2GtfError::Isoform(format!(
+                    "gene of {} not found in isoforms file",
+                    bedline.name
+                )))
+            }
+        }
+    } else {
+        &bedline.name
+    };
+
+    // unresolvable codons are empty, so incomplete: cds_start_NF/cds_end_NF
+    let (fcodon, lcodon) = if args.exon_only {
+        Default::default()
+    } else {
+        (
+            first_codon(bedline).unwrap_or_default(),
+            last_codon(bedline).unwrap_or_default(),
+        )
+    };
+    let frames = bedline.get_frames();
+
+    // the CDS stops where the codon pieces begin, even across an intron
+    let cds_end: u32 = if bedline.strand == "+" && codon_complete(&lcodon) {
+        lcodon.start()
+    } else {
+        bedline.cds_end
+    };
+
+    let cds_start = if bedline.strand == "-" && codon_complete(&fcodon) {
+        fcodon.end()
+    } else {
+        bedline.cds_start
+    };
+
+    build_gtf_line(
+        bedline,
+        gene,
+        "transcript",
+        bedline.tx_start,
+        bedline.tx_end,
+        3,
+        None,
+        args.exon_numbering,
+        &mut result,
+    );
+
+    for column in &args.passthrough {
+        if let Some(value) = column.value(bedline) {
+            result[0].attrs.push(column.key(), value);
+        }
+    }
+
+    for (i, frame) in frames.iter().enumerate() {
+        build_gtf_line(
+            bedline,
+            gene,
+            "exon",
+            bedline.exon_start[i],
+            bedline.exon_end[i],
+            3,
+            Some(i),
+            args.exon_numbering,
+            &mut result,
+        );
+        if args.exon_frames {
+            let exon = result.last_mut().unwrap();
+            exon.attrs.push("exon_frame", frame.to_string());
+        }
+        if cds_start < cds_end && !args.exon_only {
+            write_features(
+                i,
+                bedline,
+                gene,
+                cds_start,
+                cds_end,
+                *frame as u32,
+                args.exon_numbering,
+                &mut result,
+            );
+            if args.utrs {
+                write_utrs(i, bedline, gene, &mut result);
+            }
+        }
+    }
+
+    if args.exon_only {
+        if args.unique_exon_ids {
+            shared_exon_ids(&mut result);
+        }
+        return Ok(result);
+    }
+
+    if args.introns {
+        write_introns(bedline, gene, args.exon_numbering, &mut result);
+    }
+
+    let mut tags = Attributes::new();
+    if bedline.cds_start < bedline.cds_end {
+        let (start, stop) = if bedline.strand != "-" {
+            (&fcodon, &lcodon)
+        } else {
+            (&lcodon, &fcodon)
+        };
+        if !codon_complete(start) {
+            tags.push("tag", "cds_start_NF");
+        }
+        if !codon_complete(stop) {
+            tags.push("tag", "cds_end_NF");
+        }
+        if args.tag_cds_incomplete && !cds_length(bedline).is_multiple_of(3) {
+            tags.push("tag", "cds_incomplete");
+        }
+        if args.flag_nmd && nmd_candidate(bedline, args.nmd_distance) {
+            tags.push("tag", "NMD_candidate");
+        }
+    }
+
+    // with --no-codons the codons still bound the CDS and tag it above
+    if !args.no_codons {
+        if bedline.strand != "-" {
+            if codon_complete(&fcodon) {
+                write_codon(
+                    bedline,
+                    gene,
+                    "start_codon",
+                    fcodon,
+                    args.exon_numbering,
+                    &mut result,
+                );
+            }
+            if codon_complete(&lcodon) {
+                write_codon(
+                    bedline,
+                    gene,
+                    "stop_codon",
+                    lcodon,
+                    args.exon_numbering,
+                    &mut result,
+                );
+            }
+        } else {
+            if codon_complete(&lcodon) {
+                write_codon(
+                    bedline,
+                    gene,
+                    "start_codon",
+                    lcodon,
+                    args.exon_numbering,
+                    &mut result,
+                );
+            }
+            if codon_complete(&fcodon) {
+                write_codon(
+                    bedline,
+                    gene,
+                    "stop_codon",
+                    fcodon,
+                    args.exon_numbering,
+                    &mut result,
+                );
+            }
+        }
+    }
+
+    if !tags.is_empty() {
+        result.iter_mut().for_each(|line| line.attrs.extend(&tags));
+    }
+
+    let code = genetic_code(&args.genetic_code, &bedline.chrom);
+    if let Some(check) = genome.and_then(|g| check_codons(bedline, g, code, args.alt_starts)) {
+        let transcript = &mut result[0].attrs;
+        if check.start.as_bytes() != START_CODONS[0] {
+            transcript.push("tag", "non_ATG_start");
+        }
+        if !check.start_ok {
+            warn!(
+                "{} has an invalid start codon ({})",
+                bedline.name, check.start
+            );
+            transcript.push("tag", "invalid_start_codon");
+            warnings.push(
+                &bedline.name,
+                "invalid_start_codon",
+                "tagged invalid_start_codon",
+            );
+        }
+        if !check.stop_ok {
+            warn!(
+                "{} has an invalid stop codon ({})",
+                bedline.name, check.stop
+            );
+            transcript.push("tag", "invalid_stop_codon");
+            warnings.push(
+                &bedline.name,
+                "invalid_stop_codon",
+                "tagged invalid_stop_codon",
+            );
+        }
+        if let Some(codon) = check.internal_stop {
+            warn!(
+                "{} has an in-frame stop codon at codon {}",
+                bedline.name, codon
+            );
+            transcript.push("tag", "internal_stop");
+            warnings.push(
+                &bedline.name,
+                "internal_stop",
+                format!("tagged internal_stop (codon {})", codon),
+            );
+        }
+    }
+
+    if args.unique_exon_ids {
+        shared_exon_ids(&mut result);
+    }
+
+    Ok(result)
+}
+
+/// One conversion for [`convert_many`], configured exactly like a
+/// `bed2gtf` command line.
+#[derive(Debug)]
+pub struct ConversionJob {
+    args: Cli,
+}
+
+impl ConversionJob {
+    /// Builds a job from command-line arguments, without the program
+    /// name, e.g. `["-b", "a.bed", "-i", "iso.txt", "-o", "a.gtf"]`.
+    pub fn new<I, T>(args: I) -> Result<ConversionJob, Box<dyn Error>>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        let argv = std::iter::once("bed2gtf".into())
+            .chain(args.into_iter().map(Into::into))
+            .collect::<Vec<std::ffi::OsString>>();
+        let command_line = argv
+            .iter()
+            .map(|a| a.to_string_lossy().to_string())
+            .collect();
+        let mut args = Cli::try_parse_from(argv)?;
+        if args.command.is_some() {
+            return Err("conversion jobs do not take subcommands".into());
+        }
+        args.check()?;
+        args.resolve()?;
+        args.command_line = command_line;
+        Ok(ConversionJob { args })
+    }
+
+    /// Job with default options; without an isoforms file no gene
+    /// lines are written.
+    pub fn simple<P: AsRef<Path>>(
+        bed: P,
+        isoforms: Option<P>,
+        output: P,
+    ) -> Result<ConversionJob, Box<dyn Error>> {
+        let mut argv: Vec<PathBuf> = vec!["-b".into(), bed.as_ref().into()];
+        match isoforms {
+            Some(isoforms) => argv.extend(["-i".into(), isoforms.as_ref().into()]),
+            None => argv.push("--no-gene".into()),
+        }
+        argv.extend(["-o".into(), output.as_ref().into()]);
+        ConversionJob::new(argv)
+    }
+
+    pub fn args(&self) -> &Cli {
+        &self.args
+    }
+
+    /// Sends the job's progress to `hook` (see [`ProgressHook`]).
+    pub fn with_progress(mut self, hook: ProgressHook) -> ConversionJob {
+        self.args.progress = hook;
+        self
+    }
+
+    /// Lets `token` stop the job (see [`CancelToken`]).
+    pub fn with_cancel_token(mut self, token: CancelToken) -> ConversionJob {
+        self.args.cancel = token;
+        self
+    }
+
+    /// Runs the job, sending its features to `sink` (see
+    /// [`convert_into`]).
+    pub fn run_into(&self, sink: &mut dyn AnnotationSink) -> Result<(), Bed2GtfError> {
+        convert_into(&self.args, sink)
+    }
+}
+
+/// Runs many conversions over the current rayon pool (the global one, or
+/// the one installed by the caller). Each job is scheduled as its own
+/// task and its parallel stages share the same workers, so threads are
+/// never oversubscribed. Results are in job order; a failing job does
+/// not stop the others.
+pub fn convert_many(jobs: &[ConversionJob]) -> Vec<Result<(), Bed2GtfError>> {
+    jobs.par_iter()
+        .with_max_len(1)
+        .map(|job| {
+            convert(&job.args).map_err(|e| Bed2GtfError::Input {
+                path: job.args.bed().clone(),
+                source: Box::new(e),
+            })
+        })
+        .collect()
+}
+
+/// Options of [`convert_str`]: `bed2gtf` flags without the input and
+/// output paths, e.g. `["--no-gene", "--output-format", "gff3"]`.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    flags: Vec<std::ffi::OsString>,
+}
+
+impl Options {
+    pub fn new<I, T>(flags: I) -> Options
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString>,
+    {
+        Options {
+            flags: flags.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+/// Converts the BED text `bed`, with the isoforms table `isoforms`, and
+/// returns the annotation, header included. Nothing is read from or
+/// written to disk but the side outputs `opts` asks for. Without
+/// `isoforms`, `opts` must say where genes come from (`--no-gene`,
+/// `--infer-genes`, ...).
+pub fn convert_str(
+    bed: &str,
+    isoforms: Option<&str>,
+    opts: &Options,
+) -> Result<String, Bed2GtfError> {
+    let mut argv: Vec<std::ffi::OsString> =
+        vec!["bed2gtf".into(), "-b".into(), "memory.bed".into()];
+    if isoforms.is_some() {
+        argv.extend(["-i".into(), "memory.isoforms".into()]);
+    }
+    argv.extend(["-o".into(), "memory.gtf".into()]);
+    argv.extend(opts.flags.iter().cloned());
+    let mut args = Cli::try_parse_from(argv).map_err(|e| e.to_string())?;
+    if args.command.is_some() {
+        return Err("conversion options do not take subcommands".into());
+    }
+    args.inline = Some(InlineInput {
+        bed: bed.to_string(),
+        isoforms: isoforms.map(String::from),
+    });
+    args.check()?;
+    args.resolve()?;
+
+    let mut out = Vec::new();
+    header(&mut out, &args, args.format());
+    convert_into(
+        &args,
+        text_sink(&mut out, &args, args.format(), None).as_mut(),
+    )?;
+    String::from_utf8(out).map_err(|e| e.to_string().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::ProgressEvent;
+
+    #[test]
+    fn converts_many_on_one_pool() {
+        let dir = std::env::temp_dir().join(format!("bed2gtf-many-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let bed = dir.join("a.bed");
+        std::fs::write(
+            &bed,
+            "chr1\t1000\t5000\tTX1\t0\t+\t1200\t4500\t0\t3\t500,300,600,\t0,1500,3400,\n",
+        )
+        .unwrap();
+
+        let mut jobs = (0..3)
+            .map(|i| ConversionJob::simple(&bed, None, &dir.join(format!("{}.gtf", i))).unwrap())
+            .collect::<Vec<_>>();
+        let isoforms = dir.join("iso.txt");
+        std::fs::write(&isoforms, "G2\tTX2\n").unwrap();
+        jobs.push(ConversionJob::simple(&bed, Some(&isoforms), &dir.join("bad.gtf")).unwrap());
+
+        let pool = crate::par::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let results = pool.install(|| convert_many(&jobs));
+
+        assert!(results[..3].iter().all(|r| r.is_ok()));
+        let error = results[3].as_ref().unwrap_err();
+        assert!(matches!(error, Bed2GtfError::Input { .. }));
+        assert!(error.to_string().contains("TX1"));
+        let gtf = std::fs::read_to_string(dir.join("2.gtf")).unwrap();
+        assert_eq!(gtf.lines().filter(|l| l.contains("\texon\t")).count(), 3);
+
+        let mut features = Vec::new();
+        jobs[2].run_into(&mut features).unwrap();
+        assert_eq!(
+            features.len(),
+            gtf.lines().filter(|l| !l.starts_with('#')).count()
+        );
+
+        let token = CancelToken::new();
+        let cancelled = dir.join("cancelled.gtf");
+        let job = ConversionJob::simple(&bed, None, &cancelled)
+            .unwrap()
+            .with_cancel_token(token.clone());
+        token.cancel();
+        assert!(matches!(convert(job.args()), Err(Bed2GtfError::Cancelled)));
+        assert!(!cancelled.exists());
+
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = events.clone();
+        let job = ConversionJob::simple(&bed, None, &dir.join("progress.gtf"))
+            .unwrap()
+            .with_progress(ProgressHook::new(move |event| {
+                seen.lock().unwrap().push(match event {
+                    ProgressEvent::Stage(stage) => stage.name.to_string(),
+                    ProgressEvent::Records { done, total } => format!("{}/{:?}", done, total),
+                })
+            }));
+        convert(job.args()).unwrap();
+        let events = events.lock().unwrap();
+        assert_eq!(events.first().unwrap(), "read");
+        assert!(events.contains(&"1/Some(1)".to_string()));
+        assert_eq!(events.last().unwrap(), "write");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cds_stops_short_of_split_codons() {
+        let opts = Options::new(["--no-summary", "--no-gene"]);
+        let rows = |bed: &str| {
+            let gtf = convert_str(bed, None, &opts).unwrap();
+            let span = |feature: &str| {
+                gtf.lines()
+                    .filter(|l| l.split('\t').nth(2) == Some(feature))
+                    .map(|l| {
+                        let cols = l.split('\t').collect::<Vec<_>>();
+                        (
+                            cols[3].parse::<u32>().unwrap(),
+                            cols[4].parse::<u32>().unwrap(),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            };
+            (span("CDS"), span("stop_codon"))
+        };
+
+        // 2 and 1 stop codon bases in the terminal exon
+        let (cds, stop) = rows("chr1\t100\t400\tT\t0\t+\t103\t302\t0\t2\t100,100,\t0,200,\n");
+        assert_eq!(
+            (cds, stop),
+            (vec![(104, 199)], vec![(200, 200), (301, 302)])
+        );
+        let (cds, stop) = rows("chr1\t100\t400\tT\t0\t+\t102\t301\t0\t2\t100,100,\t0,200,\n");
+        assert_eq!(
+            (cds, stop),
+            (vec![(103, 198)], vec![(199, 200), (301, 301)])
+        );
+        let (cds, stop) = rows("chr1\t100\t400\tT\t0\t-\t199\t398\t0\t2\t100,100,\t0,200,\n");
+        assert_eq!(
+            (cds, stop),
+            (vec![(303, 398)], vec![(200, 200), (301, 302)])
+        );
+    }
+
+    #[test]
+    fn converts_strings_like_files() {
+        let dir = std::env::temp_dir().join(format!("bed2gtf-str-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let bed = "chr1\t1000\t5000\tTX1\t0\t+\t1200\t4500\t0\t3\t500,300,600,\t0,1500,3400,\n\
+                   chr2\t100\t900\tTX3\t0\t-\t200\t800\t0\t2\t200,300,\t0,500,\n";
+        let isoforms = "G1\tTX1\nG2\tTX3\n";
+        let (bed_path, isoforms_path) = (dir.join("a.bed"), dir.join("iso.txt"));
+        std::fs::write(&bed_path, bed).unwrap();
+        std::fs::write(&isoforms_path, isoforms).unwrap();
+
+        let output = dir.join("a.gtf");
+        let job = ConversionJob::new([
+            "-b".as_ref(),
+            bed_path.as_os_str(),
+            "-i".as_ref(),
+            isoforms_path.as_os_str(),
+            "-o".as_ref(),
+            output.as_os_str(),
+            "--no-summary".as_ref(),
+        ])
+        .unwrap();
+        convert(job.args()).unwrap();
+        let opts = Options::new(["--no-summary"]);
+        assert_eq!(
+            convert_str(bed, Some(isoforms), &opts).unwrap(),
+            std::fs::read_to_string(&output).unwrap()
+        );
+        for extra in ["--two-pass", "--assume-sorted"] {
+            let opts = Options::new(["--no-summary", extra]);
+            assert_eq!(
+                convert_str(bed, Some(isoforms), &opts).unwrap(),
+                std::fs::read_to_string(&output).unwrap()
+            );
+        }
+
+        let gff3 = Options::new(["--no-gene", "--no-summary", "--output-format", "gff3"]);
+        let converted = convert_str(bed, None, &gff3).unwrap();
+        assert!(converted.starts_with("##gff-version 3\n"));
+        assert!(!converted.contains("\tgene\t"));
+        assert!(convert_str(bed, None, &Options::default()).is_err());
+
+        // --group-by stands for --infer-genes or --auto-gene, so it
+        // conflicts with an isoforms file however the Cli is built
+        let overlap = Options::new(["--group-by", "overlap"]);
+        assert!(convert_str(bed, Some(isoforms), &overlap).is_err());
+        let mut args = ConversionJob::new([
+            "-b".as_ref(),
+            bed_path.as_os_str(),
+            "-i".as_ref(),
+            isoforms_path.as_os_str(),
+            "-o".as_ref(),
+            output.as_os_str(),
+        ])
+        .unwrap()
+        .args;
+        args.group_by = Some(crate::genes::GroupBy::Overlap);
+        assert!(args.check().is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stops_past_the_error_budget() {
+        let bed = "chr1\t1000\t5000\tTX1\t0\t+\t1200\t4500\t0\t3\t500,300,600,\t0,1500,3400,\n\
+                   chr1\t3000\tbroken\n\
+                   chr2\t900\t100\tTX3\t0\t-\t200\t800\t0\t2\t200,300,\t0,500,\n";
+        let run = |extra: &[&str]| {
+            let mut flags = vec!["--no-gene", "--no-summary", "--lenient", "--fix=skip"];
+            flags.extend(extra);
+            convert_str(bed, None, &Options::new(flags))
+        };
+        for extra in [&[][..], &["--two-pass"], &["--assume-sorted"]] {
+            let over = |flags: [&'static str; 2]| [extra, &flags[..]].concat();
+            let error = run(&over(["--max-errors", "1"])).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "2 of 3 BED records skipped or repaired, more than --max-errors 1 allows"
+            );
+            assert!(run(&over(["--max-errors", "2"]))
+                .unwrap()
+                .contains("\"TX1\""));
+            assert!(run(&over(["--max-error-rate", "0.5"])).is_err());
+            assert!(run(&over(["--max-error-rate", "0.7"])).is_ok());
+        }
+    }
+
+    #[test]
+    fn stitches_fragments_like_other_input() {
+        let bed = "chr1\t500\t600\tr1\t60\t+\n\n\
+                   chr1\t100\tx\tr1\t60\t+\n\
+                   chr1\t100\t200\tr1\t60\t+\n";
+        let run = |extra: &[&str]| {
+            let mut flags = vec!["--no-gene", "--no-summary", "--stitch-by-name"];
+            flags.extend(extra);
+            convert_str(bed, None, &Options::new(flags))
+        };
+        let error = run(&[]).unwrap_err().to_string();
+        assert!(error.contains("line 3: column 3"), "{}", error);
+        let converted = run(&["--lenient"]).unwrap();
+        assert_eq!(converted.matches("\texon\t").count(), 2);
+        assert!(converted.contains("chr1\tbed2gtf\ttranscript\t101\t600\t"));
+    }
+
+    #[test]
+    fn two_pass_matches_one_pass() {
+        let dir = std::env::temp_dir().join(format!("bed2gtf-two-pass-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (bed, isoforms) = (dir.join("a.bed"), dir.join("iso.txt"));
+        std::fs::write(
+            &bed,
+            "chr1\t1000\t5000\tTX1\t0\t+\t1200\t4500\t0\t3\t500,300,600,\t0,1500,3400,\n\
+             chr1\t3000\t8000\tTX2\t0\t+\t3000\t3000\t0\t1\t5000,\t0,\n\
+             chr2\t100\t900\tTX3\t0\t-\t200\t800\t0\t2\t200,300,\t0,500,\n",
+        )
+        .unwrap();
+        std::fs::write(&isoforms, "G1\tTX1\nG1\tTX2\nG2\tTX3\n").unwrap();
+
+        let run = |name: &str, extra: &[&str]| {
+            let output = dir.join(name);
+            let mut argv = vec![
+                "-b",
+                bed.to_str().unwrap(),
+                "-i",
+                isoforms.to_str().unwrap(),
+            ];
+            argv.extend(["-o", output.to_str().unwrap(), "--no-summary"]);
+            argv.extend(extra);
+            convert(&ConversionJob::new(argv).unwrap().args).map(|_| {
+                let mut lines = std::fs::read_to_string(&output)
+                    .unwrap()
+                    .lines()
+                    .filter(|l| !l.starts_with('#'))
+                    .map(String::from)
+                    .collect::<Vec<_>>();
+                lines.sort();
+                lines
+            })
+        };
+        let one = run("one.gtf", &[]).unwrap();
+        // the same genes, transcripts and exons, without the CDS tags
+        let kept = |l: &String| !l.contains("CDS\t") && !l.contains("_codon\t");
+        assert_eq!(
+            run("exon-only.gtf", &["--exon-only"]).unwrap(),
+            one.iter()
+                .filter(|l| kept(l))
+                .map(|l| l
+                    .replace(" tag \"cds_start_NF\";", "")
+                    .replace(" tag \"cds_end_NF\";", ""))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            run("gene-level.gtf", &["--gene-level"]).unwrap(),
+            one.iter()
+                .filter(|l| l.contains("\tgene\t"))
+                .cloned()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(run("two.gtf", &["--two-pass"]).unwrap(), one);
+        let mut without = one.clone();
+        without.retain(|l| !l.contains("_codon\t"));
+        assert!(without.len() < one.len());
+        assert_eq!(run("no-codons.gtf", &["--no-codons"]).unwrap(), without);
+
+        // chromosomes must each come in one block
+        let mut contents = std::fs::read_to_string(&bed).unwrap();
+        contents.push_str("chr1\t9000\t9500\tTX4\t0\t+\t9000\t9000\t0\t1\t500,\t0,\n");
+        std::fs::write(&bed, contents).unwrap();
+        let error = run("late.gtf", &["--two-pass"]).unwrap_err();
+        assert!(error.to_string().contains("not grouped by chromosome"));
+        // unless --max-mem sorts them first, here in several runs
+        std::fs::write(&isoforms, "G1\tTX1\nG1\tTX2\nG2\tTX3\nG1\tTX4\n").unwrap();
+        assert_eq!(
+            run("sorted.gtf", &["--two-pass", "--max-mem", "200"]).unwrap(),
+            run("late-one.gtf", &[]).unwrap()
+        );
+
+        // rejected lines keep their input line number through the sort
+        std::fs::write(
+            &bed,
+            "chr2\t100\t900\tTX3\t0\t-\t200\t800\t0\t2\t200,300,\t0,500,\n\
+             chr1\t3000\tbroken\n\
+             chr1\t1000\t5000\tTX1\t0\t+\t1200\t4500\t0\t3\t500,300,600,\t0,1500,3400,\n",
+        )
+        .unwrap();
+        for extra in [&[][..], &["--two-pass", "--max-mem", "200"]] {
+            let error = run("broken.gtf", extra).unwrap_err().to_string();
+            assert!(error.contains("line 2:"), "{}", error);
+        }
+        assert_eq!(
+            run(
+                "lenient.gtf",
+                &["--two-pass", "--max-mem", "200", "--lenient"]
+            )
+            .unwrap(),
+            run("lenient-one.gtf", &["--lenient"]).unwrap()
+        );
+        // --head picks records in input order, which sorting loses
+        let output = dir.join("head.gtf");
+        let error = ConversionJob::new([
+            "-b",
+            bed.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "--no-gene",
+            "--two-pass",
+            "--max-mem",
+            "1M",
+            "--head",
+            "1",
+        ])
+        .unwrap_err();
+        assert!(error.to_string().contains("--head with --max-mem"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reports_every_failed_transcript() {
+        let dir = std::env::temp_dir().join(format!("bed2gtf-failures-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (bed, isoforms) = (dir.join("a.bed"), dir.join("iso.txt"));
+        std::fs::write(
+            &bed,
+            "chr1\t1000\t5000\tTX1\t0\t+\t1200\t4500\t0\t3\t500,300,600,\t0,1500,3400,\n\
+             chr1\t3000\t8000\tTX2\t0\t+\t3000\t3000\t0\t1\t5000,\t0,\n\
+             chr2\t100\t900\tTX3\t0\t-\t200\t800\t0\t2\t200,300,\t0,500,\n",
+        )
+        .unwrap();
+        std::fs::write(&isoforms, "G1\tTX1\n").unwrap();
+        let output = dir.join("out.gtf");
+        let run = |extra: &[&str]| {
+            let mut argv = vec![
+                "-b",
+                bed.to_str().unwrap(),
+                "-i",
+                isoforms.to_str().unwrap(),
+            ];
+            argv.extend(["-o", output.to_str().unwrap(), "--no-summary"]);
+            argv.extend(extra);
+            convert(&ConversionJob::new(argv).unwrap().args)
+        };
+
+        for extra in [&[][..], &["--two-pass"], &["--assume-sorted"]] {
+            let Err(Bed2GtfError::Conversion(failures)) = run(extra) else {
+                panic!("unlisted transcripts converted with {:?}", extra);
+            };
+            let ids = failures
+                .iter()
+                .map(|(id, _)| id.as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(ids, ["TX2", "TX3"]);
+            assert!(!output.exists());
+
+            let mut lenient = extra.to_vec();
+            lenient.push("--lenient");
+            run(&lenient).unwrap();
+            let gtf = std::fs::read_to_string(&output).unwrap();
+            assert!(gtf.contains("\"TX1\"") && !gtf.contains("\"TX2\""));
+            std::fs::remove_file(&output).unwrap();
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,137 @@
+use crate::bed::BedRecord;
+use crate::utils::create_output;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// CDS and UTR make-up of one transcript, a row of `--cds-report`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CdsCoverage {
+    pub transcript: String,
+    pub gene: String,
+    pub chrom: String,
+    pub strand: String,
+    pub exons: usize,
+    pub coding_exons: usize,
+    pub exonic_length: u32,
+    pub cds_length: u32,
+    pub utr5_length: u32,
+    pub utr3_length: u32,
+    /// Whether the CDS begins with a whole start codon (no cds_start_NF).
+    pub start_complete: bool,
+    /// Whether the CDS ends with a whole stop codon (no cds_end_NF).
+    pub stop_complete: bool,
+}
+
+impl CdsCoverage {
+    /// Measures `record`; non-coding transcripts have no CDS or UTR.
+    pub fn new(record: &BedRecord, gene: &str) -> CdsCoverage {
+        let (mut left, mut cds, mut right, mut coding_exons) = (0, 0, 0, 0);
+        let coding = record.cds_start < record.cds_end;
+        if coding {
+            for (start, end) in record.exon_blocks() {
+                let inside = end
+                    .min(record.cds_end)
+                    .saturating_sub(start.max(record.cds_start));
+                left += end.min(record.cds_start).saturating_sub(start);
+                right += end.saturating_sub(start.max(record.cds_end));
+                cds += inside;
+                coding_exons += usize::from(inside > 0);
+            }
+        }
+        let (utr5, utr3) = if record.strand == "-" {
+            (right, left)
+        } else {
+            (left, right)
+        };
+        CdsCoverage {
+            transcript: record.name.clone(),
+            gene: gene.to_string(),
+            chrom: record.chrom.clone(),
+            strand: record.strand.clone(),
+            exons: record.exon_start.len(),
+            coding_exons,
+            exonic_length: record
+                .exon_blocks()
+                .iter()
+                .map(|(start, end)| end.saturating_sub(*start))
+                .sum(),
+            cds_length: cds,
+            utr5_length: utr5,
+            utr3_length: utr3,
+            start_complete: record.start_codon().is_complete(),
+            stop_complete: record.stop_codon().is_complete(),
+        }
+    }
+}
+
+/// Writes the `--cds-report` TSV: one row per transcript of `records`,
+/// with the gene from `isoforms` (the transcript itself when unlisted).
+/// `cds_frame_ok` tells whether the CDS length is a multiple of 3.
+pub fn write_cds_report<P: AsRef<Path>>(
+    path: P,
+    records: &[BedRecord],
+    isoforms: &HashMap<String, String>,
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(create_output(path)?);
+    writeln!(
+        writer,
+        "transcript_id\tgene_id\tchrom\tstrand\texons\tcoding_exons\texonic_length\t\
+         cds_length\tutr5_length\tutr3_length\tstart_complete\tstop_complete\tcds_frame_ok"
+    )?;
+    for record in records {
+        let gene = isoforms.get(&record.name).unwrap_or(&record.name);
+        let row = CdsCoverage::new(record, gene);
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            row.transcript,
+            row.gene,
+            row.chrom,
+            row.strand,
+            row.exons,
+            row.coding_exons,
+            row.exonic_length,
+            row.cds_length,
+            row.utr5_length,
+            row.utr3_length,
+            row.start_complete,
+            row.stop_complete,
+            row.cds_length.is_multiple_of(3)
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_cds_and_utrs() {
+        let line = "chr1\t0\t900\tT1\t0\t-\t150\t850\t0\t3\t100,100,100,\t0,400,800,";
+        let record = BedRecord::parse(line).unwrap();
+        let row = CdsCoverage::new(&record, "G1");
+        assert_eq!(
+            (row.exons, row.coding_exons, row.exonic_length),
+            (3, 2, 300)
+        );
+        // minus strand: the 5' UTR lies after thickEnd
+        assert_eq!(
+            (row.cds_length, row.utr5_length, row.utr3_length),
+            (150, 50, 100)
+        );
+        assert!(row.start_complete && row.stop_complete);
+
+        let noncoding = BedRecord::parse(&line.replace("\t150\t850\t", "\t0\t0\t")).unwrap();
+        let row = CdsCoverage::new(&noncoding, "G1");
+        assert_eq!(
+            (row.cds_length, row.utr5_length, row.utr3_length),
+            (0, 0, 0)
+        );
+        assert!(!row.start_complete);
+    }
+}
@@ -0,0 +1,71 @@
+use crate::bed::BedRecord;
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DedupMode {
+    /// Same chromosome, strand, exons and CDS.
+    Exact,
+    /// Same chromosome, strand and intron chain. Single-exon
+    /// transcripts are only collapsed when their exon is identical.
+    IntronChain,
+}
+
+type Key = (String, String, Vec<u32>, Vec<u32>, Option<(u32, u32)>);
+
+fn key(record: &BedRecord, mode: DedupMode) -> Key {
+    let (chrom, strand) = (record.chrom.clone(), record.strand.clone());
+    match mode {
+        DedupMode::IntronChain if record.exon_start.len() > 1 => {
+            let n = record.exon_start.len();
+            (
+                chrom,
+                strand,
+                record.exon_end[..n - 1].to_vec(),
+                record.exon_start[1..].to_vec(),
+                None,
+            )
+        }
+        DedupMode::IntronChain => (
+            chrom,
+            strand,
+            record.exon_start.clone(),
+            record.exon_end.clone(),
+            None,
+        ),
+        DedupMode::Exact => (
+            chrom,
+            strand,
+            record.exon_start.clone(),
+            record.exon_end.clone(),
+            Some((record.cds_start, record.cds_end)),
+        ),
+    }
+}
+
+/// Collapses duplicated transcripts, keeping the first occurrence in
+/// input order. Returns the kept records and, for every representative
+/// that absorbed others, the names it was merged from.
+pub fn dedup(
+    records: Vec<BedRecord>,
+    mode: DedupMode,
+) -> (Vec<BedRecord>, HashMap<String, Vec<String>>) {
+    let mut seen: HashMap<Key, usize> = HashMap::new();
+    let mut kept: Vec<BedRecord> = Vec::with_capacity(records.len());
+    let mut merged: HashMap<String, Vec<String>> = HashMap::new();
+
+    for record in records {
+        match seen.get(&key(&record, mode)) {
+            Some(&i) => merged
+                .entry(kept[i].name.clone())
+                .or_default()
+                .push(record.name),
+            None => {
+                seen.insert(key(&record, mode), kept.len());
+                kept.push(record);
+            }
+        }
+    }
+
+    (kept, merged)
+}
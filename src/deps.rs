@@ -0,0 +1,338 @@
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// UCSC utilities bed2gtf can run alongside its own conversion.
+pub const UCSC_TOOLS: [&str; 2] = ["bedToGenePred", "genePredToGtf"];
+
+const UCSC_URL: &str = "https://hgdownload.soe.ucsc.edu/admin/exe";
+/// Checksums of the binaries in the cache, written on first download.
+const SUMS_FILE: &str = "SHA256SUMS";
+/// Checksums UCSC publishes next to its binaries.
+const PUBLISHED_SUMS: &str = "md5sum.txt";
+
+/// How UCSC binaries are fetched.
+#[derive(Debug, Clone, Default)]
+pub struct FetchOptions {
+    /// UCSC release to pin (e.g. 469), taken from its versioned
+    /// download directory and cached apart from the latest build.
+    pub release: Option<u32>,
+    /// Never download: a tool missing from the cache is an error telling
+    /// how to provide it. Also set by a non-empty `$BED2GTF_OFFLINE`.
+    pub offline: bool,
+}
+
+impl FetchOptions {
+    pub fn new(release: Option<u32>, offline: bool) -> FetchOptions {
+        FetchOptions {
+            release,
+            offline: offline || env::var_os("BED2GTF_OFFLINE").is_some_and(|v| !v.is_empty()),
+        }
+    }
+
+    /// Cache directory of the binaries of this release.
+    pub fn dir(&self, cache: &Path) -> PathBuf {
+        match self.release {
+            Some(release) => cache.join(format!("v{}", release)),
+            None => cache.to_path_buf(),
+        }
+    }
+}
+
+/// Cache directory for downloaded binaries: `dir` if given, else
+/// `$BED2GTF_CACHE`, `$XDG_CACHE_HOME/bed2gtf` or `~/.cache/bed2gtf`.
+pub fn cache_dir(dir: Option<&Path>) -> PathBuf {
+    if let Some(dir) = dir {
+        return dir.to_path_buf();
+    }
+    if let Some(dir) = env::var_os("BED2GTF_CACHE") {
+        return PathBuf::from(dir);
+    }
+    match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) => PathBuf::from(dir).join("bed2gtf"),
+        None => PathBuf::from(env::var_os("HOME").unwrap_or_else(|| ".".into()))
+            .join(".cache")
+            .join("bed2gtf"),
+    }
+}
+
+/// Download directory of the UCSC binaries for this platform, of
+/// `release` if pinned. The base URL can be overridden with
+/// `$BED2GTF_UCSC_URL` (e.g. a local mirror).
+pub fn ucsc_url(release: Option<u32>) -> Result<String, String> {
+    let platform = match (env::consts::OS, env::consts::ARCH) {
+        ("linux", "x86_64") => "linux.x86_64",
+        ("macos", "x86_64") => "macOSX.x86_64",
+        ("macos", "aarch64") => "macOSX.arm64",
+        (os, arch) => return Err(format!("no UCSC binaries for {}/{}", os, arch)),
+    };
+    let base = env::var("BED2GTF_UCSC_URL").unwrap_or_else(|_| UCSC_URL.to_string());
+    let release = release.map(|r| format!(".v{}", r)).unwrap_or_default();
+    Ok(format!(
+        "{}/{}{}",
+        base.trim_end_matches('/'),
+        platform,
+        release
+    ))
+}
+
+/// Looks `tool` up in `$PATH`, then in the cache.
+pub fn find_tool(tool: &str, cache: &Path) -> Option<PathBuf> {
+    env::var_os("PATH")
+        .into_iter()
+        .flat_map(|paths| env::split_paths(&paths).collect::<Vec<_>>())
+        .chain(std::iter::once(cache.to_path_buf()))
+        .map(|dir| dir.join(tool))
+        .find(|path| path.is_file())
+}
+
+/// Returns the cached copy of a UCSC `tool`, downloading it first if
+/// needed. The SHA256 digest is checked against `sha256` when given, and
+/// otherwise against the one recorded when the binary was first
+/// downloaded, so a corrupted or replaced binary is never run. Downloads
+/// are also checked against the MD5 sums UCSC publishes.
+pub fn fetch_tool(
+    tool: &str,
+    cache: &Path,
+    sha256: Option<&str>,
+    options: &FetchOptions,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let cache = &options.dir(cache);
+    fs::create_dir_all(cache)?;
+    let path = cache.join(tool);
+    let mut sums = read_sums(cache)?;
+    let expected = sha256
+        .map(|s| s.to_ascii_lowercase())
+        .or_else(|| sums.get(tool).cloned());
+
+    if path.is_file() {
+        let digest = sha256_file(&path)?;
+        match &expected {
+            Some(expected) if *expected != digest => {
+                log::warn!(
+                    "Cached {} does not match its checksum, downloading again",
+                    tool
+                );
+            }
+            _ => return Ok(path),
+        }
+    }
+
+    if options.offline {
+        let release = options
+            .release
+            .map(|r| format!(" --ucsc-release {}", r))
+            .unwrap_or_default();
+        return Err(format!(
+            "{} is not in {} and downloads are disabled (--offline or $BED2GTF_OFFLINE); \
+             run `bed2gtf fetch-deps{} --cache-dir DIR` on a machine with network access \
+             and copy DIR to this machine, then point --cache-dir or $BED2GTF_CACHE at it",
+            tool,
+            cache.display(),
+            release
+        )
+        .into());
+    }
+
+    let dir_url = ucsc_url(options.release)?;
+    let url = format!("{}/{}", dir_url, tool);
+    let tmp = cache.join(format!("{}.part", tool));
+    let digest = download(&url, &tmp).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })?;
+    let mismatch = match &expected {
+        Some(expected) if *expected != digest => Some(format!(
+            "checksum mismatch for {}: expected {}, got {}",
+            url, expected, digest
+        )),
+        _ => check_published(&dir_url, tool, &tmp)?,
+    };
+    if let Some(mismatch) = mismatch {
+        fs::remove_file(&tmp)?;
+        return Err(mismatch.into());
+    }
+
+    set_executable(&tmp)?;
+    fs::rename(&tmp, &path)?;
+    sums.insert(tool.to_string(), digest);
+    write_sums(cache, &sums)?;
+    log::info!("Installed {} into {}", tool, cache.display());
+    Ok(path)
+}
+
+/// Streams `url` into `dest`, logging progress every 10%, and returns
+/// the SHA256 digest of the downloaded bytes.
+fn download(url: &str, dest: &Path) -> Result<String, Box<dyn Error>> {
+    log::info!("Downloading {}", url);
+    let response = ureq::get(url).call().map_err(|e| match e {
+        ureq::Error::Status(code, _) => format!("HTTP {} for {}", code, url),
+        e => e.to_string(),
+    })?;
+    let total = response
+        .header("Content-Length")
+        .and_then(|n| n.parse::<u64>().ok());
+
+    let mut reader = response.into_reader();
+    let mut writer = BufWriter::new(File::create(dest)?);
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 16];
+    let (mut done, mut reported) = (0u64, 0u64);
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        writer.write_all(&buf[..n])?;
+        done += n as u64;
+        if let Some(total) = total.filter(|&t| t > 0) {
+            let pct = done * 100 / total;
+            if pct >= reported + 10 {
+                reported = pct - pct % 10;
+                log::info!("{}: {}% of {} bytes", url, reported, total);
+            }
+        }
+    }
+    writer.flush()?;
+
+    if let Some(total) = total {
+        if done != total {
+            return Err(format!("{}: got {} of {} bytes", url, done, total).into());
+        }
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+/// Compares the MD5 of the downloaded `file` with the sum UCSC lists for
+/// `tool` in `dir_url`; returns the mismatch, if any. Missing sums are
+/// only logged, as mirrors may not carry them.
+fn check_published(
+    dir_url: &str,
+    tool: &str,
+    file: &Path,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let url = format!("{}/{}", dir_url, PUBLISHED_SUMS);
+    let published = match ureq::get(&url).call() {
+        Ok(response) => parse_md5sums(&response.into_string()?),
+        Err(e) => {
+            log::warn!("No published checksums at {} ({}), skipping", url, e);
+            return Ok(None);
+        }
+    };
+    let Some(expected) = published.get(tool) else {
+        log::warn!("{} does not list {}, skipping", url, tool);
+        return Ok(None);
+    };
+    let mut hasher = Md5::new();
+    io::copy(&mut File::open(file)?, &mut hasher)?;
+    let digest = hex(&hasher.finalize());
+    if *expected != digest {
+        return Ok(Some(format!(
+            "{} does not match the checksum published in {}: expected {}, got {}",
+            tool, url, expected, digest
+        )));
+    }
+    log::info!("{} matches the checksum published in {}", tool, url);
+    Ok(None)
+}
+
+/// Reads `md5sum`-style lines; names keep only their last component.
+fn parse_md5sums(contents: &str) -> BTreeMap<String, String> {
+    contents
+        .lines()
+        .filter_map(|line| line.split_once(char::is_whitespace))
+        .map(|(digest, name)| {
+            let name = name.trim().trim_start_matches('*');
+            let name = name.rsplit('/').next().unwrap_or(name);
+            (name.to_string(), digest.to_ascii_lowercase())
+        })
+        .collect()
+}
+
+fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex(&hasher.finalize()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Reads `sha256sum`-style `<digest>  <name>` lines.
+fn read_sums(cache: &Path) -> io::Result<BTreeMap<String, String>> {
+    match fs::read_to_string(cache.join(SUMS_FILE)) {
+        Ok(contents) => Ok(contents
+            .lines()
+            .filter_map(|line| line.split_once(char::is_whitespace))
+            .map(|(digest, name)| (name.trim().to_string(), digest.to_string()))
+            .collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e),
+    }
+}
+
+fn write_sums(cache: &Path, sums: &BTreeMap<String, String>) -> io::Result<()> {
+    let contents = sums
+        .iter()
+        .map(|(name, digest)| format!("{}  {}\n", digest, name))
+        .collect::<String>();
+    fs::write(cache.join(SUMS_FILE), contents)
+}
+
+#[cfg(unix)]
+fn set_executable(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sums_roundtrip() {
+        let dir = env::temp_dir().join(format!("bed2gtf-sums-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut sums = BTreeMap::new();
+        sums.insert("genePredToGtf".to_string(), hex(&Sha256::digest(b"abc")));
+        write_sums(&dir, &sums).unwrap();
+        assert_eq!(read_sums(&dir).unwrap(), sums);
+        assert_eq!(
+            sums["genePredToGtf"],
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        let published = parse_md5sums(
+            "0cc175b9c0f1b6a831c399e269772661  ./bedToGenePred\n\
+             900150983CD24FB0D6963F7D28E17F72 *genePredToGtf\n",
+        );
+        assert_eq!(
+            published["bedToGenePred"],
+            "0cc175b9c0f1b6a831c399e269772661"
+        );
+        assert_eq!(published["genePredToGtf"], hex(&Md5::digest(b"abc")));
+
+        let options = FetchOptions {
+            release: Some(469),
+            offline: true,
+        };
+        assert_eq!(options.dir(&dir), dir.join("v469"));
+        let err = fetch_tool("bedToGenePred", &dir, None, &options).unwrap_err();
+        assert!(err.to_string().contains("fetch-deps --ucsc-release 469"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+use crate::cli::CliError;
+
+use std::error::Error;
+use std::io;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Errors of the bed2gtf library, each carrying enough context to be
+/// reported on its own.
+#[derive(Debug, Error)]
+pub enum Bed2GtfError {
+    /// A malformed record in an input file.
+    #[error("Error parsing {}: {message}", path.display())]
+    Parse { path: PathBuf, message: String },
+    /// The isoforms file does not describe the BED transcripts.
+    #[error("Isoforms: {0}")]
+    Isoform(String),
+    /// A failed read or write, with what was being done.
+    #[error("{context}: {source}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+    /// Options or inputs that cannot be converted as asked.
+    #[error("{0}")]
+    Validation(String),
+    /// Transcripts that could not be converted, each with its error.
+    #[error(
+        "{} transcripts could not be converted (--lenient skips them):{}",
+        .0.len(),
+        list_failures(.0)
+    )]
+    Conversion(Vec<(String, Bed2GtfError)>),
+    /// The run was stopped through its [`CancelToken`](crate::signals::CancelToken).
+    #[error("Conversion cancelled")]
+    Cancelled,
+    /// A failure of one input among several (see
+    /// [`convert_many`](crate::convert::convert_many)).
+    #[error("{}: {source}", path.display())]
+    Input {
+        path: PathBuf,
+        #[source]
+        source: Box<Bed2GtfError>,
+    },
+}
+
+fn list_failures(failures: &[(String, Bed2GtfError)]) -> String {
+    failures
+        .iter()
+        .map(|(id, error)| format!("\n  {}: {}", id, error))
+        .collect()
+}
+
+impl Bed2GtfError {
+    pub fn io<C: Into<String>>(context: C, source: io::Error) -> Bed2GtfError {
+        Bed2GtfError::Io {
+            context: context.into(),
+            source,
+        }
+    }
+}
+
+impl From<io::Error> for Bed2GtfError {
+    fn from(source: io::Error) -> Bed2GtfError {
+        Bed2GtfError::io("I/O error", source)
+    }
+}
+
+impl From<String> for Bed2GtfError {
+    fn from(message: String) -> Bed2GtfError {
+        Bed2GtfError::Validation(message)
+    }
+}
+
+impl From<&str> for Bed2GtfError {
+    fn from(message: &str) -> Bed2GtfError {
+        Bed2GtfError::Validation(message.to_string())
+    }
+}
+
+impl From<CliError> for Bed2GtfError {
+    fn from(error: CliError) -> Bed2GtfError {
+        match error {
+            CliError::InvalidInput(message) => Bed2GtfError::Validation(message),
+            CliError::IoError(source) => Bed2GtfError::io("Invalid input", source),
+        }
+    }
+}
+
+/// Keeps the variant of errors that already are [`Bed2GtfError`]s or
+/// I/O errors when they went through a `Box<dyn Error>`.
+impl From<Box<dyn Error>> for Bed2GtfError {
+    fn from(error: Box<dyn Error>) -> Bed2GtfError {
+        let error = match error.downcast::<Bed2GtfError>() {
+            Ok(error) => return *error,
+            Err(error) => error,
+        };
+        match error.downcast::<io::Error>() {
+            Ok(source) => Bed2GtfError::from(*source),
+            Err(error) => Bed2GtfError::Validation(error.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_sources_and_variants() {
+        let missing = io::Error::new(io::ErrorKind::NotFound, "no such file");
+        let error = Bed2GtfError::io("Error reading a.bed", missing);
+        assert_eq!(error.to_string(), "Error reading a.bed: no such file");
+        assert!(error.source().is_some());
+
+        let boxed: Box<dyn Error> = Box::new(Bed2GtfError::Isoform("T1 unlisted".to_string()));
+        assert!(matches!(
+            Bed2GtfError::from(boxed),
+            Bed2GtfError::Isoform(_)
+        ));
+        let boxed: Box<dyn Error> = Box::new(io::Error::other("disk full"));
+        assert!(matches!(Bed2GtfError::from(boxed), Bed2GtfError::Io { .. }));
+    }
+}
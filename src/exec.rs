@@ -0,0 +1,195 @@
+use crate::deps::UCSC_TOOLS;
+
+use std::ffi::OsStr;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a running command is polled for exit or timeout.
+const POLL: Duration = Duration::from_millis(20);
+
+/// Environment variables passed on to commands; everything else is
+/// dropped, and `LC_ALL=C` keeps their output locale-independent.
+const PASSED_ENV: [&str; 3] = ["PATH", "HOME", "TMPDIR"];
+
+/// Result of a finished command.
+#[derive(Debug)]
+pub struct ExecOutput {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: String,
+}
+
+/// Why a command did not complete successfully.
+#[derive(Debug)]
+pub enum ExecError {
+    /// The program is not one bed2gtf runs (see [`UCSC_TOOLS`]).
+    NotAllowed(String),
+    /// The program could not be started.
+    Spawn(String, io::Error),
+    /// Killed after running longer than the timeout.
+    Timeout(String, Duration),
+    /// Exited with a non-zero status; stderr is kept for diagnosis.
+    Failed(String, ExecOutput),
+    Io(io::Error),
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecError::NotAllowed(program) => {
+                write!(f, "refusing to run {}: not a known tool", program)
+            }
+            ExecError::Spawn(program, e) => write!(f, "cannot run {}: {}", program, e),
+            ExecError::Timeout(program, limit) => {
+                write!(f, "{} timed out after {:?}", program, limit)
+            }
+            ExecError::Failed(program, output) => {
+                write!(f, "{} failed ({})", program, output.status)?;
+                match output.stderr.trim() {
+                    "" => Ok(()),
+                    stderr => write!(f, ": {}", stderr),
+                }
+            }
+            ExecError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ExecError {}
+
+impl From<io::Error> for ExecError {
+    fn from(e: io::Error) -> Self {
+        ExecError::Io(e)
+    }
+}
+
+/// Runs `program`, which must be one of [`UCSC_TOOLS`], directly with
+/// `args` as given (no shell, so paths need no quoting) and with only
+/// PATH, HOME and TMPDIR of the environment. Each stderr line is
+/// forwarded to the logger as it is written, stdout is captured, and the
+/// program is killed after `timeout`.
+pub fn exec<S: AsRef<OsStr>>(
+    program: &Path,
+    args: &[S],
+    timeout: Option<Duration>,
+) -> Result<ExecOutput, ExecError> {
+    let name = program_name(program);
+    if !UCSC_TOOLS.contains(&name.as_str()) {
+        return Err(ExecError::NotAllowed(name));
+    }
+    run(program, args, timeout)
+}
+
+fn program_name(program: &Path) -> String {
+    program
+        .file_name()
+        .unwrap_or(program.as_os_str())
+        .to_string_lossy()
+        .to_string()
+}
+
+fn run<S: AsRef<OsStr>>(
+    program: &Path,
+    args: &[S],
+    timeout: Option<Duration>,
+) -> Result<ExecOutput, ExecError> {
+    let name = program_name(program);
+    log::debug!(
+        "Running {} {}",
+        program.display(),
+        args.iter()
+            .map(|a| a.as_ref().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+
+    let mut command = Command::new(program);
+    command.env_clear().env("LC_ALL", "C");
+    for key in PASSED_ENV {
+        if let Some(value) = std::env::var_os(key) {
+            command.env(key, value);
+        }
+    }
+    let mut child = command
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| ExecError::Spawn(name.clone(), e))?;
+
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let out_thread = thread::spawn(move || {
+        let mut buf = Vec::new();
+        stdout.read_to_end(&mut buf).map(|_| buf)
+    });
+    let tag = name.clone();
+    let err_thread = thread::spawn(move || {
+        let mut kept = String::new();
+        for line in BufReader::new(stderr).lines() {
+            let line = line?;
+            log::warn!("[{}] {}", tag, line);
+            kept.push_str(&line);
+            kept.push('\n');
+        }
+        Ok::<_, io::Error>(kept)
+    });
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if let Some(limit) = timeout.filter(|&t| started.elapsed() > t) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(ExecError::Timeout(name, limit));
+        }
+        thread::sleep(POLL);
+    };
+
+    let output = ExecOutput {
+        status,
+        stdout: out_thread.join().expect("stdout reader panicked")?,
+        stderr: err_thread.join().expect("stderr reader panicked")?,
+    };
+    if output.status.success() {
+        Ok(output)
+    } else {
+        Err(ExecError::Failed(name, output))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captures_output_status_and_timeout() {
+        let sh = Path::new("/bin/sh");
+        assert!(matches!(
+            exec(sh, &["-c", "true"], None),
+            Err(ExecError::NotAllowed(_))
+        ));
+
+        let ok = run(sh, &["-c", "echo out $LC_ALL; echo err >&2"], None).unwrap();
+        assert_eq!(ok.stdout, b"out C\n");
+        assert_eq!(ok.stderr, "err\n");
+
+        match run(sh, &["-c", "echo broken >&2; exit 3"], None) {
+            Err(ExecError::Failed(_, output)) => {
+                assert_eq!(output.status.code(), Some(3));
+                assert_eq!(output.stderr, "broken\n");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let slow = run(sh, &["-c", "sleep 5"], Some(Duration::from_millis(100)));
+        assert!(matches!(slow, Err(ExecError::Timeout(..))));
+    }
+}
@@ -0,0 +1,312 @@
+use crate::bed::BedRecord;
+use crate::genetic_code::{genetic_code, CodeAssignment, GeneticCode, STANDARD_CODE};
+
+use flate2::read::MultiGzDecoder;
+
+use crate::par::prelude::*;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const LINE_WIDTH: usize = 60;
+
+pub const START_CODONS: [&[u8; 3]; 1] = [b"ATG"];
+pub const ALT_START_CODONS: [&[u8; 3]; 3] = [b"CTG", b"GTG", b"TTG"];
+pub const STOP_CODONS: [&[u8; 3]; 3] = [b"TAA", b"TAG", b"TGA"];
+
+/// In-memory genome keyed by sequence name.
+#[derive(Debug, Default)]
+pub struct Genome {
+    seqs: HashMap<String, Vec<u8>>,
+}
+
+impl Genome {
+    /// Reads a (optionally gzipped/bgzipped) FASTA file.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Genome, Box<dyn Error>> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let reader: Box<dyn Read> = match path.extension().and_then(|s| s.to_str()) {
+            Some("gz") => Box::new(MultiGzDecoder::new(BufReader::new(file))),
+            _ => Box::new(file),
+        };
+
+        Genome::from_reader(BufReader::new(reader))
+    }
+
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<Genome, Box<dyn Error>> {
+        let mut seqs = HashMap::new();
+        let mut name: Option<String> = None;
+        let mut seq = Vec::new();
+
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim_end();
+            if let Some(header) = line.strip_prefix('>') {
+                if let Some(name) = name.take() {
+                    seqs.insert(name, std::mem::take(&mut seq));
+                }
+                let id = header.split_whitespace().next().unwrap_or_default();
+                name = Some(id.to_string());
+            } else if name.is_some() {
+                seq.extend(line.bytes().map(|b| b.to_ascii_uppercase()));
+            }
+        }
+        if let Some(name) = name {
+            seqs.insert(name, seq);
+        }
+
+        if seqs.is_empty() {
+            return Err("FASTA file has no sequences".into());
+        }
+
+        Ok(Genome { seqs })
+    }
+
+    pub fn len(&self) -> usize {
+        self.seqs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seqs.is_empty()
+    }
+
+    pub fn get(&self, chrom: &str) -> Option<&[u8]> {
+        self.seqs.get(chrom).map(|s| s.as_slice())
+    }
+
+    /// Concatenates the given 0-based half-open blocks (in genomic order)
+    /// and returns them 5'->3' for the given strand.
+    pub fn spliced(&self, chrom: &str, blocks: &[(u32, u32)], strand: &str) -> Option<Vec<u8>> {
+        let seq = self.get(chrom)?;
+        let mut out = Vec::new();
+        for &(start, end) in blocks {
+            out.extend_from_slice(seq.get(start as usize..end as usize)?);
+        }
+
+        if strand == "-" {
+            out = revcomp(&out);
+        }
+        Some(out)
+    }
+}
+
+pub fn revcomp(seq: &[u8]) -> Vec<u8> {
+    seq.iter()
+        .rev()
+        .map(|b| match b {
+            b'A' => b'T',
+            b'T' => b'A',
+            b'C' => b'G',
+            b'G' => b'C',
+            b'a' => b't',
+            b't' => b'a',
+            b'c' => b'g',
+            b'g' => b'c',
+            _ => b'N',
+        })
+        .collect()
+}
+
+/// Translates a coding sequence with the standard genetic code. Codons
+/// with ambiguous bases become `X`, stops become `*`.
+pub fn translate(seq: &[u8]) -> String {
+    STANDARD_CODE.translate(seq)
+}
+
+pub fn translate_codon(codon: &[u8]) -> char {
+    STANDARD_CODE.translate_codon(codon)
+}
+
+/// Writes spliced transcript, CDS and protein FASTA files named
+/// `{prefix}.transcripts.fa`, `{prefix}.cds.fa` and `{prefix}.proteins.fa`.
+/// Non-coding transcripts only appear in the transcript file. Proteins
+/// are translated with the `--genetic-code` of their chromosome.
+pub fn write_sequences(
+    records: &[BedRecord],
+    genome: &Genome,
+    isoforms: &HashMap<String, String>,
+    prefix: &str,
+    codes: &[CodeAssignment],
+) -> io::Result<()> {
+    let seqs = records
+        .par_iter()
+        .filter_map(|record| {
+            let tx = genome.spliced(&record.chrom, &record.exon_blocks(), &record.strand)?;
+            let cds = genome.spliced(&record.chrom, &record.cds_blocks(), &record.strand);
+            Some((record, tx, cds))
+        })
+        .collect::<Vec<_>>();
+
+    let open = |suffix: &str| -> io::Result<BufWriter<File>> {
+        Ok(BufWriter::new(File::create(format!(
+            "{}.{}",
+            prefix, suffix
+        ))?))
+    };
+    let mut tx_out = open("transcripts.fa")?;
+    let mut cds_out = open("cds.fa")?;
+    let mut prot_out = open("proteins.fa")?;
+
+    for (record, tx, cds) in seqs {
+        let header = match isoforms.get(&record.name) {
+            Some(gene) => format!(">{} gene={}", record.name, gene),
+            None => format!(">{}", record.name),
+        };
+
+        write_fasta(&mut tx_out, &header, &tx)?;
+        if let Some(cds) = cds.filter(|c| !c.is_empty()) {
+            write_fasta(&mut cds_out, &header, &cds)?;
+            let protein = genetic_code(codes, &record.chrom).translate(&cds);
+            let protein = protein.strip_suffix('*').unwrap_or(&protein);
+            write_fasta(&mut prot_out, &header, protein.as_bytes())?;
+        }
+    }
+
+    tx_out.flush()?;
+    cds_out.flush()?;
+    prot_out.flush()
+}
+
+fn write_fasta<W: Write>(writer: &mut W, header: &str, seq: &[u8]) -> io::Result<()> {
+    writeln!(writer, "{}", header)?;
+    for line in seq.chunks(LINE_WIDTH) {
+        writer.write_all(line)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Result of checking a transcript's first and last codons against
+/// the genome.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodonCheck {
+    pub start: String,
+    pub stop: String,
+    pub start_ok: bool,
+    pub stop_ok: bool,
+    /// 1-based position of the first in-frame stop before the last
+    /// codon, a sign of a wrong thickStart/thickEnd or frame.
+    pub internal_stop: Option<usize>,
+}
+
+/// Extracts the first and last codons of the CDS and validates them
+/// against `code`. Returns `None` for non-coding records or when the CDS
+/// cannot be fetched (unknown contig, coordinates past the sequence end).
+pub fn check_codons(
+    record: &BedRecord,
+    genome: &Genome,
+    code: &GeneticCode,
+    alt_starts: bool,
+) -> Option<CodonCheck> {
+    let cds = genome.spliced(&record.chrom, &record.cds_blocks(), &record.strand)?;
+    if cds.len() < 6 {
+        return None;
+    }
+
+    let start = &cds[..3];
+    let stop = &cds[cds.len() - 3..];
+
+    let start_ok =
+        code.is_start(start) || (alt_starts && ALT_START_CODONS.iter().any(|c| &c[..] == start));
+    let stop_ok = code.is_stop(stop);
+    let codons = cds.len() / 3;
+    let internal_stop = cds
+        .chunks_exact(3)
+        .take(codons - 1)
+        .position(|codon| code.is_stop(codon))
+        .map(|i| i + 1);
+
+    Some(CodonCheck {
+        start: String::from_utf8_lossy(start).to_string(),
+        stop: String::from_utf8_lossy(stop).to_string(),
+        start_ok,
+        stop_ok,
+        internal_stop,
+    })
+}
+
+/// Moves the CDS start of a transcript whose first codon is not ATG to
+/// the first in-frame ATG downstream. Returns the number of coding bases
+/// dropped, or `None` when the start is already ATG, the CDS cannot be
+/// fetched or it has no in-frame ATG.
+pub fn rescan_start(record: &mut BedRecord, genome: &Genome) -> Option<u32> {
+    let blocks = record.cds_blocks();
+    let cds = genome.spliced(&record.chrom, &blocks, &record.strand)?;
+    if cds.len() < 6 || &cds[..3] == START_CODONS[0] {
+        return None;
+    }
+
+    let offset = (3..cds.len() - 2)
+        .step_by(3)
+        .find(|&i| &cds[i..i + 3] == START_CODONS[0])? as u32;
+
+    let mut left = offset;
+    if record.strand == "-" {
+        for &(start, end) in blocks.iter().rev() {
+            if left < end - start {
+                record.cds_end = end - left;
+                break;
+            }
+            left -= end - start;
+        }
+    } else {
+        for &(start, end) in &blocks {
+            if left < end - start {
+                record.cds_start = start + left;
+                break;
+            }
+            left -= end - start;
+        }
+    }
+    Some(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spliced_minus_strand() {
+        let genome = Genome::from_reader(&b">chr1\nAAATGCCCGGGTAAA\n"[..]).unwrap();
+        let seq = genome.spliced("chr1", &[(2, 8), (11, 14)], "-").unwrap();
+        assert_eq!(seq, b"TTAGGGCAT".to_vec());
+    }
+
+    #[test]
+    fn rescan_start_across_exons() {
+        // CDS: CTG A|TG CCC TAA -> first in-frame ATG at offset 3
+        let genome = Genome::from_reader(&b">chr1\nCTGAGGGGGGTGCCCTAAGG\n"[..]).unwrap();
+        let mut record =
+            BedRecord::parse("chr1\t0\t20\ttx\t0\t+\t0\t18\t0\t2\t4,10,\t0,10,").unwrap();
+
+        assert_eq!(rescan_start(&mut record, &genome), Some(3));
+        assert_eq!(record.cds_start, 3);
+        assert_eq!(
+            genome.spliced("chr1", &record.cds_blocks(), "+").unwrap(),
+            b"ATGCCCTAA".to_vec()
+        );
+    }
+
+    #[test]
+    fn finds_internal_stops() {
+        let genome = Genome::from_reader(&b">chr1\nATGTGACCCTAA\n"[..]).unwrap();
+        let record = BedRecord::parse("chr1\t0\t12\ttx\t0\t+\t0\t12\t0\t1\t12,\t0,").unwrap();
+        let check = check_codons(&record, &genome, STANDARD_CODE, false).unwrap();
+        assert!(check.start_ok && check.stop_ok);
+        assert_eq!(check.internal_stop, Some(2));
+
+        // TGA is tryptophan in vertebrate mitochondria
+        let mito = GeneticCode::by_id(2).unwrap();
+        let check = check_codons(&record, &genome, mito, false).unwrap();
+        assert_eq!(check.internal_stop, None);
+    }
+
+    #[test]
+    fn translate_standard_code() {
+        assert_eq!(translate(b"ATGGCCTAA"), "MA*");
+        assert_eq!(translate(b"ATGNNN"), "MX");
+    }
+}
@@ -0,0 +1,196 @@
+use crate::bed::BedRecord;
+use crate::utils::Rejected;
+
+use regex::Regex;
+
+use std::collections::{HashMap, HashSet};
+
+/// Strands of `--strand`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum StrandFilter {
+    #[value(name = "+", alias = "plus")]
+    Plus,
+    #[value(name = "-", alias = "minus")]
+    Minus,
+    #[default]
+    Both,
+}
+
+impl StrandFilter {
+    pub fn keeps(self, strand: &str) -> bool {
+        match self {
+            StrandFilter::Plus => strand == "+",
+            StrandFilter::Minus => strand == "-",
+            StrandFilter::Both => true,
+        }
+    }
+}
+
+/// Which BED records are converted at all, applied right after parsing
+/// (`--chroms`, `--exclude-chroms-regex`, `--min-score`, `--strand`).
+/// The default keeps everything. Gene extents only ever see the records
+/// kept.
+#[derive(Debug, Clone, Default)]
+pub struct RecordFilter {
+    /// Chromosomes to keep; empty keeps every chromosome.
+    pub chroms: HashSet<String>,
+    /// Chromosomes matching this are dropped.
+    pub exclude_chroms: Option<Regex>,
+    /// Records scoring less, or without a numeric score, are dropped.
+    pub min_score: Option<f64>,
+    pub strand: StrandFilter,
+}
+
+impl RecordFilter {
+    /// Whether the filter keeps every record.
+    pub fn is_empty(&self) -> bool {
+        self.chroms.is_empty()
+            && self.exclude_chroms.is_none()
+            && self.min_score.is_none()
+            && self.strand == StrandFilter::Both
+    }
+
+    pub fn keeps_chrom(&self, chrom: &str) -> bool {
+        (self.chroms.is_empty() || self.chroms.contains(chrom))
+            && !self
+                .exclude_chroms
+                .as_ref()
+                .is_some_and(|re| re.is_match(chrom))
+    }
+
+    pub fn keeps(&self, record: &BedRecord) -> bool {
+        self.keeps_chrom(&record.chrom)
+            && self.strand.keeps(&record.strand)
+            && self
+                .min_score
+                .is_none_or(|min| record.score.is_some_and(|score| score >= min))
+    }
+
+    /// Drops the records (and malformed lines, by their first column)
+    /// the filter does not keep. Returns how many records were dropped.
+    pub fn apply(&self, records: &mut Vec<BedRecord>, rejected: &mut Vec<Rejected>) -> usize {
+        if self.is_empty() {
+            return 0;
+        }
+        let total = records.len();
+        records.retain(|record| self.keeps(record));
+        rejected.retain(|line| self.keeps_chrom(line.line.split('\t').next().unwrap_or_default()));
+        total - records.len()
+    }
+}
+
+/// `--head`: keeps the first `n` records of the input, or of every
+/// chromosome with `per_chrom`, for a quick preview. Counts carry over
+/// from one [`Head::apply`] to the next, so the input can come in chunks.
+#[derive(Debug, Clone, Default)]
+pub struct Head {
+    n: usize,
+    per_chrom: bool,
+    seen: HashMap<String, usize>,
+}
+
+impl Head {
+    pub fn new(n: usize, per_chrom: bool) -> Self {
+        Head {
+            n,
+            per_chrom,
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Drops the records past the limit; returns how many were dropped.
+    pub fn apply(&mut self, records: &mut Vec<BedRecord>) -> usize {
+        let total = records.len();
+        records.retain(|record| {
+            let key = if self.per_chrom {
+                &record.chrom[..]
+            } else {
+                ""
+            };
+            let seen = match self.seen.get_mut(key) {
+                Some(seen) => seen,
+                None => self.seen.entry(key.to_string()).or_default(),
+            };
+            *seen += 1;
+            *seen <= self.n
+        });
+        total - records.len()
+    }
+}
+
+/// Parses the pattern of `--exclude-chroms-regex`.
+pub fn parse_regex(pattern: &str) -> Result<Regex, String> {
+    Regex::new(pattern).map_err(|e| format!("invalid pattern {:?}: {}", pattern, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_listed_and_unexcluded_chroms() {
+        let filter = RecordFilter {
+            chroms: HashSet::new(),
+            exclude_chroms: Some(parse_regex("_(alt|random)$").unwrap()),
+            min_score: None,
+            strand: StrandFilter::Both,
+        };
+        assert!(filter.keeps_chrom("chr1"));
+        assert!(!filter.keeps_chrom("chr1_KI270706v1_random"));
+        assert!(!filter.keeps_chrom("chr6_GL000250v2_alt"));
+
+        let filter = RecordFilter {
+            chroms: ["chr1", "chrX"].map(String::from).into(),
+            ..filter
+        };
+        let line = "chr1\t0\t100\tT1\t0\t+\t0\t0\t0\t1\t100,\t0,";
+        let mut records = ["chr1", "chr2", "chrX", "chrX_alt"]
+            .map(|chrom| BedRecord::parse(&line.replacen("chr1", chrom, 1)).unwrap())
+            .to_vec();
+        let mut rejected = vec![Rejected {
+            line_number: 5,
+            line: "chr2\tbroken".to_string(),
+            error: BedRecord::parse_bytes(b"chr2\tbroken").unwrap_err(),
+        }];
+        assert_eq!(filter.apply(&mut records, &mut rejected), 2);
+        assert_eq!(
+            records.iter().map(|r| r.chrom.as_str()).collect::<Vec<_>>(),
+            ["chr1", "chrX"]
+        );
+        assert!(rejected.is_empty());
+
+        let filter = RecordFilter {
+            min_score: Some(500.0),
+            ..Default::default()
+        };
+        let mut records = ["\t0\t+", "\t500\t+", "\t750.5\t+", "\t.\t+"]
+            .map(|score| BedRecord::parse(&line.replacen("\t0\t+", score, 1)).unwrap())
+            .to_vec();
+        assert_eq!(filter.apply(&mut records, &mut Vec::new()), 2);
+        assert_eq!(records[1].score, Some(750.5));
+
+        let filter = RecordFilter {
+            strand: StrandFilter::Minus,
+            ..Default::default()
+        };
+        let mut records = ["+", "-", "."]
+            .map(|strand| {
+                BedRecord::parse(&line.replacen("\t+\t", &format!("\t{}\t", strand), 1)).unwrap()
+            })
+            .to_vec();
+        assert_eq!(filter.apply(&mut records, &mut Vec::new()), 2);
+        assert_eq!(records[0].strand, "-");
+
+        let chunk = || {
+            ["chr1", "chr1", "chr2"]
+                .map(|chrom| BedRecord::parse(&line.replacen("chr1", chrom, 1)).unwrap())
+                .to_vec()
+        };
+        let mut head = Head::new(2, true);
+        let (mut first, mut second) = (chunk(), chunk());
+        assert_eq!((head.apply(&mut first), head.apply(&mut second)), (0, 2));
+        assert_eq!(second[0].chrom, "chr2");
+        let mut records = chunk();
+        assert_eq!(Head::new(1, false).apply(&mut records), 2);
+    }
+}
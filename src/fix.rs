@@ -0,0 +1,352 @@
+use crate::bed::BedRecord;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum FixMode {
+    /// Swap the inverted coordinates, re-anchoring the exon blocks on
+    /// the new chromStart.
+    Swap,
+    /// Drop the record.
+    Skip,
+    /// Trim CDS lengths that are not a multiple of 3 at their 3' end.
+    TrimCds,
+    /// Move chromStart/chromEnd to the extent of the exon blocks.
+    SpanToBlocks,
+    /// Stretch (or shrink) the first and last blocks to
+    /// chromStart/chromEnd.
+    BlocksToSpan,
+}
+
+/// gffread-style cleanups run right after conversion, done on the BED
+/// records instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum CleanMode {
+    /// Drop transcripts without a non-empty exon.
+    NoExons,
+    /// Remove zero-length exon blocks, which would become features
+    /// ending before they start.
+    ZeroLength,
+    /// Merge exon blocks that touch or overlap, joining their CDS chunks
+    /// when they are in the same frame.
+    MergeCds,
+}
+
+/// Whether `record` has an exon block covering at least one base.
+pub fn has_exons(record: &BedRecord) -> bool {
+    record
+        .exon_start
+        .iter()
+        .zip(&record.exon_end)
+        .any(|(start, end)| start < end)
+}
+
+/// Removes the exon blocks (and `--frames`) flagged in `remove`.
+fn remove_blocks(record: &mut BedRecord, remove: &[bool]) {
+    let mut flags = remove.iter();
+    record.exon_start.retain(|_| !flags.next().unwrap());
+    let mut flags = remove.iter();
+    record.exon_end.retain(|_| !flags.next().unwrap());
+    if let Some(frames) = &mut record.frames {
+        let mut flags = remove.iter();
+        frames.retain(|_| !flags.next().unwrap());
+    }
+    record.exon_count = record.exon_start.len() as u32;
+}
+
+/// `--clean=zero-length`: removes empty exon blocks and returns how
+/// many. A record with nothing but empty blocks keeps them; see
+/// [`has_exons`].
+pub fn drop_empty_blocks(record: &mut BedRecord) -> usize {
+    let empty = record
+        .exon_start
+        .iter()
+        .zip(&record.exon_end)
+        .map(|(start, end)| start >= end)
+        .collect::<Vec<_>>();
+    let n = empty.iter().filter(|&&e| e).count();
+    if n == 0 || n == empty.len() {
+        return 0;
+    }
+    remove_blocks(record, &empty);
+    n
+}
+
+/// `--clean=merge-cds`: merges exon blocks that touch or overlap their
+/// predecessor and returns how many were merged away. With `--frames`,
+/// blocks whose frames do not follow each other are kept apart.
+pub fn merge_touching_blocks(record: &mut BedRecord) -> usize {
+    let mut merged = vec![false; record.exon_start.len()];
+    let mut last = 0;
+    for i in 1..record.exon_start.len() {
+        if record.exon_start[i] > record.exon_end[last] {
+            last = i;
+            continue;
+        }
+        if let Some(frames) = &mut record.frames {
+            // frames run in transcription order: the 5' block comes first
+            let (first, second, coding) = if record.strand == "-" {
+                let start = record.exon_end[last].max(record.cds_start);
+                let end = record.exon_end[i].min(record.cds_end);
+                (frames[i], frames[last], end.saturating_sub(start))
+            } else {
+                let start = record.exon_start[last].max(record.cds_start);
+                let end = record.exon_start[i].min(record.cds_end);
+                (frames[last], frames[i], end.saturating_sub(start))
+            };
+            if first >= 0 && second >= 0 && (first as u32 + coding) % 3 != second as u32 {
+                last = i;
+                continue;
+            }
+            frames[last] = if first >= 0 { first } else { second };
+        }
+        record.exon_end[last] = record.exon_end[last].max(record.exon_end[i]);
+        merged[i] = true;
+    }
+    let n = merged.iter().filter(|&&m| m).count();
+    if n > 0 {
+        remove_blocks(record, &merged);
+    }
+    n
+}
+
+/// `--merge-exons`: merges exon blocks separated from their predecessor
+/// by at most `gap` bases (0: touching or overlapping), the gap becoming
+/// exonic, and returns how many were merged away. Frames given with
+/// `--frames` are dropped from merged records, to be computed again.
+pub fn merge_close_blocks(record: &mut BedRecord, gap: u32) -> usize {
+    let mut last = 0;
+    let merged = (0..record.exon_start.len())
+        .map(|i| {
+            if i == 0 || record.exon_start[i] > record.exon_end[last].saturating_add(gap) {
+                last = i;
+                return false;
+            }
+            record.exon_end[last] = record.exon_end[last].max(record.exon_end[i]);
+            true
+        })
+        .collect::<Vec<_>>();
+    let n = merged.iter().filter(|&&m| m).count();
+    if n > 0 {
+        remove_blocks(record, &merged);
+        record.frames = None;
+    }
+    n
+}
+
+/// Whether chromStart > chromEnd or thickStart > thickEnd, as left by
+/// buggy liftOver runs.
+pub fn inverted(record: &BedRecord) -> bool {
+    record.tx_start > record.tx_end || record.cds_start > record.cds_end
+}
+
+/// Swaps the inverted coordinates of `record`. Exon blocks are relative
+/// to chromStart, so they move with it.
+fn swap(record: &mut BedRecord) {
+    if record.tx_start > record.tx_end {
+        let shift = record.tx_start - record.tx_end;
+        std::mem::swap(&mut record.tx_start, &mut record.tx_end);
+        for pos in record.exon_start.iter_mut().chain(&mut record.exon_end) {
+            *pos = pos.saturating_sub(shift);
+        }
+    }
+    if record.cds_start > record.cds_end {
+        std::mem::swap(&mut record.cds_start, &mut record.cds_end);
+    }
+}
+
+/// Coding bases of `record`.
+pub fn cds_length(record: &BedRecord) -> u32 {
+    record
+        .cds_blocks()
+        .iter()
+        .map(|(start, end)| end - start)
+        .sum()
+}
+
+/// `--fix=trim-cds`: drops the `length % 3` dangling bases at the 3' end
+/// of the CDS, across exon boundaries if needed, and returns how many.
+/// A CDS shorter than a codon leaves the record non-coding.
+pub fn trim_cds(record: &mut BedRecord) -> u32 {
+    let blocks = record.cds_blocks();
+    let length = blocks.iter().map(|(start, end)| end - start).sum::<u32>();
+    let extra = length % 3;
+    if extra == 0 {
+        return 0;
+    }
+    if extra == length {
+        record.cds_start = record.tx_start;
+        record.cds_end = record.tx_start;
+        return extra;
+    }
+    let mut left = extra;
+    if record.strand == "-" {
+        for &(start, end) in &blocks {
+            if left < end - start {
+                record.cds_start = start + left;
+                break;
+            }
+            left -= end - start;
+        }
+    } else {
+        for &(start, end) in blocks.iter().rev() {
+            if left < end - start {
+                record.cds_end = end - left;
+                break;
+            }
+            left -= end - start;
+        }
+    }
+    extra
+}
+
+/// Whether the exon blocks start at chromStart and end at chromEnd, as
+/// BED requires. Records without blocks pass.
+pub fn blocks_span_tx(record: &BedRecord) -> bool {
+    match (record.exon_start.first(), record.exon_end.last()) {
+        (Some(&start), Some(&end)) => start == record.tx_start && end == record.tx_end,
+        _ => true,
+    }
+}
+
+/// Makes the transcript span and the exon blocks of `record` agree (see
+/// [`blocks_span_tx`]). [`FixMode::BlocksToSpan`] moves the outer edges
+/// of the first and last blocks, unless that would empty one of them;
+/// then, as with [`FixMode::SpanToBlocks`], the span moves to the
+/// blocks instead and a CDS sticking out of it is clipped.
+pub fn fix_span(record: &mut BedRecord, mode: FixMode) {
+    let (Some(&first_end), Some(&last_start)) = (record.exon_end.first(), record.exon_start.last())
+    else {
+        return;
+    };
+    if mode == FixMode::BlocksToSpan && record.tx_start < first_end && last_start < record.tx_end {
+        record.exon_start[0] = record.tx_start;
+        *record.exon_end.last_mut().unwrap() = record.tx_end;
+        return;
+    }
+    record.tx_start = record.exon_start[0];
+    record.tx_end = *record.exon_end.last().unwrap();
+    if record.cds_start < record.cds_end {
+        record.cds_start = record.cds_start.max(record.tx_start);
+        record.cds_end = record.cds_end.min(record.tx_end);
+        if record.cds_start >= record.cds_end {
+            (record.cds_start, record.cds_end) = (record.tx_start, record.tx_start);
+        }
+    }
+}
+
+/// Applies `--fix` to the [`inverted`] records. Returns the records to
+/// convert and the names of the records fixed (or skipped).
+pub fn fix_inverted(records: Vec<BedRecord>, mode: FixMode) -> (Vec<BedRecord>, Vec<String>) {
+    let mut fixed = Vec::new();
+    let mut kept = Vec::with_capacity(records.len());
+    for mut record in records {
+        if !inverted(&record) {
+            kept.push(record);
+            continue;
+        }
+        fixed.push(record.name.clone());
+        if mode != FixMode::Skip {
+            swap(&mut record);
+            kept.push(record);
+        }
+    }
+    (kept, fixed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swaps_or_skips_inverted_records() {
+        let records = [
+            "chr1\t500\t100\tT1\t0\t+\t100\t500\t0\t2\t100,100,\t0,300,",
+            "chr1\t100\t500\tT2\t0\t+\t400\t200\t0\t1\t400,\t0,",
+            "chr1\t100\t500\tT3\t0\t+\t200\t400\t0\t1\t400,\t0,",
+        ]
+        .iter()
+        .map(|line| BedRecord::parse(line).unwrap())
+        .collect::<Vec<_>>();
+        assert_eq!(records.iter().filter(|r| inverted(r)).count(), 2);
+
+        let (kept, fixed) = fix_inverted(records.clone(), FixMode::Swap);
+        assert_eq!(fixed, ["T1", "T2"]);
+        assert_eq!((kept[0].tx_start, kept[0].tx_end), (100, 500));
+        assert_eq!(kept[0].exon_blocks(), [(100, 200), (400, 500)]);
+        assert_eq!((kept[1].cds_start, kept[1].cds_end), (200, 400));
+        assert!(!kept.iter().any(inverted));
+
+        let (kept, _) = fix_inverted(records, FixMode::Skip);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].name, "T3");
+
+        // 100 + 1 coding bases: the last exon's base and one more go
+        let mut dangling =
+            BedRecord::parse("chr1\t0\t500\tT4\t0\t+\t100\t301\t0\t2\t200,200,\t0,300,").unwrap();
+        assert_eq!(cds_length(&dangling), 101);
+        assert_eq!(trim_cds(&mut dangling), 2);
+        assert_eq!((dangling.cds_start, dangling.cds_end), (100, 199));
+        dangling.strand = "-".to_string();
+        dangling.cds_start = 99;
+        assert_eq!(trim_cds(&mut dangling), 1);
+        assert_eq!((dangling.cds_start, dangling.cds_end), (100, 199));
+    }
+
+    #[test]
+    fn cleans_blocks_like_gffread() {
+        let mut record = BedRecord::parse(
+            "chr1\t0\t500\tT1\t0\t+\t50\t450\t0\t4\t100,0,100,200,\t0,150,100,300,",
+        )
+        .unwrap();
+        assert_eq!(drop_empty_blocks(&mut record), 1);
+        assert_eq!(merge_touching_blocks(&mut record), 1);
+        assert_eq!(record.exon_blocks(), [(0, 200), (300, 500)]);
+        assert_eq!(record.exon_count, 2);
+
+        // 50 coding bases in frame 0 are followed by frame 2, not 1
+        let mut framed =
+            BedRecord::parse("chr1\t0\t300\tT2\t0\t+\t50\t250\t0\t2\t100,200,\t0,100,").unwrap();
+        framed.frames = Some(vec![0, 1]);
+        assert_eq!(merge_touching_blocks(&mut framed), 0);
+        framed.frames = Some(vec![0, 2]);
+        assert_eq!(merge_touching_blocks(&mut framed), 1);
+        assert_eq!(framed.frames, Some(vec![0]));
+
+        // a 5 bp intron goes with a gap of 5, whatever the frames
+        let mut close =
+            BedRecord::parse("chr1\t0\t300\tT4\t0\t+\t50\t250\t0\t3\t100,95,50,\t0,105,250,")
+                .unwrap();
+        close.frames = Some(vec![0, 1, 2]);
+        assert_eq!(merge_close_blocks(&mut close.clone(), 4), 0);
+        assert_eq!(merge_close_blocks(&mut close, 5), 1);
+        assert_eq!(close.exon_blocks(), [(0, 200), (250, 300)]);
+        assert_eq!((close.exon_count, close.frames), (2, None));
+
+        let empty = BedRecord::parse("chr1\t0\t0\tT3\t0\t+\t0\t0\t0\t1\t0,\t0,").unwrap();
+        assert!(!has_exons(&empty));
+    }
+
+    #[test]
+    fn aligns_span_and_blocks() {
+        // blocks end 20 bases before chromEnd, past the CDS end
+        let record =
+            BedRecord::parse("chr1\t100\t600\tT1\t0\t+\t150\t590\t0\t2\t100,180,\t0,300,").unwrap();
+        assert!(!blocks_span_tx(&record));
+
+        let mut span = record.clone();
+        fix_span(&mut span, FixMode::SpanToBlocks);
+        assert_eq!((span.tx_start, span.tx_end), (100, 580));
+        assert_eq!((span.cds_start, span.cds_end), (150, 580));
+        assert!(blocks_span_tx(&span));
+
+        let mut blocks = record.clone();
+        fix_span(&mut blocks, FixMode::BlocksToSpan);
+        assert_eq!(blocks.exon_blocks(), [(100, 200), (400, 600)]);
+        assert_eq!((blocks.cds_start, blocks.cds_end), (150, 590));
+
+        // a last block starting at chromEnd cannot be stretched
+        let mut past =
+            BedRecord::parse("chr1\t100\t300\tT2\t0\t+\t100\t100\t0\t2\t100,50,\t0,200,").unwrap();
+        fix_span(&mut past, FixMode::BlocksToSpan);
+        assert_eq!((past.tx_start, past.tx_end), (100, 350));
+    }
+}
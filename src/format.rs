@@ -0,0 +1,308 @@
+use crate::bed::BedRecord;
+use crate::lines::GtfRecord;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// Annotation format of the main output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Format {
+    /// GTF2.2: `key "value";` attributes.
+    #[default]
+    Gtf,
+    /// GFF3: `key=value` attributes linked by ID/Parent.
+    Gff3,
+}
+
+/// Format of the input BED.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum InputFormat {
+    /// Plain-text BED.
+    Bed,
+    /// Gzip- or bgzip-compressed BED.
+    #[value(name = "bed.gz")]
+    BedGz,
+}
+
+/// Producer-specific reading of BED12 columns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Dialect {
+    /// UCSC semantics: thickStart/thickEnd delimit the CDS.
+    #[default]
+    Ucsc,
+    /// Spliced alignments from minimap2 `--junc-bed`/paftools: the thick
+    /// region (often zero-length or the whole alignment) and the RGB
+    /// column carry no CDS information, and one read may be listed
+    /// several times (secondary/supplementary alignments).
+    Minimap2,
+    /// TransDecoder ORF BEDs: non-coding transcripts have a 0/0 or empty
+    /// thick region, and an ORF may run a base past the last block.
+    Transdecoder,
+}
+
+impl Dialect {
+    /// Rewrites `records` into UCSC semantics. For minimap2, every record
+    /// is made non-coding and repeated names get a `_2`, `_3`, ... suffix
+    /// in file order so each alignment becomes its own transcript. For
+    /// TransDecoder, empty thick regions become non-coding and the thick
+    /// region is clipped to the exons.
+    pub fn normalize(self, records: &mut [BedRecord]) {
+        match self {
+            Dialect::Ucsc => return,
+            Dialect::Transdecoder => {
+                for record in records.iter_mut() {
+                    let first = record
+                        .exon_start
+                        .first()
+                        .copied()
+                        .unwrap_or(record.tx_start);
+                    let last = record.exon_end.last().copied().unwrap_or(record.tx_end);
+                    record.cds_start = record.cds_start.clamp(first, last);
+                    record.cds_end = record.cds_end.clamp(first, last);
+                    if record.cds_start >= record.cds_end {
+                        record.cds_start = record.tx_start;
+                        record.cds_end = record.tx_start;
+                    }
+                }
+                return;
+            }
+            Dialect::Minimap2 => (),
+        }
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        for record in records.iter_mut() {
+            record.cds_start = record.tx_start;
+            record.cds_end = record.tx_start;
+            let n = seen.entry(record.name.clone()).or_default();
+            *n += 1;
+            if *n > 1 {
+                record.rename(format!("{}_{}", record.name, n));
+            }
+        }
+    }
+}
+
+/// Compression of the main output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Codec {
+    #[default]
+    None,
+    /// Multi-threaded BGZF-compatible gzip.
+    Gzip,
+    Zstd,
+}
+
+/// A file the converted records are written to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputSpec {
+    pub path: PathBuf,
+    pub format: Format,
+    pub codec: Codec,
+}
+
+/// Splits `name` into its compression and the rest of the name.
+fn split_codec(name: &str) -> (&str, Codec) {
+    for (suffix, codec) in [
+        (".gz", Codec::Gzip),
+        (".bgz", Codec::Gzip),
+        (".zst", Codec::Zstd),
+    ] {
+        if let Some(stem) = name.strip_suffix(suffix) {
+            return (stem, codec);
+        }
+    }
+    (name, Codec::None)
+}
+
+/// Compression implied by an output name.
+pub fn infer_codec(path: &Path) -> Codec {
+    split_codec(&path.to_string_lossy().to_ascii_lowercase()).1
+}
+
+/// Format and compression implied by an output name: `.gtf`, `.gff3`
+/// or `.gff`, optionally followed by `.gz`/`.bgz` or `.zst`.
+pub fn infer_output(path: &Path) -> Result<(Format, Codec), String> {
+    let name = path.to_string_lossy().to_ascii_lowercase();
+    let (stem, codec) = split_codec(&name);
+    let format = match Path::new(stem).extension().and_then(|e| e.to_str()) {
+        Some("gtf") => Format::Gtf,
+        Some("gff3" | "gff") => Format::Gff3,
+        _ => {
+            return Err(format!(
+                "cannot tell the output format of {:?}: use .gtf or .gff3 \
+                 (optionally .gz or .zst), or --output-format",
+                path
+            ))
+        }
+    };
+    Ok((format, codec))
+}
+
+/// Input format implied by a BED name: `.bed` or `.bed.gz`.
+pub fn infer_input(path: &Path) -> Result<InputFormat, String> {
+    let name = path.to_string_lossy().to_ascii_lowercase();
+    match split_codec(&name) {
+        (stem, Codec::None) if stem.ends_with(".bed") => Ok(InputFormat::Bed),
+        (stem, Codec::Gzip) if stem.ends_with(".bed") => Ok(InputFormat::BedGz),
+        _ => Err(format!(
+            "{:?} is not a .bed/.bed.gz file; use --input-format to read it anyway",
+            path
+        )),
+    }
+}
+
+/// GFF3 feature type of `record`: transcripts become `mRNA` when
+/// `coding` and `noncoding` (e.g. `ncRNA`) otherwise, as validators such
+/// as ENA's reject bare `transcript` features. Other types are kept.
+pub fn gff3_type<'a>(record: &'a GtfRecord, coding: bool, noncoding: &'a str) -> &'a str {
+    match record.feature.as_str() {
+        "transcript" if coding => "mRNA",
+        "transcript" => noncoding,
+        feature => feature,
+    }
+}
+
+/// transcript_ids with a CDS among `records`, which must hold whole
+/// transcripts.
+pub fn coding_transcripts(records: &[GtfRecord]) -> HashSet<&str> {
+    records
+        .iter()
+        .filter(|r| r.feature == "CDS")
+        .filter_map(|r| r.attrs.get("transcript_id"))
+        .collect()
+}
+
+/// Escapes the characters GFF3 reserves in column 9.
+fn escape(value: &str, out: &mut String) {
+    for c in value.chars() {
+        match c {
+            ';' | '=' | '&' | ',' | '%' => {
+                write!(out, "%{:02X}", c as u32).expect("Failed to write attribute")
+            }
+            c if c.is_control() => {
+                write!(out, "%{:02X}", c as u32).expect("Failed to write attribute")
+            }
+            c => out.push(c),
+        }
+    }
+}
+
+/// Renders the GFF3 column 9 of a record. Genes are identified by their
+/// gene_id and transcripts by their transcript_id; transcripts point to
+/// their gene (only when gene lines are written, `genes`) and every other
+/// feature to its transcript. Exons take their exon_id as ID and CDS
+/// segments share one `CDS:<transcript>` ID, as the spec asks. Other
+/// attributes follow in order, repeated keys joined with commas.
+pub fn gff3_attributes(record: &GtfRecord, genes: bool) -> String {
+    let attrs = &record.attrs;
+    let gene_id = attrs.get("gene_id").unwrap_or_default();
+    let transcript_id = attrs.get("transcript_id").unwrap_or_default();
+
+    let mut pairs: Vec<(String, Vec<String>)> = Vec::new();
+    let mut push = |key: &str, value: String| match pairs.iter_mut().find(|(k, _)| *k == key) {
+        Some((_, values)) => values.push(value),
+        None => pairs.push((key.to_string(), vec![value])),
+    };
+    let skip: &[&str] = match record.feature.as_str() {
+        "gene" => {
+            push("ID", gene_id.to_string());
+            &["gene_id"]
+        }
+        "transcript" => {
+            push("ID", transcript_id.to_string());
+            if genes {
+                push("Parent", gene_id.to_string());
+            }
+            &["gene_id", "transcript_id"]
+        }
+        feature => {
+            if feature == "exon" {
+                if let Some(exon_id) = attrs.get("exon_id") {
+                    push("ID", exon_id.to_string());
+                }
+            } else if feature == "CDS" {
+                push("ID", format!("CDS:{}", transcript_id));
+            }
+            push("Parent", transcript_id.to_string());
+            &["gene_id", "transcript_id", "exon_id"]
+        }
+    };
+    for (k, v) in attrs.iter().filter(|(k, _)| !skip.contains(&k.as_str())) {
+        push(k, v.clone());
+    }
+
+    let mut out = String::new();
+    for (i, (key, values)) in pairs.iter().enumerate() {
+        if i > 0 {
+            out.push(';');
+        }
+        escape(key, &mut out);
+        out.push('=');
+        for (j, value) in values.iter().enumerate() {
+            if j > 0 {
+                out.push(',');
+            }
+            escape(value, &mut out);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lines::Attributes;
+
+    #[test]
+    fn infers_format_and_gff3_hierarchy() {
+        let infer = |name: &str| infer_output(Path::new(name));
+        assert_eq!(infer("a.gtf"), Ok((Format::Gtf, Codec::None)));
+        assert_eq!(infer("a.GTF.gz"), Ok((Format::Gtf, Codec::Gzip)));
+        assert_eq!(infer("a.gff3.zst"), Ok((Format::Gff3, Codec::Zstd)));
+        assert!(infer("a.txt.gz").is_err());
+        assert_eq!(infer_input(Path::new("a.bed.gz")), Ok(InputFormat::BedGz));
+        assert!(infer_input(Path::new("a.bed12")).is_err());
+
+        let read = "chr1\t100\t500\tr1\t60\t+\t100\t500\t0,0,255\t2\t100,100,\t0,300,";
+        let mut reads = vec![BedRecord::parse(read).unwrap(); 2];
+        Dialect::Minimap2.normalize(&mut reads);
+        assert_eq!(reads[1].name, "r1_2");
+        assert_eq!(reads[0].cds_start, reads[0].cds_end);
+
+        let orf = "chr1\t100\t500\tt1\t0\t+\t150\t501\t0\t2\t100,100,\t0,300,";
+        let empty = "chr1\t100\t500\tt2\t0\t+\t0\t0\t0\t2\t100,100,\t0,300,";
+        let mut orfs = [orf, empty].map(|l| BedRecord::parse(l).unwrap());
+        Dialect::Transdecoder.normalize(&mut orfs);
+        assert_eq!((orfs[0].cds_start, orfs[0].cds_end), (150, 500));
+        assert_eq!((orfs[1].cds_start, orfs[1].cds_end), (100, 100));
+
+        let mut attrs = Attributes::new();
+        attrs.push("gene_id", "G1");
+        attrs.push("transcript_id", "T1");
+        attrs.push("exon_number", "1");
+        attrs.push("tag", "cds_start_NF");
+        attrs.push("tag", "a;b");
+        let record = GtfRecord {
+            chrom: "chr1".to_string(),
+            feature: "CDS".to_string(),
+            start: 1,
+            end: 10,
+            strand: "+".to_string(),
+            frame: Some(0),
+            attrs,
+            source: None,
+        };
+        assert_eq!(
+            gff3_attributes(&record, true),
+            "ID=CDS:T1;Parent=T1;exon_number=1;tag=cds_start_NF,a%3Bb"
+        );
+        assert_eq!(coding_transcripts(std::slice::from_ref(&record)).len(), 1);
+        let transcript = GtfRecord {
+            feature: "transcript".to_string(),
+            ..record.clone()
+        };
+        assert_eq!(gff3_type(&transcript, true, "ncRNA"), "mRNA");
+        assert_eq!(gff3_type(&transcript, false, "lnc_RNA"), "lnc_RNA");
+        assert_eq!(gff3_type(&record, false, "ncRNA"), "CDS");
+    }
+}
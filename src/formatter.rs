@@ -0,0 +1,261 @@
+use crate::format::{gff3_attributes, gff3_type, Format};
+use crate::lines::{AttrStyle, GtfRecord, PhaseStyle};
+
+use std::fmt::Write as _;
+
+const SOURCE: &str = "bed2gtf";
+
+/// Renders features as the lines of one output dialect, for a
+/// [`FeatureWriter`](crate::sink::FeatureWriter). Each method appends
+/// whole lines, newline included, to `out`; appending nothing leaves the
+/// feature out. A new dialect is one more implementation.
+pub trait FeatureFormatter: Sync {
+    /// Grammar of the lines, checked with `--verify`.
+    fn format(&self) -> Format;
+
+    fn format_gene(&self, out: &mut String, gene: &GtfRecord);
+
+    /// `coding` tells whether the transcript has a CDS.
+    fn format_transcript(&self, out: &mut String, transcript: &GtfRecord, coding: bool);
+
+    /// Exons, CDS, codons, introns and every other transcript feature.
+    fn format_child(&self, out: &mut String, child: &GtfRecord);
+
+    /// Whether [`FeatureFormatter::format_transcript`] looks at
+    /// `coding`; writers only work it out for batches when it does.
+    fn needs_coding(&self) -> bool {
+        false
+    }
+}
+
+/// Sends `record` to the method of its kind.
+pub fn format_record<F>(formatter: &F, out: &mut String, record: &GtfRecord, coding: bool)
+where
+    F: FeatureFormatter + ?Sized,
+{
+    match record.feature.as_str() {
+        "gene" => formatter.format_gene(out, record),
+        "transcript" => formatter.format_transcript(out, record, coding),
+        _ => formatter.format_child(out, record),
+    }
+}
+
+/// GTF dialects of `--gtf-flavor`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum GtfFlavor {
+    /// GTF2.2 with every attribute of the conversion.
+    #[default]
+    #[value(name = "gtf2.2")]
+    Gtf22,
+    /// The lines of UCSC genePredToGtf: no genes, and only gene_id,
+    /// transcript_id, exon_number, exon_id and gene_name.
+    Ucsc,
+}
+
+/// The eight columns before the attributes.
+fn columns(out: &mut String, record: &GtfRecord, feature: &str, phase: PhaseStyle) {
+    write!(
+        out,
+        "{}\t{}\t{}\t{}\t{}\t.\t{}\t{}\t",
+        record.chrom,
+        record.source.as_deref().unwrap_or(SOURCE),
+        feature,
+        record.start,
+        record.end,
+        record.strand,
+        record.phase_char(phase)
+    )
+    .expect("Failed to render record");
+}
+
+/// GTF2.2: every feature with all its attributes.
+#[derive(Debug, Clone, Default)]
+pub struct GtfFormatter {
+    /// Attribute keys written first, in this order (`--attr-order`).
+    pub attr_order: Vec<String>,
+    /// Separators and quoting of the attributes (`--attr-style`).
+    pub attr_style: AttrStyle,
+    pub phase: PhaseStyle,
+}
+
+impl GtfFormatter {
+    fn line(&self, out: &mut String, record: &GtfRecord) {
+        columns(out, record, &record.feature, self.phase);
+        record
+            .attrs
+            .render_into(&self.attr_order, self.attr_style, out);
+        out.push('\n');
+    }
+}
+
+impl FeatureFormatter for GtfFormatter {
+    fn format(&self) -> Format {
+        Format::Gtf
+    }
+
+    fn format_gene(&self, out: &mut String, gene: &GtfRecord) {
+        self.line(out, gene)
+    }
+
+    fn format_transcript(&self, out: &mut String, transcript: &GtfRecord, _coding: bool) {
+        self.line(out, transcript)
+    }
+
+    fn format_child(&self, out: &mut String, child: &GtfRecord) {
+        self.line(out, child)
+    }
+}
+
+/// GFF3: features linked by ID/Parent, coding transcripts as mRNA.
+#[derive(Debug, Clone)]
+pub struct Gff3Formatter {
+    /// Feature type of transcripts without a CDS.
+    pub noncoding_type: String,
+    /// Whether transcripts name their gene as Parent.
+    pub gene_parents: bool,
+    pub phase: PhaseStyle,
+}
+
+impl Default for Gff3Formatter {
+    fn default() -> Self {
+        Gff3Formatter {
+            noncoding_type: "ncRNA".to_string(),
+            gene_parents: true,
+            phase: PhaseStyle::Gff3,
+        }
+    }
+}
+
+impl Gff3Formatter {
+    fn line(&self, out: &mut String, record: &GtfRecord, coding: bool) {
+        let feature = gff3_type(record, coding, &self.noncoding_type);
+        columns(out, record, feature, self.phase);
+        out.push_str(&gff3_attributes(record, self.gene_parents));
+        out.push('\n');
+    }
+}
+
+impl FeatureFormatter for Gff3Formatter {
+    fn format(&self) -> Format {
+        Format::Gff3
+    }
+
+    fn format_gene(&self, out: &mut String, gene: &GtfRecord) {
+        self.line(out, gene, false)
+    }
+
+    fn format_transcript(&self, out: &mut String, transcript: &GtfRecord, coding: bool) {
+        self.line(out, transcript, coding)
+    }
+
+    fn format_child(&self, out: &mut String, child: &GtfRecord) {
+        self.line(out, child, false)
+    }
+
+    fn needs_coding(&self) -> bool {
+        true
+    }
+}
+
+/// GTF as written by UCSC genePredToGtf, for pipelines that diff against
+/// or expect it: no gene lines, and attributes reduced to gene_id,
+/// transcript_id, the quoted exon_number with an exon_id of
+/// `transcript.number` on numbered features, and gene_name (the gene_id
+/// unless one is set).
+#[derive(Debug, Clone, Default)]
+pub struct UcscFormatter {
+    pub phase: PhaseStyle,
+}
+
+impl UcscFormatter {
+    fn line(&self, out: &mut String, record: &GtfRecord) {
+        columns(out, record, &record.feature, self.phase);
+        let attr = |key| record.attrs.get(key).unwrap_or_default();
+        let (gene, transcript) = (attr("gene_id"), attr("transcript_id"));
+        write!(
+            out,
+            "gene_id \"{}\"; transcript_id \"{}\";",
+            gene, transcript
+        )
+        .expect("Failed to render record");
+        if let Some(number) = record.attrs.get("exon_number") {
+            write!(
+                out,
+                " exon_number \"{}\"; exon_id \"{}.{}\";",
+                number, transcript, number
+            )
+            .expect("Failed to render record");
+        }
+        let name = record.attrs.get("gene_name").unwrap_or(gene);
+        writeln!(out, " gene_name \"{}\";", name).expect("Failed to render record");
+    }
+}
+
+impl FeatureFormatter for UcscFormatter {
+    fn format(&self) -> Format {
+        Format::Gtf
+    }
+
+    fn format_gene(&self, _out: &mut String, _gene: &GtfRecord) {}
+
+    fn format_transcript(&self, out: &mut String, transcript: &GtfRecord, _coding: bool) {
+        self.line(out, transcript)
+    }
+
+    fn format_child(&self, out: &mut String, child: &GtfRecord) {
+        self.line(out, child)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lines::Attributes;
+
+    #[test]
+    fn formats_each_dialect() {
+        let record = |feature: &str, number: Option<&str>| {
+            let mut attrs = Attributes::new();
+            attrs.push("gene_id", "G1");
+            attrs.push("transcript_id", "T1");
+            if let Some(number) = number {
+                attrs.push("exon_number", number);
+            }
+            attrs.push("exon_frame", "0");
+            GtfRecord {
+                chrom: "chr1".to_string(),
+                feature: feature.to_string(),
+                start: 51,
+                end: 100,
+                strand: "-".to_string(),
+                frame: Some(1),
+                attrs,
+                source: None,
+            }
+        };
+        let exon = record("CDS", Some("2"));
+        let render = |formatter: &dyn FeatureFormatter, record: &GtfRecord| {
+            let mut out = String::new();
+            format_record(formatter, &mut out, record, true);
+            out
+        };
+
+        assert_eq!(
+            render(&GtfFormatter::default(), &exon),
+            "chr1\tbed2gtf\tCDS\t51\t100\t.\t-\t2\tgene_id \"G1\"; transcript_id \"T1\"; \
+             exon_number \"2\"; exon_frame \"0\";\n"
+        );
+        assert_eq!(
+            render(&UcscFormatter::default(), &exon),
+            "chr1\tbed2gtf\tCDS\t51\t100\t.\t-\t2\tgene_id \"G1\"; transcript_id \"T1\"; \
+             exon_number \"2\"; exon_id \"T1.2\"; gene_name \"G1\";\n"
+        );
+        assert_eq!(
+            render(&UcscFormatter::default(), &record("transcript", None)),
+            "chr1\tbed2gtf\ttranscript\t51\t100\t.\t-\t2\tgene_id \"G1\"; transcript_id \"T1\"; \
+             gene_name \"G1\";\n"
+        );
+        assert_eq!(render(&UcscFormatter::default(), &record("gene", None)), "");
+        assert!(render(&Gff3Formatter::default(), &exon).contains("\tCDS\t51\t100\t.\t-\t2\t"));
+    }
+}
@@ -0,0 +1,94 @@
+/// Reading frame of one exon, as in genePred `exonFrames`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frame {
+    /// A coding exon, holding the CDS bases before it in transcription
+    /// order, mod 3.
+    Coding(u8),
+    NonCoding,
+}
+
+impl Frame {
+    /// The genePred value: 0 to 2, or -1 for non-coding exons.
+    pub fn exon_frame(self) -> i16 {
+        match self {
+            Frame::Coding(frame) => frame as i16,
+            Frame::NonCoding => -1,
+        }
+    }
+
+    /// Reads a genePred value; negative ones are non-coding.
+    pub fn from_exon_frame(frame: i16) -> Frame {
+        match frame {
+            f if f < 0 => Frame::NonCoding,
+            f => Frame::Coding((f % 3) as u8),
+        }
+    }
+
+    /// GTF/GFF3 phase of the exon's first coding base: bases to skip to
+    /// reach the next codon.
+    pub fn phase(self) -> Option<u8> {
+        match self {
+            Frame::Coding(frame) => Some((3 - frame) % 3),
+            Frame::NonCoding => None,
+        }
+    }
+}
+
+/// Frames of `exons` (0-based half-open, in genomic order) for the CDS
+/// `cds`, counted from its 5' end: from the left on `+`, from the right
+/// on `-`. Exons without a coding base are [`Frame::NonCoding`], as are
+/// all of them when the CDS is empty. Unsorted or empty exons never
+/// panic; each is clipped to the CDS on its own.
+pub fn compute_frames(exons: &[(u64, u64)], cds: (u64, u64), strand: &str) -> Vec<Frame> {
+    let mut frames = vec![Frame::NonCoding; exons.len()];
+    let mut order = (0..exons.len()).collect::<Vec<_>>();
+    if strand == "-" {
+        order.reverse();
+    }
+    let mut frame: u64 = 0;
+    for i in order {
+        let (start, end) = (exons[i].0.max(cds.0), exons[i].1.min(cds.1));
+        if start < end {
+            frames[i] = Frame::Coding(frame as u8);
+            frame = (frame + (end - start) % 3) % 3;
+        }
+    }
+    frames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_follow_the_cds() {
+        let exons = [(0, 100), (200, 250), (300, 400)];
+        assert_eq!(
+            compute_frames(&exons, (50, 350), "+"),
+            [Frame::Coding(0), Frame::Coding(2), Frame::Coding(1)]
+        );
+        assert_eq!(
+            compute_frames(&exons, (50, 350), "-"),
+            [Frame::Coding(1), Frame::Coding(2), Frame::Coding(0)]
+        );
+        assert_eq!(
+            compute_frames(&exons, (220, 240), "+"),
+            [Frame::NonCoding, Frame::Coding(0), Frame::NonCoding]
+        );
+        assert!(compute_frames(&exons, (0, 0), "+")
+            .iter()
+            .all(|&f| f == Frame::NonCoding));
+
+        // coordinates past u32::MAX
+        let far = 5_000_000_000;
+        let exons = [(far, far + 100), (far + 200, far + 250)];
+        assert_eq!(
+            compute_frames(&exons, (far + 50, far + 250), "+"),
+            [Frame::Coding(0), Frame::Coding(2)]
+        );
+
+        assert_eq!(Frame::Coding(2).phase(), Some(1));
+        assert_eq!(Frame::from_exon_frame(-1).exon_frame(), -1);
+        assert_eq!(Frame::from_exon_frame(2), Frame::Coding(2));
+    }
+}
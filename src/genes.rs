@@ -0,0 +1,696 @@
+use crate::bed::BedRecord;
+use crate::lines::{Attributes, GtfRecord};
+use crate::utils::{strip_version, GeneTrack};
+
+use crate::par::prelude::*;
+use natord::compare;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+
+const GENE_PREFIX: &str = "BGENE";
+/// Tag of the gene lines of [`placeholder_genes`].
+pub const NO_TRANSCRIPTS: &str = "no_transcripts";
+
+/// How `--infer-genes` names the genes it finds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum GeneIds {
+    /// `BGENE000001`, ... in genomic order.
+    #[default]
+    Numbered,
+    /// `BGENE_<chrom>_` and the first 12 hex digits of the SHA-256 of
+    /// `chrom:start-end:strand`, kept by unchanged loci across inputs.
+    Hashed,
+}
+
+/// Hashed gene ID of a locus (see [`GeneIds::Hashed`]).
+fn hashed_gene_id(chrom: &str, start: u32, end: u32, strand: &str) -> String {
+    let key = format!("{}:{}-{}:{}", chrom, start, end, strand);
+    let digest = Sha256::digest(key.as_bytes());
+    let mut id = format!("{}_{}_", GENE_PREFIX, chrom);
+    for byte in &digest[..6] {
+        write!(id, "{:02x}", byte).expect("Failed to write gene_id");
+    }
+    id
+}
+
+/// How `--auto-gene` (or `--group-by` by name) derives a gene_id from
+/// a transcript name.
+#[derive(Debug, Clone)]
+pub enum AutoGene {
+    /// The transcript name itself.
+    Identity,
+    /// The transcript name without its `.N` version.
+    StripVersion,
+    /// The first capture group (or the whole match) of a pattern.
+    Regex(Regex),
+    /// The first field of a compound name such as `G1;G1.1;Q7KRI2`
+    /// (`--group-by delimiter=<sep>`).
+    Delimiter(String),
+}
+
+impl AutoGene {
+    /// Parses `identity`, `strip-version` or `regex:<pattern>`.
+    pub fn parse(s: &str) -> Result<AutoGene, String> {
+        match s {
+            "identity" => Ok(AutoGene::Identity),
+            "strip-version" => Ok(AutoGene::StripVersion),
+            _ => {
+                if let Some(pattern) = s.strip_prefix("regex:") {
+                    Regex::new(pattern)
+                        .map(AutoGene::Regex)
+                        .map_err(|e| format!("invalid pattern {:?}: {}", pattern, e))
+                } else {
+                    Err(format!(
+                        "expected identity, strip-version or regex:<pattern>, got {:?}",
+                        s
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Gene of `transcript`, or None when the pattern does not match.
+    pub fn gene<'a>(&self, transcript: &'a str) -> Option<&'a str> {
+        match self {
+            AutoGene::Identity => Some(transcript),
+            AutoGene::StripVersion => Some(strip_version(transcript)),
+            AutoGene::Regex(re) => {
+                let captures = re.captures(transcript)?;
+                captures
+                    .get(1)
+                    .or_else(|| captures.get(0))
+                    .map(|m| m.as_str())
+            }
+            AutoGene::Delimiter(sep) => transcript
+                .split_once(sep.as_str())
+                .map(|(gene, _)| gene)
+                .filter(|gene| !gene.is_empty()),
+        }
+    }
+}
+
+/// How `--group-by` groups transcripts into genes without an isoforms
+/// file; it stands for `--infer-genes` or an `--auto-gene` rule.
+#[derive(Debug, Clone)]
+pub enum GroupBy {
+    /// Same-strand transcripts sharing exonic bases, as `--infer-genes`.
+    Overlap,
+    /// A gene_id taken from the transcript name, as `--auto-gene`.
+    Name(AutoGene),
+}
+
+impl GroupBy {
+    /// Parses `overlap`, `delimiter=<sep>` or `regex=<pattern>`.
+    pub fn parse(s: &str) -> Result<GroupBy, String> {
+        match s.split_once('=') {
+            None if s == "overlap" => Ok(GroupBy::Overlap),
+            Some(("delimiter", "")) => {
+                Err("delimiter= needs a separator, e.g. delimiter=;".to_string())
+            }
+            Some(("delimiter", sep)) => Ok(GroupBy::Name(AutoGene::Delimiter(sep.to_string()))),
+            Some(("regex", pattern)) => {
+                AutoGene::parse(&format!("regex:{}", pattern)).map(GroupBy::Name)
+            }
+            _ => Err(format!(
+                "expected overlap, delimiter=<sep> or regex=<pattern>, got {:?}",
+                s
+            )),
+        }
+    }
+}
+
+/// Transcript -> gene map derived from the transcript names. Names the
+/// pattern does not match are their own gene and are returned apart.
+pub fn auto_genes(
+    records: &[BedRecord],
+    rule: &AutoGene,
+) -> (HashMap<String, String>, Vec<String>) {
+    let mut unmatched = Vec::new();
+    let map = records
+        .iter()
+        .map(|record| {
+            let gene = rule.gene(&record.name).unwrap_or_else(|| {
+                unmatched.push(record.name.clone());
+                &record.name
+            });
+            (record.name.clone(), gene.to_string())
+        })
+        .collect();
+    (map, unmatched)
+}
+
+/// Clusters transcripts into genes by strand-aware exonic overlap and
+/// returns a transcript -> gene map with synthetic gene IDs.
+///
+/// Transcripts on the same chromosome and strand sharing at least one
+/// exonic base end up in the same gene (single linkage). Gene IDs are
+/// numbered in chromosome (natural order) and start order, so the same
+/// input always yields the same IDs; [`GeneIds::Hashed`] IDs only
+/// depend on the locus itself, so they also survive other loci being
+/// added or removed.
+pub fn infer_genes(records: &[BedRecord], ids: GeneIds) -> HashMap<String, String> {
+    let mut groups: HashMap<(&str, &str), Vec<&BedRecord>> = HashMap::new();
+    for record in records {
+        groups
+            .entry((&record.chrom, &record.strand))
+            .or_default()
+            .push(record);
+    }
+
+    let mut clusters = groups
+        .into_par_iter()
+        .flat_map(|(_, mut group)| {
+            group.sort_by_key(|r| (r.tx_start, r.tx_end));
+            cluster(&group)
+        })
+        .collect::<Vec<_>>();
+
+    clusters.sort_by(|a, b| {
+        compare(&a[0].chrom, &b[0].chrom)
+            .then(a[0].tx_start.cmp(&b[0].tx_start))
+            .then(a[0].strand.cmp(&b[0].strand))
+    });
+
+    let mut map = HashMap::new();
+    let mut seen = HashSet::new();
+    for (i, members) in clusters.iter().enumerate() {
+        let gene = match ids {
+            GeneIds::Numbered => format!("{}{:06}", GENE_PREFIX, i + 1),
+            GeneIds::Hashed => {
+                let start = members.iter().map(|r| r.tx_start).min().unwrap_or_default();
+                let end = members.iter().map(|r| r.tx_end).max().unwrap_or_default();
+                let id = hashed_gene_id(&members[0].chrom, start, end, &members[0].strand);
+                // a (most unlikely) collision is numbered in genomic order
+                let mut unique = id.clone();
+                for n in 2.. {
+                    if seen.insert(unique.clone()) {
+                        break;
+                    }
+                    unique = format!("{}_{}", id, n);
+                }
+                unique
+            }
+        };
+        for record in members {
+            map.insert(record.name.clone(), gene.clone());
+        }
+    }
+    map
+}
+
+/// What `--strand-conflict` does with genes whose transcripts lie on
+/// more than one strand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum StrandConflict {
+    /// The gene line takes the strand of whichever transcript comes first.
+    #[default]
+    First,
+    /// The gene line takes the strand of most of its transcripts; ties go
+    /// to `+`.
+    Majority,
+    /// Stop the conversion.
+    Error,
+    /// One gene per strand: `GENE_plus`, `GENE_minus` (and
+    /// `GENE_unstranded` for `.`).
+    Split,
+}
+
+/// Genes whose transcripts lie on more than one strand, sorted, each with
+/// the number of transcripts on every strand, most common first.
+pub fn mixed_strand_genes(
+    isoforms: &HashMap<String, String>,
+    gene_track: &GeneTrack,
+) -> Vec<(String, Vec<(String, usize)>)> {
+    let mut strands: HashMap<&str, HashMap<&str, usize>> = HashMap::new();
+    for (tx, gene) in isoforms {
+        if let Some((_, _, _, strand)) = gene_track.get(tx) {
+            *strands
+                .entry(gene.as_str())
+                .or_default()
+                .entry(strand.as_str())
+                .or_default() += 1;
+        }
+    }
+
+    let mut mixed = strands
+        .into_iter()
+        .filter(|(_, counts)| counts.len() > 1)
+        .map(|(gene, counts)| {
+            let mut counts = counts
+                .into_iter()
+                .map(|(strand, n)| (strand.to_string(), n))
+                .collect::<Vec<_>>();
+            counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+            (gene.to_string(), counts)
+        })
+        .collect::<Vec<_>>();
+    mixed.sort();
+    mixed
+}
+
+/// Name of the per-strand gene [`StrandConflict::Split`] makes of `gene`.
+pub fn strand_gene(gene: &str, strand: &str) -> String {
+    let suffix = match strand {
+        "+" => "plus",
+        "-" => "minus",
+        _ => "unstranded",
+    };
+    format!("{}_{}", gene, suffix)
+}
+
+/// Moves the transcripts of every gene of `genes` to the gene of their
+/// strand (see [`strand_gene`]). Updates `isoforms` in place.
+pub fn split_strands(
+    isoforms: &mut HashMap<String, String>,
+    gene_track: &GeneTrack,
+    genes: &HashSet<&str>,
+) {
+    for (tx, gene) in isoforms.iter_mut() {
+        if let Some((_, _, _, strand)) = gene_track.get(tx) {
+            if genes.contains(gene.as_str()) {
+                *gene = strand_gene(gene, strand);
+            }
+        }
+    }
+}
+
+/// Splits genes whose transcripts sit on different chromosomes/strands
+/// or are separated by more than `max_gap` bp into locus-level genes
+/// named `GENE_1`, `GENE_2`, ... (numbered in genomic order). Updates
+/// `isoforms` in place and returns each affected gene with its number
+/// of loci.
+pub fn split_distant_loci(
+    isoforms: &mut HashMap<String, String>,
+    gene_track: &GeneTrack,
+    max_gap: u32,
+) -> Vec<(String, usize)> {
+    let mut members: HashMap<&str, Vec<_>> = HashMap::new();
+    for (tx, gene) in isoforms.iter() {
+        if let Some((chrom, start, end, strand)) = gene_track.get(tx) {
+            members.entry(gene.as_str()).or_default().push((
+                tx.as_str(),
+                chrom,
+                *start,
+                *end,
+                strand,
+            ));
+        }
+    }
+
+    let mut renamed: Vec<(String, String)> = Vec::new();
+    let mut affected = Vec::new();
+    for (gene, mut txs) in members {
+        txs.sort_by(|a, b| compare(a.1, b.1).then(a.4.cmp(b.4)).then(a.2.cmp(&b.2)));
+
+        let mut locus = 1;
+        let mut assigned = Vec::with_capacity(txs.len());
+        let (mut chrom, mut strand, mut reach) = (txs[0].1, txs[0].4, txs[0].3);
+        for &(tx, c, start, end, s) in &txs {
+            if c != chrom || s != strand || start > reach.saturating_add(max_gap) {
+                locus += 1;
+                (chrom, strand, reach) = (c, s, end);
+            }
+            reach = reach.max(end);
+            assigned.push((tx, locus));
+        }
+
+        if locus > 1 {
+            affected.push((gene.to_string(), locus));
+            renamed.extend(
+                assigned
+                    .into_iter()
+                    .map(|(tx, n)| (tx.to_string(), format!("{}_{}", gene, n))),
+            );
+        }
+    }
+
+    for (tx, gene) in renamed {
+        isoforms.insert(tx, gene);
+    }
+    affected.sort();
+    affected
+}
+
+/// Reads curated gene extents from a BED6+ file whose name column holds
+/// the gene_id. Header, track and comment lines are skipped.
+pub fn read_gene_bed(contents: &str) -> Result<HashMap<String, BedRecord>, String> {
+    let mut bounds = HashMap::new();
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+        let record = BedRecord::parse_blocks(line).map_err(|e| format!("line {}: {}", i + 1, e))?;
+        bounds.insert(record.name.clone(), record);
+    }
+    Ok(bounds)
+}
+
+/// Moves gene lines onto their curated extents in `bounds`. Genes on
+/// another chromosome or strand than their curated entry keep the span
+/// of their transcripts. Returns the number of genes moved and, apart,
+/// the mismatched genes and those whose transcripts reach outside the
+/// curated extent (which are moved anyway).
+pub fn apply_gene_bounds(
+    genes: &mut [GtfRecord],
+    bounds: &HashMap<String, BedRecord>,
+) -> (usize, Vec<String>, Vec<String>) {
+    let (mut moved, mut mismatched, mut outside) = (0, Vec::new(), Vec::new());
+    for gene in genes.iter_mut() {
+        let Some(id) = gene.attrs.get("gene_id") else {
+            continue;
+        };
+        let Some(bound) = bounds.get(id) else {
+            continue;
+        };
+        if bound.chrom != gene.chrom || bound.strand != gene.strand {
+            mismatched.push(id.to_string());
+            continue;
+        }
+        if gene.start <= bound.tx_start || gene.end > bound.tx_end {
+            outside.push(id.to_string());
+        }
+        gene.start = bound.tx_start + 1;
+        gene.end = bound.tx_end;
+        moved += 1;
+    }
+    (moved, mismatched, outside)
+}
+
+/// Gene lines, tagged `no_transcripts`, at the curated extents in
+/// `bounds` of genes none of whose transcripts were converted. Returns
+/// them and, apart, the genes without an extent.
+pub fn placeholder_genes(
+    ids: &[String],
+    bounds: &HashMap<String, BedRecord>,
+) -> (Vec<GtfRecord>, Vec<String>) {
+    let (mut genes, mut missing) = (Vec::new(), Vec::new());
+    for id in ids {
+        let Some(bound) = bounds.get(id) else {
+            missing.push(id.clone());
+            continue;
+        };
+        let mut attrs = Attributes::new();
+        attrs.push("gene_id", id.as_str());
+        attrs.push("tag", NO_TRANSCRIPTS);
+        genes.push(GtfRecord {
+            chrom: bound.chrom.clone(),
+            feature: "gene".to_string(),
+            start: bound.tx_start + 1,
+            end: bound.tx_end,
+            strand: bound.strand.clone(),
+            frame: None,
+            attrs,
+            source: None,
+        });
+    }
+    (genes, missing)
+}
+
+/// Flattens every gene into non-overlapping `exonic_part` features
+/// (DEXSeq-style counting bins): the union of its isoforms' exons, cut
+/// at every exon boundary. Each part lists the transcripts covering it
+/// and is numbered in genomic order. Records without an entry in
+/// `isoforms` are treated as their own gene.
+pub fn flatten_genes(records: &[BedRecord], isoforms: &HashMap<String, String>) -> Vec<GtfRecord> {
+    let mut genes: HashMap<(&str, &str, &str), Vec<&BedRecord>> = HashMap::new();
+    for record in records {
+        let gene = isoforms.get(&record.name).unwrap_or(&record.name);
+        genes
+            .entry((gene, &record.chrom, &record.strand))
+            .or_default()
+            .push(record);
+    }
+
+    genes
+        .into_par_iter()
+        .flat_map_iter(|((gene, chrom, strand), members)| {
+            let mut bounds = members
+                .iter()
+                .flat_map(|r| r.exon_start.iter().chain(r.exon_end.iter()).copied())
+                .collect::<Vec<_>>();
+            bounds.sort_unstable();
+            bounds.dedup();
+
+            let mut parts = Vec::new();
+            for w in bounds.windows(2) {
+                let (start, end) = (w[0], w[1]);
+                let mut txs = members
+                    .iter()
+                    .filter(|r| r.exon_blocks().iter().any(|&(s, e)| s <= start && end <= e))
+                    .map(|r| r.name.as_str())
+                    .collect::<Vec<_>>();
+                if txs.is_empty() {
+                    continue;
+                }
+                txs.sort_unstable();
+
+                let mut attrs = Attributes::new();
+                attrs.push("gene_id", gene);
+                attrs.push("transcripts", txs.join("+"));
+                attrs.push("exonic_part_number", format!("{:03}", parts.len() + 1));
+                parts.push(GtfRecord {
+                    chrom: chrom.to_string(),
+                    feature: "exonic_part".to_string(),
+                    start: start + 1,
+                    end,
+                    strand: strand.to_string(),
+                    frame: None,
+                    attrs,
+                    source: None,
+                });
+            }
+            parts
+        })
+        .collect()
+}
+
+/// Single-linkage clustering over records sorted by start. Each cluster
+/// is returned sorted by start.
+fn cluster<'a>(sorted: &[&'a BedRecord]) -> Vec<Vec<&'a BedRecord>> {
+    let mut parent: Vec<usize> = (0..sorted.len()).collect();
+    let mut active: Vec<usize> = Vec::new();
+
+    for (i, record) in sorted.iter().enumerate() {
+        active.retain(|&j| sorted[j].tx_end > record.tx_start);
+        for &j in &active {
+            if exons_overlap(record, sorted[j]) {
+                let (a, b) = (find(&mut parent, i), find(&mut parent, j));
+                parent[a.max(b)] = a.min(b);
+            }
+        }
+        active.push(i);
+    }
+
+    let mut clusters: HashMap<usize, Vec<&BedRecord>> = HashMap::new();
+    for (i, record) in sorted.iter().enumerate() {
+        let root = find(&mut parent, i);
+        clusters.entry(root).or_default().push(record);
+    }
+    clusters.into_values().collect()
+}
+
+fn find(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+pub fn exons_overlap(a: &BedRecord, b: &BedRecord) -> bool {
+    let (mut i, mut j) = (0, 0);
+    while i < a.exon_start.len() && j < b.exon_start.len() {
+        if a.exon_start[i] < b.exon_end[j] && b.exon_start[j] < a.exon_end[i] {
+            return true;
+        }
+        if a.exon_end[i] <= b.exon_end[j] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(
+        name: &str,
+        strand: &str,
+        start: u32,
+        sizes: &str,
+        starts: &str,
+        n: u16,
+    ) -> BedRecord {
+        let line = format!(
+            "chr1\t{start}\t{end}\t{name}\t0\t{strand}\t{start}\t{start}\t0\t{n}\t{sizes}\t{starts}",
+            end = start + 1000
+        );
+        BedRecord::parse(&line).unwrap()
+    }
+
+    #[test]
+    fn flattens_into_exonic_parts() {
+        let records = vec![
+            record("a", "+", 0, "100,100,", "0,900,", 2),
+            record("b", "+", 0, "150,", "50,", 1),
+        ];
+        let isoforms = HashMap::from([
+            ("a".to_string(), "G".to_string()),
+            ("b".to_string(), "G".to_string()),
+        ]);
+
+        let parts = flatten_genes(&records, &isoforms)
+            .into_iter()
+            .map(|p| {
+                (
+                    p.start,
+                    p.end,
+                    p.attrs.get("transcripts").unwrap().to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            parts,
+            vec![
+                (1, 50, "a".to_string()),
+                (51, 100, "a+b".to_string()),
+                (101, 200, "b".to_string()),
+                (901, 1000, "a".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn clusters_by_exonic_overlap() {
+        let records = vec![
+            record("a", "+", 0, "100,100,", "0,900,", 2),
+            // overlaps a only through its intron
+            record("b", "+", 200, "100,", "0,", 1),
+            // shares the last exon of a
+            record("c", "+", 900, "100,", "0,", 1),
+            // same span as a, other strand
+            record("d", "-", 0, "1000,", "0,", 1),
+        ];
+        let map = infer_genes(&records, GeneIds::Numbered);
+
+        assert_eq!(map["a"], map["c"]);
+        assert_ne!(map["a"], map["b"]);
+        assert_ne!(map["a"], map["d"]);
+        assert_eq!(map["a"], "BGENE000001");
+
+        // hashed IDs do not move when another locus comes first
+        let hashed = infer_genes(&records, GeneIds::Hashed);
+        let fewer = infer_genes(&records[1..], GeneIds::Hashed);
+        assert!(hashed["b"].starts_with("BGENE_chr1_") && hashed["b"].len() == 23);
+        assert_eq!(hashed["b"], fewer["b"]);
+        assert_ne!(map["b"], infer_genes(&records[1..], GeneIds::Numbered)["b"]);
+    }
+
+    #[test]
+    fn derives_genes_from_names() {
+        let records = vec![
+            record("ENST1.2", "+", 0, "100,", "0,", 1),
+            record("GENEA-201", "+", 0, "100,", "0,", 1),
+        ];
+        let (map, _) = auto_genes(&records, &AutoGene::StripVersion);
+        assert_eq!(map["ENST1.2"], "ENST1");
+
+        let rule = AutoGene::parse(r"regex:^(\w+)-\d+$").unwrap();
+        let (map, unmatched) = auto_genes(&records, &rule);
+        assert_eq!(map["GENEA-201"], "GENEA");
+        assert_eq!((map["ENST1.2"].as_str(), unmatched.len()), ("ENST1.2", 1));
+        assert!(AutoGene::parse("regex:(").is_err());
+
+        assert!(AutoGene::parse("delimiter:;").is_err());
+
+        let rule = AutoGene::Delimiter(";".to_string());
+        assert_eq!(rule.gene("G1;G1.1;Q7KRI2;P1"), Some("G1"));
+        assert_eq!(rule.gene("G2"), None);
+
+        let group = |s: &str| match GroupBy::parse(s).unwrap() {
+            GroupBy::Name(rule) => rule.gene("G1;G1.1;Q7KRI2").map(str::to_string),
+            GroupBy::Overlap => None,
+        };
+        assert_eq!(group("delimiter=;").as_deref(), Some("G1"));
+        // the separator is taken as given, quotes included
+        assert_eq!(group("delimiter=';'"), None);
+        assert!(GroupBy::parse("delimiter=").is_err());
+        assert_eq!(group(r"regex=^(\w+);").as_deref(), Some("G1"));
+        assert!(matches!(GroupBy::parse("overlap"), Ok(GroupBy::Overlap)));
+        assert!(GroupBy::parse("delimiter:;").is_err());
+    }
+
+    #[test]
+    fn applies_curated_gene_bounds() {
+        let bounds =
+            read_gene_bed("track name=genes\nchr1\t0\t5000\tG1\t0\t+\nchr1\t0\t500\tG2\t0\t+\n")
+                .unwrap();
+        let gene = |id: &str, strand: &str| {
+            let mut attrs = Attributes::new();
+            attrs.push("gene_id", id);
+            GtfRecord {
+                chrom: "chr1".to_string(),
+                feature: "gene".to_string(),
+                start: 101,
+                end: 1000,
+                strand: strand.to_string(),
+                frame: None,
+                attrs,
+                source: None,
+            }
+        };
+        let mut genes = [gene("G1", "+"), gene("G2", "+"), gene("G3", "-")];
+        let (moved, mismatched, outside) = apply_gene_bounds(&mut genes, &bounds);
+        assert_eq!((moved, mismatched.len()), (2, 0));
+        assert_eq!(outside, ["G2"]);
+        assert_eq!((genes[0].start, genes[0].end), (1, 5000));
+        assert_eq!((genes[2].start, genes[2].end), (101, 1000));
+
+        let (placed, missing) = placeholder_genes(&["G2".into(), "G9".into()], &bounds);
+        assert_eq!((placed[0].start, placed[0].end), (1, 500));
+        assert_eq!(placed[0].attrs.get("tag"), Some("no_transcripts"));
+        assert_eq!(missing, ["G9"]);
+    }
+
+    #[test]
+    fn resolves_mixed_strands() {
+        let track = |strand: &str| ("chr1".to_string(), 0, 100, strand.to_string());
+        let gene_track = HashMap::from([
+            ("a".to_string(), track("+")),
+            ("b".to_string(), track("-")),
+            ("c".to_string(), track("-")),
+            ("d".to_string(), track("+")),
+        ]);
+        let mut isoforms = HashMap::from([
+            ("a".to_string(), "G1".to_string()),
+            ("b".to_string(), "G1".to_string()),
+            ("c".to_string(), "G1".to_string()),
+            ("d".to_string(), "G2".to_string()),
+        ]);
+
+        let mixed = mixed_strand_genes(&isoforms, &gene_track);
+        assert_eq!(
+            mixed,
+            [(
+                "G1".to_string(),
+                vec![("-".to_string(), 2), ("+".to_string(), 1)]
+            )]
+        );
+
+        split_strands(&mut isoforms, &gene_track, &HashSet::from(["G1"]));
+        assert_eq!(isoforms["a"], "G1_plus");
+        assert_eq!(isoforms["c"], "G1_minus");
+        assert_eq!(isoforms["d"], "G2");
+        assert!(mixed_strand_genes(&isoforms, &gene_track).is_empty());
+    }
+}
@@ -0,0 +1,189 @@
+use std::str::FromStr;
+
+const BASES: &[u8; 4] = b"TCAG";
+
+/// An NCBI translation table: amino acids and initiation codons of the
+/// 64 codons in TCAG order, as NCBI lists them.
+#[derive(Debug, PartialEq, Eq)]
+pub struct GeneticCode {
+    pub id: u8,
+    pub name: &'static str,
+    aas: &'static [u8; 64],
+    starts: &'static [u8; 64],
+}
+
+/// Tables of `--genetic-code`. The alternative initiators of the
+/// standard code (CTG, TTG) are left to `--alt-starts`.
+pub const GENETIC_CODES: [GeneticCode; 11] = [
+    GeneticCode {
+        id: 1,
+        name: "Standard",
+        aas: b"FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M----------------------------",
+    },
+    GeneticCode {
+        id: 2,
+        name: "Vertebrate Mitochondrial",
+        aas: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSS**VVVVAAAADDEEGGGG",
+        starts: b"--------------------------------MMMM---------------M------------",
+    },
+    GeneticCode {
+        id: 3,
+        name: "Yeast Mitochondrial",
+        aas: b"FFLLSSSSYY**CCWWTTTTPPPPHHQQRRRRIIMMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"----------------------------------MM---------------M------------",
+    },
+    GeneticCode {
+        id: 4,
+        name: "Mold, Protozoan and Coelenterate Mitochondrial; Mycoplasma",
+        aas: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"--MM---------------M------------MMMM---------------M------------",
+    },
+    GeneticCode {
+        id: 5,
+        name: "Invertebrate Mitochondrial",
+        aas: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSSSSVVVVAAAADDEEGGGG",
+        starts: b"---M----------------------------MMMM---------------M------------",
+    },
+    GeneticCode {
+        id: 6,
+        name: "Ciliate, Dasycladacean and Hexamita Nuclear",
+        aas: b"FFLLSSSSYYQQCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M----------------------------",
+    },
+    GeneticCode {
+        id: 9,
+        name: "Echinoderm and Flatworm Mitochondrial",
+        aas: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M---------------M------------",
+    },
+    GeneticCode {
+        id: 10,
+        name: "Euplotid Nuclear",
+        aas: b"FFLLSSSSYY**CCCWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M----------------------------",
+    },
+    GeneticCode {
+        id: 11,
+        name: "Bacterial, Archaeal and Plant Plastid",
+        aas: b"FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"---M---------------M------------MMMM---------------M------------",
+    },
+    GeneticCode {
+        id: 12,
+        name: "Alternative Yeast Nuclear",
+        aas: b"FFLLSSSSYY**CC*WLLLSPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"-------------------M---------------M----------------------------",
+    },
+    GeneticCode {
+        id: 13,
+        name: "Ascidian Mitochondrial",
+        aas: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSSGGVVVVAAAADDEEGGGG",
+        starts: b"---M------------------------------MM---------------M------------",
+    },
+];
+
+/// The standard code (NCBI table 1).
+pub const STANDARD_CODE: &GeneticCode = &GENETIC_CODES[0];
+
+fn index(codon: &[u8]) -> Option<usize> {
+    if codon.len() != 3 {
+        return None;
+    }
+    codon.iter().try_fold(0usize, |acc, &b| {
+        BASES
+            .iter()
+            .position(|&x| x == b.to_ascii_uppercase())
+            .map(|i| acc * 4 + i)
+    })
+}
+
+impl GeneticCode {
+    pub fn by_id(id: u8) -> Option<&'static GeneticCode> {
+        GENETIC_CODES.iter().find(|code| code.id == id)
+    }
+
+    /// Amino acid of a codon; `*` for stops and `X` for ambiguous bases.
+    pub fn translate_codon(&self, codon: &[u8]) -> char {
+        index(codon).map_or('X', |i| self.aas[i] as char)
+    }
+
+    /// Translates a coding sequence codon by codon, ignoring a trailing
+    /// partial codon.
+    pub fn translate(&self, seq: &[u8]) -> String {
+        seq.chunks_exact(3)
+            .map(|codon| self.translate_codon(codon))
+            .collect()
+    }
+
+    pub fn is_start(&self, codon: &[u8]) -> bool {
+        index(codon).is_some_and(|i| self.starts[i] == b'M')
+    }
+
+    pub fn is_stop(&self, codon: &[u8]) -> bool {
+        index(codon).is_some_and(|i| self.aas[i] == b'*')
+    }
+}
+
+/// One `--genetic-code` value: a table for every chromosome, or for one
+/// (`chrM:2`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeAssignment {
+    pub chrom: Option<String>,
+    pub code: &'static GeneticCode,
+}
+
+impl FromStr for CodeAssignment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<CodeAssignment, String> {
+        let (chrom, id) = match s.rsplit_once(':') {
+            Some((chrom, id)) if !chrom.is_empty() => (Some(chrom), id),
+            _ => (None, s),
+        };
+        let code = id
+            .parse::<u8>()
+            .ok()
+            .and_then(GeneticCode::by_id)
+            .ok_or_else(|| {
+                let ids = GENETIC_CODES.map(|c| c.id.to_string()).join(", ");
+                format!("unknown NCBI translation table {:?}; known: {}", id, ids)
+            })?;
+        Ok(CodeAssignment {
+            chrom: chrom.map(String::from),
+            code,
+        })
+    }
+}
+
+/// Table of `chrom`: its own assignment, else the last one without a
+/// chromosome, else the standard code.
+pub fn genetic_code(assignments: &[CodeAssignment], chrom: &str) -> &'static GeneticCode {
+    assignments
+        .iter()
+        .rev()
+        .find(|a| a.chrom.as_deref() == Some(chrom))
+        .or_else(|| assignments.iter().rev().find(|a| a.chrom.is_none()))
+        .map_or(STANDARD_CODE, |a| a.code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mitochondrial_code_per_chromosome() {
+        let assignments = ["chrM:2".parse::<CodeAssignment>().unwrap()];
+        let mito = genetic_code(&assignments, "chrM");
+        assert_eq!(mito.id, 2);
+        assert_eq!(genetic_code(&assignments, "chr1"), STANDARD_CODE);
+
+        assert!(mito.is_stop(b"AGA") && !STANDARD_CODE.is_stop(b"AGA"));
+        assert!(mito.is_start(b"ATA") && !STANDARD_CODE.is_start(b"ATA"));
+        assert_eq!(mito.translate(b"ATGTGAAGG"), "MW*");
+        assert_eq!(STANDARD_CODE.translate(b"ATGTGAAGG"), "M*R");
+
+        assert_eq!("11".parse::<CodeAssignment>().unwrap().code.id, 11);
+        assert!("7".parse::<CodeAssignment>().is_err());
+    }
+}
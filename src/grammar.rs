@@ -0,0 +1,185 @@
+use crate::format::Format;
+
+/// Characters GFF3 allows unescaped in a seqid.
+fn gff3_seqid_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || ".:^*$@!+_?-|%".contains(c)
+}
+
+/// Whether every `%` in `value` starts a two-digit hex escape.
+fn escapes_valid(value: &str) -> bool {
+    let bytes = value.as_bytes();
+    bytes.iter().enumerate().all(|(i, &b)| {
+        b != b'%'
+            || bytes
+                .get(i + 1..i + 3)
+                .is_some_and(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+    })
+}
+
+/// Checks a GTF column 9: `key "value"` pairs each ending in `;` and
+/// separated by a single space, integers possibly unquoted, with a
+/// gene_id and, on everything but genes, a transcript_id. The space and
+/// the last `;` may be missing, as `--attr-style` allows.
+fn gtf_attributes(attrs: &str, feature: &str) -> Result<(), String> {
+    let mut keys = Vec::new();
+    let mut rest = attrs;
+    while !rest.is_empty() {
+        let (key, after) = rest
+            .split_once(' ')
+            .ok_or_else(|| format!("attribute {:?} has no value", rest))?;
+        if key.is_empty()
+            || !key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(format!("invalid attribute key {:?}", key));
+        }
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted
+                    .find('"')
+                    .ok_or_else(|| format!("unterminated value of {}", key))?;
+                (&quoted[..end], &quoted[end + 1..])
+            }
+            None => {
+                let end = after.find(';').unwrap_or(after.len());
+                let value = &after[..end];
+                if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(format!("value of {} is neither quoted nor an integer", key));
+                }
+                (value, &after[end..])
+            }
+        };
+        if value.contains(['\t', '\n']) {
+            return Err(format!("value of {} holds a tab or newline", key));
+        }
+        rest = match after.strip_prefix(';') {
+            Some(next) => next,
+            None if after.is_empty() => after,
+            None => return Err(format!("attribute {} does not end with ';'", key)),
+        };
+        rest = match rest.strip_prefix(' ') {
+            Some("") => return Err("trailing space".into()),
+            Some(next) => next,
+            None => rest,
+        };
+        keys.push(key);
+    }
+    if !keys.contains(&"gene_id") {
+        return Err("no gene_id".into());
+    }
+    if feature != "gene" && !keys.contains(&"transcript_id") {
+        return Err("no transcript_id".into());
+    }
+    Ok(())
+}
+
+/// Checks a GFF3 column 9: `tag=value[,value]` pairs separated by `;`,
+/// with reserved characters percent-escaped.
+fn gff3_attributes(attrs: &str) -> Result<(), String> {
+    if attrs == "." {
+        return Ok(());
+    }
+    for pair in attrs.strip_suffix(';').unwrap_or(attrs).split(';') {
+        let (tag, values) = pair
+            .split_once('=')
+            .ok_or_else(|| format!("attribute {:?} is not tag=value", pair))?;
+        if tag.is_empty() || tag.contains([' ', '&', ',']) || !escapes_valid(tag) {
+            return Err(format!("invalid attribute tag {:?}", tag));
+        }
+        for value in values.split(',') {
+            if value.is_empty() || value.contains(['=', '&']) || !escapes_valid(value) {
+                return Err(format!("invalid value {:?} of {}", value, tag));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks one feature line against the `format` grammar: nine
+/// tab-separated non-empty columns, a seqid without whitespace,
+/// 1-based `start <= end`, a numeric or `.` score, a `+`/`-`/`.`/`?`
+/// strand, a `0`/`1`/`2` phase (required on CDS) and the dialect's
+/// attribute syntax. Returns what is wrong.
+pub fn check_line(line: &str, format: Format) -> Result<(), String> {
+    let columns = line.split('\t').collect::<Vec<_>>();
+    if columns.len() != 9 {
+        return Err(format!("{} columns instead of 9", columns.len()));
+    }
+    if let Some(i) = columns.iter().position(|c| c.is_empty()) {
+        return Err(format!("column {} is empty", i + 1));
+    }
+    let (seqid, feature) = (columns[0], columns[2]);
+    let valid_seqid = match format {
+        Format::Gtf => !seqid.contains(char::is_whitespace),
+        Format::Gff3 => !seqid.starts_with('>') && seqid.chars().all(gff3_seqid_char),
+    };
+    if !valid_seqid {
+        return Err(format!("invalid seqid {:?}", seqid));
+    }
+    let position = |i: usize| {
+        columns[i]
+            .parse::<u64>()
+            .ok()
+            .filter(|&n| n >= 1)
+            .ok_or_else(|| format!("column {} is not a 1-based position", i + 1))
+    };
+    let (start, end) = (position(3)?, position(4)?);
+    if start > end {
+        return Err(format!("start {} is past end {}", start, end));
+    }
+    if columns[5] != "." && columns[5].parse::<f64>().is_err() {
+        return Err(format!("invalid score {:?}", columns[5]));
+    }
+    if !matches!(columns[6], "+" | "-" | "." | "?") {
+        return Err(format!("invalid strand {:?}", columns[6]));
+    }
+    match (columns[7], feature) {
+        (".", "CDS") => return Err("CDS without a phase".into()),
+        ("." | "0" | "1" | "2", _) => {}
+        (phase, _) => return Err(format!("invalid phase {:?}", phase)),
+    }
+    match format {
+        Format::Gtf => gtf_attributes(columns[8], feature),
+        Format::Gff3 => gff3_attributes(columns[8]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_both_grammars() {
+        let gtf = "chr1\tbed2gtf\tCDS\t51\t100\t.\t+\t0\tgene_id \"G1\"; transcript_id \"T1\"; exon_number 1;";
+        assert_eq!(check_line(gtf, Format::Gtf), Ok(()));
+        let compact = gtf.replace("; ", ";");
+        assert_eq!(
+            check_line(compact.trim_end_matches(';'), Format::Gtf),
+            Ok(())
+        );
+        for (broken, problem) in [
+            (
+                gtf.replace("\t51\t", "\t151\t"),
+                "start 151 is past end 100",
+            ),
+            (gtf.replace("\t0\tgene", "\t.\tgene"), "CDS without a phase"),
+            (
+                gtf.replace("\"T1\";", "\"T1\""),
+                "attribute transcript_id does not end with ';'",
+            ),
+            (
+                gtf.replace(" transcript_id \"T1\";", ""),
+                "no transcript_id",
+            ),
+            (gtf.replace("\t+\t", "\t\t"), "column 7 is empty"),
+        ] {
+            assert_eq!(check_line(&broken, Format::Gtf), Err(problem.to_string()));
+        }
+
+        let gff3 = "chr1\tbed2gtf\tmRNA\t1\t900\t.\t+\t.\tID=T1;Parent=G1;Note=a%3Bb";
+        assert_eq!(check_line(gff3, Format::Gff3), Ok(()));
+        assert!(check_line(&gff3.replace("%3B", "%G"), Format::Gff3).is_err());
+        assert!(check_line(&gff3.replace("a%3Bb", "a=b"), Format::Gff3).is_err());
+        assert!(check_line(&gff3.replace("chr1", "chr 1"), Format::Gff3).is_err());
+    }
+}
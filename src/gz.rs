@@ -0,0 +1,201 @@
+use crate::par::prelude::*;
+use crate::par::ThreadPool;
+use flate2::write::DeflateEncoder;
+use flate2::{Compression, Crc};
+
+use std::io::{self, Write};
+use std::sync::Arc;
+
+/// Uncompressed bytes per block. Kept below 64 KiB so that every
+/// compressed member fits the BGZF block size limit.
+pub(crate) const BLOCK_SIZE: usize = 0xff00;
+
+/// Empty BGZF block marking the end of the stream.
+pub(crate) const EOF_BLOCK: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Multi-threaded gzip writer.
+///
+/// Buffers input and compresses it as independent BGZF blocks on the
+/// rayon pool, writing them back in order. The result is a regular
+/// multi-member gzip stream readable by `gzip`/`zcat` and by htslib.
+pub struct ParGzWriter<W: Write> {
+    inner: Option<W>,
+    buffer: Vec<u8>,
+    level: Compression,
+    batch: usize,
+    pool: Option<Arc<ThreadPool>>,
+}
+
+impl<W: Write> ParGzWriter<W> {
+    pub fn new(inner: W, level: Compression) -> Self {
+        let batch = BLOCK_SIZE * crate::par::current_num_threads().max(1) * 4;
+        ParGzWriter {
+            inner: Some(inner),
+            buffer: Vec::with_capacity(batch),
+            level,
+            batch,
+            pool: None,
+        }
+    }
+
+    /// Compresses on `pool` instead of the current rayon pool.
+    pub fn with_pool(mut self, pool: Arc<ThreadPool>) -> Self {
+        self.batch = BLOCK_SIZE * pool.current_num_threads().max(1) * 4;
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Compresses and writes all buffered data, then the EOF marker, and
+    /// returns the inner writer. An error here means a truncated stream,
+    /// so the success path must call this rather than rely on drop.
+    pub fn finish(mut self) -> io::Result<W> {
+        let ended = self.end();
+        let inner = self.inner.take();
+        ended?;
+        inner.ok_or_else(|| io::Error::other("writer already finished"))
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        self.dump(true)?;
+        let inner = self
+            .inner
+            .as_mut()
+            .ok_or_else(|| io::Error::other("writer already finished"))?;
+        inner.write_all(&EOF_BLOCK)?;
+        inner.flush()
+    }
+
+    fn dump(&mut self, all: bool) -> io::Result<()> {
+        let n = if all {
+            self.buffer.len()
+        } else {
+            self.buffer.len() - self.buffer.len() % BLOCK_SIZE
+        };
+        if n == 0 {
+            return Ok(());
+        }
+
+        let (data, level) = (&self.buffer[..n], self.level);
+        let blocks = match &self.pool {
+            Some(pool) => pool.install(|| compress_blocks(data, level))?,
+            None => compress_blocks(data, level)?,
+        };
+
+        let inner = self
+            .inner
+            .as_mut()
+            .ok_or_else(|| io::Error::other("writer already finished"))?;
+        for block in &blocks {
+            inner.write_all(block)?;
+        }
+
+        self.buffer.drain(..n);
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ParGzWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= self.batch {
+            self.dump(false)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.dump(true)?;
+        match self.inner.as_mut() {
+            Some(inner) => inner.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Best effort for writers dropped without [`ParGzWriter::finish`], e.g.
+/// on an error path; errors are lost.
+impl<W: Write> Drop for ParGzWriter<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.end();
+        }
+    }
+}
+
+/// Parses a gzip level: `1`-`9`, `fast` (1) or `best` (9).
+pub fn parse_level(s: &str) -> Result<Compression, String> {
+    match s {
+        "fast" => Ok(Compression::fast()),
+        "best" => Ok(Compression::best()),
+        _ => match s.parse::<u32>() {
+            Ok(level @ 1..=9) => Ok(Compression::new(level)),
+            _ => Err(format!("invalid gzip level {:?} [1-9, fast, best]", s)),
+        },
+    }
+}
+
+/// Splits `data` into [`BLOCK_SIZE`] chunks and compresses them in
+/// parallel, returning the BGZF blocks in order.
+pub fn compress_blocks(data: &[u8], level: Compression) -> io::Result<Vec<Vec<u8>>> {
+    data.par_chunks(BLOCK_SIZE)
+        .map(|chunk| compress_block(chunk, level))
+        .collect()
+}
+
+/// Compresses one chunk into a standalone BGZF block.
+pub fn compress_block(data: &[u8], level: Compression) -> io::Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::with_capacity(data.len() / 2), level);
+    encoder.write_all(data)?;
+    let cdata = encoder.finish()?;
+
+    let mut crc = Crc::new();
+    crc.update(data);
+
+    let bsize = (18 + cdata.len() + 8 - 1) as u16;
+    let mut block = Vec::with_capacity(cdata.len() + 26);
+    block.extend_from_slice(&[
+        0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02,
+        0x00,
+    ]);
+    block.extend_from_slice(&bsize.to_le_bytes());
+    block.extend_from_slice(&cdata);
+    block.extend_from_slice(&crc.sum().to_le_bytes());
+    block.extend_from_slice(&(data.len() as u32).to_le_bytes());
+
+    Ok(block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::MultiGzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn roundtrip_multi_block() {
+        let data = (0..200_000u32)
+            .map(|i| format!("chr1\tbed2gtf\texon\t{}\n", i))
+            .collect::<String>();
+
+        let mut out = Vec::new();
+        {
+            let mut writer = ParGzWriter::new(&mut out, Compression::default());
+            writer.write_all(data.as_bytes()).unwrap();
+            writer.finish().unwrap();
+        }
+        assert!(out.ends_with(&EOF_BLOCK));
+
+        let mut decoded = String::new();
+        MultiGzDecoder::new(&out[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+
+        assert_eq!(parse_level("best"), Ok(Compression::best()));
+        assert_eq!(parse_level("3"), Ok(Compression::new(3)));
+        assert!(parse_level("0").is_err());
+    }
+}
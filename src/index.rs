@@ -0,0 +1,215 @@
+use crate::gz::{compress_blocks, BLOCK_SIZE, EOF_BLOCK};
+use crate::signals::PartialOutput;
+use crate::sort::{is_grouped, read_text, sort_features, split_features, ChromOrder, Feature};
+
+use flate2::Compression;
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Linear index window, 16 kbp.
+const MIN_SHIFT: u32 = 14;
+const DEPTH: u32 = 5;
+/// tabix `-p gff` preset: generic format, seq/start/end in columns
+/// 1/4/5, `#` comments.
+const TBX_GENERIC: i32 = 0;
+const COL_SEQ: i32 = 1;
+const COL_BEG: i32 = 4;
+const COL_END: i32 = 5;
+
+/// bgzips and tabix-indexes a GTF/GFF file, sorting it first (natural
+/// chromosome order) unless it is already grouped by chromosome and
+/// sorted by start. `output` defaults to the
+/// input path (plus `.gz` for uncompressed input); the index is written
+/// next to it as `.tbi`. Returns the path of the compressed file.
+pub fn index_gtf(
+    input: &Path,
+    output: Option<&Path>,
+    level: Compression,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let contents = read_text(input)?;
+
+    let output = match output {
+        Some(path) => path.to_path_buf(),
+        None if input.extension().is_some_and(|e| e == "gz") => input.to_path_buf(),
+        None => PathBuf::from(format!("{}.gz", input.display())),
+    };
+
+    let (header, mut features) = split_features(&contents)?;
+    if !is_grouped(&features) {
+        sort_features(&mut features, &ChromOrder::Natural);
+        log::warn!("{} was not sorted, sorted before indexing", input.display());
+    }
+
+    let mut text = String::with_capacity(contents.len() + 1);
+    for line in header {
+        text.push_str(line);
+        text.push('\n');
+    }
+    let mut offsets = Vec::with_capacity(features.len());
+    for feature in &features {
+        let begin = text.len();
+        text.push_str(feature.line);
+        text.push('\n');
+        offsets.push((begin, text.len()));
+    }
+
+    let blocks = compress_blocks(text.as_bytes(), level)?;
+    let mut block_offsets = Vec::with_capacity(blocks.len() + 1);
+    let mut total = 0u64;
+    for block in &blocks {
+        block_offsets.push(total);
+        total += block.len() as u64;
+    }
+    block_offsets.push(total);
+
+    let voffset =
+        |pos: usize| -> u64 { (block_offsets[pos / BLOCK_SIZE] << 16) | (pos % BLOCK_SIZE) as u64 };
+
+    let mut tbi = TabixIndex::default();
+    for (feature, &(begin, end)) in features.iter().zip(&offsets) {
+        tbi.push(feature, voffset(begin), voffset(end));
+    }
+
+    let tmp = PathBuf::from(format!("{}.tmp", output.display()));
+    let partial = PartialOutput::new(&tmp);
+    {
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        for block in &blocks {
+            writer.write_all(block)?;
+        }
+        writer.write_all(&EOF_BLOCK)?;
+        writer.flush()?;
+    }
+    fs::rename(&tmp, &output)?;
+    partial.keep();
+
+    let index = tbi.to_bytes();
+    let tbi_path = format!("{}.tbi", output.display());
+    let partial = PartialOutput::new(&tbi_path);
+    let mut writer = BufWriter::new(File::create(&tbi_path)?);
+    for block in compress_blocks(&index, Compression::default())? {
+        writer.write_all(&block)?;
+    }
+    writer.write_all(&EOF_BLOCK)?;
+    writer.flush()?;
+    partial.keep();
+
+    Ok(output)
+}
+
+#[derive(Default)]
+struct RefIndex {
+    bins: BTreeMap<u32, Vec<(u64, u64)>>,
+    linear: Vec<Option<u64>>,
+}
+
+#[derive(Default)]
+struct TabixIndex {
+    names: Vec<String>,
+    refs: HashMap<String, RefIndex>,
+}
+
+impl TabixIndex {
+    fn push(&mut self, feature: &Feature, begin: u64, end: u64) {
+        if !self.refs.contains_key(feature.chrom) {
+            self.names.push(feature.chrom.to_string());
+        }
+        let idx = self.refs.entry(feature.chrom.to_string()).or_default();
+
+        // GTF is 1-based closed; the binning scheme is 0-based half-open
+        let beg = feature.start.saturating_sub(1);
+        let stop = feature.end.max(beg + 1);
+        let chunks = idx.bins.entry(reg2bin(beg, stop)).or_default();
+        match chunks.last_mut() {
+            Some(last) if last.1 == begin => last.1 = end,
+            _ => chunks.push((begin, end)),
+        }
+
+        let (first, last) = (beg >> MIN_SHIFT, (stop - 1) >> MIN_SHIFT);
+        if idx.linear.len() <= last as usize {
+            idx.linear.resize(last as usize + 1, None);
+        }
+        for window in &mut idx.linear[first as usize..=last as usize] {
+            window.get_or_insert(begin);
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(b"TBI\x01");
+
+        let names = self
+            .names
+            .iter()
+            .flat_map(|n| n.bytes().chain(std::iter::once(0)))
+            .collect::<Vec<u8>>();
+        for value in [
+            self.names.len() as i32,
+            TBX_GENERIC,
+            COL_SEQ,
+            COL_BEG,
+            COL_END,
+            b'#' as i32,
+            0,
+            names.len() as i32,
+        ] {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out.extend_from_slice(&names);
+
+        for name in &self.names {
+            let idx = &self.refs[name];
+            out.extend_from_slice(&(idx.bins.len() as i32).to_le_bytes());
+            for (bin, chunks) in &idx.bins {
+                out.extend_from_slice(&bin.to_le_bytes());
+                out.extend_from_slice(&(chunks.len() as i32).to_le_bytes());
+                for (begin, end) in chunks {
+                    out.extend_from_slice(&begin.to_le_bytes());
+                    out.extend_from_slice(&end.to_le_bytes());
+                }
+            }
+
+            // empty windows point at the closest preceding feature
+            out.extend_from_slice(&(idx.linear.len() as i32).to_le_bytes());
+            let mut previous = 0u64;
+            for window in &idx.linear {
+                previous = window.unwrap_or(previous);
+                out.extend_from_slice(&previous.to_le_bytes());
+            }
+        }
+        out
+    }
+}
+
+/// Smallest UCSC/htslib bin fully containing the 0-based half-open
+/// interval `[beg, end)`.
+fn reg2bin(beg: u32, end: u32) -> u32 {
+    let end = end - 1;
+    let mut shift = MIN_SHIFT;
+    let mut offset = ((1 << (DEPTH * 3)) - 1) / 7;
+    for level in (1..=DEPTH).rev() {
+        if beg >> shift == end >> shift {
+            return offset + (beg >> shift);
+        }
+        shift += 3;
+        offset -= 1 << ((level - 1) * 3);
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bins_match_htslib() {
+        assert_eq!(reg2bin(0, 1), 4681);
+        assert_eq!(reg2bin(16384, 16385), 4682);
+        assert_eq!(reg2bin(0, 16385), 585);
+        assert_eq!(reg2bin(0, 1 << 29), 0);
+    }
+}
@@ -0,0 +1,496 @@
+use crate::utils::strip_version;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Column order of the isoforms file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum IsoformsOrder {
+    /// Pick the column that matches the BED transcript names.
+    #[default]
+    Auto,
+    /// gene<TAB>transcript, the historical layout.
+    GeneTranscript,
+    /// transcript<TAB>gene.
+    TranscriptGene,
+}
+
+/// What to do with a transcript listed under more than one gene.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OnConflict {
+    /// Refuse to convert.
+    #[default]
+    Error,
+    /// Keep the gene listed first.
+    First,
+    /// Keep the gene listed last.
+    Last,
+    /// Leave the transcript out of the map.
+    Skip,
+    /// Convert the transcript once per gene; copies are named
+    /// `<transcript>_<gene>`.
+    Duplicate,
+    /// Keep the first gene and list the others in a `secondary_gene_ids`
+    /// attribute.
+    Secondary,
+}
+
+impl OnConflict {
+    /// Whether every gene of a many-to-many transcript is kept, i.e. the
+    /// mapping is legitimate rather than a conflict.
+    pub fn keeps_all(self) -> bool {
+        matches!(self, OnConflict::Duplicate | OnConflict::Secondary)
+    }
+}
+
+/// A transcript listed under several genes (a conflict, or a readthrough
+/// locus), with how many times each pairing appears, in file order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub transcript: String,
+    pub genes: Vec<(String, usize)>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} is listed under ", self.transcript)?;
+        for (i, (gene, n)) in self.genes.iter().enumerate() {
+            let sep = if i == 0 { "" } else { ", " };
+            write!(f, "{}{} ({}x)", sep, gene, n)?;
+        }
+        Ok(())
+    }
+}
+
+/// Transcripts on only one side of a BED/isoforms pair, and the genes
+/// of the isoforms map losing transcripts for it, sorted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IsoformsAudit {
+    /// In the BED but not in the isoforms map.
+    pub unlisted: Vec<String>,
+    /// In the isoforms map but not in the BED.
+    pub absent: Vec<String>,
+    /// Genes with some, but not all, of their transcripts absent.
+    pub shrunk_genes: Vec<String>,
+    /// Genes with every transcript absent, which get no gene line.
+    pub absent_genes: Vec<String>,
+}
+
+/// Examples listed per side by the [`IsoformsAudit`] report.
+const AUDIT_EXAMPLES: usize = 5;
+
+impl IsoformsAudit {
+    pub fn new(names: &HashSet<&str>, isoforms: &HashMap<String, String>) -> IsoformsAudit {
+        let mut unlisted = names
+            .iter()
+            .filter(|name| !isoforms.contains_key(**name))
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+        let mut absent = isoforms
+            .keys()
+            .filter(|tx| !names.contains(tx.as_str()))
+            .cloned()
+            .collect::<Vec<_>>();
+        unlisted.sort_unstable();
+        absent.sort_unstable();
+
+        // gene -> (transcripts, absent transcripts)
+        let mut genes: HashMap<&str, (usize, usize)> = HashMap::new();
+        for (tx, gene) in isoforms {
+            let counts = genes.entry(gene).or_default();
+            counts.0 += 1;
+            counts.1 += usize::from(!names.contains(tx.as_str()));
+        }
+        let (mut shrunk_genes, mut absent_genes) = (Vec::new(), Vec::new());
+        for (gene, (total, missing)) in genes {
+            match missing {
+                0 => {}
+                n if n == total => absent_genes.push(gene.to_string()),
+                _ => shrunk_genes.push(gene.to_string()),
+            }
+        }
+        shrunk_genes.sort_unstable();
+        absent_genes.sort_unstable();
+        IsoformsAudit {
+            unlisted,
+            absent,
+            shrunk_genes,
+            absent_genes,
+        }
+    }
+
+    /// What the BED lacks of the isoforms map, for the log: absent
+    /// transcripts and the genes they shrink or empty, with examples.
+    pub fn absent_summary(&self) -> String {
+        format!(
+            "{} isoforms transcripts have no BED record{}; {} genes lost some of their \
+             transcripts and {} genes lost all of them{}",
+            self.absent.len(),
+            examples(&self.absent),
+            self.shrunk_genes.len(),
+            self.absent_genes.len(),
+            examples(&self.absent_genes)
+        )
+    }
+}
+
+/// The first few of `ids` in parentheses, for messages; nothing when
+/// there are none.
+fn examples(ids: &[String]) -> String {
+    if ids.is_empty() {
+        return String::new();
+    }
+    let mut list = ids[..ids.len().min(AUDIT_EXAMPLES)].join(", ");
+    if ids.len() > AUDIT_EXAMPLES {
+        list.push_str(", ...");
+    }
+    format!(" ({})", list)
+}
+
+impl fmt::Display for IsoformsAudit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} BED transcripts missing from the isoforms file{}; \
+             {} isoforms transcripts missing from the BED{}",
+            self.unlisted.len(),
+            examples(&self.unlisted),
+            self.absent.len(),
+            examples(&self.absent)
+        )
+    }
+}
+
+/// UCSC tables accepted as isoforms files, recognized by their `#`
+/// header: name, transcript column, gene column.
+const UCSC_TABLES: [(&str, &str, &str); 4] = [
+    ("kgXref", "kgID", "geneSymbol"),
+    ("refLink", "mrnaAcc", "name"),
+    ("knownIsoforms", "transcript", "clusterId"),
+    ("knownToEnsembl", "name", "value"),
+];
+
+/// Names a header row may give the transcript and the gene column,
+/// compared ignoring case.
+const TRANSCRIPT_HEADERS: [&str; 4] = [
+    "transcript_id",
+    "transcript",
+    "tx_id",
+    "transcript stable id",
+];
+const GENE_HEADERS: [&str; 3] = ["gene_id", "gene", "gene stable id"];
+
+/// Reads `line` as a header row: a UCSC table's `#` header (see
+/// [`UCSC_TABLES`]) or a row naming its transcript and gene columns
+/// (`gene_id<TAB>transcript_id`, possibly after a `#`). Returns what the
+/// header is, for the log, and the indices of both columns.
+fn header_columns(line: &str) -> Option<(String, usize, usize)> {
+    let columns = line
+        .strip_prefix('#')
+        .unwrap_or(line)
+        .split('\t')
+        .map(str::trim)
+        .collect::<Vec<_>>();
+    let index = |name: &str| columns.iter().position(|c| c.eq_ignore_ascii_case(name));
+    if line.starts_with('#') {
+        let table = UCSC_TABLES
+            .iter()
+            .find_map(|&(table, tx, gene)| Some((table, index(tx)?, index(gene)?)));
+        if let Some((table, tx, gene)) = table {
+            return Some((format!("UCSC {} table", table), tx, gene));
+        }
+    }
+    let tx = TRANSCRIPT_HEADERS.iter().find_map(|name| index(name))?;
+    let gene = GENE_HEADERS.iter().find_map(|name| index(name))?;
+    Some((
+        format!("header ({}, {})", columns[tx], columns[gene]),
+        tx,
+        gene,
+    ))
+}
+
+/// Counts how many BED `names` appear in the first and in the second
+/// column of the `rows` of an isoforms file.
+fn column_hits(rows: &[&str], names: &HashSet<&str>, strip: bool) -> (usize, usize) {
+    let matches = |word: &str| {
+        let word = if strip { strip_version(word) } else { word };
+        names.contains(word) as usize
+    };
+    rows.iter()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            Some((words.next()?, words.next()?))
+        })
+        .fold((0, 0), |(a, b), (first, second)| {
+            (a + matches(first), b + matches(second))
+        })
+}
+
+/// Reads an isoforms file into a transcript -> gene map in the given
+/// column `order`, detecting it from the BED `names` with
+/// [`IsoformsOrder::Auto`]. An explicit order that matches none of the
+/// names while the other column does is reported as swapped columns.
+/// `#` comments are skipped. A header row, whether a UCSC table dump
+/// (kgXref, refLink, knownIsoforms, knownToEnsembl) or one naming
+/// gene_id and transcript_id columns, selects the columns by name
+/// instead.
+/// Versions are stripped from both ids with `strip`. Transcripts listed
+/// under several genes are logged, returned, and resolved by
+/// `on_conflict`.
+pub fn read_isoforms(
+    contents: &str,
+    names: &HashSet<&str>,
+    order: IsoformsOrder,
+    strip: bool,
+    on_conflict: OnConflict,
+) -> Result<(HashMap<String, String>, Vec<Conflict>), String> {
+    // comments and blank lines before the first row, which may be a header
+    let mut lines = contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .peekable();
+    let mut header = None;
+    while let Some(line) = lines.next_if(|line| {
+        header.is_none() && (line.starts_with('#') || header_columns(line).is_some())
+    }) {
+        header = header_columns(line);
+    }
+    let rows = lines
+        .filter(|line| !line.starts_with('#'))
+        .collect::<Vec<_>>();
+
+    let pairs = match header {
+        Some((header, tx, gene)) => {
+            log::info!("Isoforms file read by its {}", header);
+            rows.iter()
+                .filter_map(|line| {
+                    let columns = line.split('\t').map(str::trim).collect::<Vec<_>>();
+                    Some((*columns.get(tx)?, *columns.get(gene)?))
+                })
+                .filter(|(tx, gene)| !tx.is_empty() && !gene.is_empty())
+                .collect()
+        }
+        None => two_column_pairs(&rows, names, order, strip)?,
+    };
+
+    let mut genes: HashMap<String, Vec<(String, usize)>> = HashMap::new();
+    let mut transcripts = Vec::new();
+    for (tx, gene) in pairs {
+        let (tx, gene) = if strip {
+            (strip_version(tx), strip_version(gene))
+        } else {
+            (tx, gene)
+        };
+        let seen = genes.entry(tx.to_string()).or_insert_with(|| {
+            transcripts.push(tx.to_string());
+            Vec::new()
+        });
+        match seen.iter_mut().find(|(g, _)| g == gene) {
+            Some((_, n)) => *n += 1,
+            None => seen.push((gene.to_string(), 1)),
+        }
+    }
+    if genes.is_empty() {
+        return Err("isoforms file has no gene/transcript pairs".to_string());
+    }
+
+    let conflicts = transcripts
+        .iter()
+        .filter(|tx| genes[*tx].len() > 1)
+        .map(|tx| Conflict {
+            transcript: tx.clone(),
+            genes: genes[tx].clone(),
+        })
+        .collect::<Vec<_>>();
+    if !conflicts.is_empty() {
+        if on_conflict.keeps_all() {
+            log::info!("{} transcripts belong to several genes", conflicts.len());
+        } else {
+            for conflict in &conflicts {
+                log::warn!("{}", conflict);
+            }
+        }
+        if on_conflict == OnConflict::Error {
+            return Err(format!(
+                "{} transcripts map to more than one gene (e.g. {}); pick one with \
+                 --on-conflict first, last or skip",
+                conflicts.len(),
+                conflicts[0]
+            ));
+        }
+    }
+
+    let map = genes
+        .into_iter()
+        .filter_map(|(tx, genes)| {
+            let gene = match on_conflict {
+                _ if genes.len() == 1 => &genes[0],
+                OnConflict::Last => genes.last()?,
+                OnConflict::Skip => return None,
+                _ => &genes[0],
+            };
+            Some((tx, gene.0.clone()))
+        })
+        .collect();
+    Ok((map, conflicts))
+}
+
+/// (transcript, gene) pairs of the `rows` of a plain two-column
+/// isoforms file, in the column `order` (see [`read_isoforms`]).
+fn two_column_pairs<'a>(
+    rows: &[&'a str],
+    names: &HashSet<&str>,
+    order: IsoformsOrder,
+    strip: bool,
+) -> Result<Vec<(&'a str, &'a str)>, String> {
+    let (first, second) = column_hits(rows, names, strip);
+    let order = match order {
+        IsoformsOrder::Auto if names.is_empty() => {
+            return Err(
+                "no BED records left after filters to match the isoforms file against".to_string(),
+            )
+        }
+        IsoformsOrder::Auto if first == 0 && second == 0 => {
+            return Err(format!(
+                "no BED transcript (e.g. {}) appears in either column of the isoforms file",
+                names.iter().min().unwrap_or(&"-")
+            ))
+        }
+        IsoformsOrder::Auto if first > second => {
+            log::info!("Isoforms file read as transcript<TAB>gene");
+            IsoformsOrder::TranscriptGene
+        }
+        IsoformsOrder::Auto => IsoformsOrder::GeneTranscript,
+        IsoformsOrder::GeneTranscript if second == 0 && first > 0 => {
+            return Err(format!(
+                "isoforms file looks like transcript<TAB>gene ({} BED transcripts in the first \
+                 column, none in the second); use --isoforms-order transcript-gene",
+                first
+            ))
+        }
+        IsoformsOrder::TranscriptGene if first == 0 && second > 0 => {
+            return Err(format!(
+                "isoforms file looks like gene<TAB>transcript ({} BED transcripts in the second \
+                 column, none in the first); use --isoforms-order gene-transcript",
+                second
+            ))
+        }
+        order => order,
+    };
+    Ok(rows
+        .iter()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            let (first, second) = (words.next()?, words.next()?);
+            Some(match order {
+                IsoformsOrder::TranscriptGene => (first, second),
+                _ => (second, first),
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_column_order() {
+        let names = HashSet::from(["T1", "T2"]);
+        let swapped = "T1.1\tG1\nT2.3\tG1\n";
+        let read =
+            |contents, order| read_isoforms(contents, &names, order, true, OnConflict::Error);
+
+        let (map, _) = read(swapped, IsoformsOrder::Auto).unwrap();
+        assert_eq!(map["T1"], "G1");
+        let err = read(swapped, IsoformsOrder::GeneTranscript);
+        assert!(err.unwrap_err().contains("transcript-gene"));
+
+        let (map, _) = read("G1\tT1\n", IsoformsOrder::Auto).unwrap();
+        assert_eq!(map["T1"], "G1");
+        assert!(read("G1\tX\n", IsoformsOrder::Auto).is_err());
+
+        // an empty BED, or one emptied by --min-score and the like
+        let none = HashSet::new();
+        let err = read_isoforms(swapped, &none, IsoformsOrder::Auto, true, OnConflict::Error);
+        assert!(err
+            .unwrap_err()
+            .starts_with("no BED records left after filters"));
+    }
+
+    #[test]
+    fn resolves_conflicts() {
+        let names = HashSet::from(["T1", "T2"]);
+        let contents = "G1\tT1\nG2\tT1\nG1\tT1\nG1\tT2\n";
+        let read = |policy| read_isoforms(contents, &names, IsoformsOrder::Auto, false, policy);
+
+        let err = read(OnConflict::Error).unwrap_err();
+        assert!(err.contains("T1 is listed under G1 (2x), G2 (1x)"));
+        let (map, conflicts) = read(OnConflict::Last).unwrap();
+        assert_eq!((map["T1"].as_str(), conflicts.len()), ("G2", 1));
+        assert_eq!(read(OnConflict::First).unwrap().0["T1"], "G1");
+        let (map, _) = read(OnConflict::Skip).unwrap();
+        assert!(!map.contains_key("T1") && map["T2"] == "G1");
+        let (map, conflicts) = read(OnConflict::Secondary).unwrap();
+        assert_eq!((map["T1"].as_str(), conflicts[0].genes.len()), ("G1", 2));
+    }
+
+    #[test]
+    fn reads_headers() {
+        let names = HashSet::from(["uc001aaa.3", "NM_000014"]);
+        let read = |contents| {
+            read_isoforms(
+                contents,
+                &names,
+                IsoformsOrder::Auto,
+                false,
+                OnConflict::Error,
+            )
+            .unwrap()
+            .0
+        };
+
+        let kgxref = "#kgID\tmRNA\tspID\tspDisplayID\tgeneSymbol\trefseq\tprotAcc\tdescription\n\
+                      uc001aaa.3\tBC032353\t\t\tDDX11L1\t\t\tDEAD/H box polypeptide 11 like 1\n";
+        assert_eq!(read(kgxref)["uc001aaa.3"], "DDX11L1");
+        let reflink = "#name\tproduct\tmrnaAcc\tprotAcc\n\
+                       A2M\talpha-2-macroglobulin isoform a\tNM_000014\tNP_000005\n";
+        assert_eq!(read(reflink)["NM_000014"], "A2M");
+        let known = "#name\tvalue\nuc001aaa.3\tENST00000456328.2\n";
+        assert_eq!(read(known)["uc001aaa.3"], "ENST00000456328.2");
+
+        // comments, then columns picked by name
+        let exported = "# exported from R\n\"\"\ttranscript_id\tgene_id\n1\tNM_000014\tA2M\n";
+        let map = read(exported);
+        assert_eq!((map.len(), map["NM_000014"].as_str()), (1, "A2M"));
+        let map = read("#comment\nGENE_ID\tTRANSCRIPT_ID\r\nA2M\tNM_000014\r\n");
+        assert_eq!((map.len(), map["NM_000014"].as_str()), (1, "A2M"));
+    }
+
+    #[test]
+    fn audits_both_sides() {
+        let names = HashSet::from(["T1", "T2", "T3"]);
+        let isoforms = HashMap::from([
+            ("T1".to_string(), "G1".to_string()),
+            ("T9".to_string(), "G1".to_string()),
+        ]);
+        let audit = IsoformsAudit::new(&names, &isoforms);
+        assert_eq!(audit.unlisted, ["T2", "T3"]);
+        assert_eq!(audit.absent, ["T9"]);
+        assert_eq!(
+            audit.to_string(),
+            "2 BED transcripts missing from the isoforms file (T2, T3); \
+             1 isoforms transcripts missing from the BED (T9)"
+        );
+        assert_eq!(audit.shrunk_genes, ["G1"]);
+
+        let isoforms = HashMap::from([("T8".to_string(), "G2".to_string())]);
+        let audit = IsoformsAudit::new(&names, &isoforms);
+        assert_eq!(audit.absent_genes, ["G2"]);
+        assert_eq!(
+            audit.absent_summary(),
+            "1 isoforms transcripts have no BED record (T8); \
+             0 genes lost some of their transcripts and 1 genes lost all of them (G2)"
+        );
+    }
+}
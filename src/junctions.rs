@@ -0,0 +1,151 @@
+use crate::bed::BedRecord;
+use crate::fasta::{revcomp, Genome};
+use crate::utils::create_output;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// An intron shared by one or more transcripts, a row of
+/// `--junctions-out`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Junction {
+    pub chrom: String,
+    /// First intron base, 1-based.
+    pub start: u32,
+    /// Last intron base, 1-based.
+    pub end: u32,
+    pub strand: String,
+    pub transcripts: Vec<String>,
+    /// Genes of `transcripts`, without repeats.
+    pub genes: Vec<String>,
+    /// Donor and acceptor dinucleotides 5'->3', e.g. `GT/AG`, when the
+    /// genome is known.
+    pub motif: Option<String>,
+}
+
+/// Donor and acceptor dinucleotides of the 0-based intron `start..end`.
+fn motif(genome: &Genome, chrom: &str, start: u32, end: u32, strand: &str) -> Option<String> {
+    let seq = genome.get(chrom)?;
+    let (start, end) = (start as usize, end as usize);
+    if end < start + 2 {
+        return None;
+    }
+    let (first, last) = (seq.get(start..start + 2)?, seq.get(end - 2..end)?);
+    let (donor, acceptor) = if strand == "-" {
+        (revcomp(last), revcomp(first))
+    } else {
+        (first.to_vec(), last.to_vec())
+    };
+    Some(format!(
+        "{}/{}",
+        String::from_utf8_lossy(&donor),
+        String::from_utf8_lossy(&acceptor)
+    ))
+}
+
+/// The distinct introns of `records`, with the transcripts sharing each
+/// and their genes from `isoforms` (the transcript itself when
+/// unlisted). Junctions follow the chromosomes in input order, then
+/// coordinates.
+pub fn collect_junctions(
+    records: &[BedRecord],
+    isoforms: &HashMap<String, String>,
+    genome: Option<&Genome>,
+) -> Vec<Junction> {
+    let mut chroms: HashMap<&str, usize> = HashMap::new();
+    let mut introns = Vec::new();
+    for record in records {
+        let next = chroms.len();
+        let chrom = *chroms.entry(&record.chrom).or_insert(next);
+        for w in record.exon_blocks().windows(2) {
+            let (start, end) = (w[0].1, w[1].0);
+            if start < end {
+                introns.push(((chrom, start, end, &record.strand), record));
+            }
+        }
+    }
+    introns.sort_by(|a, b| (&a.0, &a.1.name).cmp(&(&b.0, &b.1.name)));
+    introns
+        .chunk_by(|a, b| a.0 == b.0)
+        .map(|group| {
+            let ((_, start, end, strand), record) = group[0];
+            let mut genes = Vec::new();
+            for (_, r) in group {
+                let gene = isoforms.get(&r.name).unwrap_or(&r.name);
+                if !genes.contains(gene) {
+                    genes.push(gene.clone());
+                }
+            }
+            Junction {
+                chrom: record.chrom.clone(),
+                start: start + 1,
+                end,
+                strand: strand.clone(),
+                transcripts: group.iter().map(|(_, r)| r.name.clone()).collect(),
+                genes,
+                motif: genome.and_then(|g| motif(g, &record.chrom, start, end, strand)),
+            }
+        })
+        .collect()
+}
+
+/// Writes the [`collect_junctions`] of `records` in the layout STAR
+/// takes with `--sjdbFileChrStartEnd` (chrom, 1-based first and last
+/// intron base, strand), followed by the transcripts, the genes and the
+/// motif (`.` without a genome). STAR ignores the extra columns.
+pub fn write_junctions<P: AsRef<Path>>(
+    path: P,
+    records: &[BedRecord],
+    isoforms: &HashMap<String, String>,
+    genome: Option<&Genome>,
+) -> Result<usize, Box<dyn Error>> {
+    let junctions = collect_junctions(records, isoforms, genome);
+    let mut writer = BufWriter::new(create_output(path)?);
+    for junction in &junctions {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            junction.chrom,
+            junction.start,
+            junction.end,
+            junction.strand,
+            junction.transcripts.join(","),
+            junction.genes.join(","),
+            junction.motif.as_deref().unwrap_or(".")
+        )?;
+    }
+    writer.flush()?;
+    Ok(junctions.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collapses_shared_introns() {
+        let line = "chr1\t0\t20\tT1\t0\t-\t0\t0\t0\t2\t4,4,\t0,16,";
+        let records = [
+            line.to_string(),
+            line.replace("T1", "T2"),
+            line.replace("T1", "T3")
+                .replace("4,4,\t0,16,", "4,2,\t0,18,"),
+        ]
+        .map(|line| BedRecord::parse(&line).unwrap());
+        let isoforms = [("T1", "G1"), ("T2", "G2")]
+            .map(|(t, g)| (t.to_string(), g.to_string()))
+            .into();
+        let genome = Genome::from_reader(&b">chr1\nAAAACTACCCCCCCACAAAAAA\n"[..]).unwrap();
+
+        let junctions = collect_junctions(&records, &isoforms, Some(&genome));
+        assert_eq!(junctions.len(), 2);
+        assert_eq!((junctions[0].start, junctions[0].end), (5, 16));
+        assert_eq!(junctions[0].transcripts, ["T1", "T2"]);
+        assert_eq!(junctions[0].genes, ["G1", "G2"]);
+        // minus strand: the donor is the reverse complement of the last bases
+        assert_eq!(junctions[0].motif.as_deref(), Some("GT/AG"));
+        assert_eq!(junctions[1].genes, ["T3"]);
+    }
+}
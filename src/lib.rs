@@ -4,6 +4,12 @@ pub use bed::BedRecord;
 pub mod codon;
 pub use codon::*;
 
+pub mod frame;
+pub use frame::*;
+
+pub mod check;
+pub use check::*;
+
 pub mod lines;
 pub use lines::*;
 
@@ -12,3 +18,123 @@ pub use utils::*;
 
 pub mod cli;
 pub use cli::*;
+
+pub mod version;
+pub use version::*;
+
+pub mod error;
+pub use error::*;
+
+pub mod gz;
+pub use gz::*;
+
+pub mod fasta;
+pub use fasta::*;
+
+pub mod genetic_code;
+pub use genetic_code::*;
+
+pub mod genes;
+pub use genes::*;
+
+pub mod biotype;
+pub use biotype::*;
+
+pub mod dedup;
+pub use dedup::*;
+
+pub mod fix;
+pub use fix::*;
+
+pub mod filter;
+pub use filter::*;
+
+pub mod compat;
+pub use compat::*;
+
+pub mod meta;
+pub use meta::*;
+
+pub mod sort;
+pub use sort::*;
+
+pub mod index;
+pub use index::*;
+
+pub mod compare;
+pub use compare::*;
+
+pub mod merge;
+pub use merge::*;
+
+pub mod convert;
+pub use convert::*;
+
+pub mod deps;
+pub use deps::*;
+
+pub mod exec;
+pub use exec::*;
+
+pub mod logging;
+pub use logging::*;
+
+pub mod signals;
+pub use signals::*;
+
+pub mod warnings;
+pub use warnings::*;
+
+pub mod report;
+pub use report::*;
+pub mod manifest;
+pub use manifest::*;
+
+pub mod profile;
+pub use profile::*;
+
+pub mod format;
+pub use format::*;
+
+pub mod isoforms;
+pub use isoforms::*;
+
+pub mod alias;
+pub use alias::*;
+
+pub mod par;
+
+pub mod pipeline;
+pub use pipeline::*;
+
+pub mod spill;
+pub use spill::*;
+
+pub mod formatter;
+pub use formatter::*;
+
+pub mod sink;
+pub use sink::*;
+
+pub mod grammar;
+pub use grammar::*;
+
+pub mod coverage;
+pub use coverage::*;
+
+pub mod sites;
+pub use sites::*;
+
+pub mod junctions;
+pub use junctions::*;
+
+pub mod verify;
+pub use verify::*;
+
+pub mod selftest;
+pub use selftest::*;
+
+#[cfg(feature = "cloud")]
+pub mod cloud;
+#[cfg(feature = "cloud")]
+pub use cloud::*;
@@ -1,15 +1,526 @@
 use crate::bed::BedRecord;
 use crate::codon::*;
+use crate::formatter::{format_record, GtfFormatter};
+use crate::par::prelude::*;
+use crate::sort::ChromOrder;
+use sha2::{Digest, Sha256};
 use std::cmp::{max, min};
-use std::collections::HashMap;
-use std::fmt::Write;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
+use std::str::FromStr;
+
+/// How the frame/phase column is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum PhaseStyle {
+    /// Bases to skip to reach the next codon (GTF2.2 frame).
+    #[default]
+    Gtf,
+    /// GFF3 phase; same definition as the GTF2.2 frame column.
+    Gff3,
+    /// BED/genePred exon frame, passed through untouched.
+    Raw,
+}
+
+/// How exon and intron numbers are assigned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ExonNumbering {
+    /// 1 at the 5' end of the transcript: ascending on +, descending on -.
+    #[default]
+    Transcriptional,
+    /// 1 at the lowest coordinate, whatever the strand.
+    Genomic,
+}
+
+impl ExonNumbering {
+    /// 1-based number of block `index` (0-based, in genomic order) out of
+    /// `count` blocks.
+    pub fn number(self, index: usize, count: usize, strand: &str) -> usize {
+        assert!(index < count, "block {} out of {}", index, count);
+        match self {
+            ExonNumbering::Transcriptional if strand == "-" => count - index,
+            _ => index + 1,
+        }
+    }
+}
+
+/// How the children of a transcript (exons, CDS, codons...) are ordered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ExonOrder {
+    /// Ascending start, whatever the strand.
+    #[default]
+    Genomic,
+    /// 5' to 3' along the transcript: descending on -, as in Ensembl GTFs.
+    Transcription,
+}
+
+impl ExonOrder {
+    /// Sorts the children of one transcript of `strand`; the sort is
+    /// stable, so an exon stays ahead of its CDS.
+    pub fn sort(self, children: &mut [GtfRecord], strand: &str) {
+        match self {
+            ExonOrder::Transcription if strand == "-" => {
+                children.sort_by_key(|r| std::cmp::Reverse(r.end))
+            }
+            _ => children.sort_by_key(|r| r.start),
+        }
+    }
+}
+
+/// Order of the output lines (`--order`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OutputOrder {
+    /// By chromosome (see `--chrom-order`) and start.
+    #[default]
+    Coords,
+    /// By gene_id, then transcript_id (byte order, as `LC_ALL=C sort`),
+    /// then feature, for `diff` and `join` against ID-sorted tables.
+    ById,
+}
+
+/// Rank of each feature within a transcript in [`OutputOrder::ById`];
+/// others come last, by name.
+const FEATURE_RANK: [&str; 10] = [
+    "gene",
+    "transcript",
+    "exon",
+    "CDS",
+    "five_prime_utr",
+    "three_prime_utr",
+    "UTR",
+    "start_codon",
+    "stop_codon",
+    "intron",
+];
+
+/// Sorts `records` in [`OutputOrder::ById`]; features of one kind follow
+/// `exon_order`.
+pub fn sort_by_id(records: &mut [GtfRecord], exon_order: ExonOrder) {
+    let rank = |r: &GtfRecord| {
+        FEATURE_RANK
+            .iter()
+            .position(|f| *f == r.feature)
+            .unwrap_or(FEATURE_RANK.len())
+    };
+    // str order is byte order
+    fn id<'a>(r: &'a GtfRecord, key: &str) -> &'a str {
+        r.attrs.get(key).unwrap_or_default()
+    }
+    let position = |r: &GtfRecord| match exon_order {
+        ExonOrder::Transcription if r.strand == "-" => -(r.end as i64),
+        _ => r.start as i64,
+    };
+    records.par_sort_by(|a, b| {
+        id(a, "gene_id")
+            .cmp(id(b, "gene_id"))
+            .then_with(|| id(a, "transcript_id").cmp(id(b, "transcript_id")))
+            .then_with(|| rank(a).cmp(&rank(b)))
+            .then_with(|| a.feature.cmp(&b.feature))
+            .then_with(|| position(a).cmp(&position(b)))
+    });
+}
+
+pub const DEFAULT_ATTR_ORDER: [&str; 4] = ["gene_id", "transcript_id", "exon_number", "exon_id"];
+
+/// Attributes holding feature identifiers.
+pub const ID_ATTRS: [&str; 3] = ["gene_id", "transcript_id", "exon_id"];
+
+/// Syntax tweaks of GTF attribute strings (`--attr-style`), for readers
+/// that are stricter or looser than GTF2.2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum AttrSyntax {
+    /// No space between attributes: `gene_id "G1";transcript_id "T1";`.
+    NoSpace,
+    /// No `;` after the last attribute.
+    NoTrailingSemicolon,
+    /// Integer values, e.g. exon_number, unquoted: `exon_number 1;`.
+    BareNumbers,
+}
+
+/// How [`Attributes`] are serialized. The default is GTF2.2: every value
+/// quoted, attributes separated by `; ` and ending in `;`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttrStyle {
+    pub space: bool,
+    pub trailing_semicolon: bool,
+    pub quote_numbers: bool,
+}
+
+impl Default for AttrStyle {
+    fn default() -> Self {
+        AttrStyle {
+            space: true,
+            trailing_semicolon: true,
+            quote_numbers: true,
+        }
+    }
+}
+
+impl AttrStyle {
+    /// The GTF2.2 style with `syntax` applied.
+    pub fn new(syntax: &[AttrSyntax]) -> Self {
+        AttrStyle {
+            space: !syntax.contains(&AttrSyntax::NoSpace),
+            trailing_semicolon: !syntax.contains(&AttrSyntax::NoTrailingSemicolon),
+            quote_numbers: !syntax.contains(&AttrSyntax::BareNumbers),
+        }
+    }
+
+    fn quotes(self, value: &str) -> bool {
+        self.quote_numbers || value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit())
+    }
+}
+
+/// Ordered list of key/value attributes. Keys may repeat (e.g. `tag`).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Attributes(Vec<(String, String)>);
+
+impl Attributes {
+    pub fn new() -> Self {
+        Attributes(Vec::new())
+    }
+
+    pub fn push<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+        self.0.push((key.into(), value.into()));
+    }
+
+    pub fn extend(&mut self, other: &Attributes) {
+        self.0.extend(other.0.iter().cloned());
+    }
+
+    /// First value stored under `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Rewrites every value stored under `key`.
+    pub fn update<F: Fn(&str) -> String>(&mut self, key: &str, f: F) {
+        for (_, v) in self.0.iter_mut().filter(|(k, _)| k == key) {
+            *v = f(v);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(String, String)> {
+        self.0.iter()
+    }
+
+    /// Renders `key "value";` pairs in `style`. Keys listed in `order`
+    /// come first, in that order; everything else follows in insertion
+    /// order.
+    pub fn render(&self, order: &[String], style: AttrStyle) -> String {
+        let mut out = String::new();
+        self.render_into(order, style, &mut out);
+        out
+    }
+
+    /// [`Attributes::render`] appending to `out`, so callers can reuse
+    /// one buffer for many lines.
+    pub fn render_into(&self, order: &[String], style: AttrStyle, out: &mut String) {
+        let mut first = true;
+        let mut emit = |k: &str, v: &str| {
+            if !first {
+                out.push(';');
+                if style.space {
+                    out.push(' ');
+                }
+            }
+            first = false;
+            if style.quotes(v) {
+                write!(out, "{} \"{}\"", k, v).expect("Failed to write attribute");
+            } else {
+                write!(out, "{} {}", k, v).expect("Failed to write attribute");
+            }
+        };
+
+        for key in order {
+            self.0
+                .iter()
+                .filter(|(k, _)| k == key)
+                .for_each(|(k, v)| emit(k, v));
+        }
+        self.0
+            .iter()
+            .filter(|(k, _)| !order.contains(k))
+            .for_each(|(k, v)| emit(k, v));
+        if !first && style.trailing_semicolon {
+            out.push(';');
+        }
+    }
+}
+
+/// One output feature line. `frame` holds the BED/genePred exon frame
+/// (bases of CDS preceding the feature, mod 3) and is rendered into
+/// the phase column according to a [`PhaseStyle`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct GtfRecord {
+    pub chrom: String,
+    pub feature: String,
+    pub start: u32,
+    pub end: u32,
+    pub strand: String,
+    pub frame: Option<u8>,
+    pub attrs: Attributes,
+    /// Column 2 when not `bed2gtf`, e.g. from `--source-map`.
+    pub source: Option<String>,
+}
+
+impl GtfRecord {
+    pub fn phase(&self, style: PhaseStyle) -> String {
+        self.phase_char(style).to_string()
+    }
+
+    /// The phase column as a single character.
+    pub fn phase_char(&self, style: PhaseStyle) -> char {
+        match (self.frame, style) {
+            (None, _) => '.',
+            (Some(frame), PhaseStyle::Raw) => (b'0' + frame) as char,
+            (Some(frame), _) => (b'0' + (3 - frame) % 3) as char,
+        }
+    }
+}
+
+/// Parses `key "value";` pairs in any [`AttrStyle`]: values may be
+/// unquoted, and the space and the last `;` may be missing.
+impl FromStr for Attributes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Attributes, String> {
+        let mut attrs = Attributes::new();
+        let mut rest = s.trim();
+        while !rest.is_empty() {
+            let (key, after) = rest
+                .split_once(' ')
+                .ok_or_else(|| format!("attribute {:?} has no value", rest))?;
+            let (value, after) = match after.strip_prefix('"') {
+                Some(quoted) => {
+                    let end = quoted
+                        .find('"')
+                        .ok_or_else(|| format!("unterminated value of {}", key))?;
+                    (&quoted[..end], &quoted[end + 1..])
+                }
+                None => {
+                    let end = after.find(';').unwrap_or(after.len());
+                    (after[..end].trim_end(), &after[end..])
+                }
+            };
+            attrs.push(key, value);
+            let after = after.trim_start();
+            rest = after.strip_prefix(';').unwrap_or(after).trim_start();
+        }
+        Ok(attrs)
+    }
+}
+
+/// The GTF2.2 line of the record, without its newline, as
+/// [`GtfFormatter`] writes it with the default options.
+impl fmt::Display for GtfRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = String::new();
+        format_record(&GtfFormatter::default(), &mut out, self, false);
+        f.write_str(out.trim_end_matches('\n'))
+    }
+}
+
+/// Parses a GTF line, reading column 8 as a GTF2.2 phase. The score
+/// column is not kept.
+impl FromStr for GtfRecord {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<GtfRecord, String> {
+        let fields = line
+            .trim_end_matches(['\r', '\n'])
+            .split('\t')
+            .collect::<Vec<_>>();
+        if fields.len() != 9 {
+            return Err(format!("expected 9 columns, found {}", fields.len()));
+        }
+        let number = |i: usize| {
+            fields[i]
+                .parse::<u32>()
+                .map_err(|_| format!("invalid coordinate {:?}", fields[i]))
+        };
+        let frame = match fields[7] {
+            "." => None,
+            "0" => Some(0),
+            "1" => Some(2),
+            "2" => Some(1),
+            phase => return Err(format!("invalid phase {:?}", phase)),
+        };
+        Ok(GtfRecord {
+            chrom: fields[0].to_string(),
+            feature: fields[2].to_string(),
+            start: number(3)?,
+            end: number(4)?,
+            strand: fields[6].to_string(),
+            frame,
+            attrs: fields[8].parse()?,
+            source: Some(fields[1])
+                .filter(|source| *source != "bed2gtf")
+                .map(String::from),
+        })
+    }
+}
+
+/// Wraps every gene_id, transcript_id and exon_id in `prefix`/`suffix`.
+pub fn add_id_affixes(records: &mut [GtfRecord], prefix: &str, suffix: &str) {
+    records.par_iter_mut().for_each(|record| {
+        for key in ID_ATTRS {
+            record
+                .attrs
+                .update(key, |id| format!("{}{}{}", prefix, id, suffix));
+        }
+    });
+}
+
+/// Replaces the per-transcript `{tx}.{n}` exon_ids of one transcript's
+/// lines with IDs shared by identical exons across isoforms: `E` and the
+/// first 16 hex digits of the SHA-256 of `chrom:start-end:strand`. CDS
+/// and codon lines keep pointing at their exon.
+pub fn shared_exon_ids(records: &mut [GtfRecord]) {
+    let ids = records
+        .iter()
+        .filter(|r| r.feature == "exon")
+        .filter_map(|r| {
+            let key = format!("{}:{}-{}:{}", r.chrom, r.start, r.end, r.strand);
+            let digest = Sha256::digest(key.as_bytes());
+            let mut id = String::from("E");
+            for byte in &digest[..8] {
+                write!(id, "{:02x}", byte).expect("Failed to write exon_id");
+            }
+            Some((r.attrs.get("exon_id")?.to_string(), id))
+        })
+        .collect::<HashMap<_, _>>();
+    for record in records.iter_mut() {
+        record.attrs.update("exon_id", |old| {
+            ids.get(old).cloned().unwrap_or_else(|| old.to_string())
+        });
+    }
+}
+
+/// Splits features on circular contigs that run past the contig end into
+/// two joined segments: `start..=size` and `1..=end - size`. Both pieces
+/// keep the attributes of the original line; CDS frames are shifted so
+/// that each piece keeps its reading frame.
+pub fn wrap_circular(
+    records: Vec<GtfRecord>,
+    sizes: &HashMap<String, u32>,
+    circular: &HashSet<String>,
+) -> Vec<GtfRecord> {
+    let mut out = Vec::with_capacity(records.len());
+    for record in records {
+        let size = match sizes.get(&record.chrom) {
+            Some(&size) if circular.contains(&record.chrom) && record.end > size => size,
+            _ => {
+                out.push(record);
+                continue;
+            }
+        };
+
+        if record.start > size {
+            // entirely past the origin
+            out.push(GtfRecord {
+                start: record.start - size,
+                end: record.end - size,
+                ..record
+            });
+            continue;
+        }
+
+        let head_len = size - record.start + 1;
+        let tail_len = record.end - size;
+        let (head_frame, tail_frame) = match (record.frame, record.strand.as_str()) {
+            (Some(f), "-") => (Some(((f as u32 + tail_len) % 3) as u8), Some(f)),
+            (Some(f), _) => (Some(f), Some(((f as u32 + head_len) % 3) as u8)),
+            (None, _) => (None, None),
+        };
+
+        out.push(GtfRecord {
+            start: 1,
+            end: tail_len,
+            frame: tail_frame,
+            ..record.clone()
+        });
+        out.push(GtfRecord {
+            end: size,
+            frame: head_frame,
+            ..record
+        });
+    }
+    out
+}
+
+/// Lays out records in BED input order instead of by coordinate: each
+/// gene line (followed by any `extra` records of that gene) right before
+/// its first transcript, then the transcript line and its children in
+/// `exon_order`.
+pub fn input_order(
+    genes: Vec<GtfRecord>,
+    transcripts: Vec<Vec<GtfRecord>>,
+    extra: Vec<GtfRecord>,
+    exon_order: ExonOrder,
+) -> Vec<GtfRecord> {
+    let gene_id = |r: &GtfRecord| r.attrs.get("gene_id").unwrap_or_default().to_string();
+
+    let mut genes = genes
+        .into_iter()
+        .map(|g| (gene_id(&g), vec![g]))
+        .collect::<HashMap<_, _>>();
+    for record in extra {
+        genes.entry(gene_id(&record)).or_default().push(record);
+    }
+
+    let mut out = Vec::with_capacity(transcripts.iter().map(|t| t.len()).sum::<usize>());
+    for mut lines in transcripts {
+        if let Some(gene) = lines.first().and_then(|t| genes.remove(&gene_id(t))) {
+            out.extend(gene);
+        }
+        // the transcript line stays first
+        let strand = lines[0].strand.clone();
+        exon_order.sort(&mut lines[1..], &strand);
+        out.extend(lines);
+    }
+    out
+}
+
+/// Sorts converted transcripts (transcript line first) so that those of
+/// a gene are adjacent and genes follow `order` and their start, for
+/// [`input_order`] to lay out Ensembl-style gene blocks.
+pub fn gene_order(transcripts: &mut [Vec<GtfRecord>], genes: &[GtfRecord], order: &ChromOrder) {
+    let starts = genes
+        .iter()
+        .filter_map(|g| Some((g.attrs.get("gene_id")?, g.start)))
+        .collect::<HashMap<_, _>>();
+    fn gene_id(lines: &[GtfRecord]) -> &str {
+        lines[0].attrs.get("gene_id").unwrap_or_default()
+    }
+    let gene_start = |lines: &[GtfRecord]| {
+        starts
+            .get(gene_id(lines))
+            .copied()
+            .unwrap_or(lines[0].start)
+    };
+    let ranks = order.ranks(transcripts.iter().map(|t| t[0].chrom.as_str()));
+    transcripts.sort_by(|a, b| {
+        ranks[&a[0].chrom]
+            .cmp(&ranks[&b[0].chrom])
+            .then(gene_start(a).cmp(&gene_start(b)))
+            .then_with(|| gene_id(a).cmp(gene_id(b)))
+            .then(a[0].start.cmp(&b[0].start))
+    });
+}
 
 pub fn build_gene_line(
-    gene: &String,
+    gene: &str,
     record: &BedRecord,
     coords: &HashMap<String, (u32, u32)>,
 ) -> String {
-    assert!(gene.len() > 0);
+    assert!(!gene.is_empty());
 
     let (tx_start, tx_end) = coords.get(gene).unwrap();
 
@@ -25,126 +536,475 @@ pub fn build_gene_line(
     gene_line
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn build_gtf_line(
     record: &BedRecord,
-    gene: &String,
+    gene: &str,
     gene_type: &str,
     exon_start: u32,
     exon_end: u32,
     frame: u32,
-    exon: i16,
-    result: &mut Vec<(String, String, u32, u32, String, String, String)>,
+    exon: Option<usize>,
+    numbering: ExonNumbering,
+    result: &mut Vec<GtfRecord>,
 ) {
     assert!(record.tx_start < record.tx_end);
 
-    let phase = match frame {
-        0 => "0",
-        1 => "2",
-        2 => "1",
-        _ => ".",
-    };
+    let frame = if frame < 3 { Some(frame as u8) } else { None };
 
-    let mut attr = format!("gene_id \"{}\"; transcript_id \"{}\";", gene, record.name);
+    let mut attrs = Attributes::new();
+    attrs.push("gene_id", gene);
+    attrs.push("transcript_id", record.name.as_str());
 
-    if exon >= 0 {
-        let (exon_id, nexon) = if record.strand == "+" {
-            let exon_id = exon + 1;
-            (exon_id as u16, exon + 1)
-        } else {
-            let exon_id = record.exon_count - exon as u16;
-            (exon_id, exon_id as i16)
-        };
+    if let Some(exon) = exon {
+        let number = numbering.number(exon, record.exon_start.len(), &record.strand);
+        attrs.push("exon_number", number.to_string());
+        attrs.push("exon_id", format!("{}.{}", record.name, number));
+    }
 
-        write!(
-            attr,
-            " exon_number \"{}\"; exon_id \"{}.{}\";",
-            nexon, record.name, exon_id
+    result.push(GtfRecord {
+        chrom: record.chrom.clone(),
+        feature: gene_type.to_string(),
+        start: exon_start + 1,
+        end: exon_end,
+        strand: record.strand.clone(),
+        frame,
+        attrs,
+        source: None,
+    });
+}
+
+/// Pushes one `intron` feature per gap between consecutive exons, with
+/// `intron_number` counted 5'->3'.
+pub fn write_introns(
+    record: &BedRecord,
+    gene: &str,
+    numbering: ExonNumbering,
+    result: &mut Vec<GtfRecord>,
+) {
+    let n = record.exon_start.len();
+    for i in 0..n.saturating_sub(1) {
+        let (start, end) = (record.exon_end[i], record.exon_start[i + 1]);
+        if start >= end {
+            continue;
+        }
+
+        let number = numbering.number(i, n - 1, &record.strand);
+        let mut attrs = Attributes::new();
+        attrs.push("gene_id", gene);
+        attrs.push("transcript_id", record.name.as_str());
+        attrs.push("intron_number", number.to_string());
+        result.push(GtfRecord {
+            chrom: record.chrom.clone(),
+            feature: "intron".to_string(),
+            start: start + 1,
+            end,
+            strand: record.strand.clone(),
+            frame: None,
+            attrs,
+            source: None,
+        });
+    }
+}
+
+/// Promoter regions of `--add-promoters UP[,DOWN]`, in bp upstream of
+/// the TSS and downstream of it, into the transcript.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Promoters {
+    pub up: u32,
+    pub down: u32,
+}
+
+/// Parses the `UP[,DOWN]` of `--add-promoters`; DOWN defaults to 0.
+pub fn parse_promoters(s: &str) -> Result<Promoters, String> {
+    let number = |value: &str| {
+        value
+            .trim()
+            .parse::<u32>()
+            .map_err(|_| format!("invalid length {:?}, expected UP[,DOWN] in bp", value))
+    };
+    let (up, down) = match s.split_once(',') {
+        Some((up, down)) => (number(up)?, number(down)?),
+        None => (number(s)?, 0),
+    };
+    if up == 0 && down == 0 {
+        return Err("promoters need a length upstream or downstream".to_string());
+    }
+    Ok(Promoters { up, down })
+}
+
+/// Adds the `promoter` feature of `record`, from `promoters.up` bp
+/// upstream of its TSS to `promoters.down` bp downstream, on the
+/// transcript strand. It is clipped at 0 and, when known, at the
+/// `contig_len`; nothing is added when clipping leaves it empty.
+pub fn write_promoter(
+    record: &BedRecord,
+    gene: &str,
+    promoters: Promoters,
+    contig_len: Option<u32>,
+    result: &mut Vec<GtfRecord>,
+) {
+    let (start, end) = if record.strand == "-" {
+        (
+            record.tx_end.saturating_sub(promoters.down),
+            record.tx_end.saturating_add(promoters.up),
         )
-        .expect("Failed to write exon information");
+    } else {
+        (
+            record.tx_start.saturating_sub(promoters.up),
+            record.tx_start.saturating_add(promoters.down),
+        )
+    };
+    let end = contig_len.map_or(end, |len| end.min(len));
+    if start >= end {
+        return;
     }
 
-    result.push((
-        record.chrom.clone(),
-        gene_type.to_string(),
-        exon_start + 1,
-        exon_end,
-        record.strand.clone(),
-        phase.to_string(),
-        attr,
-    ));
+    let mut attrs = Attributes::new();
+    attrs.push("gene_id", gene);
+    attrs.push("transcript_id", record.name.as_str());
+    result.push(GtfRecord {
+        chrom: record.chrom.clone(),
+        feature: "promoter".to_string(),
+        start: start + 1,
+        end,
+        strand: record.strand.clone(),
+        frame: None,
+        attrs,
+        source: None,
+    });
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn write_features(
     i: usize,
     record: &BedRecord,
-    gene: &String,
-    // first_utr_end: u32,
+    gene: &str,
     cds_start: u32,
     cds_end: u32,
-    // last_utr_start: u32,
     frame: u32,
-    result: &mut Vec<(String, String, u32, u32, String, String, String)>,
+    numbering: ExonNumbering,
+    result: &mut Vec<GtfRecord>,
 ) {
     let exon_start = record.exon_start[i];
     let exon_end = record.exon_end[i];
 
-    // if exon_start < first_utr_end {
-    //     let end = min(exon_end, first_utr_end);
-    //     let utr_type = if record.strand == "+" {
-    //         "five_prime_utr"
-    //     } else {
-    //         "three_prime_utr"
-    //     };
-    //     build_gtf_line(record, gene, utr_type, exon_start, end, frame, -1, result);
-    // }
-
     if record.cds_start < exon_end && exon_start < record.cds_end {
         let start = max(exon_start, cds_start);
         let end = min(exon_end, cds_end);
         if start < end {
-            build_gtf_line(record, gene, "CDS", start, end, frame, i as i16, result);
+            build_gtf_line(
+                record,
+                gene,
+                "CDS",
+                start,
+                end,
+                frame,
+                Some(i),
+                numbering,
+                result,
+            );
         }
     }
+}
 
-    // if exon_end > last_utr_start {
-    //     let start = max(exon_start, last_utr_start);
-    //     let utr_type = if record.strand == "+" {
-    //         "three_prime_utr"
-    //     } else {
-    //         "five_prime_utr"
-    //     };
-    //     build_gtf_line(record, gene, utr_type, start, exon_end, frame, -1, result);
-    // }
+/// Pushes the `five_prime_utr`/`three_prime_utr` parts of exon `i`, those
+/// outside the thick region of a coding `record`. UTRs carry no frame and
+/// no exon number, as in Ensembl.
+pub fn write_utrs(i: usize, record: &BedRecord, gene: &str, result: &mut Vec<GtfRecord>) {
+    let (exon_start, exon_end) = (record.exon_start[i], record.exon_end[i]);
+    let (left, right) = if record.strand == "-" {
+        ("three_prime_utr", "five_prime_utr")
+    } else {
+        ("five_prime_utr", "three_prime_utr")
+    };
+    let numbering = ExonNumbering::Transcriptional;
+
+    if exon_start < record.cds_start {
+        let end = min(exon_end, record.cds_start);
+        build_gtf_line(
+            record, gene, left, exon_start, end, 3, None, numbering, result,
+        );
+    }
+    if exon_end > record.cds_end {
+        let start = max(exon_start, record.cds_end);
+        build_gtf_line(
+            record, gene, right, start, exon_end, 3, None, numbering, result,
+        );
+    }
 }
 
+/// Writes a start/stop codon. Codons split by introns are written as one
+/// line per genomic piece, each with its own exon number and the frame
+/// of the codon bases preceding it in transcription order.
 pub fn write_codon(
     record: &BedRecord,
-    gene: &String,
+    gene: &str,
     gene_type: &str,
     codon: Codon,
-    result: &mut Vec<(String, String, u32, u32, String, String, String)>,
+    numbering: ExonNumbering,
+    result: &mut Vec<GtfRecord>,
 ) {
-    build_gtf_line(
-        record,
-        gene,
-        gene_type,
-        codon.start,
-        codon.end,
-        0,
-        codon.index as i16,
-        result,
-    );
+    let mut before = 0;
+    let mut frames = vec![0; codon.pieces.len()];
+    let order: Vec<usize> = if record.strand == "-" {
+        (0..codon.pieces.len()).rev().collect()
+    } else {
+        (0..codon.pieces.len()).collect()
+    };
+    for i in order {
+        let (start, end, _) = codon.pieces[i];
+        frames[i] = before;
+        before += end - start;
+    }
 
-    if codon.start2 < codon.end2 {
+    for (&(start, end, index), frame) in codon.pieces.iter().zip(frames) {
         build_gtf_line(
             record,
             gene,
             gene_type,
-            codon.start,
-            codon.end,
-            codon.start2,
-            (codon.end - codon.start) as i16,
+            start,
+            end,
+            frame,
+            Some(index as usize),
+            numbering,
             result,
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_attributes_in_order() {
+        let mut attrs = Attributes::new();
+        attrs.push("transcript_id", "T1");
+        attrs.push("tag", "cds_end_NF");
+        attrs.push("gene_id", "G1");
+        attrs.push("tag", "basic");
+
+        let order = DEFAULT_ATTR_ORDER.map(String::from);
+        let style = AttrStyle::default();
+        assert_eq!(
+            attrs.render(&order, style),
+            "gene_id \"G1\"; transcript_id \"T1\"; tag \"cds_end_NF\"; tag \"basic\";"
+        );
+        assert_eq!(
+            attrs.render(&["tag".to_string()], style),
+            "tag \"cds_end_NF\"; tag \"basic\"; transcript_id \"T1\"; gene_id \"G1\";"
+        );
+
+        let mut attrs = Attributes::new();
+        attrs.push("gene_id", "7");
+        attrs.push("exon_number", "12");
+        attrs.push("exon_id", "T1.12");
+        let style = AttrStyle::new(&[AttrSyntax::NoSpace, AttrSyntax::NoTrailingSemicolon]);
+        assert_eq!(
+            attrs.render(&[], style),
+            "gene_id \"7\";exon_number \"12\";exon_id \"T1.12\""
+        );
+        let style = AttrStyle::new(&[AttrSyntax::BareNumbers]);
+        assert_eq!(
+            attrs.render(&[], style),
+            "gene_id 7; exon_number 12; exon_id \"T1.12\";"
+        );
+    }
+
+    #[test]
+    fn round_trips_gtf_lines() {
+        let line = "chr1\tbed2gtf\tCDS\t51\t100\t.\t-\t2\tgene_id \"G1\"; transcript_id \"T1\"; \
+                    exon_number \"2\"; tag \"a; b\";";
+        let record = line.parse::<GtfRecord>().unwrap();
+        assert_eq!(record.frame, Some(1));
+        assert_eq!(record.attrs.get("tag"), Some("a; b"));
+        assert_eq!(record.to_string(), line);
+
+        let compact = "gene_id \"G1\";exon_number 2";
+        let attrs = compact.parse::<Attributes>().unwrap();
+        assert_eq!(attrs.get("exon_number"), Some("2"));
+        assert_eq!(
+            attrs.render(&[], AttrStyle::default()),
+            "gene_id \"G1\"; exon_number \"2\";"
+        );
+        assert!("chr1\tbed2gtf\texon".parse::<GtfRecord>().is_err());
+    }
+
+    #[test]
+    fn promoters_follow_strand_and_contig_bounds() {
+        let line = "chr1\t100\t900\tT1\t0\t+\t0\t0\t0\t1\t800,\t0,";
+        let promoters = parse_promoters("500,50").unwrap();
+        let span = |line: &str, contig_len| {
+            let mut result = Vec::new();
+            let record = BedRecord::parse(line).unwrap();
+            write_promoter(&record, "G1", promoters, contig_len, &mut result);
+            result.iter().map(|r| (r.start, r.end)).collect::<Vec<_>>()
+        };
+        assert_eq!(span(line, None), [(1, 150)]);
+        let minus = line.replace("\t+\t", "\t-\t");
+        assert_eq!(span(&minus, None), [(851, 1400)]);
+        assert_eq!(span(&minus, Some(1000)), [(851, 1000)]);
+        assert_eq!(span(&minus, Some(850)), []);
+        assert_eq!(parse_promoters("2000"), Ok(Promoters { up: 2000, down: 0 }));
+        assert!(parse_promoters("0,0").is_err());
+    }
+
+    #[test]
+    fn utrs_follow_strand() {
+        let line = "chr1\t0\t900\tT\t0\t+\t50\t850\t0\t2\t100,100,\t0,800,";
+        let utrs = |line: &str| {
+            let record = BedRecord::parse(line).unwrap();
+            let mut result = Vec::new();
+            for i in 0..record.exon_start.len() {
+                write_utrs(i, &record, "G", &mut result);
+            }
+            result
+                .iter()
+                .map(|r| (r.feature.clone(), r.start, r.end))
+                .collect::<Vec<_>>()
+        };
+        let five = |start, end| ("five_prime_utr".to_string(), start, end);
+        let three = |start, end| ("three_prime_utr".to_string(), start, end);
+        assert_eq!(utrs(line), [five(1, 50), three(851, 900)]);
+        let minus = line.replace("\t+\t", "\t-\t");
+        assert_eq!(utrs(&minus), [three(1, 50), five(851, 900)]);
+    }
+
+    #[test]
+    fn exon_numbering_by_strand() {
+        let exons = |strand: &str, numbering: ExonNumbering| {
+            let record = BedRecord::parse(&format!(
+                "chr1\t0\t900\tT\t0\t{}\t0\t0\t0\t3\t100,100,100,\t0,400,800,",
+                strand
+            ))
+            .unwrap();
+            let mut lines = Vec::new();
+            for i in 0..3 {
+                let (start, end) = (record.exon_start[i], record.exon_end[i]);
+                build_gtf_line(
+                    &record,
+                    "G",
+                    "exon",
+                    start,
+                    end,
+                    3,
+                    Some(i),
+                    numbering,
+                    &mut lines,
+                );
+            }
+            lines
+                .iter()
+                .map(|l| l.attrs.get("exon_number").unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        // lines are in genomic order: the first is the 3' exon on -
+        assert_eq!(exons("+", ExonNumbering::Transcriptional), ["1", "2", "3"]);
+        assert_eq!(exons("-", ExonNumbering::Transcriptional), ["3", "2", "1"]);
+        assert_eq!(exons("+", ExonNumbering::Genomic), ["1", "2", "3"]);
+        assert_eq!(exons("-", ExonNumbering::Genomic), ["1", "2", "3"]);
+    }
+
+    #[test]
+    fn exons_in_transcription_order() {
+        let record =
+            BedRecord::parse("chr1\t0\t900\tT\t0\t-\t50\t850\t0\t2\t100,100,\t0,800,").unwrap();
+        let mut lines = Vec::new();
+        for (feature, start, end) in [("exon", 0, 100), ("CDS", 50, 100), ("exon", 800, 900)] {
+            build_gtf_line(
+                &record,
+                "G",
+                feature,
+                start,
+                end,
+                0,
+                Some(0),
+                ExonNumbering::Transcriptional,
+                &mut lines,
+            );
+        }
+        let order = |lines: &[GtfRecord]| {
+            lines
+                .iter()
+                .map(|l| format!("{}:{}", l.feature, l.start))
+                .collect::<Vec<_>>()
+        };
+        ExonOrder::Transcription.sort(&mut lines, "-");
+        assert_eq!(order(&lines), ["exon:801", "exon:1", "CDS:51"]);
+        ExonOrder::Genomic.sort(&mut lines, "-");
+        assert_eq!(order(&lines), ["exon:1", "CDS:51", "exon:801"]);
+        sort_by_id(&mut lines, ExonOrder::Transcription);
+        assert_eq!(order(&lines), ["exon:801", "exon:1", "CDS:51"]);
+
+        // transcripts of a gene stay together even when another gene starts between them
+        let transcript = |gene: &str, chrom: &str, start: u32| {
+            let mut attrs = Attributes::new();
+            attrs.push("gene_id", gene);
+            vec![GtfRecord {
+                chrom: chrom.to_string(),
+                feature: "transcript".to_string(),
+                start,
+                end: start + 100,
+                strand: "+".to_string(),
+                frame: None,
+                attrs,
+                source: None,
+            }]
+        };
+        let genes = [transcript("A", "chr1", 10), transcript("B", "chr1", 20)].concat();
+        let mut transcripts = vec![
+            transcript("A", "chr2", 5),
+            transcript("A", "chr1", 30),
+            transcript("B", "chr1", 20),
+            transcript("A", "chr1", 10),
+        ];
+        gene_order(&mut transcripts, &genes, &ChromOrder::Natural);
+        let starts = transcripts
+            .iter()
+            .map(|t| (t[0].chrom.as_str(), t[0].start))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            starts,
+            [("chr1", 10), ("chr1", 30), ("chr1", 20), ("chr2", 5)]
+        );
+    }
+
+    #[test]
+    fn shares_ids_of_identical_exons() {
+        let lines = |name: &str, sizes: &str, starts: &str| {
+            let record = BedRecord::parse(&format!(
+                "chr1\t0\t900\t{}\t0\t+\t0\t0\t0\t2\t{}\t{}",
+                name, sizes, starts
+            ))
+            .unwrap();
+            let mut lines = Vec::new();
+            for i in 0..2 {
+                let (start, end) = (record.exon_start[i], record.exon_end[i]);
+                build_gtf_line(
+                    &record,
+                    "G",
+                    "exon",
+                    start,
+                    end,
+                    3,
+                    Some(i),
+                    ExonNumbering::Transcriptional,
+                    &mut lines,
+                );
+            }
+            shared_exon_ids(&mut lines);
+            lines
+                .iter()
+                .map(|l| l.attrs.get("exon_id").unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let a = lines("A", "100,100,", "0,800,");
+        let b = lines("B", "200,100,", "0,800,");
+        assert_ne!(a[0], b[0]);
+        assert_eq!(a[1], b[1]);
+        assert!(a[1].starts_with('E') && a[1].len() == 17);
+    }
+}
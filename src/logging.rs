@@ -0,0 +1,122 @@
+use crate::utils::use_color;
+
+use log::{LevelFilter, Log, Metadata, Record};
+use simple_logger::SimpleLogger;
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Console logger that also appends every record, timestamped, to a
+/// `--log-file`. The file keeps debug messages whatever the console
+/// shows, and every record is written straight through so nothing is
+/// lost when the process exits early.
+pub struct TeeLogger {
+    console: SimpleLogger,
+    console_level: LevelFilter,
+    file: Option<Mutex<File>>,
+}
+
+impl TeeLogger {
+    pub fn new(level: LevelFilter, color: bool) -> TeeLogger {
+        TeeLogger {
+            console: SimpleLogger::new().with_level(level).with_colors(color),
+            console_level: level,
+            file: None,
+        }
+    }
+
+    /// Appends to `path`, so array tasks sharing a file are kept apart
+    /// only by their timestamps and targets.
+    pub fn with_file<P: AsRef<Path>>(mut self, path: P) -> io::Result<TeeLogger> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        self.file = Some(Mutex::new(file));
+        Ok(self)
+    }
+
+    /// Installs the logger as the global `log` backend.
+    pub fn init(self) -> Result<(), log::SetLoggerError> {
+        let max = match self.file {
+            Some(_) => LevelFilter::Debug.max(self.console_level),
+            None => self.console_level,
+        };
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(max);
+        Ok(())
+    }
+}
+
+/// Sends the conversion's log messages to stderr at `level`, for
+/// programs embedding the library (the library itself never installs a
+/// logger). Safe to call more than once and alongside the host's own
+/// logger: when one is already installed it is kept and this does
+/// nothing. Returns whether this call installed the logger.
+pub fn init_logging(level: LevelFilter) -> bool {
+    TeeLogger::new(level, use_color()).init().is_ok()
+}
+
+impl Log for TeeLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.console_level
+            || (self.file.is_some() && metadata.level() <= LevelFilter::Debug)
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() <= self.console_level {
+            self.console.log(record);
+        }
+        if let (Some(file), true) = (&self.file, record.level() <= LevelFilter::Debug) {
+            let line = format!(
+                "{} {:<5} [{}] {}\n",
+                chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                record.level(),
+                record.target(),
+                record.args()
+            );
+            if let Ok(mut file) = file.lock() {
+                let _ = file.write_all(line.as_bytes());
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(Ok(mut file)) = self.file.as_ref().map(Mutex::lock) {
+            let _ = file.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tees_records_to_file() {
+        let path = std::env::temp_dir().join(format!("bed2gtf-log-{}.log", std::process::id()));
+        let logger = TeeLogger::new(LevelFilter::Warn, false)
+            .with_file(&path)
+            .unwrap();
+        for level in [log::Level::Debug, log::Level::Trace] {
+            logger.log(
+                &Record::builder()
+                    .level(level)
+                    .target("bed2gtf")
+                    .args(format_args!("{} message", level))
+                    .build(),
+            );
+        }
+        logger.flush();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(contents.contains("DEBUG [bed2gtf] DEBUG message"));
+        assert!(!contents.contains("TRACE"));
+        std::fs::remove_file(&path).unwrap();
+
+        let installed = init_logging(LevelFilter::Warn);
+        assert!(!init_logging(LevelFilter::Debug));
+        if installed {
+            assert_eq!(log::max_level(), LevelFilter::Warn);
+        }
+    }
+}
@@ -32,7 +32,7 @@
 //! - `input.bed` is the input BED file you want to convert.
 //! - `isoforms.txt` is a file that contains information about isoforms.
 //! - `output.gff3` is the output GTF file where the conversion results
-//! will be stored.
+//!   will be stored.
 //!
 //! ## Output
 //!
@@ -47,122 +47,164 @@
 //! visit the [GitHub repository](https://github.com/alejandrogzi/bed2gtf).
 //! We welcome your feedback and contributions to enhance this tool.
 
-use std::collections::HashMap;
 use std::error::Error;
-use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::io::{BufWriter, IsTerminal, Write};
 use std::path::Path;
-use std::string::String;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use clap::Parser;
-use flate2::write::GzEncoder;
-use flate2::Compression;
-use log::{error, Level};
-use natord::compare;
-use rayon::prelude::*;
+use log::{error, warn, LevelFilter};
 
 use bed2gtf::*;
 
-const SOURCE: &str = "bed2gtf";
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let args = Cli::parse();
-    args.check().unwrap_or_else(|e| {
-        error!("{}", e);
-        std::process::exit(1);
-    });
+    let color = use_color();
+    colored::control::set_override(color);
+    let mut args = Cli::parse();
+    args.command_line = std::env::args().collect();
+    args.invoked_format = std::env::args_os()
+        .next()
+        .map_or(Ok(None), |program| invocation_format(Path::new(&program)))
+        .unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        });
+    if args.version_json {
+        print_version_json(&args).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        });
+        return Ok(());
+    }
+
+    let logger = TeeLogger::new(LevelFilter::Info, color);
+    let logger = match &args.log_file {
+        Some(path) => logger.with_file(path).unwrap_or_else(|e| {
+            eprintln!("Error: cannot open log file {}: {}", path.display(), e);
+            std::process::exit(1);
+        }),
+        None => logger,
+    };
+    logger.init().unwrap();
+    log::debug!("Command line: {}", args.command_line.join(" "));
 
-    msg();
-    simple_logger::init_with_level(Level::Info).unwrap();
+    args.check().unwrap_or_else(|e| fail(e.into()));
+    args.resolve().unwrap_or_else(|e| fail(e.into()));
 
-    rayon::ThreadPoolBuilder::new()
+    if args.writes_stdout() {
+        restore_sigpipe();
+    }
+    if !args.no_banner && std::io::stderr().is_terminal() {
+        msg();
+    }
+    install_handlers();
+
+    bed2gtf::par::ThreadPoolBuilder::new()
         .num_threads(args.threads)
         .build_global()
         .unwrap();
 
-    log::info!("Using {} threads", args.threads);
+    log::info!("Using {} threads", bed2gtf::par::current_num_threads());
 
     let start = Instant::now();
     let bmem = max_mem_usage_mb();
 
-    let imap = if !args.no_gene {
-        let isf = reader(&args.isoforms.unwrap()).unwrap_or_else(|_| {
-            let message = format!("Error reading isoforms file",);
-            panic!("{}", message);
-        });
-        get_isoforms(&isf)
-    } else {
-        HashMap::new()
-    };
-
-    let bed = match args.bed.extension().and_then(|s| s.to_str()) {
-        Some("gz") => {
-            let bed = match Path::new(args.bed.file_stem().unwrap())
-                .extension()
-                .expect("ERROR: No extension found")
-                .to_str()
-            {
-                Some("bed") => {
-                    let contents = with_gz(&args.bed)?;
-                    parallel_parse(&contents)?
-                }
-                _ => panic!("ERROR: Not a .BED/.BED.GZ. Wrong file format!"),
+    match &args.command {
+        Some(Command::Index(index)) => {
+            let output = index_gtf(&index.gtf, index.output.as_deref(), args.gz_level)?;
+            log::info!("Wrote {} and {}.tbi", output.display(), output.display());
+            log::info!("Elapsed: {:.4?} secs", start.elapsed().as_secs_f32());
+            return Ok(());
+        }
+        Some(Command::Sort(sort)) => {
+            let n = sort_gtf(&sort.gtf, &sort.output, &args.chrom_order, args.gz_level)?;
+            log::info!("Sorted {} features into {}", n, sort.output.display());
+            log::info!("Elapsed: {:.4?} secs", start.elapsed().as_secs_f32());
+            return Ok(());
+        }
+        Some(Command::FetchDeps(fetch)) => {
+            let cache = cache_dir(fetch.cache_dir.as_deref());
+            let options = FetchOptions::new(fetch.ucsc_release, fetch.offline);
+            let tools = if fetch.tools.is_empty() {
+                UCSC_TOOLS.iter().map(|t| t.to_string()).collect()
+            } else {
+                fetch.tools.clone()
             };
-
-            bed
+            for tool in &tools {
+                let sha256 = fetch
+                    .sha256
+                    .iter()
+                    .find(|(t, _)| t == tool)
+                    .map(|(_, s)| s.as_str());
+                let path = fetch_tool(tool, &cache, sha256, &options).unwrap_or_else(|e| {
+                    error!("Could not fetch {}: {}", tool, e);
+                    std::process::exit(1);
+                });
+                log::info!("{} ready at {}", tool, path.display());
+            }
+            return Ok(());
         }
-        Some("bed") => {
-            let contents = raw(&args.bed)?;
-            parallel_parse(&contents)?
+        Some(Command::VerifyAgainstUcsc(verify)) => {
+            let report = verify_against_ucsc(
+                &verify.bed,
+                verify.isoforms.as_deref(),
+                verify.cache_dir.as_deref(),
+                &FetchOptions::new(verify.ucsc_release, verify.offline),
+                &args.tmpdir(),
+                verify.timeout.map(Duration::from_secs),
+            )
+            .unwrap_or_else(|e| {
+                error!("{}", e);
+                std::process::exit(1);
+            });
+            if report.differences.is_empty() {
+                log::info!("Outputs match: {} lines compared", report.shared);
+            } else {
+                warn!(
+                    "{} lines differ across {} transcripts ({} shared)",
+                    report.differences.len(),
+                    report.transcripts(),
+                    report.shared
+                );
+            }
+            if let Some(output) = &verify.output {
+                write_differences(&report, output)?;
+                log::info!("Differences written to {}", output.display());
+            }
+            log::info!("Elapsed: {:.4?} secs", start.elapsed().as_secs_f32());
+            return Ok(());
         }
-        _ => panic!("ERROR: Not a .BED/.BED.GZ. Wrong file format!"),
-    };
-
-    let gene_track = custom_par_parse(&bed).unwrap_or_else(|_| {
-        let message = format!("Error parsing BED file {}", args.bed.display());
-        panic!("{}", message);
-    });
-
-    let results = bed
-        .par_iter()
-        .filter_map(|record| to_gtf(record, &imap).ok())
-        .flatten()
-        .collect::<Vec<_>>();
-
-    let mut blocks = combine_maps_par(&imap, &gene_track);
-    blocks.extend(results);
-
-    blocks.par_sort_unstable_by(|a, b| {
-        let chr_cmp = compare(&a.0, &b.0);
-        if chr_cmp == std::cmp::Ordering::Equal {
-            a.2.cmp(&b.2)
-        } else {
-            chr_cmp
+        Some(Command::Selftest) => {
+            let results = selftest()?;
+            for result in &results {
+                match &result.failure {
+                    None => println!("ok\t{}", result.name),
+                    Some(failure) => println!("FAIL\t{}\t{}", result.name, failure),
+                }
+            }
+            let failed = results.iter().filter(|r| !r.passed()).count();
+            if failed > 0 {
+                error!("{} of {} self-tests failed", failed, results.len());
+                std::process::exit(1);
+            }
+            log::info!("All {} self-tests passed", results.len());
+            return Ok(());
         }
-    });
-
-    let writer_boxed: Box<dyn Write> = if args.gz {
-        let file = File::create(&args.output).unwrap();
-        let encoder = GzEncoder::new(file, Compression::default());
-        Box::new(BufWriter::new(encoder))
-    } else {
-        let file = File::create(&args.output).unwrap();
-        Box::new(BufWriter::new(file))
-    };
-
-    let mut writer = writer_boxed;
-
-    comments(&mut writer);
+        Some(Command::Compare(cmp)) => {
+            run_compare(cmp)?;
+            log::info!("Elapsed: {:.4?} secs", start.elapsed().as_secs_f32());
+            return Ok(());
+        }
+        None => (),
+    }
 
-    for entry in &blocks {
-        writeln!(
-            writer,
-            "{}\t{}\t{}\t{}\t{}\t.\t{}\t{}\t{}",
-            entry.0, SOURCE, entry.1, entry.2, entry.3, entry.4, entry.5, entry.6
-        )
-        .unwrap();
+    if args.profile {
+        count_allocations();
     }
+    convert(&args).unwrap_or_else(|e| fail(e));
 
     let peak_mem = (max_mem_usage_mb() - bmem).max(0.0);
     log::info!("Memory usage: {} MB", peak_mem);
@@ -171,142 +213,39 @@ fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn to_gtf(
-    bedline: &BedRecord,
-    isoforms: &HashMap<String, String>,
-) -> Result<Vec<(String, String, u32, u32, String, String, String)>, Box<dyn Error>> {
-    let mut result: Vec<(String, String, u32, u32, String, String, String)> = Vec::new();
-
-    let gene = if !isoforms.is_empty() {
-        match isoforms.get(&bedline.name) {
-            Some(g) => g,
-            None => {
-                error!("Gene {} not found in isoforms file.", bedline.name);
-                std::process::exit(1)
-            }
-        }
-    } else {
-        &bedline.name
-    };
-
-    let fcodon = first_codon(bedline)
-        .unwrap_or_else(|| panic!("No start codon found for {}.", bedline.name));
-    let lcodon = last_codon(bedline).unwrap_or_else(|| {
-        panic!("No stop codon found for {}.", bedline.name);
-    });
-    // let first_utr_end = bedline.cds_start;
-    // let last_utr_start = bedline.cds_end;
-    let frames = bedline.get_frames();
-
-    let cds_end: u32 = if bedline.strand == "+" && codon_complete(&lcodon) {
-        move_pos(bedline, lcodon.end, -3)
-    } else {
-        bedline.cds_end
-    };
-
-    let cds_start = if bedline.strand == "-" && codon_complete(&fcodon) {
-        move_pos(bedline, fcodon.start, 3)
-    } else {
-        bedline.cds_start
-    };
+/// Reports a fatal error and exits.
+fn fail(e: Bed2GtfError) -> ! {
+    error!("{}", e);
+    std::process::exit(1);
+}
 
-    build_gtf_line(
-        bedline,
-        gene,
-        "transcript",
-        bedline.tx_start,
-        bedline.tx_end,
-        3,
-        -1,
-        &mut result,
+fn run_compare(args: &CompareArgs) -> Result<(), Box<dyn Error>> {
+    let reference = read_models(&args.reference)?;
+    let query = read_models(&args.query)?;
+    log::info!(
+        "Comparing {} reference against {} query transcripts",
+        reference.len(),
+        query.len()
     );
 
-    for i in 0..bedline.exon_count as usize {
-        build_gtf_line(
-            bedline,
-            gene,
-            "exon",
-            bedline.exon_start[i],
-            bedline.exon_end[i],
-            3,
-            i as i16,
-            &mut result,
-        );
-        if cds_start < cds_end {
-            write_features(
-                i,
-                bedline,
-                gene,
-                // first_utr_end,
-                cds_start,
-                cds_end,
-                // last_utr_start,
-                frames[i] as u32,
-                &mut result,
-            );
-        }
-    }
-
-    if bedline.strand != "-" {
-        if codon_complete(&fcodon) {
-            write_codon(bedline, gene, "start_codon", fcodon, &mut result);
-        }
-        if codon_complete(&lcodon) {
-            write_codon(bedline, gene, "stop_codon", lcodon, &mut result);
-        }
-    } else {
-        if codon_complete(&lcodon) {
-            write_codon(bedline, gene, "start_codon", lcodon, &mut result);
-        }
-        if codon_complete(&fcodon) {
-            write_codon(bedline, gene, "stop_codon", fcodon, &mut result);
-        }
+    let results = compare_models(&reference, &query);
+    let mut writer = BufWriter::new(create_output(&args.output)?);
+    writeln!(writer, "gene_id\treference\tquery\tclass")?;
+    for r in &results {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}",
+            r.gene,
+            r.reference.as_deref().unwrap_or("."),
+            r.query.as_deref().unwrap_or("."),
+            r.class
+        )?;
     }
+    writer.flush()?;
 
-    Ok(result)
-}
-
-fn move_pos(record: &BedRecord, pos: u32, dist: i32) -> u32 {
-    let mut pos = pos;
-    assert!(record.tx_start <= pos && pos <= record.tx_end);
-
-    let mut exon_index = record
-        .exon_start
-        .iter()
-        .zip(record.exon_end.iter())
-        .position(|(start, end)| pos >= *start && pos <= *end)
-        .unwrap_or_else(|| {
-            let message = format!("Position {} not in exons.", pos);
-            panic!("{}", message);
-        }) as i16;
-
-    let mut steps = dist.abs();
-    let direction = if dist >= 0 { 1 } else { -1 };
-
-    while steps > 0 {
-        let (exon_start, exon_end) = (
-            record.exon_start[exon_index as usize],
-            record.exon_end[exon_index as usize],
-        );
-
-        if pos >= exon_start && pos <= exon_end {
-            pos += direction as u32;
-            steps -= 1;
-        } else if direction >= 0 {
-            exon_index += 1;
-            if (exon_index as usize) < record.exon_count as usize {
-                pos = record.exon_start[exon_index as usize];
-            }
-        } else {
-            exon_index -= 1;
-            if exon_index >= 0 {
-                pos = record.exon_end[exon_index as usize] - 1;
-                steps -= 1;
-            }
-        }
+    for (class, n) in summarize(&results) {
+        log::info!("{}: {}", class, n);
     }
-    if steps > 0 {
-        panic!("can't move {} by {}", pos, dist);
-    }
-    pos
+    log::info!("Report written to {}", args.output.display());
+    Ok(())
 }
@@ -0,0 +1,183 @@
+use crate::cli::Cli;
+use crate::utils::create_output;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// A file read or written by the run, with its SHA-256 and size; both
+/// are missing for streams, URLs and files that cannot be read.
+#[derive(Debug, Serialize)]
+pub struct ManifestFile {
+    pub path: PathBuf,
+    pub sha256: Option<String>,
+    pub bytes: Option<u64>,
+}
+
+impl ManifestFile {
+    pub fn new(path: &Path) -> ManifestFile {
+        let (sha256, bytes) = match sha256_file(path) {
+            Ok((digest, bytes)) => (Some(digest), Some(bytes)),
+            Err(_) => (None, None),
+        };
+        ManifestFile {
+            path: path.to_path_buf(),
+            sha256,
+            bytes,
+        }
+    }
+}
+
+/// Provenance of one conversion, written with `--manifest` so the
+/// converted annotation can carry an audit trail: what was read and
+/// written (with checksums), with which options, by which build, where
+/// and when.
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    pub name: &'static str,
+    pub version: &'static str,
+    /// Short commit of the build, or "unknown" outside a git checkout.
+    pub git_commit: &'static str,
+    pub hostname: Option<String>,
+    /// RFC 3339 times, UTC.
+    pub started: String,
+    pub finished: String,
+    /// Command line exactly as given.
+    pub command_line: Vec<String>,
+    /// Every option of the run, defaults included.
+    pub options: BTreeMap<String, Vec<String>>,
+    pub inputs: Vec<ManifestFile>,
+    pub outputs: Vec<ManifestFile>,
+}
+
+impl Manifest {
+    /// Manifest of a run of `args` begun at `started`, finished now. Call
+    /// it once every output is written and flushed.
+    pub fn new(args: &Cli, started: DateTime<Utc>) -> Manifest {
+        let inputs = [
+            args.bed.as_ref(),
+            args.isoforms.as_ref(),
+            args.frames.as_ref(),
+            args.tx_meta.as_ref(),
+            args.gene_meta.as_ref(),
+            args.gene_bed.as_ref(),
+            args.chrom_sizes.as_ref(),
+            args.fasta.as_ref(),
+            args.header_file.as_ref(),
+            args.merge_into.as_ref(),
+        ];
+        let side_outputs = [
+            args.warnings_out.as_ref(),
+            args.rejects.as_ref(),
+            args.report.as_ref(),
+            args.cds_report.as_ref(),
+            args.tss_out.as_ref(),
+            args.tes_out.as_ref(),
+            args.junctions_out.as_ref(),
+        ];
+        let outputs = args
+            .outputs()
+            .into_iter()
+            .map(|spec| spec.path)
+            .chain(side_outputs.into_iter().flatten().cloned());
+        Manifest {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            git_commit: env!("BED2GTF_GIT_COMMIT"),
+            hostname: hostname(),
+            started: started.to_rfc3339_opts(SecondsFormat::Millis, true),
+            finished: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            command_line: args.command_line.clone(),
+            options: args.effective_options(),
+            inputs: inputs
+                .into_iter()
+                .flatten()
+                .map(|path| ManifestFile::new(path))
+                .collect(),
+            outputs: outputs.map(|path| ManifestFile::new(&path)).collect(),
+        }
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(create_output(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Hex SHA-256 and length of the file at `path`.
+fn sha256_file(path: &Path) -> io::Result<(String, u64)> {
+    let mut hasher = Sha256::new();
+    let bytes = io::copy(&mut File::open(path)?, &mut hasher)?;
+    let digest = hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    Ok((digest, bytes))
+}
+
+#[cfg(unix)]
+fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    let rc = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+    if rc != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    Some(String::from_utf8_lossy(&buf[..len]).into_owned()).filter(|name| !name.is_empty())
+}
+
+#[cfg(not(unix))]
+fn hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn records_checksums_and_options() {
+        let dir = std::env::temp_dir().join(format!("bed2gtf-manifest-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let bed = dir.join("in.bed");
+        std::fs::write(&bed, "abc").unwrap();
+
+        let file = ManifestFile::new(&bed);
+        assert_eq!(
+            file.sha256.as_deref(),
+            Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+        assert_eq!(file.bytes, Some(3));
+        assert_eq!(ManifestFile::new(&dir.join("missing")).sha256, None);
+
+        let argv = [
+            "bed2gtf",
+            "-b",
+            bed.to_str().unwrap(),
+            "-o",
+            "-",
+            "--no-gene",
+        ];
+        let mut args = Cli::parse_from(argv);
+        args.command_line = argv.map(String::from).to_vec();
+        let json = serde_json::to_value(Manifest::new(&args, Utc::now())).unwrap();
+        assert_eq!(json["inputs"][0]["bytes"], 3);
+        assert_eq!(json["outputs"][0]["sha256"], serde_json::Value::Null);
+        assert_eq!(json["options"]["no_gene"][0], "true");
+        assert!(json["options"]["threads"].is_array());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,179 @@
+use crate::compare::{parse_attributes, parse_gtf_models, Model};
+use crate::sort::{sort_features, split_features, ChromOrder};
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::Write;
+
+/// What [`merge_annotations`] did with the new transcripts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MergeStats {
+    /// Transcripts added to the base annotation.
+    pub added: usize,
+    /// Transcripts left out because the base has an identical one.
+    pub duplicates: usize,
+    /// Base genes whose extent grew to cover added transcripts.
+    pub extended: usize,
+}
+
+type ModelKey<'a> = (&'a str, &'a str, &'a [(u32, u32)], Option<(u32, u32)>);
+
+/// Transcripts are identical when exons and coding span match.
+fn key(model: &Model) -> ModelKey<'_> {
+    (&model.chrom, &model.strand, &model.exons, model.coding)
+}
+
+/// Feature type and gene_id of a GTF line.
+fn feature_and_gene(line: &str) -> (&str, Option<&str>) {
+    let fields = line.split('\t').collect::<Vec<_>>();
+    let feature = fields.get(2).copied().unwrap_or_default();
+    let gene = fields
+        .get(8)
+        .and_then(|attrs| parse_attributes(attrs).get("gene_id").copied());
+    (feature, gene)
+}
+
+/// Adds the transcripts of the GTF `new` to the GTF `base`: transcripts
+/// identical to a base one are dropped, base genes gaining transcripts
+/// are stretched over them (their own gene line is dropped), and the
+/// result is sorted by `order` below the comment lines of `base`. A new
+/// transcript reusing a base transcript_id for another structure is an
+/// error.
+pub fn merge_annotations(
+    base: &str,
+    new: &str,
+    order: &ChromOrder,
+) -> Result<(String, MergeStats), Box<dyn Error>> {
+    let base_models = parse_gtf_models(base)?;
+    let new_models = parse_gtf_models(new)?;
+    let known = base_models.iter().map(key).collect::<HashSet<_>>();
+    let base_ids = base_models
+        .iter()
+        .map(|m| m.transcript.as_str())
+        .collect::<HashSet<_>>();
+
+    let mut duplicates = HashSet::new();
+    for model in &new_models {
+        if known.contains(&key(model)) {
+            duplicates.insert(model.transcript.as_str());
+        } else if base_ids.contains(model.transcript.as_str()) {
+            return Err(format!(
+                "transcript {} is already in the base annotation with another structure",
+                model.transcript
+            )
+            .into());
+        }
+    }
+    let mut stats = MergeStats {
+        added: new_models.len() - duplicates.len(),
+        duplicates: duplicates.len(),
+        extended: 0,
+    };
+
+    // kept new lines, and the span they give each gene
+    let (_, new_features) = split_features(new)?;
+    let mut spans: HashMap<&str, (u32, u32)> = HashMap::new();
+    let mut new_genes = Vec::new();
+    let mut kept = Vec::new();
+    for feature in &new_features {
+        let attrs = feature.line.rsplit('\t').next().unwrap_or_default();
+        let transcript = parse_attributes(attrs).get("transcript_id").copied();
+        if transcript.is_some_and(|tx| duplicates.contains(tx)) {
+            continue;
+        }
+        match feature_and_gene(feature.line) {
+            ("gene", gene) => new_genes.push((gene, feature.line)),
+            (_, gene) => {
+                if let Some(gene) = gene {
+                    let span = spans.entry(gene).or_insert((feature.start, feature.end));
+                    span.0 = span.0.min(feature.start);
+                    span.1 = span.1.max(feature.end);
+                }
+                kept.push(feature.line);
+            }
+        }
+    }
+
+    let (header, base_features) = split_features(base)?;
+    let mut base_genes = HashSet::new();
+    let mut body = String::with_capacity(base.len() + new.len());
+    for feature in &base_features {
+        let span = match feature_and_gene(feature.line) {
+            ("gene", Some(gene)) => {
+                base_genes.insert(gene);
+                spans.get(gene)
+            }
+            _ => None,
+        };
+        match span {
+            Some(&(start, end)) if start < feature.start || end > feature.end => {
+                let mut fields = feature.line.split('\t').collect::<Vec<_>>();
+                let (start, end) = (
+                    start.min(feature.start).to_string(),
+                    end.max(feature.end).to_string(),
+                );
+                fields[3] = &start;
+                fields[4] = &end;
+                writeln!(body, "{}", fields.join("\t"))?;
+                stats.extended += 1;
+            }
+            _ => writeln!(body, "{}", feature.line)?,
+        }
+    }
+    for (gene, line) in new_genes {
+        if gene.is_some_and(|g| spans.contains_key(g) && !base_genes.contains(g)) {
+            writeln!(body, "{}", line)?;
+        }
+    }
+    for line in kept {
+        writeln!(body, "{}", line)?;
+    }
+
+    let (_, mut features) = split_features(&body)?;
+    order.check(features.iter().map(|f| f.chrom))?;
+    sort_features(&mut features, order);
+    let mut merged = String::with_capacity(body.len() + 1024);
+    for line in header {
+        writeln!(merged, "{}", line)?;
+    }
+    for feature in &features {
+        writeln!(merged, "{}", feature.line)?;
+    }
+    Ok((merged, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_novel_transcripts() {
+        let base = "#!genome-build test\n\
+            chr1\tHAVANA\tgene\t101\t500\t.\t+\t.\tgene_id \"G1\";\n\
+            chr1\tHAVANA\ttranscript\t101\t500\t.\t+\t.\tgene_id \"G1\"; transcript_id \"T1\";\n\
+            chr1\tHAVANA\texon\t101\t500\t.\t+\t.\tgene_id \"G1\"; transcript_id \"T1\";\n";
+        let new = "chr1\tbed2gtf\tgene\t51\t500\t.\t+\t.\tgene_id \"G1\";\n\
+            chr1\tbed2gtf\ttranscript\t101\t500\t.\t+\t.\tgene_id \"G1\"; transcript_id \"T1b\";\n\
+            chr1\tbed2gtf\texon\t101\t500\t.\t+\t.\tgene_id \"G1\"; transcript_id \"T1b\";\n\
+            chr1\tbed2gtf\ttranscript\t51\t300\t.\t+\t.\tgene_id \"G1\"; transcript_id \"T2\";\n\
+            chr1\tbed2gtf\texon\t51\t300\t.\t+\t.\tgene_id \"G1\"; transcript_id \"T2\";\n";
+
+        let (merged, stats) = merge_annotations(base, new, &ChromOrder::Natural).unwrap();
+        assert_eq!(
+            stats,
+            MergeStats {
+                added: 1,
+                duplicates: 1,
+                extended: 1
+            }
+        );
+        let lines = merged.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "#!genome-build test");
+        assert!(lines[1].starts_with("chr1\tHAVANA\tgene\t51\t500\t"));
+        assert_eq!(lines.len(), 6);
+        assert!(!merged.contains("T1b"));
+
+        let clash = new.replace("T2", "T1");
+        assert!(merge_annotations(base, &clash, &ChromOrder::Natural).is_err());
+    }
+}
@@ -0,0 +1,281 @@
+use crate::lines::Attributes;
+use crate::utils::raw;
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::Debug;
+use std::path::Path;
+
+/// What to do with gene_ids of a metadata table sharing a gene_name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum DuplicateNames {
+    /// Write the shared name as is (only reported).
+    #[default]
+    Keep,
+    /// Number the name in every gene sharing it: SYMBOL_1, SYMBOL_2...
+    Uniquify,
+}
+
+/// BioMart headers whose attribute is not their snake_case form,
+/// compared ignoring case.
+const BIOMART_ATTRIBUTES: [(&str, &str); 6] = [
+    ("gene stable id", "gene_id"),
+    ("transcript stable id", "transcript_id"),
+    ("gene type", "gene_biotype"),
+    ("transcript type", "transcript_biotype"),
+    ("gene description", "description"),
+    ("transcript support level (tsl)", "transcript_support_level"),
+];
+
+/// Attribute name of a BioMart column: "Gene type" is gene_biotype,
+/// "Gene name" gene_name.
+fn biomart_attribute(header: &str) -> String {
+    if let Some((_, name)) = BIOMART_ATTRIBUTES
+        .iter()
+        .find(|(column, _)| column.eq_ignore_ascii_case(header))
+    {
+        return name.to_string();
+    }
+    header
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// Tab-separated table with a header row, keyed by one of its columns.
+/// The remaining columns become attributes named after their header.
+#[derive(Debug, Clone, Default)]
+pub struct MetaTable {
+    columns: Vec<String>,
+    rows: HashMap<String, Vec<String>>,
+}
+
+impl MetaTable {
+    /// Reads a table keyed by the `key` column, or by the first column
+    /// if there is no column with that name.
+    pub fn from_path<P: AsRef<Path> + Debug>(f: P, key: &str) -> Result<MetaTable, Box<dyn Error>> {
+        MetaTable::parse(&raw(f)?, key)
+    }
+
+    pub fn parse(contents: &str, key: &str) -> Result<MetaTable, Box<dyn Error>> {
+        let mut lines = contents
+            .lines()
+            .filter(|l| !l.trim().is_empty() && !l.starts_with('#'));
+        let header = lines.next().ok_or("metadata table is empty")?;
+        let mut columns = header
+            .split('\t')
+            .map(|c| c.trim().to_string())
+            .collect::<Vec<_>>();
+        let key_idx = columns.iter().position(|c| c == key).unwrap_or(0);
+        columns.remove(key_idx);
+
+        let mut rows = HashMap::new();
+        for line in lines {
+            let mut values = line
+                .split('\t')
+                .map(|v| v.trim().to_string())
+                .collect::<Vec<_>>();
+            if values.len() != columns.len() + 1 {
+                return Err(format!(
+                    "metadata row has {} columns, header has {}: {}",
+                    values.len(),
+                    columns.len() + 1,
+                    line
+                )
+                .into());
+            }
+            let id = values.remove(key_idx);
+            if rows.contains_key(&id) {
+                log::warn!("Duplicated metadata row for {}, keeping the first", id);
+                continue;
+            }
+            rows.insert(id, values);
+        }
+
+        Ok(MetaTable { columns, rows })
+    }
+
+    /// Splits an Ensembl BioMart export (tab-separated, with its header
+    /// row) into a transcript table keyed by "Transcript stable ID" and a
+    /// gene table keyed by "Gene stable ID". Columns are named as GTF
+    /// attributes ("Gene type" becomes gene_biotype, "Transcript name"
+    /// transcript_name); gene_* columns and the description go to the
+    /// gene table, taken from the first row of each gene, and the rest
+    /// to the transcript table.
+    pub fn from_biomart(contents: &str) -> Result<(MetaTable, MetaTable), Box<dyn Error>> {
+        let mut lines = contents
+            .lines()
+            .filter(|l| !l.trim().is_empty() && !l.starts_with('#'));
+        let header = lines.next().ok_or("BioMart export is empty")?;
+        let names = header
+            .split('\t')
+            .map(|c| biomart_attribute(c.trim()))
+            .collect::<Vec<_>>();
+        let index = |name: &str| {
+            names
+                .iter()
+                .position(|c| c == name)
+                .ok_or_else(|| format!("BioMart export has no {} column", name))
+        };
+        let (tx_idx, gene_idx) = (index("transcript_id")?, index("gene_id")?);
+        let is_gene = |name: &str| name.starts_with("gene_") || name == "description";
+        let (gene_cols, tx_cols): (Vec<_>, Vec<_>) = (0..names.len())
+            .filter(|&i| i != tx_idx && i != gene_idx)
+            .partition(|&i| is_gene(&names[i]));
+        let table = |cols: &[usize]| MetaTable {
+            columns: cols.iter().map(|&i| names[i].clone()).collect(),
+            rows: HashMap::new(),
+        };
+        let (mut transcripts, mut genes) = (table(&tx_cols), table(&gene_cols));
+
+        for line in lines {
+            let values = line.split('\t').map(str::trim).collect::<Vec<_>>();
+            if values.len() != names.len() {
+                return Err(format!(
+                    "BioMart row has {} columns, header has {}: {}",
+                    values.len(),
+                    names.len(),
+                    line
+                )
+                .into());
+            }
+            let pick = |cols: &[usize]| cols.iter().map(|&i| values[i].to_string()).collect();
+            let (tx, gene) = (values[tx_idx], values[gene_idx]);
+            if transcripts.rows.contains_key(tx) {
+                log::warn!("Duplicated BioMart row for {}, keeping the first", tx);
+            } else if !tx.is_empty() {
+                transcripts.rows.insert(tx.to_string(), pick(&tx_cols));
+            }
+            if !gene.is_empty() && !genes.rows.contains_key(gene) {
+                genes.rows.insert(gene.to_string(), pick(&gene_cols));
+            }
+        }
+
+        Ok((transcripts, genes))
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Values of `column` shared by several of `ids`, with those ids in
+    /// `ids` order. Empty cells and `.` are not names.
+    pub fn duplicates(&self, column: &str, ids: &[&str]) -> BTreeMap<String, Vec<String>> {
+        let Some(col) = self.columns.iter().position(|c| c == column) else {
+            return BTreeMap::new();
+        };
+        let mut by_value: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for id in ids {
+            match self.rows.get(*id).map(|row| row[col].as_str()) {
+                Some("" | ".") | None => {}
+                Some(value) => by_value
+                    .entry(value.to_string())
+                    .or_default()
+                    .push(id.to_string()),
+            }
+        }
+        by_value.retain(|_, ids| ids.len() > 1);
+        by_value
+    }
+
+    /// Rewrites `column` of every id in `duplicates` (see
+    /// [`duplicates`](MetaTable::duplicates)) to `VALUE_n`, numbered in
+    /// their order.
+    pub fn uniquify(&mut self, column: &str, duplicates: &BTreeMap<String, Vec<String>>) {
+        let Some(col) = self.columns.iter().position(|c| c == column) else {
+            return;
+        };
+        for (value, ids) in duplicates {
+            for (n, id) in ids.iter().enumerate() {
+                if let Some(row) = self.rows.get_mut(id) {
+                    row[col] = format!("{}_{}", value, n + 1);
+                }
+            }
+        }
+    }
+
+    /// Non-empty values for `id` as attributes, in column order.
+    /// Empty cells and `.` are skipped.
+    pub fn attributes(&self, id: &str) -> Option<Attributes> {
+        let values = self.rows.get(id)?;
+        let mut attrs = Attributes::new();
+        for (column, value) in self.columns.iter().zip(values) {
+            if !value.is_empty() && value != "." {
+                attrs.push(column.as_str(), value.as_str());
+            }
+        }
+        Some(attrs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyed_by_named_column() {
+        let table = MetaTable::parse(
+            "tsl\ttranscript_id\tccds_id\n1\tT1\tCCDS1.1\n.\tT2\tCCDS2.1\n",
+            "transcript_id",
+        )
+        .unwrap();
+
+        let attrs = table.attributes("T2").unwrap();
+        assert_eq!(attrs.get("tsl"), None);
+        assert_eq!(attrs.get("ccds_id"), Some("CCDS2.1"));
+        assert_eq!(table.attributes("T1").unwrap().get("tsl"), Some("1"));
+        assert!(table.attributes("T3").is_none());
+    }
+
+    #[test]
+    fn splits_biomart_exports() {
+        let (transcripts, genes) = MetaTable::from_biomart(
+            "Gene stable ID\tTranscript stable ID\tGene name\tGene type\tTranscript type\t\
+             Transcript support level (TSL)\n\
+             ENSG1\tENST1\tABC\tprotein_coding\tprotein_coding\ttsl1\n\
+             ENSG1\tENST2\tABC\tprotein_coding\tretained_intron\t\n",
+        )
+        .unwrap();
+        let tx = transcripts.attributes("ENST2").unwrap();
+        assert_eq!(tx.get("transcript_biotype"), Some("retained_intron"));
+        assert_eq!(tx.get("transcript_support_level"), None);
+        assert_eq!(tx.get("gene_name"), None);
+        let gene = genes.attributes("ENSG1").unwrap();
+        assert_eq!(gene.get("gene_name"), Some("ABC"));
+        assert_eq!(gene.get("gene_biotype"), Some("protein_coding"));
+        assert_eq!(genes.len(), 1);
+        assert!(MetaTable::from_biomart("Gene name\tGene type\n").is_err());
+    }
+
+    #[test]
+    fn uniquifies_shared_gene_names() {
+        let mut table = MetaTable::parse(
+            "gene_id\tgene_name\nG1\tABC\nG2\tXYZ\nG3\tABC\nG4\t.\nG5\t.\nG6\tABC\n",
+            "gene_id",
+        )
+        .unwrap();
+        // G6 is not in the output
+        let duplicates = table.duplicates("gene_name", &["G3", "G1", "G2", "G4", "G5"]);
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates["ABC"], ["G3", "G1"]);
+
+        table.uniquify("gene_name", &duplicates);
+        let name = |id| {
+            table
+                .attributes(id)
+                .unwrap()
+                .get("gene_name")
+                .map(String::from)
+        };
+        assert_eq!(name("G3").as_deref(), Some("ABC_1"));
+        assert_eq!(name("G1").as_deref(), Some("ABC_2"));
+        assert_eq!(name("G2").as_deref(), Some("XYZ"));
+        assert_eq!(name("G6").as_deref(), Some("ABC"));
+    }
+}
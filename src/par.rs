@@ -0,0 +1,207 @@
+//! Data parallelism: rayon with the `parallel` feature (on by default),
+//! plain iterators without it. Call sites use the same `par_*` methods
+//! either way, so a build without the feature runs every stage on the
+//! calling thread and needs neither rayon nor a thread pool (static musl
+//! or wasm targets, tiny inputs).
+
+#[cfg(feature = "parallel")]
+pub use rayon::{current_num_threads, prelude, ThreadPool, ThreadPoolBuilder};
+
+#[cfg(not(feature = "parallel"))]
+pub use sequential::{current_num_threads, ThreadPool, ThreadPoolBuilder};
+
+#[cfg(not(feature = "parallel"))]
+pub mod prelude {
+    pub use super::sequential::{
+        IntoParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelSlice,
+        ParallelSliceMut, ParallelString, Seq,
+    };
+}
+
+#[cfg(not(feature = "parallel"))]
+mod sequential {
+    use std::cmp::Ordering;
+    use std::fmt;
+
+    /// A sequential stand-in for a rayon parallel iterator: a plain
+    /// iterator plus the rayon adaptors without a std equivalent.
+    pub struct Seq<I>(I);
+
+    impl<I: Iterator> Iterator for Seq<I> {
+        type Item = I::Item;
+
+        fn next(&mut self) -> Option<I::Item> {
+            self.0.next()
+        }
+
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            self.0.size_hint()
+        }
+    }
+
+    impl<I: Iterator> Seq<I> {
+        /// rayon's `fold`: one accumulator, as there is a single worker.
+        pub fn fold<T, ID, F>(self, identity: ID, f: F) -> Seq<std::option::IntoIter<T>>
+        where
+            ID: Fn() -> T,
+            F: FnMut(T, I::Item) -> T,
+        {
+            Seq(Some(self.0.fold(identity(), f)).into_iter())
+        }
+
+        /// rayon's `reduce`, starting from `identity()`.
+        pub fn reduce<ID, F>(self, identity: ID, op: F) -> I::Item
+        where
+            ID: Fn() -> I::Item,
+            F: FnMut(I::Item, I::Item) -> I::Item,
+        {
+            self.0.fold(identity(), op)
+        }
+
+        pub fn flat_map_iter<U, F>(self, f: F) -> Seq<std::iter::FlatMap<I, U, F>>
+        where
+            U: IntoIterator,
+            F: FnMut(I::Item) -> U,
+        {
+            Seq(self.0.flat_map(f))
+        }
+
+        pub fn with_max_len(self, _max: usize) -> Self {
+            self
+        }
+
+        pub fn with_min_len(self, _min: usize) -> Self {
+            self
+        }
+    }
+
+    pub trait IntoParallelIterator: IntoIterator + Sized {
+        fn into_par_iter(self) -> Seq<Self::IntoIter> {
+            Seq(self.into_iter())
+        }
+    }
+
+    impl<T: IntoIterator> IntoParallelIterator for T {}
+
+    pub trait IntoParallelRefIterator<'data> {
+        type Iter: Iterator;
+
+        fn par_iter(&'data self) -> Seq<Self::Iter>;
+    }
+
+    impl<'data, T: 'data + ?Sized> IntoParallelRefIterator<'data> for T
+    where
+        &'data T: IntoIterator,
+    {
+        type Iter = <&'data T as IntoIterator>::IntoIter;
+
+        fn par_iter(&'data self) -> Seq<Self::Iter> {
+            Seq(self.into_iter())
+        }
+    }
+
+    pub trait IntoParallelRefMutIterator<'data> {
+        type Iter: Iterator;
+
+        fn par_iter_mut(&'data mut self) -> Seq<Self::Iter>;
+    }
+
+    impl<'data, T: 'data + ?Sized> IntoParallelRefMutIterator<'data> for T
+    where
+        &'data mut T: IntoIterator,
+    {
+        type Iter = <&'data mut T as IntoIterator>::IntoIter;
+
+        fn par_iter_mut(&'data mut self) -> Seq<Self::Iter> {
+            Seq(self.into_iter())
+        }
+    }
+
+    pub trait ParallelSlice<T> {
+        fn par_chunks(&self, size: usize) -> Seq<std::slice::Chunks<'_, T>>;
+    }
+
+    impl<T> ParallelSlice<T> for [T] {
+        fn par_chunks(&self, size: usize) -> Seq<std::slice::Chunks<'_, T>> {
+            Seq(self.chunks(size))
+        }
+    }
+
+    pub trait ParallelSliceMut<T> {
+        fn par_sort_by<F: FnMut(&T, &T) -> Ordering>(&mut self, compare: F);
+        fn par_sort_unstable_by<F: FnMut(&T, &T) -> Ordering>(&mut self, compare: F);
+    }
+
+    impl<T> ParallelSliceMut<T> for [T] {
+        fn par_sort_by<F: FnMut(&T, &T) -> Ordering>(&mut self, compare: F) {
+            self.sort_by(compare)
+        }
+
+        fn par_sort_unstable_by<F: FnMut(&T, &T) -> Ordering>(&mut self, compare: F) {
+            self.sort_unstable_by(compare)
+        }
+    }
+
+    pub trait ParallelString {
+        fn par_lines(&self) -> Seq<std::str::Lines<'_>>;
+    }
+
+    impl ParallelString for str {
+        fn par_lines(&self) -> Seq<std::str::Lines<'_>> {
+            Seq(self.lines())
+        }
+    }
+
+    pub fn current_num_threads() -> usize {
+        1
+    }
+
+    /// Accepts the rayon settings; every "pool" is the calling thread.
+    #[derive(Debug, Default)]
+    pub struct ThreadPoolBuilder;
+
+    #[derive(Debug)]
+    pub struct ThreadPoolBuildError;
+
+    impl fmt::Display for ThreadPoolBuildError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                f,
+                "thread pools are not available without the parallel feature"
+            )
+        }
+    }
+
+    impl std::error::Error for ThreadPoolBuildError {}
+
+    impl ThreadPoolBuilder {
+        pub fn new() -> Self {
+            ThreadPoolBuilder
+        }
+
+        pub fn num_threads(self, _threads: usize) -> Self {
+            self
+        }
+
+        pub fn build(self) -> Result<ThreadPool, ThreadPoolBuildError> {
+            Ok(ThreadPool)
+        }
+
+        pub fn build_global(self) -> Result<(), ThreadPoolBuildError> {
+            Ok(())
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct ThreadPool;
+
+    impl ThreadPool {
+        pub fn install<R, F: FnOnce() -> R>(&self, f: F) -> R {
+            f()
+        }
+
+        pub fn current_num_threads(&self) -> usize {
+            1
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+
+/// Chunks or blocks in flight between two pipeline stages. Small, so a
+/// fast stage only runs a few chunks ahead of a slow one.
+pub const PIPELINE_DEPTH: usize = 4;
+
+/// Bytes handed to the background writer at a time.
+const WRITE_BYTES: usize = 1 << 20;
+
+/// Reads `reader` on its own thread in chunks of whole lines of about
+/// `chunk_bytes`, and calls `f` on each chunk here as it arrives, so that
+/// reading (and decompressing) the next chunk overlaps with processing
+/// this one.
+pub fn for_each_chunk<R, F>(reader: R, chunk_bytes: usize, mut f: F) -> Result<(), Box<dyn Error>>
+where
+    R: BufRead + Send,
+    F: FnMut(&str) -> Result<(), Box<dyn Error>>,
+{
+    thread::scope(|scope| {
+        let (tx, rx) = sync_channel::<io::Result<String>>(PIPELINE_DEPTH);
+        scope.spawn(move || {
+            let mut reader = reader;
+            loop {
+                let mut chunk = String::with_capacity(chunk_bytes + 4096);
+                let result = loop {
+                    match reader.read_line(&mut chunk) {
+                        Ok(0) => break Ok(true),
+                        Ok(_) if chunk.len() >= chunk_bytes => break Ok(false),
+                        Ok(_) => continue,
+                        Err(e) => break Err(e),
+                    }
+                };
+                let done = !matches!(result, Ok(false));
+                let sent = match result {
+                    Err(e) => tx.send(Err(e)),
+                    Ok(_) if chunk.is_empty() => Ok(()),
+                    Ok(_) => tx.send(Ok(chunk)),
+                };
+                // a closed channel means the consumer gave up
+                if done || sent.is_err() {
+                    return;
+                }
+            }
+        });
+        for chunk in rx {
+            f(&chunk?)?;
+        }
+        Ok(())
+    })
+}
+
+enum Message {
+    Data(Vec<u8>),
+    Flush(SyncSender<io::Result<()>>),
+}
+
+/// Writer that hands its data, in large blocks, to a thread doing the
+/// actual writes, so slow storage does not stall conversion. `flush`
+/// waits until everything written so far reached the inner writer and
+/// reports any error it hit. [`BackgroundWriter::finish`] ends the
+/// output and reports the errors of the last writes; dropping the
+/// writer waits as well but loses them.
+pub struct BackgroundWriter {
+    buffer: Vec<u8>,
+    tx: Option<SyncSender<Message>>,
+    handle: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl BackgroundWriter {
+    pub fn new<W: Write + Send + 'static>(inner: W) -> BackgroundWriter {
+        let (tx, rx) = sync_channel(PIPELINE_DEPTH);
+        let handle = thread::spawn(move || BackgroundWriter::run(inner, rx));
+        BackgroundWriter {
+            buffer: Vec::with_capacity(WRITE_BYTES),
+            tx: Some(tx),
+            handle: Some(handle),
+        }
+    }
+
+    fn run<W: Write>(mut inner: W, rx: Receiver<Message>) -> io::Result<()> {
+        for message in rx {
+            match message {
+                Message::Data(data) => inner.write_all(&data)?,
+                Message::Flush(ack) => {
+                    let _ = ack.send(inner.flush());
+                }
+            }
+        }
+        inner.flush()
+    }
+
+    fn send(&mut self, message: Message) -> io::Result<()> {
+        let sent = self.tx.as_ref().map(|tx| tx.send(message).is_ok());
+        if sent == Some(true) {
+            return Ok(());
+        }
+        // the writer thread stopped: surface its error
+        self.tx = None;
+        match self.handle.take().map(JoinHandle::join) {
+            Some(Ok(Err(e))) => Err(e),
+            _ => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "output writer stopped",
+            )),
+        }
+    }
+
+    /// Writes out what is left, stops the writer thread and returns its
+    /// error, including that of the final flush.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.send_buffer()?;
+        self.tx = None;
+        match self.handle.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            _ => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "output writer stopped",
+            )),
+        }
+    }
+
+    fn send_buffer(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let data = std::mem::replace(&mut self.buffer, Vec::with_capacity(WRITE_BYTES));
+        self.send(Message::Data(data))
+    }
+}
+
+impl Write for BackgroundWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= WRITE_BYTES {
+            self.send_buffer()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_buffer()?;
+        let (ack, done) = sync_channel(1);
+        self.send(Message::Flush(ack))?;
+        done.recv().unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "output writer stopped",
+            ))
+        })
+    }
+}
+
+impl Drop for BackgroundWriter {
+    fn drop(&mut self) {
+        let _ = self.send_buffer();
+        self.tx = None;
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+
+    #[test]
+    fn overlaps_reading_and_writing() {
+        let text = (0..1000)
+            .map(|i| format!("line{}\n", i))
+            .collect::<String>();
+        let mut chunks = Vec::new();
+        for_each_chunk(BufReader::new(text.as_bytes()), 100, |chunk| {
+            assert!(chunk.ends_with('\n'));
+            chunks.push(chunk.to_string());
+            Ok(())
+        })
+        .unwrap();
+        assert!(chunks.len() > 10);
+        assert_eq!(chunks.concat(), text);
+
+        let path = std::env::temp_dir().join(format!("bed2gtf-bg-{}.txt", std::process::id()));
+        let mut writer = BackgroundWriter::new(std::fs::File::create(&path).unwrap());
+        writer.write_all(text.as_bytes()).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), text);
+        writer.write_all(b"last\n").unwrap();
+        writer.finish().unwrap();
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .ends_with("line999\nlast\n"));
+        std::fs::remove_file(&path).unwrap();
+
+        // a failing last flush surfaces in finish
+        struct FailingFlush;
+        impl Write for FailingFlush {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Err(io::Error::other("disk full"))
+            }
+        }
+        let mut writer = BackgroundWriter::new(FailingFlush);
+        writer.write_all(b"x").unwrap();
+        assert_eq!(writer.finish().unwrap_err().to_string(), "disk full");
+    }
+}
@@ -0,0 +1,81 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+static COUNTING: AtomicBool = AtomicBool::new(false);
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// System allocator that counts allocations once [`count_allocations`]
+/// is called, for `--profile`. Until then it costs one relaxed load per
+/// allocation.
+pub struct CountingAlloc;
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if COUNTING.load(Ordering::Relaxed) {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            ALLOCATED_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if COUNTING.load(Ordering::Relaxed) {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            ALLOCATED_BYTES.fetch_add(
+                new_size.saturating_sub(layout.size()) as u64,
+                Ordering::Relaxed,
+            );
+        }
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+/// Starts counting allocations; only meaningful in binaries using
+/// [`CountingAlloc`] as their global allocator.
+pub fn count_allocations() {
+    COUNTING.store(true, Ordering::Relaxed);
+}
+
+/// Allocations and bytes allocated so far, or `None` when not counting.
+pub fn allocations() -> Option<(u64, u64)> {
+    COUNTING.load(Ordering::Relaxed).then(|| {
+        (
+            ALLOCATIONS.load(Ordering::Relaxed),
+            ALLOCATED_BYTES.load(Ordering::Relaxed),
+        )
+    })
+}
+
+/// User plus system CPU time of the process so far, over all threads.
+pub fn cpu_seconds() -> f64 {
+    let rusage = unsafe {
+        let mut rusage = std::mem::MaybeUninit::uninit();
+        libc::getrusage(libc::RUSAGE_SELF, rusage.as_mut_ptr());
+        rusage.assume_init()
+    };
+    let seconds = |t: libc::timeval| t.tv_sec as f64 + t.tv_usec as f64 / 1e6;
+    seconds(rusage.ru_utime) + seconds(rusage.ru_stime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_through_the_allocator() {
+        count_allocations();
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        unsafe {
+            let ptr = CountingAlloc.alloc(layout);
+            CountingAlloc.dealloc(ptr, layout);
+        }
+        let (count, bytes) = allocations().unwrap();
+        assert!(count >= 1 && bytes >= 64);
+        assert!(cpu_seconds() >= 0.0);
+    }
+}
@@ -0,0 +1,525 @@
+use crate::cli::Cli;
+use crate::lines::GtfRecord;
+use crate::profile::{allocations, cpu_seconds};
+use crate::sort::ChromOrder;
+use crate::utils::{create_output, current_mem_usage_mb, max_mem_usage_mb};
+use crate::warnings::Warnings;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{self, Write as _};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Feature types always listed in the report, even when none were written.
+const SUMMARY_FEATURES: [&str; 6] = [
+    "gene",
+    "transcript",
+    "exon",
+    "CDS",
+    "start_codon",
+    "stop_codon",
+];
+
+/// Chromosomes listed one by one in the summary table; the rest are
+/// folded into a single row.
+const TABLE_ROWS: usize = 25;
+
+/// Genes, transcripts and exons written on one chromosome.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ChromCounts {
+    pub genes: usize,
+    pub transcripts: usize,
+    pub exons: usize,
+}
+
+/// Time (and, with `--profile`, allocations) spent in one step of a
+/// conversion.
+#[derive(Debug, Clone, Serialize)]
+pub struct Stage {
+    pub name: &'static str,
+    /// Wall time.
+    pub seconds: f64,
+    /// CPU time over all threads; above `seconds` when parallel.
+    pub cpu_seconds: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allocations: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allocated_mb: Option<f64>,
+    /// Resident memory when the stage ended, where the platform tells.
+    pub rss_mb: Option<f64>,
+    /// High-water mark of resident memory up to the end of the stage.
+    pub peak_rss_mb: f64,
+}
+
+/// What a conversion tells its [`ProgressHook`].
+#[derive(Debug, Clone, Copy)]
+pub enum ProgressEvent<'a> {
+    /// A step of the conversion is done (the steps of `--profile`).
+    Stage(&'a Stage),
+    /// BED records converted so far, out of the total when known.
+    Records { done: usize, total: Option<usize> },
+}
+
+/// Callback for the progress of a conversion, so embedding applications
+/// can show it without scraping the log. It is called from the thread
+/// driving the conversion, between stages and input chunks, and should
+/// return quickly.
+#[derive(Clone, Default)]
+pub struct ProgressHook(Option<Arc<ProgressCallback>>);
+
+type ProgressCallback = dyn Fn(ProgressEvent) + Send + Sync;
+
+impl ProgressHook {
+    pub fn new<F>(callback: F) -> ProgressHook
+    where
+        F: Fn(ProgressEvent) + Send + Sync + 'static,
+    {
+        ProgressHook(Some(Arc::new(callback)))
+    }
+
+    pub fn emit(&self, event: ProgressEvent) {
+        if let Some(callback) = &self.0 {
+            callback(event);
+        }
+    }
+}
+
+impl fmt::Debug for ProgressHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self.0 {
+            Some(_) => "ProgressHook(..)",
+            None => "ProgressHook(none)",
+        })
+    }
+}
+
+/// Structured summary of a conversion, written with `--report` so runs
+/// over many samples can be aggregated (e.g. by MultiQC).
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub version: &'static str,
+    /// Command line exactly as given.
+    pub command_line: Vec<String>,
+    pub input: PathBuf,
+    pub isoforms: Option<PathBuf>,
+    pub output: PathBuf,
+    /// `--genome-build` and `--assembly-accession`.
+    pub genome_build: Option<String>,
+    pub assembly_accession: Option<String>,
+    /// BED records read, before any deduplication.
+    pub bed_records: usize,
+    /// BED records read but not written as a transcript (e.g. collapsed
+    /// by `--dedup`).
+    pub skipped_records: usize,
+    /// BED records repaired by `--fix`, `--clean` or `--merge-exons`.
+    pub fixed_records: usize,
+    /// Lines written per feature type.
+    pub features: BTreeMap<String, usize>,
+    /// Lines written per chromosome.
+    pub chromosomes: BTreeMap<String, ChromCounts>,
+    /// Non-fatal issues per code, as in `--warnings-out`.
+    pub warnings: BTreeMap<&'static str, usize>,
+    pub stages: Vec<Stage>,
+    pub elapsed_seconds: f64,
+    pub peak_memory_mb: f64,
+    #[serde(skip)]
+    started: Instant,
+    #[serde(skip)]
+    started_at: DateTime<Utc>,
+    #[serde(skip)]
+    last: Instant,
+    #[serde(skip)]
+    last_cpu: f64,
+    #[serde(skip)]
+    last_allocations: Option<(u64, u64)>,
+    #[serde(skip)]
+    hook: ProgressHook,
+}
+
+impl Report {
+    pub fn new(args: &Cli) -> Report {
+        let now = Instant::now();
+        Report {
+            version: env!("CARGO_PKG_VERSION"),
+            command_line: args.command_line.clone(),
+            input: args.bed().to_path_buf(),
+            isoforms: args.isoforms.clone(),
+            output: args.output().to_path_buf(),
+            genome_build: args.genome_build.clone(),
+            assembly_accession: args.assembly_accession.clone(),
+            bed_records: 0,
+            skipped_records: 0,
+            fixed_records: 0,
+            features: BTreeMap::new(),
+            chromosomes: BTreeMap::new(),
+            warnings: BTreeMap::new(),
+            stages: Vec::new(),
+            elapsed_seconds: 0.0,
+            peak_memory_mb: 0.0,
+            started: now,
+            started_at: Utc::now(),
+            last: now,
+            last_cpu: cpu_seconds(),
+            last_allocations: allocations(),
+            hook: args.progress.clone(),
+        }
+    }
+
+    /// Wall-clock time the report was created, i.e. the run began.
+    pub fn started_at(&self) -> DateTime<Utc> {
+        self.started_at
+    }
+
+    /// Closes the current stage: records the time (and allocations)
+    /// since the previous call, or since the report was created, under
+    /// `name`.
+    pub fn stage(&mut self, name: &'static str) {
+        let (now, cpu, allocs) = (Instant::now(), cpu_seconds(), allocations());
+        let spent = match (allocs, self.last_allocations) {
+            (Some((count, bytes)), Some((last_count, last_bytes))) => {
+                Some((count - last_count, bytes - last_bytes))
+            }
+            _ => None,
+        };
+        self.stages.push(Stage {
+            name,
+            seconds: (now - self.last).as_secs_f64(),
+            cpu_seconds: cpu - self.last_cpu,
+            allocations: spent.map(|(count, _)| count),
+            allocated_mb: spent.map(|(_, bytes)| bytes as f64 / 1024.0 / 1024.0),
+            rss_mb: current_mem_usage_mb(),
+            peak_rss_mb: max_mem_usage_mb(),
+        });
+        (self.last, self.last_cpu, self.last_allocations) = (now, cpu, allocs);
+        if let Some(stage) = self.stages.last() {
+            self.hook.emit(ProgressEvent::Stage(stage));
+        }
+    }
+
+    /// Tells the progress hook that `done` of `total` BED records are
+    /// converted.
+    pub fn progress(&self, done: usize, total: Option<usize>) {
+        self.hook.emit(ProgressEvent::Records { done, total });
+    }
+
+    pub fn count(&mut self, record: &GtfRecord) {
+        match self.features.get_mut(&record.feature) {
+            Some(n) => *n += 1,
+            None => {
+                self.features.insert(record.feature.clone(), 1);
+            }
+        }
+        if !matches!(record.feature.as_str(), "gene" | "transcript" | "exon") {
+            return;
+        }
+        let counts = match self.chromosomes.get_mut(&record.chrom) {
+            Some(counts) => counts,
+            None => self.chromosomes.entry(record.chrom.clone()).or_default(),
+        };
+        match record.feature.as_str() {
+            "gene" => counts.genes += 1,
+            "transcript" => counts.transcripts += 1,
+            _ => counts.exons += 1,
+        }
+    }
+
+    /// One-line feature count summary, logged at the end of a run.
+    pub fn summary(&self) -> String {
+        let n = |feature: &str| self.features.get(feature).copied().unwrap_or_default();
+        format!(
+            "Wrote {} genes, {} transcripts, {} exons, {} CDS, {} start and {} stop codons; \
+             {} of {} BED records skipped",
+            n("gene"),
+            n("transcript"),
+            n("exon"),
+            n("CDS"),
+            n("start_codon"),
+            n("stop_codon"),
+            self.skipped_records,
+            self.bed_records
+        )
+    }
+
+    /// Per-chromosome table of genes, transcripts, exons and warnings,
+    /// printed at the end of a run unless `--no-summary`. Past
+    /// [`TABLE_ROWS`] chromosomes, the smallest ones share a row.
+    pub fn table(&self, warnings: &Warnings, order: &ChromOrder) -> String {
+        let by_chrom = warnings.counts_by_chrom();
+        let mut chroms = self.chromosomes.iter().collect::<Vec<_>>();
+        let rest = if chroms.len() > TABLE_ROWS {
+            chroms.sort_by_key(|(_, counts)| std::cmp::Reverse(counts.transcripts));
+            chroms.split_off(TABLE_ROWS - 1)
+        } else {
+            Vec::new()
+        };
+        chroms.sort_by(|a, b| order.compare(a.0, b.0));
+
+        let row = |name: String, counts: ChromCounts, warnings: usize| {
+            [
+                name,
+                counts.genes.to_string(),
+                counts.transcripts.to_string(),
+                counts.exons.to_string(),
+                warnings.to_string(),
+            ]
+        };
+        let sum = |chroms: &[(&String, &ChromCounts)]| {
+            chroms
+                .iter()
+                .fold(ChromCounts::default(), |acc, (_, c)| ChromCounts {
+                    genes: acc.genes + c.genes,
+                    transcripts: acc.transcripts + c.transcripts,
+                    exons: acc.exons + c.exons,
+                })
+        };
+        let warned = |chroms: &[(&String, &ChromCounts)]| {
+            chroms
+                .iter()
+                .map(|(chrom, _)| by_chrom.get(chrom.as_str()).copied().unwrap_or_default())
+                .sum::<usize>()
+        };
+
+        let mut rows = vec![[
+            "chrom".to_string(),
+            "genes".to_string(),
+            "transcripts".to_string(),
+            "exons".to_string(),
+            "warnings".to_string(),
+        ]];
+        for &(chrom, counts) in &chroms {
+            let n = by_chrom.get(chrom.as_str()).copied().unwrap_or_default();
+            rows.push(row(chrom.clone(), *counts, n));
+        }
+        if !rest.is_empty() {
+            let name = format!("({} others)", rest.len());
+            rows.push(row(name, sum(&rest), warned(&rest)));
+        }
+        let all = self.chromosomes.iter().collect::<Vec<_>>();
+        rows.push(row("total".to_string(), sum(&all), warnings.len()));
+
+        render_table(&rows)
+    }
+
+    /// `--profile` table: wall and CPU time, allocations, bytes
+    /// allocated and resident memory per stage, then the totals.
+    pub fn profile_table(&self) -> String {
+        let mb = |mb: Option<f64>| mb.map_or("-".to_string(), |mb| format!("{:.1}", mb));
+        let row = |stage: &Stage| {
+            let ratio = match stage.seconds > 0.0 {
+                true => stage.cpu_seconds / stage.seconds,
+                false => 0.0,
+            };
+            [
+                stage.name.to_string(),
+                format!("{:.3}", stage.seconds),
+                format!("{:.3}", stage.cpu_seconds),
+                format!("{:.2}", ratio),
+                stage.allocations.map_or("-".to_string(), |n| n.to_string()),
+                mb(stage.allocated_mb),
+                mb(stage.rss_mb),
+                mb(Some(stage.peak_rss_mb)),
+            ]
+        };
+        let total = Stage {
+            name: "total",
+            seconds: self.stages.iter().map(|s| s.seconds).sum(),
+            cpu_seconds: self.stages.iter().map(|s| s.cpu_seconds).sum(),
+            allocations: self.stages.iter().map(|s| s.allocations).sum(),
+            allocated_mb: self.stages.iter().map(|s| s.allocated_mb).sum(),
+            rss_mb: current_mem_usage_mb(),
+            peak_rss_mb: max_mem_usage_mb(),
+        };
+
+        let header = [
+            "stage", "wall s", "cpu s", "cpu/wall", "allocs", "alloc MB", "rss MB", "peak MB",
+        ];
+        let mut rows = vec![header.map(String::from)];
+        rows.extend(self.stages.iter().chain([&total]).map(row));
+        render_table(&rows)
+    }
+
+    /// Fills in the warning counts, total time and peak memory, and
+    /// writes the report as pretty-printed JSON.
+    pub fn write<P: AsRef<Path>>(
+        mut self,
+        path: P,
+        warnings: &Warnings,
+    ) -> Result<(), Box<dyn Error>> {
+        self.warnings = warnings.counts();
+        for feature in SUMMARY_FEATURES {
+            self.features.entry(feature.to_string()).or_default();
+        }
+        self.elapsed_seconds = self.started.elapsed().as_secs_f64();
+        self.peak_memory_mb = max_mem_usage_mb();
+
+        let mut writer = BufWriter::new(create_output(path)?);
+        serde_json::to_writer_pretty(&mut writer, &self)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// The run in the Prometheus text exposition format, as read by the
+    /// node_exporter textfile collector: record counts, features written,
+    /// warnings per code, duration and peak resident memory.
+    pub fn metrics(&self, warnings: &Warnings) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, help: &str, samples: &[(String, f64)]| {
+            let _ = writeln!(out, "# HELP bed2gtf_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE bed2gtf_{} gauge", name);
+            for (labels, value) in samples {
+                let _ = writeln!(out, "bed2gtf_{}{} {}", name, labels, value);
+            }
+        };
+        let one = |value: f64| [(String::new(), value)];
+        let converted = self.bed_records.saturating_sub(self.skipped_records);
+        metric(
+            "bed_records",
+            "BED records read.",
+            &one(self.bed_records as f64),
+        );
+        metric(
+            "records_converted",
+            "BED records written as a transcript.",
+            &one(converted as f64),
+        );
+        metric(
+            "records_skipped",
+            "BED records read but not written as a transcript.",
+            &one(self.skipped_records as f64),
+        );
+        metric(
+            "records_repaired",
+            "BED records repaired by --fix, --clean or --merge-exons.",
+            &one(self.fixed_records as f64),
+        );
+        let mut features = self.features.clone();
+        for feature in SUMMARY_FEATURES {
+            features.entry(feature.to_string()).or_default();
+        }
+        let features = features
+            .iter()
+            .map(|(feature, n)| (format!("{{feature=\"{}\"}}", feature), *n as f64))
+            .collect::<Vec<_>>();
+        metric(
+            "features_written",
+            "Lines written per feature type.",
+            &features,
+        );
+        let codes = warnings
+            .counts()
+            .into_iter()
+            .map(|(code, n)| (format!("{{code=\"{}\"}}", code), n as f64))
+            .collect::<Vec<_>>();
+        metric("warnings", "Non-fatal issues per code.", &codes);
+        metric(
+            "duration_seconds",
+            "Wall time of the run.",
+            &one(self.started.elapsed().as_secs_f64()),
+        );
+        metric(
+            "peak_memory_bytes",
+            "High-water mark of resident memory.",
+            &one((max_mem_usage_mb() * 1024.0 * 1024.0).round()),
+        );
+        metric(
+            "last_run_timestamp_seconds",
+            "Unix time the run started.",
+            &one(self.started_at.timestamp() as f64),
+        );
+        out
+    }
+
+    /// Writes [`Report::metrics`] to `path` (`--metrics-out`).
+    pub fn write_metrics<P: AsRef<Path>>(
+        &self,
+        path: P,
+        warnings: &Warnings,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(create_output(path)?);
+        writer.write_all(self.metrics(warnings).as_bytes())?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Aligns `rows` in columns, the first left-aligned and the others
+/// right-aligned, with a rule above the last (total) row.
+fn render_table<const N: usize>(rows: &[[String; N]]) -> String {
+    let mut widths = [0; N];
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let mut table = String::new();
+    for (i, row) in rows.iter().enumerate() {
+        if i + 1 == rows.len() {
+            let rule = widths.iter().sum::<usize>() + 2 * (N - 1);
+            let _ = writeln!(table, "{}", "-".repeat(rule));
+        }
+        let _ = write!(table, "{:<w$}", row[0], w = widths[0]);
+        for (cell, width) in row.iter().zip(widths).skip(1) {
+            let _ = write!(table, "  {:>w$}", cell, w = width);
+        }
+        table.push('\n');
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn report_counts_features_and_stages() {
+        let args = Cli::try_parse_from(["bed2gtf", "-b", "a.bed", "-i", "iso.txt", "-o", "a.gtf"])
+            .unwrap();
+        let mut report = Report::new(&args);
+        for feature in ["exon", "CDS", "exon"] {
+            report.count(&GtfRecord {
+                chrom: "chr1".to_string(),
+                feature: feature.to_string(),
+                start: 1,
+                end: 10,
+                strand: "+".to_string(),
+                frame: None,
+                attrs: Default::default(),
+                source: None,
+            });
+        }
+        report.stage("convert");
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["features"]["exon"], 2);
+        assert_eq!(json["input"], "a.bed");
+        assert_eq!(json["stages"][0]["name"], "convert");
+        assert!(report.profile_table().starts_with("stage"));
+        assert!(json.get("started").is_none());
+        let table = report.table(&Warnings::default(), &ChromOrder::Natural);
+        assert_eq!(
+            table.lines().nth(1).unwrap(),
+            "chr1       0            0      2         0"
+        );
+        assert!(table.ends_with("total      0            0      2         0\n"));
+        assert!(report
+            .summary()
+            .starts_with("Wrote 0 genes, 0 transcripts, 2 exons, 1 CDS"));
+
+        let warnings = Warnings::default();
+        warnings.push("T1", "cds_incomplete", "reported");
+        let metrics = report.metrics(&warnings);
+        assert!(metrics.starts_with("# HELP bed2gtf_bed_records BED records read.\n"));
+        assert!(metrics.contains("\nbed2gtf_features_written{feature=\"exon\"} 2\n"));
+        assert!(metrics.contains("\nbed2gtf_features_written{feature=\"gene\"} 0\n"));
+        assert!(metrics.contains("\nbed2gtf_warnings{code=\"cds_incomplete\"} 1\n"));
+        assert!(metrics.contains("\n# TYPE bed2gtf_peak_memory_bytes gauge\n"));
+    }
+}
@@ -0,0 +1,155 @@
+use crate::convert::{convert, ConversionJob};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+const ISOFORMS: &str = include_str!("selftest/isoforms.txt");
+
+/// A fixture of `bed2gtf selftest`: a BED and the GTF it must convert
+/// to, header aside. `gzip` converts the BED gzipped.
+struct Case {
+    name: &'static str,
+    bed: &'static str,
+    golden: &'static str,
+    gzip: bool,
+}
+
+const CASES: [Case; 6] = [
+    Case {
+        name: "plus_strand",
+        bed: include_str!("selftest/plus.bed"),
+        golden: include_str!("selftest/plus.gtf"),
+        gzip: false,
+    },
+    Case {
+        name: "minus_strand",
+        bed: include_str!("selftest/minus.bed"),
+        golden: include_str!("selftest/minus.gtf"),
+        gzip: false,
+    },
+    Case {
+        name: "split_codons",
+        bed: include_str!("selftest/split_codons.bed"),
+        golden: include_str!("selftest/split_codons.gtf"),
+        gzip: false,
+    },
+    Case {
+        name: "noncoding",
+        bed: include_str!("selftest/noncoding.bed"),
+        golden: include_str!("selftest/noncoding.gtf"),
+        gzip: false,
+    },
+    Case {
+        name: "single_exon",
+        bed: include_str!("selftest/single_exon.bed"),
+        golden: include_str!("selftest/single_exon.gtf"),
+        gzip: false,
+    },
+    Case {
+        name: "gzipped",
+        bed: include_str!("selftest/plus.bed"),
+        golden: include_str!("selftest/plus.gtf"),
+        gzip: true,
+    },
+];
+
+/// Outcome of one fixture; `failure` says what went wrong.
+#[derive(Debug)]
+pub struct SelftestResult {
+    pub name: &'static str,
+    pub failure: Option<String>,
+}
+
+impl SelftestResult {
+    pub fn passed(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+/// `bed2gtf selftest`: converts every embedded fixture BED in a scratch
+/// directory and compares the output with its embedded golden GTF, so a
+/// build can be checked to behave like the one that made the goldens.
+pub fn selftest() -> Result<Vec<SelftestResult>, Box<dyn Error>> {
+    let dir = std::env::temp_dir().join(format!("bed2gtf-selftest-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let results = CASES
+        .iter()
+        .map(|case| SelftestResult {
+            name: case.name,
+            failure: run_case(case, &dir).err().map(|e| e.to_string()),
+        })
+        .collect();
+    fs::remove_dir_all(&dir)?;
+    Ok(results)
+}
+
+fn run_case(case: &Case, dir: &Path) -> Result<(), Box<dyn Error>> {
+    let isoforms = dir.join("isoforms.txt");
+    fs::write(&isoforms, ISOFORMS)?;
+    let output = dir.join(format!("{}.gtf", case.name));
+    let bed = match case.gzip {
+        true => {
+            let bed = dir.join(format!("{}.bed.gz", case.name));
+            let mut encoder = GzEncoder::new(fs::File::create(&bed)?, Compression::default());
+            encoder.write_all(case.bed.as_bytes())?;
+            encoder.finish()?;
+            bed
+        }
+        false => {
+            let bed = dir.join(format!("{}.bed", case.name));
+            fs::write(&bed, case.bed)?;
+            bed
+        }
+    };
+
+    let job = ConversionJob::new([
+        "-b".as_ref(),
+        bed.as_os_str(),
+        "-i".as_ref(),
+        isoforms.as_os_str(),
+        "-o".as_ref(),
+        output.as_os_str(),
+        "--no-summary".as_ref(),
+    ])?;
+    convert(job.args())?;
+
+    let actual = fs::read_to_string(&output)?;
+    let actual = actual.lines().filter(|line| !line.starts_with('#'));
+    let mut expected = case.golden.lines();
+    for (n, line) in actual.enumerate() {
+        match expected.next() {
+            Some(golden) if golden == line => {}
+            Some(golden) => {
+                return Err(format!(
+                    "line {} differs: expected {:?}, got {:?}",
+                    n + 1,
+                    golden,
+                    line
+                )
+                .into())
+            }
+            None => return Err(format!("unexpected line {}: {:?}", n + 1, line).into()),
+        }
+    }
+    match expected.next() {
+        Some(golden) => Err(format!("missing line {:?}", golden).into()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixtures_match_goldens() {
+        for result in selftest().unwrap() {
+            assert!(result.passed(), "{}: {:?}", result.name, result.failure);
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use std::sync::Arc;
+
+use crate::error::Bed2GtfError;
+
+/// Output files that may be removed from a signal handler. Plain atomic
+/// pointers, because nothing else is async-signal-safe.
+const SLOTS: usize = 8;
+static PARTIAL: [AtomicPtr<c_char>; SLOTS] = [const { AtomicPtr::new(ptr::null_mut()) }; SLOTS];
+
+/// Lets a closed downstream pipe (`| head`) end the process quietly
+/// instead of surfacing as a write error. Only for runs writing to
+/// stdout: sockets used by URL inputs must keep SIGPIPE ignored.
+pub fn restore_sigpipe() {
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+    }
+}
+
+/// Removes registered partial outputs on SIGINT/SIGTERM, then dies from
+/// the same signal so callers still see the interruption.
+pub fn install_handlers() {
+    #[cfg(unix)]
+    unsafe {
+        let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn on_signal(sig: libc::c_int) {
+    for slot in &PARTIAL {
+        let path = slot.swap(ptr::null_mut(), Ordering::SeqCst);
+        if !path.is_null() {
+            unsafe {
+                libc::unlink(path);
+            }
+        }
+    }
+    unsafe {
+        libc::signal(sig, libc::SIG_DFL);
+        libc::raise(sig);
+    }
+}
+
+/// Cooperative cancellation of a conversion, for embedders (GUIs,
+/// servers) that need to stop a run without tearing down its thread
+/// pool. Clones share the flag. A cancelled run stops at its next
+/// checkpoint (between stages and input chunks) with
+/// [`Bed2GtfError::Cancelled`], removing its partial outputs and
+/// temporary files like any other failed run.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Asks every run holding a clone of this token to stop.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// A checkpoint: fails once the token is cancelled.
+    pub fn check(&self) -> Result<(), Bed2GtfError> {
+        match self.is_cancelled() {
+            true => Err(Bed2GtfError::Cancelled),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Output file that is deleted unless [`PartialOutput::keep`] is called:
+/// on error (drop) or when the process is interrupted, so a truncated
+/// file never looks complete to downstream jobs. Stdout, object store
+/// outputs and existing non-regular files are not tracked.
+pub struct PartialOutput {
+    path: PathBuf,
+    slot: Option<usize>,
+}
+
+impl PartialOutput {
+    pub fn new<P: AsRef<Path>>(path: P) -> PartialOutput {
+        let path = path.as_ref().to_path_buf();
+        // devices and FIFOs (/dev/null, process substitution) are not ours to remove
+        let special = std::fs::metadata(&path).is_ok_and(|m| !m.is_file());
+        let tracked =
+            !crate::utils::is_stdout(&path) && !crate::utils::is_object_url(&path) && !special;
+        let slot = tracked.then(|| register(&path)).flatten();
+        PartialOutput { path, slot }
+    }
+
+    /// The output is complete: stop tracking it.
+    pub fn keep(mut self) {
+        self.release();
+    }
+
+    fn release(&mut self) -> Option<()> {
+        let ptr = PARTIAL[self.slot.take()?].swap(ptr::null_mut(), Ordering::SeqCst);
+        if !ptr.is_null() {
+            drop(unsafe { CString::from_raw(ptr) });
+        }
+        Some(())
+    }
+}
+
+impl Drop for PartialOutput {
+    fn drop(&mut self) {
+        if self.release().is_some() && self.path.exists() {
+            log::warn!("Removing incomplete {}", self.path.display());
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+fn register(path: &Path) -> Option<usize> {
+    let c = CString::new(path.to_string_lossy().as_bytes())
+        .ok()?
+        .into_raw();
+    for (i, slot) in PARTIAL.iter().enumerate() {
+        if slot
+            .compare_exchange(ptr::null_mut(), c, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            return Some(i);
+        }
+    }
+    drop(unsafe { CString::from_raw(c) });
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_unless_kept() {
+        let dir = std::env::temp_dir();
+        let (a, b) = (
+            dir.join(format!("bed2gtf-partial-a-{}", std::process::id())),
+            dir.join(format!("bed2gtf-partial-b-{}", std::process::id())),
+        );
+        std::fs::write(&a, "x").unwrap();
+        std::fs::write(&b, "x").unwrap();
+
+        drop(PartialOutput::new(&a));
+        PartialOutput::new(&b).keep();
+
+        assert!(!a.exists());
+        assert!(b.exists());
+        std::fs::remove_file(&b).unwrap();
+    }
+}
@@ -0,0 +1,419 @@
+use crate::format::{coding_transcripts, Format};
+use crate::formatter::{format_record, FeatureFormatter, Gff3Formatter, GtfFormatter};
+use crate::grammar::check_line;
+use crate::lines::{AttrStyle, GtfRecord, PhaseStyle};
+use crate::par::prelude::*;
+
+use std::collections::HashSet;
+use std::io::{self, Write};
+
+/// Rendered bytes a text writer holds before passing them on.
+const BUFFER_BYTES: usize = 64 * 1024;
+
+/// Records rendered into one buffer by [`AnnotationSink::write_records`]
+/// of the text writers.
+const WRITE_BLOCK: usize = 4096;
+
+/// Destination of converted features. The conversion sends genes,
+/// transcripts and their features (exons, CDS, codons, introns, ...)
+/// through these methods, so an application can collect them, load
+/// them into a database or stream them elsewhere without rendering and
+/// re-parsing text. [`GtfWriter`], [`Gff3Writer`] and other
+/// [`FeatureWriter`]s write the usual files; `Vec<GtfRecord>` and
+/// [`FeatureCollector`] keep them in memory.
+pub trait AnnotationSink {
+    fn write_gene(&mut self, gene: &GtfRecord) -> io::Result<()>;
+
+    /// A transcript line; `coding` tells whether the transcript has a
+    /// CDS, as it comes before its CDS features.
+    fn write_transcript(&mut self, transcript: &GtfRecord, coding: bool) -> io::Result<()>;
+
+    fn write_feature(&mut self, feature: &GtfRecord) -> io::Result<()>;
+
+    /// Called once every feature has been sent.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Sends `records`, which hold whole transcripts, to the methods
+    /// above in order.
+    fn write_records(&mut self, records: &[GtfRecord]) -> io::Result<()> {
+        let coding = coding_transcripts(records);
+        for record in records {
+            match record.feature.as_str() {
+                "gene" => self.write_gene(record)?,
+                "transcript" => {
+                    let tx = record.attrs.get("transcript_id");
+                    self.write_transcript(record, tx.is_some_and(|tx| coding.contains(tx)))?
+                }
+                _ => self.write_feature(record)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S: AnnotationSink + ?Sized> AnnotationSink for &mut S {
+    fn write_gene(&mut self, gene: &GtfRecord) -> io::Result<()> {
+        (**self).write_gene(gene)
+    }
+
+    fn write_transcript(&mut self, transcript: &GtfRecord, coding: bool) -> io::Result<()> {
+        (**self).write_transcript(transcript, coding)
+    }
+
+    fn write_feature(&mut self, feature: &GtfRecord) -> io::Result<()> {
+        (**self).write_feature(feature)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+
+    fn write_records(&mut self, records: &[GtfRecord]) -> io::Result<()> {
+        (**self).write_records(records)
+    }
+}
+
+/// Every feature, in the order it was sent.
+impl AnnotationSink for Vec<GtfRecord> {
+    fn write_gene(&mut self, gene: &GtfRecord) -> io::Result<()> {
+        self.push(gene.clone());
+        Ok(())
+    }
+
+    fn write_transcript(&mut self, transcript: &GtfRecord, _coding: bool) -> io::Result<()> {
+        self.push(transcript.clone());
+        Ok(())
+    }
+
+    fn write_feature(&mut self, feature: &GtfRecord) -> io::Result<()> {
+        self.push(feature.clone());
+        Ok(())
+    }
+
+    fn write_records(&mut self, records: &[GtfRecord]) -> io::Result<()> {
+        self.extend_from_slice(records);
+        Ok(())
+    }
+}
+
+/// Features kept apart by kind, with the transcript_ids of the coding
+/// transcripts.
+#[derive(Debug, Clone, Default)]
+pub struct FeatureCollector {
+    pub genes: Vec<GtfRecord>,
+    pub transcripts: Vec<GtfRecord>,
+    pub features: Vec<GtfRecord>,
+    pub coding: HashSet<String>,
+}
+
+impl AnnotationSink for FeatureCollector {
+    fn write_gene(&mut self, gene: &GtfRecord) -> io::Result<()> {
+        self.genes.push(gene.clone());
+        Ok(())
+    }
+
+    fn write_transcript(&mut self, transcript: &GtfRecord, coding: bool) -> io::Result<()> {
+        if coding {
+            let tx = transcript.attrs.get("transcript_id").unwrap_or_default();
+            self.coding.insert(tx.to_string());
+        }
+        self.transcripts.push(transcript.clone());
+        Ok(())
+    }
+
+    fn write_feature(&mut self, feature: &GtfRecord) -> io::Result<()> {
+        self.features.push(feature.clone());
+        Ok(())
+    }
+}
+
+/// Fails on a rendered `line` (without its newline) that breaks the
+/// `format` grammar, giving its line number in the output.
+fn checked(line: &str, format: Format, number: usize) -> io::Result<()> {
+    check_line(line, format).map_err(|problem| {
+        let format = match format {
+            Format::Gtf => "GTF",
+            Format::Gff3 => "GFF3",
+        };
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "line {} is not valid {}: {}: {}",
+                number, format, problem, line
+            ),
+        )
+    })
+}
+
+/// Checks every line of `text` (whole lines) against the `format`
+/// grammar, the first being line `first` of the output.
+fn checked_lines(text: &str, format: Format, first: usize) -> io::Result<()> {
+    text.lines()
+        .enumerate()
+        .try_for_each(|(i, line)| checked(line, format, first + i))
+}
+
+/// Writes `records` as large preformatted blocks: every [`WRITE_BLOCK`]
+/// records are rendered into one buffer, blocks are rendered in
+/// parallel and each reaches the writer in a single call. With `check`,
+/// every line is checked against the grammar of its format first, the
+/// first one being on the given line. Returns the lines written.
+fn write_blocks<W, F>(
+    writer: &mut W,
+    records: &[GtfRecord],
+    check: Option<(Format, usize)>,
+    render: F,
+) -> io::Result<usize>
+where
+    W: Write + ?Sized,
+    F: Fn(&mut String, &GtfRecord) + Sync,
+{
+    let mut lines = 0;
+    let group = WRITE_BLOCK * crate::par::current_num_threads().max(1);
+    for records in records.chunks(group) {
+        let blocks = records
+            .par_chunks(WRITE_BLOCK)
+            .map(|block| {
+                let mut out = String::with_capacity(block.len() * 192);
+                block.iter().for_each(|record| render(&mut out, record));
+                let count = out.bytes().filter(|&b| b == b'\n').count();
+                // checked here, in parallel; numbered once the lines before are counted
+                let bad = check.and_then(|(format, _)| checked_lines(&out, format, 1).err());
+                (out, count, bad)
+            })
+            .collect::<Vec<_>>();
+        for (block, count, bad) in blocks {
+            if let (Some((format, first)), Some(_)) = (check, bad) {
+                checked_lines(&block, format, first + lines)?;
+            }
+            writer.write_all(block.as_bytes())?;
+            lines += count;
+        }
+    }
+    Ok(lines)
+}
+
+/// Writes features as text lines of the dialect of its
+/// [`FeatureFormatter`]. Header lines are left to the caller; lines are
+/// buffered until [`AnnotationSink::flush`] or drop.
+pub struct FeatureWriter<W: Write, F: FeatureFormatter> {
+    writer: W,
+    buffer: String,
+    formatter: F,
+    /// Output line of the next line written, when lines are checked.
+    check: Option<usize>,
+}
+
+/// Writes features as GTF2.2 lines.
+pub type GtfWriter<W> = FeatureWriter<W, GtfFormatter>;
+
+/// Writes features as GFF3 lines linked by ID/Parent. The
+/// `##gff-version 3` pragma is left to the caller with the other header
+/// lines.
+pub type Gff3Writer<W> = FeatureWriter<W, Gff3Formatter>;
+
+impl<W: Write, F: FeatureFormatter + Default> FeatureWriter<W, F> {
+    pub fn new(writer: W) -> Self {
+        FeatureWriter::with_formatter(writer, F::default())
+    }
+}
+
+impl<W: Write, F: FeatureFormatter> FeatureWriter<W, F> {
+    pub fn with_formatter(writer: W, formatter: F) -> Self {
+        FeatureWriter {
+            writer,
+            buffer: String::new(),
+            formatter,
+            check: None,
+        }
+    }
+
+    /// Checks every line against the grammar of the formatter before it
+    /// is written (`--verify`); the first one goes on line `first_line`.
+    pub fn with_grammar_check(mut self, first_line: usize) -> Self {
+        self.check = Some(first_line);
+        self
+    }
+
+    fn push(&mut self, record: &GtfRecord, coding: bool) -> io::Result<()> {
+        let from = self.buffer.len();
+        format_record(&self.formatter, &mut self.buffer, record, coding);
+        if let Some(number) = self.check {
+            let lines = &self.buffer[from..];
+            let count = checked_lines(lines, self.formatter.format(), number)
+                .map(|_| lines.lines().count())
+                .inspect_err(|_| self.buffer.truncate(from))?;
+            self.check = Some(number + count);
+        }
+        if self.buffer.len() >= BUFFER_BYTES {
+            self.dump()?;
+        }
+        Ok(())
+    }
+
+    fn dump(&mut self) -> io::Result<()> {
+        self.writer.write_all(self.buffer.as_bytes())?;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> GtfWriter<W> {
+    /// Attribute keys written first, in this order (`--attr-order`).
+    pub fn with_attr_order(mut self, order: Vec<String>) -> Self {
+        self.formatter.attr_order = order;
+        self
+    }
+
+    /// Separators and quoting of the attributes (`--attr-style`).
+    pub fn with_attr_style(mut self, style: AttrStyle) -> Self {
+        self.formatter.attr_style = style;
+        self
+    }
+
+    pub fn with_phase_style(mut self, phase: PhaseStyle) -> Self {
+        self.formatter.phase = phase;
+        self
+    }
+}
+
+impl<W: Write> Gff3Writer<W> {
+    /// Feature type of transcripts without a CDS (coding ones are mRNA).
+    pub fn with_noncoding_type<T: Into<String>>(mut self, feature: T) -> Self {
+        self.formatter.noncoding_type = feature.into();
+        self
+    }
+
+    /// Whether transcripts name their gene as Parent; turn off when no
+    /// gene lines are written.
+    pub fn with_gene_parents(mut self, parents: bool) -> Self {
+        self.formatter.gene_parents = parents;
+        self
+    }
+
+    pub fn with_phase_style(mut self, phase: PhaseStyle) -> Self {
+        self.formatter.phase = phase;
+        self
+    }
+}
+
+impl<W: Write, F: FeatureFormatter> AnnotationSink for FeatureWriter<W, F> {
+    fn write_gene(&mut self, gene: &GtfRecord) -> io::Result<()> {
+        self.push(gene, false)
+    }
+
+    fn write_transcript(&mut self, transcript: &GtfRecord, coding: bool) -> io::Result<()> {
+        self.push(transcript, coding)
+    }
+
+    fn write_feature(&mut self, feature: &GtfRecord) -> io::Result<()> {
+        self.push(feature, false)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.dump()?;
+        self.writer.flush()
+    }
+
+    fn write_records(&mut self, records: &[GtfRecord]) -> io::Result<()> {
+        self.dump()?;
+        let formatter = &self.formatter;
+        let coding = if formatter.needs_coding() {
+            coding_transcripts(records)
+        } else {
+            HashSet::new()
+        };
+        let check = self.check.map(|first| (formatter.format(), first));
+        let lines = write_blocks(&mut self.writer, records, check, |out, r| {
+            let tx = r.attrs.get("transcript_id");
+            format_record(formatter, out, r, tx.is_some_and(|tx| coding.contains(tx)))
+        })?;
+        if let Some(number) = &mut self.check {
+            *number += lines;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write, F: FeatureFormatter> Drop for FeatureWriter<W, F> {
+    fn drop(&mut self) {
+        let _ = self.dump();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lines::Attributes;
+
+    #[test]
+    fn sinks_agree_on_features() {
+        let record = |feature: &str, start: u32, end: u32, frame: Option<u8>| {
+            let mut attrs = Attributes::new();
+            attrs.push("gene_id", "G1");
+            if feature != "gene" {
+                attrs.push("transcript_id", "T1");
+            }
+            GtfRecord {
+                chrom: "chr1".to_string(),
+                feature: feature.to_string(),
+                start,
+                end,
+                strand: "+".to_string(),
+                frame,
+                attrs,
+                source: None,
+            }
+        };
+        let records = vec![
+            record("gene", 1, 900, None),
+            record("transcript", 1, 900, None),
+            record("exon", 1, 900, None),
+            record("CDS", 51, 850, Some(0)),
+        ];
+
+        // one call per record or one batch give the same text
+        let mut single = Vec::new();
+        let mut writer = Gff3Writer::new(&mut single).with_gene_parents(true);
+        writer.write_gene(&records[0]).unwrap();
+        writer.write_transcript(&records[1], true).unwrap();
+        records[2..]
+            .iter()
+            .for_each(|r| writer.write_feature(r).unwrap());
+        AnnotationSink::flush(&mut writer).unwrap();
+        drop(writer);
+        let mut batch = Vec::new();
+        Gff3Writer::new(&mut batch).write_records(&records).unwrap();
+        assert_eq!(single, batch);
+        let gff3 = String::from_utf8(batch).unwrap();
+        assert!(gff3.contains("\tmRNA\t1\t900\t.\t+\t.\tID=T1;Parent=G1\n"));
+
+        let mut gtf = Vec::new();
+        GtfWriter::new(&mut gtf)
+            .with_grammar_check(1)
+            .write_records(&records)
+            .unwrap();
+        let mut broken = records[3].clone();
+        broken.frame = None;
+        let error = GtfWriter::new(Vec::new())
+            .with_grammar_check(5)
+            .write_records(&[records[2].clone(), broken])
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("line 6 is not valid GTF: CDS without a phase"));
+        assert!(String::from_utf8(gtf).unwrap().ends_with(
+            "chr1\tbed2gtf\tCDS\t51\t850\t.\t+\t0\tgene_id \"G1\"; transcript_id \"T1\";\n"
+        ));
+
+        let mut collector = FeatureCollector::default();
+        collector.write_records(&records).unwrap();
+        assert_eq!((collector.genes.len(), collector.transcripts.len()), (1, 1));
+        assert!(collector.coding.contains("T1"));
+        let mut all = Vec::new();
+        all.write_records(&records).unwrap();
+        assert_eq!(all, records);
+    }
+}
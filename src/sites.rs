@@ -0,0 +1,130 @@
+use crate::bed::BedRecord;
+use crate::utils::create_output;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Which end of transcripts `--tss-out` and `--tes-out` write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SiteKind {
+    /// Transcription start site: the 5' base.
+    Start,
+    /// Transcription end site: the 3' base.
+    End,
+}
+
+impl SiteKind {
+    /// 0-based position of the site of `record`; anything but `-` is
+    /// read as the plus strand.
+    pub fn position(self, record: &BedRecord) -> u32 {
+        let last = record.tx_end.saturating_sub(1).max(record.tx_start);
+        match (self, record.strand == "-") {
+            (SiteKind::Start, false) | (SiteKind::End, true) => record.tx_start,
+            (SiteKind::End, false) | (SiteKind::Start, true) => last,
+        }
+    }
+}
+
+/// The 1-bp site shared by the transcripts of one gene.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Site {
+    pub chrom: String,
+    /// 0-based position.
+    pub position: u32,
+    pub strand: String,
+    pub gene: String,
+    pub transcripts: Vec<String>,
+}
+
+/// The sites of `records`, one per gene, strand and position, with the
+/// gene from `isoforms` (the transcript itself when unlisted). Sites
+/// follow the chromosomes in input order, then positions.
+pub fn collapse_sites(
+    records: &[BedRecord],
+    isoforms: &HashMap<String, String>,
+    kind: SiteKind,
+) -> Vec<Site> {
+    let mut chroms: HashMap<&str, usize> = HashMap::new();
+    let mut ends = records
+        .iter()
+        .map(|record| {
+            let next = chroms.len();
+            let chrom = *chroms.entry(&record.chrom).or_insert(next);
+            let gene = isoforms.get(&record.name).unwrap_or(&record.name);
+            ((chrom, kind.position(record), gene, &record.strand), record)
+        })
+        .collect::<Vec<_>>();
+    ends.sort_by(|a, b| (&a.0, &a.1.name).cmp(&(&b.0, &b.1.name)));
+    ends.chunk_by(|a, b| a.0 == b.0)
+        .map(|group| {
+            let ((_, position, gene, strand), record) = group[0];
+            Site {
+                chrom: record.chrom.clone(),
+                position,
+                strand: strand.clone(),
+                gene: gene.clone(),
+                transcripts: group.iter().map(|(_, r)| r.name.clone()).collect(),
+            }
+        })
+        .collect()
+}
+
+/// Writes the [`collapse_sites`] of `records` as BED6+1: the gene as
+/// name, how many of its transcripts share the site as score, and their
+/// ids, comma-separated, in column 7.
+pub fn write_sites<P: AsRef<Path>>(
+    path: P,
+    records: &[BedRecord],
+    isoforms: &HashMap<String, String>,
+    kind: SiteKind,
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(create_output(path)?);
+    for site in collapse_sites(records, isoforms, kind) {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            site.chrom,
+            site.position,
+            site.position + 1,
+            site.gene,
+            site.transcripts.len(),
+            site.strand,
+            site.transcripts.join(",")
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collapses_shared_sites_per_gene() {
+        let line = "chr1\t100\t900\tT1\t0\t+\t0\t0\t0\t2\t100,100,\t0,700,";
+        let records = [
+            line.to_string(),
+            line.replace("T1", "T2").replace("\t900\t", "\t950\t"),
+            line.replace("T1", "T3").replace("\t+\t", "\t-\t"),
+        ]
+        .map(|line| BedRecord::parse(&line).unwrap());
+        assert_eq!(SiteKind::Start.position(&records[0]), 100);
+        assert_eq!(SiteKind::End.position(&records[1]), 949);
+        assert_eq!(SiteKind::Start.position(&records[2]), 899);
+
+        let isoforms = ["T1", "T2", "T3"]
+            .map(|t| (t.to_string(), "G1".to_string()))
+            .into();
+        let sites = collapse_sites(&records, &isoforms, SiteKind::Start);
+        assert_eq!(
+            sites
+                .iter()
+                .map(|s| (s.position, s.transcripts.join(",")))
+                .collect::<Vec<_>>(),
+            [(100, "T1,T2".to_string()), (899, "T3".to_string())]
+        );
+    }
+}
@@ -0,0 +1,485 @@
+use crate::gz::ParGzWriter;
+use crate::signals::PartialOutput;
+use crate::utils::{create_output, raw};
+
+use crate::par::prelude::*;
+use flate2::read::MultiGzDecoder;
+use flate2::Compression;
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// How chromosomes are ordered in the output.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ChromOrder {
+    /// Natural order: chr1, chr2, ..., chr10, ..., chrX (default).
+    #[default]
+    Natural,
+    /// Plain byte order: chr1, chr10, chr2, ...
+    Lexicographic,
+    /// chr1..22, X, Y, M, then everything else (alts, unplaced) in
+    /// natural order; `chr` prefixes are optional.
+    Karyotypic,
+    /// Natural order of the primary chromosomes, then unlocalized and
+    /// unplaced contigs (`_random`, `chrUn_`), alternative haplotypes
+    /// (`_alt`) and patches (`_fix`), as in reference FASTA files.
+    PrimaryFirst,
+    /// Order listed in a file (`file:PATH`).
+    File(Arc<ContigList>),
+}
+
+/// Where contigs missing from a `file:` order go.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Unlisted {
+    /// After all listed contigs, in natural order.
+    #[default]
+    Last,
+    /// Before all listed contigs, in natural order.
+    First,
+    /// Refuse to write contigs that are not listed.
+    Error,
+}
+
+/// Contig names in the desired order, read from one name per line
+/// (first column, so chrom.sizes/.fai files work) or from `@SQ` lines
+/// of a SAM header.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContigList {
+    ranks: HashMap<String, usize>,
+    pub unlisted: Unlisted,
+}
+
+impl ContigList {
+    pub fn from_path<P: AsRef<Path> + Debug>(f: P) -> Result<ContigList, Box<dyn Error>> {
+        Ok(ContigList::parse(&raw(f)?))
+    }
+
+    pub fn parse(contents: &str) -> ContigList {
+        let mut ranks = HashMap::new();
+        for line in contents.lines() {
+            let name = if line.starts_with("@SQ") {
+                line.split('\t').find_map(|f| f.strip_prefix("SN:"))
+            } else if line.starts_with('@') || line.starts_with('#') {
+                None
+            } else {
+                line.split_whitespace().next()
+            };
+            if let Some(name) = name {
+                let next = ranks.len();
+                ranks.entry(name.to_string()).or_insert(next);
+            }
+        }
+        ContigList {
+            ranks,
+            unlisted: Unlisted::default(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranks.is_empty()
+    }
+
+    pub fn contains(&self, chrom: &str) -> bool {
+        self.ranks.contains_key(chrom)
+    }
+
+    fn rank(&self, chrom: &str) -> (u8, usize) {
+        match (self.ranks.get(chrom), self.unlisted) {
+            (Some(&i), Unlisted::First) => (1, i),
+            (Some(&i), _) => (0, i),
+            (None, Unlisted::First) => (0, 0),
+            (None, _) => (1, 0),
+        }
+    }
+}
+
+impl ChromOrder {
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        match self {
+            ChromOrder::Natural => natord::compare(a, b),
+            ChromOrder::Lexicographic => a.cmp(b),
+            ChromOrder::Karyotypic => karyotypic_rank(a)
+                .cmp(&karyotypic_rank(b))
+                .then_with(|| natord::compare(a, b)),
+            ChromOrder::PrimaryFirst => contig_group(a)
+                .cmp(&contig_group(b))
+                .then_with(|| natord::compare(a, b)),
+            ChromOrder::File(list) => list
+                .rank(a)
+                .cmp(&list.rank(b))
+                .then_with(|| natord::compare(a, b)),
+        }
+    }
+
+    /// Sets where contigs missing from a `file:` order go.
+    pub fn set_unlisted(&mut self, unlisted: Unlisted) {
+        if let ChromOrder::File(list) = self {
+            Arc::make_mut(list).unlisted = unlisted;
+        }
+    }
+
+    /// Fails on contigs absent from a `file:` order when they are
+    /// configured as errors.
+    pub fn check<'a, I: IntoIterator<Item = &'a str>>(&self, chroms: I) -> Result<(), String> {
+        let list = match self {
+            ChromOrder::File(list) if list.unlisted == Unlisted::Error => list,
+            _ => return Ok(()),
+        };
+        let mut missing = chroms
+            .into_iter()
+            .filter(|c| !list.contains(c))
+            .collect::<Vec<_>>();
+        missing.sort_unstable();
+        missing.dedup();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} contigs are not in the chromosome order file: {}",
+                missing.len(),
+                missing.join(", ")
+            ))
+        }
+    }
+
+    /// Chromosome order, then start position.
+    pub fn cmp_coords(&self, a: (&str, u32), b: (&str, u32)) -> Ordering {
+        self.compare(a.0, b.0).then(a.1.cmp(&b.1))
+    }
+
+    /// Rank of every distinct name of `chroms` in this order; names that
+    /// compare equal share a rank. Sorting by rank compares integers
+    /// where [`ChromOrder::compare`] walks both names.
+    pub fn ranks<'a, I: IntoIterator<Item = &'a str>>(&self, chroms: I) -> HashMap<String, usize> {
+        let mut names = chroms
+            .into_iter()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        names.par_sort_unstable_by(|a, b| self.compare(a, b));
+        let mut ranks = HashMap::with_capacity(names.len());
+        let mut rank = 0;
+        for (i, name) in names.iter().enumerate() {
+            if i > 0 && self.compare(names[i - 1], name).is_ne() {
+                rank += 1;
+            }
+            ranks.insert(name.to_string(), rank);
+        }
+        ranks
+    }
+
+    /// Stably sorts `items` by the chromosome and start `coords` gives,
+    /// like [`ChromOrder::cmp_coords`]. Names are ranked once and the
+    /// items sorted by (rank, start, index) keys, so draft assemblies
+    /// with 100k+ contigs never compare names per pair of features.
+    pub fn sort_by_coords<T, F>(&self, items: &mut [T], coords: F)
+    where
+        F: Fn(&T) -> (&str, u32),
+    {
+        let ranks = self.ranks(items.iter().map(|item| coords(item).0));
+        let mut keys = items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| {
+                let (chrom, start) = coords(item);
+                (ranks[chrom], start, i)
+            })
+            .collect::<Vec<_>>();
+        drop(ranks);
+        keys.par_sort_unstable_by(|a, b| a.cmp(b));
+
+        // item `order[k]` goes to `k`: follow each cycle of the permutation
+        let mut order = keys.into_iter().map(|(_, _, i)| i).collect::<Vec<_>>();
+        for first in 0..order.len() {
+            let mut at = first;
+            while order[at] != first {
+                let next = order[at];
+                items.swap(at, next);
+                order[at] = at;
+                at = next;
+            }
+            order[at] = at;
+        }
+    }
+}
+
+impl FromStr for ChromOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "natural" => Ok(ChromOrder::Natural),
+            "lexicographic" => Ok(ChromOrder::Lexicographic),
+            "karyotypic" => Ok(ChromOrder::Karyotypic),
+            "primary-first" => Ok(ChromOrder::PrimaryFirst),
+            _ => match s.strip_prefix("file:") {
+                Some(path) => {
+                    let list = ContigList::from_path(path)
+                        .map_err(|e| format!("cannot read {}: {}", path, e))?;
+                    if list.is_empty() {
+                        return Err(format!("{} lists no contigs", path));
+                    }
+                    Ok(ChromOrder::File(Arc::new(list)))
+                }
+                None => Err(format!(
+                    "unknown chromosome order {:?} [natural, lexicographic, karyotypic, primary-first, file:PATH]",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+/// Primary chromosomes first (autosomes by number, then X, Y, M), then
+/// everything else.
+fn karyotypic_rank(chrom: &str) -> (u8, u32) {
+    let name = chrom
+        .strip_prefix("chr")
+        .or_else(|| chrom.strip_prefix("Chr"))
+        .unwrap_or(chrom);
+    match name {
+        "X" => (1, 0),
+        "Y" => (1, 1),
+        "M" | "MT" => (1, 2),
+        _ => match name.parse::<u32>() {
+            Ok(n) => (0, n),
+            Err(_) => (2, 0),
+        },
+    }
+}
+
+/// 0 for primary chromosomes, then unlocalized/unplaced, alt and patch
+/// contigs.
+fn contig_group(chrom: &str) -> u8 {
+    let lower = chrom.to_ascii_lowercase();
+    if lower.ends_with("_fix") || lower.ends_with("_novel") {
+        3
+    } else if lower.ends_with("_alt") {
+        2
+    } else if lower.ends_with("_random") || lower.starts_with("chrun") || lower.starts_with("un_") {
+        1
+    } else {
+        0
+    }
+}
+
+/// A GTF/GFF data line with the columns needed for sorting/indexing.
+pub struct Feature<'a> {
+    pub line: &'a str,
+    pub chrom: &'a str,
+    pub start: u32,
+    pub end: u32,
+}
+
+/// Comment/blank lines and features of a GTF/GFF file.
+pub type SplitLines<'a> = (Vec<&'a str>, Vec<Feature<'a>>);
+
+/// Splits GTF/GFF text into comment/blank lines and features.
+pub fn split_features(contents: &str) -> Result<SplitLines<'_>, Box<dyn Error>> {
+    let mut header = Vec::new();
+    let mut features = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        if line.starts_with('#') || line.trim().is_empty() {
+            header.push(line);
+            continue;
+        }
+        features.push(
+            parse_feature(line)
+                .ok_or_else(|| format!("line {}: not a valid GTF/GFF feature: {}", i + 1, line))?,
+        );
+    }
+    Ok((header, features))
+}
+
+fn parse_feature(line: &str) -> Option<Feature<'_>> {
+    let mut fields = line.split('\t');
+    let chrom = fields.next()?;
+    let start = fields.nth(2)?.parse().ok()?;
+    let end = fields.next()?.parse().ok()?;
+    Some(Feature {
+        line,
+        chrom,
+        start,
+        end,
+    })
+}
+
+/// Sorts features into output order. The sort is stable, so features
+/// sharing a start keep their input order (gene before transcript
+/// before exon for `bed2gtf` output). Returns false if the input was
+/// already sorted.
+pub fn sort_features(features: &mut [Feature], chrom_order: &ChromOrder) -> bool {
+    if features.windows(2).all(|w| {
+        chrom_order
+            .cmp_coords((w[0].chrom, w[0].start), (w[1].chrom, w[1].start))
+            .is_le()
+    }) {
+        return false;
+    }
+    chrom_order.sort_by_coords(features, |f| (f.chrom, f.start));
+    true
+}
+
+/// True if every chromosome forms one contiguous run and starts are
+/// ascending within it, whatever the chromosome order.
+pub fn is_grouped(features: &[Feature]) -> bool {
+    let mut seen = HashSet::new();
+    features.iter().enumerate().all(|(i, f)| match i {
+        0 => seen.insert(f.chrom),
+        _ if features[i - 1].chrom == f.chrom => features[i - 1].start <= f.start,
+        _ => seen.insert(f.chrom),
+    })
+}
+
+/// Reads a plain or gzipped/bgzipped text file.
+pub fn read_text(path: &Path) -> Result<String, Box<dyn Error>> {
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+
+    // sniff the gzip magic rather than trusting the extension
+    let mut contents = String::new();
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        MultiGzDecoder::new(&bytes[..]).read_to_string(&mut contents)?;
+    } else {
+        contents = String::from_utf8(bytes)?;
+    }
+    Ok(contents)
+}
+
+/// Sorts an existing GTF/GFF file by chromosome and start.
+/// Comment lines are kept at the top; output ending in `.gz` is
+/// bgzipped.
+pub fn sort_gtf(
+    input: &Path,
+    output: &Path,
+    chrom_order: &ChromOrder,
+    level: Compression,
+) -> Result<usize, Box<dyn Error>> {
+    let contents = read_text(input)?;
+    let (header, mut features) = split_features(&contents)?;
+    chrom_order.check(features.iter().map(|f| f.chrom))?;
+    sort_features(&mut features, chrom_order);
+
+    let partial = PartialOutput::new(output);
+    let file = create_output(output)?;
+    let write = |writer: &mut dyn Write| -> io::Result<()> {
+        for line in &header {
+            writeln!(writer, "{}", line)?;
+        }
+        for feature in &features {
+            writeln!(writer, "{}", feature.line)?;
+        }
+        Ok(())
+    };
+    match output.extension().and_then(|s| s.to_str()) {
+        Some("gz") => {
+            let mut writer = ParGzWriter::new(file, level);
+            write(&mut writer)?;
+            writer.finish()?.flush()?;
+        }
+        _ => {
+            let mut writer = BufWriter::new(file);
+            write(&mut writer)?;
+            writer.flush()?;
+        }
+    }
+    partial.keep();
+
+    Ok(features.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chromosome_orders() {
+        let mut chroms = vec![
+            "chrUn_gl1",
+            "chrM",
+            "chr10",
+            "chrX",
+            "chr2",
+            "chr1_alt",
+            "chr1",
+        ];
+
+        chroms.sort_by(|a, b| ChromOrder::Karyotypic.compare(a, b));
+        assert_eq!(
+            chroms,
+            vec![
+                "chr1",
+                "chr2",
+                "chr10",
+                "chrX",
+                "chrM",
+                "chr1_alt",
+                "chrUn_gl1"
+            ]
+        );
+
+        chroms.sort_by(|a, b| ChromOrder::Lexicographic.compare(a, b));
+        assert_eq!(
+            chroms,
+            vec![
+                "chr1",
+                "chr10",
+                "chr1_alt",
+                "chr2",
+                "chrM",
+                "chrUn_gl1",
+                "chrX"
+            ]
+        );
+
+        chroms.extend(["chr1_KI270706v1_random", "chr2_KN196472v1_fix"]);
+        chroms.sort_by(|a, b| ChromOrder::PrimaryFirst.compare(a, b));
+        assert_eq!(
+            chroms,
+            vec![
+                "chr1",
+                "chr2",
+                "chr10",
+                "chrM",
+                "chrX",
+                "chr1_KI270706v1_random",
+                "chrUn_gl1",
+                "chr1_alt",
+                "chr2_KN196472v1_fix"
+            ]
+        );
+
+        let order = ChromOrder::Natural;
+        let ranks = order.ranks(["chr10", "chr2", "chr02", "chr2"]);
+        assert_eq!((ranks["chr02"], ranks["chr2"], ranks["chr10"]), (0, 1, 2));
+        let mut items = vec![
+            ("chr10", 1),
+            ("chr2", 9),
+            ("chr02", 4),
+            ("chr10", 0),
+            ("chr2", 4),
+        ];
+        order.sort_by_coords(&mut items, |&(chrom, start)| (chrom, start));
+        assert_eq!(
+            items,
+            [
+                ("chr02", 4),
+                ("chr2", 4),
+                ("chr2", 9),
+                ("chr10", 0),
+                ("chr10", 1)
+            ]
+        );
+    }
+}
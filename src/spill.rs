@@ -0,0 +1,283 @@
+use crate::lines::GtfRecord;
+use crate::sort::ChromOrder;
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+/// Parses a memory size: bytes, or a number with a K/M/G/T suffix
+/// (powers of 1024, optional trailing `B`), e.g. `8G`, `512MB`.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let upper = s.trim().to_ascii_uppercase();
+    let digits = upper.strip_suffix('B').unwrap_or(&upper);
+    let (number, shift) = match digits.chars().last() {
+        Some('K') => (&digits[..digits.len() - 1], 10),
+        Some('M') => (&digits[..digits.len() - 1], 20),
+        Some('G') => (&digits[..digits.len() - 1], 30),
+        Some('T') => (&digits[..digits.len() - 1], 40),
+        _ => (digits, 0),
+    };
+    let value = number
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|v| *v > 0.0)
+        .ok_or_else(|| format!("invalid size {:?}, expected e.g. 8G or 512M", s))?;
+    Ok((value * (1u64 << shift) as f64) as u64)
+}
+
+/// Rough heap footprint of a record, used to decide when to spill.
+pub fn approx_size(record: &GtfRecord) -> usize {
+    std::mem::size_of::<GtfRecord>()
+        + record.chrom.len()
+        + record.feature.len()
+        + record.strand.len()
+        + record
+            .attrs
+            .iter()
+            .map(|(k, v)| k.len() + v.len() + 2 * std::mem::size_of::<String>())
+            .sum::<usize>()
+}
+
+/// Column holding the start of GTF lines.
+const GTF_START: usize = 3;
+
+/// Column holding the start of BED lines.
+const BED_START: usize = 1;
+
+/// Sorted runs of rendered feature lines, or of raw BED lines for
+/// `--two-pass --max-mem`, kept on disk and merged back in
+/// chromosome/start order. The run
+/// directory is made in `tmpdir` and removed when the spill is dropped.
+pub struct Spill {
+    dir: PathBuf,
+    runs: Vec<PathBuf>,
+    /// Column of the lines holding their start.
+    start_column: usize,
+}
+
+impl Spill {
+    pub fn new(tmpdir: &Path) -> io::Result<Spill> {
+        // several conversions may spill at once in one process
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = tmpdir.join(format!(
+            "bed2gtf-spill-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, AtomicOrdering::Relaxed)
+        ));
+        fs::create_dir_all(&dir)?;
+        Ok(Spill {
+            dir,
+            runs: Vec::new(),
+            start_column: GTF_START,
+        })
+    }
+
+    /// A spill of raw BED lines, written with [`Spill::write_lines`].
+    pub fn bed(tmpdir: &Path) -> io::Result<Spill> {
+        let mut spill = Spill::new(tmpdir)?;
+        spill.start_column = BED_START;
+        Ok(spill)
+    }
+
+    pub fn runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// A path in the run directory, removed along with the runs.
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    /// Sorts `records` and writes them as a new run, one line each, with
+    /// `render`.
+    pub fn write_run<F>(
+        &mut self,
+        records: &mut [GtfRecord],
+        order: &ChromOrder,
+        mut render: F,
+    ) -> io::Result<()>
+    where
+        F: FnMut(&mut dyn Write, &[GtfRecord]) -> io::Result<()>,
+    {
+        order.sort_by_coords(records, |r| (&r.chrom, r.start));
+        let path = self.dir.join(format!("run{}.gtf", self.runs.len()));
+        let mut writer = BufWriter::new(File::create(&path)?);
+        render(&mut writer, records)?;
+        writer.flush()?;
+        self.runs.push(path);
+        Ok(())
+    }
+
+    /// Sorts `lines` by their chromosome and start and writes them as a
+    /// new run. Lines without a numeric start, left for the parser to
+    /// reject, sort first in their chromosome.
+    pub fn write_lines(&mut self, lines: &mut [String], order: &ChromOrder) -> io::Result<()> {
+        let column = self.start_column;
+        order.sort_by_coords(lines, |line| {
+            let mut fields = line.split('\t');
+            let chrom = fields.next().unwrap_or_default();
+            let start = fields.nth(column - 1).and_then(|s| s.parse().ok());
+            (chrom, start.unwrap_or(0))
+        });
+        let path = self.dir.join(format!("run{}.txt", self.runs.len()));
+        let mut writer = BufWriter::new(File::create(&path)?);
+        for line in lines.iter() {
+            writeln!(writer, "{}", line)?;
+        }
+        writer.flush()?;
+        self.runs.push(path);
+        Ok(())
+    }
+
+    /// k-way merges all runs into `writer`.
+    pub fn merge<W: Write + ?Sized>(&self, order: &ChromOrder, writer: &mut W) -> io::Result<()> {
+        let mut readers = self
+            .runs
+            .iter()
+            .map(|path| File::open(path).map(|f| BufReader::new(f).lines()))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let column = self.start_column;
+        let mut heap = BinaryHeap::new();
+        for (i, reader) in readers.iter_mut().enumerate() {
+            if let Some(line) = reader.next() {
+                heap.push(Reverse(Head::new(line?, column, i, order)?));
+            }
+        }
+        while let Some(Reverse(head)) = heap.pop() {
+            writeln!(writer, "{}", head.line)?;
+            if let Some(line) = readers[head.run].next() {
+                heap.push(Reverse(Head::new(line?, column, head.run, order)?));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Next line of a run, ordered by contig rank and start.
+struct Head<'a> {
+    line: String,
+    start: u32,
+    run: usize,
+    order: &'a ChromOrder,
+}
+
+impl<'a> Head<'a> {
+    fn new(line: String, column: usize, run: usize, order: &'a ChromOrder) -> io::Result<Head<'a>> {
+        let start = match line.split('\t').nth(column).and_then(|s| s.parse().ok()) {
+            Some(start) => start,
+            // as in write_lines; only GTF runs are rendered by us
+            None if column == BED_START => 0,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "corrupt spill run",
+                ))
+            }
+        };
+        Ok(Head {
+            line,
+            start,
+            run,
+            order,
+        })
+    }
+
+    fn chrom(&self) -> &str {
+        self.line.split('\t').next().unwrap_or_default()
+    }
+}
+
+impl Ord for Head<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.order
+            .cmp_coords((self.chrom(), self.start), (other.chrom(), other.start))
+            .then(self.run.cmp(&other.run))
+    }
+}
+
+impl PartialOrd for Head<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Head<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Head<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lines::Attributes;
+
+    #[test]
+    fn merges_runs_in_order() {
+        assert_eq!(parse_size("8G"), Ok(8 << 30));
+        assert_eq!(parse_size("1.5k"), Ok(1536));
+        assert!(parse_size("lots").is_err());
+
+        let record = |chrom: &str, start: u32| GtfRecord {
+            chrom: chrom.to_string(),
+            feature: "exon".to_string(),
+            start,
+            end: start + 10,
+            strand: "+".to_string(),
+            frame: None,
+            attrs: Attributes::new(),
+            source: None,
+        };
+        let render = |w: &mut dyn Write, records: &[GtfRecord]| {
+            for r in records {
+                writeln!(w, "{}\t.\t{}\t{}\t{}", r.chrom, r.feature, r.start, r.end)?;
+            }
+            Ok(())
+        };
+        let order = ChromOrder::Natural;
+        let mut spill = Spill::new(&std::env::temp_dir()).unwrap();
+        spill
+            .write_run(&mut [record("chr10", 5), record("chr2", 7)], &order, render)
+            .unwrap();
+        spill
+            .write_run(&mut [record("chr2", 3), record("chr1", 9)], &order, render)
+            .unwrap();
+
+        let mut out = Vec::new();
+        spill.merge(&order, &mut out).unwrap();
+        let starts = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|l| l.split('\t').nth(3).unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(starts, ["9", "3", "7", "5"]);
+
+        let mut spill = Spill::bed(&std::env::temp_dir()).unwrap();
+        let lines = |lines: &[&str]| lines.iter().map(|l| l.to_string()).collect::<Vec<_>>();
+        spill
+            .write_lines(&mut lines(&["chr2\t50\tB", "chr1\t80\tA"]), &order)
+            .unwrap();
+        spill
+            .write_lines(&mut lines(&["chr1\t10\tC", "chr1\tx\tbad"]), &order)
+            .unwrap();
+        let mut out = Vec::new();
+        spill.merge(&order, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "chr1\tx\tbad\nchr1\t10\tC\nchr1\t80\tA\nchr2\t50\tB\n"
+        );
+    }
+}
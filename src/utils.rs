@@ -1,63 +1,468 @@
-use crate::bed::BedRecord;
+use crate::bed::{BedRecord, ParseError};
+use crate::error::Bed2GtfError;
+use crate::lines::{Attributes, GtfRecord};
+use crate::pipeline::for_each_chunk;
 
+use crate::par::prelude::*;
 use chrono::Datelike;
 use colored::Colorize;
-use flate2::read::GzDecoder;
+use flate2::read::MultiGzDecoder;
 use indoc::indoc;
-use rayon::prelude::*;
 
 use std::collections::HashMap;
 use std::error::Error;
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
 use std::fs::File;
-use std::io::{self, BufReader, Read, Write};
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Write};
+use std::net::TcpStream;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 const SOURCE: &str = "bed2gtf";
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const REPOSITORY: &str = env!("CARGO_PKG_REPOSITORY");
+const CHUNK_BYTES: usize = 8 * 1024 * 1024;
+const REMOTE_RETRIES: u32 = 4;
+const REMOTE_BACKOFF_MS: u64 = 500;
 
-pub fn get_isoforms(file: &String) -> HashMap<String, String> {
+pub type GeneTrack = HashMap<String, (String, u32, u32, String)>;
+
+/// Drops a trailing `.N` version from an identifier (`ENST0001.4` ->
+/// `ENST0001`). Anything else is returned untouched.
+pub fn strip_version(id: &str) -> &str {
+    match id.rsplit_once('.') {
+        Some((base, version))
+            if !base.is_empty()
+                && !version.is_empty()
+                && version.bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            base
+        }
+        _ => id,
+    }
+}
+
+/// Transcript -> gene map of a gene<TAB>transcript isoforms file.
+pub fn get_isoforms(file: &str) -> Result<HashMap<String, String>, Bed2GtfError> {
     let pairs = parallel_hash_rev(file);
-    // let rev_pairs = parallel_hash(&file);
+    if pairs.is_empty() {
+        return Err(Bed2GtfError::Isoform(
+            "isoforms file has no gene/transcript pairs".to_string(),
+        ));
+    }
+    Ok(pairs)
+}
 
-    if pairs.len() == 0 {
-        println!(
-            "{} {}",
-            "Fail:".bright_red().bold(),
-            "BED file could not be converted. Please check your isoforms file."
-        );
-        std::process::exit(1);
+/// True for http://, https:// and ftp:// locations.
+pub fn is_url<P: AsRef<Path>>(f: P) -> bool {
+    let s = f.as_ref().to_string_lossy();
+    s.starts_with("http://") || s.starts_with("https://") || s.starts_with("ftp://")
+}
+
+/// True for s3:// and gs:// object store locations.
+pub fn is_object_url<P: AsRef<Path>>(f: P) -> bool {
+    let s = f.as_ref().to_string_lossy();
+    s.starts_with("s3://") || s.starts_with("gs://")
+}
+
+/// Opens a local file or a remote URL as a byte stream. Remote
+/// connections are retried with exponential backoff; once the transfer
+/// has started the body is streamed as-is.
+pub fn open_input<P: AsRef<Path>>(f: P) -> io::Result<Box<dyn Read + Send>> {
+    if is_object_url(&f) {
+        return open_object_input(&f.as_ref().to_string_lossy());
+    }
+    if !is_url(&f) {
+        return Ok(Box::new(File::open(f)?));
+    }
+
+    let url = f.as_ref().to_string_lossy().to_string();
+    let mut attempt = 0;
+    loop {
+        let result = if url.starts_with("ftp://") {
+            ftp_get(&url)
+        } else {
+            http_get(&url)
+        };
+
+        match result {
+            Ok(stream) => return Ok(stream),
+            Err(e) if is_transient(&e) && attempt + 1 < REMOTE_RETRIES => {
+                let wait = REMOTE_BACKOFF_MS * 2u64.pow(attempt);
+                log::warn!("Fetching {} failed ({}), retrying in {} ms", url, e, wait);
+                std::thread::sleep(Duration::from_millis(wait));
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(feature = "cloud")]
+fn open_object_input(url: &str) -> io::Result<Box<dyn Read + Send>> {
+    crate::cloud::open_object(url)
+}
+
+#[cfg(not(feature = "cloud"))]
+fn open_object_input(url: &str) -> io::Result<Box<dyn Read + Send>> {
+    Err(no_cloud_support(url))
+}
+
+#[cfg(not(feature = "cloud"))]
+fn no_cloud_support(url: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} requires bed2gtf built with the `cloud` feature", url),
+    )
+}
+
+/// `-` stands for standard output.
+pub fn is_stdout<P: AsRef<Path>>(f: P) -> bool {
+    f.as_ref() == Path::new("-")
+}
+
+/// Creates the output file, standard output for `-`, or an object store
+/// upload for s3:// and gs:// locations.
+pub fn create_output<P: AsRef<Path>>(f: P) -> io::Result<Box<dyn Write + Send>> {
+    if is_stdout(&f) {
+        return Ok(Box::new(io::stdout()));
+    }
+    if !is_object_url(&f) {
+        return Ok(Box::new(File::create(f)?));
+    }
+
+    #[cfg(feature = "cloud")]
+    {
+        let url = f.as_ref().to_string_lossy();
+        Ok(Box::new(crate::cloud::ObjectWriter::create(&url)?))
+    }
+    #[cfg(not(feature = "cloud"))]
+    {
+        Err(no_cloud_support(&f.as_ref().to_string_lossy()))
+    }
+}
+
+/// Client errors (missing file, bad request) are not worth retrying.
+fn is_transient(e: &io::Error) -> bool {
+    !matches!(
+        e.kind(),
+        io::ErrorKind::NotFound | io::ErrorKind::InvalidInput
+    )
+}
+
+fn http_get(url: &str) -> io::Result<Box<dyn Read + Send>> {
+    match ureq::get(url).call() {
+        Ok(response) => Ok(Box::new(response.into_reader())),
+        Err(ureq::Error::Status(code, _)) => {
+            let kind = match code {
+                404 | 410 => io::ErrorKind::NotFound,
+                408 | 429 | 500.. => io::ErrorKind::Other,
+                _ => io::ErrorKind::InvalidInput,
+            };
+            Err(io::Error::new(kind, format!("HTTP {} for {}", code, url)))
+        }
+        Err(e) => Err(io::Error::other(e.to_string())),
     }
-    // (pairs, rev_pairs)
-    pairs
+}
+
+/// Minimal anonymous, passive-mode FTP download.
+fn ftp_get(url: &str) -> io::Result<Box<dyn Read + Send>> {
+    let rest = url.trim_start_matches("ftp://");
+    let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+    let addr = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:21", host)
+    };
+
+    let control = TcpStream::connect(&addr)?;
+    control.set_read_timeout(Some(Duration::from_secs(60)))?;
+    let mut writer = control.try_clone()?;
+    let mut reader = BufReader::new(control);
+
+    let expect = |reader: &mut BufReader<TcpStream>, codes: &[&str]| -> io::Result<String> {
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            if line.is_empty() {
+                return Err(io::Error::other("FTP connection closed"));
+            }
+            // skip continuation lines of multi-line replies
+            if line.len() >= 4 && &line[3..4] == "-" {
+                continue;
+            }
+            return if codes.iter().any(|c| line.starts_with(c)) {
+                Ok(line)
+            } else {
+                Err(io::Error::other(format!("FTP error: {}", line.trim_end())))
+            };
+        }
+    };
+
+    expect(&mut reader, &["220"])?;
+    writer.write_all(b"USER anonymous\r\n")?;
+    if expect(&mut reader, &["230", "331"])?.starts_with("331") {
+        writer.write_all(b"PASS anonymous@\r\n")?;
+        expect(&mut reader, &["230"])?;
+    }
+    writer.write_all(b"TYPE I\r\n")?;
+    expect(&mut reader, &["200"])?;
+    writer.write_all(b"PASV\r\n")?;
+    let pasv = expect(&mut reader, &["227"])?;
+
+    let nums = pasv
+        .split(['(', ')'])
+        .nth(1)
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|n| n.trim().parse::<u16>().ok())
+        .collect::<Vec<_>>();
+    if nums.len() != 6 {
+        return Err(io::Error::other(format!(
+            "Bad PASV reply: {}",
+            pasv.trim_end()
+        )));
+    }
+    let data_addr = format!(
+        "{}.{}.{}.{}:{}",
+        nums[0],
+        nums[1],
+        nums[2],
+        nums[3],
+        nums[4] * 256 + nums[5]
+    );
+
+    let data = TcpStream::connect(data_addr)?;
+    writer.write_all(format!("RETR /{}\r\n", path).as_bytes())?;
+    expect(&mut reader, &["150", "125"])?;
+
+    Ok(Box::new(data))
 }
 
 pub fn reader(file: &PathBuf) -> io::Result<String> {
-    let mut file = File::open(file)?;
+    let mut file = open_input(file)?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
+    strip_bom(&mut contents);
     Ok(contents)
 }
 
+/// Drops the UTF-8 byte order mark Windows editors and Excel put at the
+/// start of text files. CRLF endings are left to `lines()`, which
+/// already removes the `\r`.
+pub fn strip_bom(contents: &mut String) {
+    if contents.starts_with('\u{feff}') {
+        contents.drain(..'\u{feff}'.len_utf8());
+    }
+}
+
+/// Reads a UCSC chrom.sizes file (name<TAB>length).
+pub fn read_chrom_sizes<P: AsRef<Path> + Debug>(
+    f: P,
+) -> Result<HashMap<String, u32>, Box<dyn Error>> {
+    let contents = raw(f)?;
+    let mut sizes = HashMap::new();
+    for line in contents.lines().filter(|l| !l.trim().is_empty()) {
+        let mut fields = line.split_whitespace();
+        match (fields.next(), fields.next().map(|s| s.parse::<u32>())) {
+            (Some(chrom), Some(Ok(size))) => {
+                sizes.insert(chrom.to_string(), size);
+            }
+            _ => return Err(format!("Invalid chrom.sizes line: {}", line).into()),
+        }
+    }
+    Ok(sizes)
+}
+
 pub fn raw<P: AsRef<Path> + Debug>(f: P) -> Result<String, Box<dyn Error>> {
-    let mut file = File::open(f)?;
+    let mut file = open_input(f)?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
+    strip_bom(&mut contents);
     Ok(contents)
 }
 
+/// Decodes every member of a gzip stream, so `cat a.gz b.gz` and
+/// bgzip output are read in full.
 pub fn with_gz<P: AsRef<Path> + Debug>(f: P) -> Result<String, Box<dyn Error>> {
-    let file = File::open(f)?;
-    let mut decoder = GzDecoder::new(BufReader::new(file));
+    let file = open_input(f)?;
+    let mut decoder = MultiGzDecoder::new(BufReader::new(file));
 
     let mut contents = String::new();
     decoder.read_to_string(&mut contents)?;
+    strip_bom(&mut contents);
 
     Ok(contents)
 }
 
-pub fn parallel_hash<'a>(s: &'a str) -> HashMap<String, String> {
+/// A BED line that could not be parsed, set aside in lenient mode.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rejected {
+    /// 1-based line number in the input.
+    pub line_number: usize,
+    pub line: String,
+    pub error: ParseError,
+}
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line_number, self.error)
+    }
+}
+
+/// Parses the lines of `s`, the first of which is line `first` of its
+/// file, setting aside those that do not parse. Records keep their
+/// input order. Each parallel task takes at least `chunk_size` lines.
+pub fn parse_lines(
+    s: &str,
+    first: usize,
+    chunk_size: Option<usize>,
+) -> (Vec<BedRecord>, Vec<Rejected>) {
+    let lines = s
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (first + i, line))
+        .collect();
+    parse_each(lines, chunk_size, BedRecord::parse_bytes)
+}
+
+/// [`parse_lines`] for `--stitch-by-name` fragments, BED12 or BED6+
+/// lines (see [`BedRecord::parse_blocks`]); blank lines are skipped.
+pub fn parse_block_lines(
+    s: &str,
+    first: usize,
+    chunk_size: Option<usize>,
+) -> (Vec<BedRecord>, Vec<Rejected>) {
+    let lines = s
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (first + i, line))
+        .filter(|(_, line)| !line.trim().is_empty())
+        .collect();
+    parse_each(lines, chunk_size, BedRecord::parse_blocks_bytes)
+}
+
+/// [`parse_lines`] for lines that carry their line number as a last
+/// tab-separated field, as spilled by `--two-pass --max-mem` once sorted.
+pub fn parse_numbered_lines(s: &str, chunk_size: Option<usize>) -> (Vec<BedRecord>, Vec<Rejected>) {
+    let lines = s
+        .lines()
+        .map(|line| match line.rsplit_once('\t') {
+            Some((line, number)) => (number.parse().unwrap_or(0), line),
+            None => (0, line),
+        })
+        .collect();
+    parse_each(lines, chunk_size, BedRecord::parse_bytes)
+}
+
+fn parse_each(
+    lines: Vec<(usize, &str)>,
+    chunk_size: Option<usize>,
+    parse: fn(&[u8]) -> Result<BedRecord, ParseError>,
+) -> (Vec<BedRecord>, Vec<Rejected>) {
+    let parsed = lines
+        .into_par_iter()
+        .with_min_len(chunk_size.unwrap_or(1))
+        .map(|(line_number, line)| {
+            parse(line.as_bytes()).map_err(|error| Rejected {
+                line_number,
+                line: line.to_string(),
+                error,
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut records = Vec::with_capacity(parsed.len());
+    let mut rejected = Vec::new();
+    for result in parsed {
+        match result {
+            Ok(record) => records.push(record),
+            Err(line) => rejected.push(line),
+        }
+    }
+    (records, rejected)
+}
+
+/// Parses a BED stream in chunks of whole lines, so only a few chunks of
+/// text are held in memory at a time. The next chunk is read (and
+/// decompressed) on a separate thread while the current one is parsed.
+/// Malformed lines stop the parse unless `lenient`, in which case they
+/// are returned apart. See [`parse_lines`] for `chunk_size`.
+pub fn parallel_parse_reader<R: BufRead + Send>(
+    reader: R,
+    lenient: bool,
+    chunk_size: Option<usize>,
+) -> Result<(Vec<BedRecord>, Vec<Rejected>), Box<dyn Error>> {
+    parse_reader_with(reader, lenient, chunk_size, parse_lines)
+}
+
+/// [`parallel_parse_reader`] for `--stitch-by-name` fragments (see
+/// [`parse_block_lines`]).
+pub fn parse_blocks_reader<R: BufRead + Send>(
+    reader: R,
+    lenient: bool,
+    chunk_size: Option<usize>,
+) -> Result<(Vec<BedRecord>, Vec<Rejected>), Box<dyn Error>> {
+    parse_reader_with(reader, lenient, chunk_size, parse_block_lines)
+}
+
+/// Parser of a chunk of lines, as [`parse_lines`].
+type ChunkParser = fn(&str, usize, Option<usize>) -> (Vec<BedRecord>, Vec<Rejected>);
+
+fn parse_reader_with<R: BufRead + Send>(
+    reader: R,
+    lenient: bool,
+    chunk_size: Option<usize>,
+    parse: ChunkParser,
+) -> Result<(Vec<BedRecord>, Vec<Rejected>), Box<dyn Error>> {
+    let mut records = Vec::new();
+    let mut rejected = Vec::new();
+    let mut line = 1;
+    for_each_chunk(reader, CHUNK_BYTES, |chunk| {
+        let (parsed, bad) = parse(chunk, line, chunk_size);
+        if let (false, Some(first)) = (lenient, bad.first()) {
+            return Err(first.to_string().into());
+        }
+        line += chunk.lines().count();
+        records.extend(parsed);
+        rejected.extend(bad);
+        Ok(())
+    })?;
+    Ok((records, rejected))
+}
+
+/// Opens a BED input, decompressing it if it starts with the gzip magic
+/// (a pipe such as `<(cat a.bed.gz)` has no extension to tell).
+pub fn open_bed<P: AsRef<Path>>(f: P) -> io::Result<Box<dyn BufRead + Send>> {
+    let mut reader = BufReader::new(open_input(f)?);
+    if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        let decoder = MultiGzDecoder::new(reader);
+        return Ok(Box::new(BufReader::new(decoder)));
+    }
+    Ok(Box::new(reader))
+}
+
+/// Parses a plain (or gzipped, see [`open_bed`]) BED file chunk by
+/// chunk; see [`parallel_parse_reader`].
+pub fn parse_plain<P: AsRef<Path> + Debug>(
+    f: P,
+    lenient: bool,
+    chunk_size: Option<usize>,
+) -> Result<(Vec<BedRecord>, Vec<Rejected>), Box<dyn Error>> {
+    parallel_parse_reader(open_bed(f)?, lenient, chunk_size)
+}
+
+/// Stream-decompresses a .bed.gz file and parses it chunk by chunk.
+pub fn parse_gz<P: AsRef<Path> + Debug>(
+    f: P,
+    lenient: bool,
+    chunk_size: Option<usize>,
+) -> Result<(Vec<BedRecord>, Vec<Rejected>), Box<dyn Error>> {
+    let file = open_input(f)?;
+    let decoder = MultiGzDecoder::new(BufReader::new(file));
+    parallel_parse_reader(BufReader::new(decoder), lenient, chunk_size)
+}
+
+pub fn parallel_hash(s: &str) -> HashMap<String, String> {
     s.par_lines()
         .filter_map(|line| {
             let mut words = line.split_whitespace();
@@ -72,7 +477,7 @@ pub fn parallel_hash<'a>(s: &'a str) -> HashMap<String, String> {
         .collect()
 }
 
-pub fn parallel_hash_rev<'a>(s: &'a str) -> HashMap<String, String> {
+pub fn parallel_hash_rev(s: &str) -> HashMap<String, String> {
     s.par_lines()
         .filter_map(|line| {
             let mut words = line.split_whitespace();
@@ -86,7 +491,7 @@ pub fn parallel_hash_rev<'a>(s: &'a str) -> HashMap<String, String> {
         .collect()
 }
 
-pub fn parallel_parse<'a>(s: &'a str) -> Result<Vec<BedRecord>, String> {
+pub fn parallel_parse(s: &str) -> Result<Vec<BedRecord>, String> {
     let records = s
         .par_lines()
         // .map(|line| BedRecord::parse(line))
@@ -99,16 +504,14 @@ pub fn parallel_parse<'a>(s: &'a str) -> Result<Vec<BedRecord>, String> {
         })
         .collect::<Result<Vec<BedRecord>, String>>();
 
-    Ok(records?)
+    records
 }
 
-pub fn custom_par_parse(
-    records: &Vec<BedRecord>,
-) -> Result<HashMap<String, (String, u32, u32, String)>, &'static str> {
+pub fn custom_par_parse(records: &[BedRecord]) -> Result<GeneTrack, &'static str> {
     let gene_coordinates = records
         .into_par_iter()
         .fold(
-            || HashMap::new(),
+            HashMap::new,
             |mut acc: HashMap<String, (String, u32, u32, String)>, record| {
                 acc.entry(record.name.clone()).or_insert((
                     record.chrom.clone(),
@@ -120,7 +523,7 @@ pub fn custom_par_parse(
             },
         )
         .reduce(
-            || HashMap::new(),
+            HashMap::new,
             |mut a: HashMap<String, (String, u32, u32, String)>, b| {
                 for (key, (chrom, start, end, strand)) in b {
                     a.entry(key).or_insert((chrom, start, end, strand));
@@ -133,12 +536,12 @@ pub fn custom_par_parse(
 
 pub fn combine_maps_par(
     isoforms: &HashMap<String, String>,
-    gene_track: &HashMap<String, (String, u32, u32, String)>,
-) -> Vec<(String, String, u32, u32, String, String, String)> {
+    gene_track: &GeneTrack,
+) -> Vec<GtfRecord> {
     let coords = isoforms
         .par_iter()
         .fold(
-            || HashMap::new(),
+            HashMap::new,
             |mut acc: HashMap<String, (String, u32, u32, String)>, (transcript, gene)| {
                 if let Some(&(ref chrom, start, end, ref strand)) = gene_track.get(transcript) {
                     let entry = acc.entry(gene.clone()).or_insert((
@@ -153,30 +556,30 @@ pub fn combine_maps_par(
                 acc
             },
         )
-        .reduce(
-            || HashMap::new(),
-            |mut a, b| {
-                for (gene, (chrom, start, end, strand)) in b {
-                    let entry = a.entry(gene).or_insert((chrom, start, end, strand));
-                    entry.1 = entry.1.min(start); // Update min start
-                    entry.2 = entry.2.max(end); // Update max end
-                }
-                a
-            },
-        );
+        .reduce(HashMap::new, |mut a, b| {
+            for (gene, (chrom, start, end, strand)) in b {
+                let entry = a.entry(gene).or_insert((chrom, start, end, strand));
+                entry.1 = entry.1.min(start); // Update min start
+                entry.2 = entry.2.max(end); // Update max end
+            }
+            a
+        });
 
     let lines = coords
         .par_iter()
         .map(|(gene, (chrom, start, end, strand))| {
-            (
-                chrom.to_string(),
-                "gene".to_string(),
-                start + 1,
-                *end,
-                strand.to_string(),
-                ".".to_string(),
-                format!("gene_id \"{}\";", gene),
-            )
+            let mut attrs = Attributes::new();
+            attrs.push("gene_id", gene.as_str());
+            GtfRecord {
+                chrom: chrom.to_string(),
+                feature: "gene".to_string(),
+                start: start + 1,
+                end: *end,
+                strand: strand.to_string(),
+                frame: None,
+                attrs,
+                source: None,
+            }
         })
         .collect();
     lines
@@ -196,8 +599,37 @@ pub fn max_mem_usage_mb() -> f64 {
     }
 }
 
+/// FIFOs and process substitutions (`<(...)`) are read like files but
+/// have no size or extension to check.
+#[cfg(unix)]
+pub fn is_pipe<P: AsRef<Path>>(path: P) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    std::fs::metadata(path).is_ok_and(|m| m.file_type().is_fifo() || m.file_type().is_char_device())
+}
+
+#[cfg(not(unix))]
+pub fn is_pipe<P: AsRef<Path>>(_path: P) -> bool {
+    false
+}
+
+/// Current resident set size, where the platform exposes it cheaply.
+pub fn current_mem_usage_mb() -> Option<f64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages = statm.split_whitespace().nth(1)?.parse::<f64>().ok()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as f64;
+    Some(pages * page_size / 1024.0 / 1024.0)
+}
+
+/// Whether to color terminal text: not when `NO_COLOR` is set (to any
+/// non-empty value) or stderr, where logs go, is not a terminal.
+pub fn use_color() -> bool {
+    std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && std::io::stderr().is_terminal()
+}
+
+/// Prints the startup banner to stderr, so stdout only ever carries
+/// data.
 pub fn msg() {
-    println!(
+    eprintln!(
         "{}\n{}\n{}\n",
         "\n##### BED2GTF #####".bright_cyan().bold(),
         indoc!(
@@ -218,9 +650,144 @@ pub fn get_date() -> String {
     format!("{}-{}-{}", year, month, day)
 }
 
-pub fn comments(file: &mut Box<dyn Write>) {
-    let _ = file.write_all(format!("#provider: {}\n", SOURCE).as_bytes());
-    let _ = file.write_all(format!("#version: {}\n", VERSION).as_bytes());
-    let _ = file.write_all(format!("#contact: {}\n", REPOSITORY).as_bytes());
-    let _ = file.write_all(format!("#date: {}\n", get_date()).as_bytes());
+/// Writes the GTF header: user-supplied `--header-line`s first (e.g.
+/// GENCODE-style `#!genome-build`), then bed2gtf's own provenance.
+pub fn comments<W: Write + ?Sized>(file: &mut W, extra: &[String]) {
+    for line in header_lines(extra) {
+        let _ = writeln!(file, "{}", line);
+    }
+}
+
+/// The header lines [`comments`] writes today.
+pub fn header_lines(extra: &[String]) -> Vec<String> {
+    let mut lines = extra.to_vec();
+    lines.push(format!("#provider: {}", SOURCE));
+    lines.push(format!("#version: {}", VERSION));
+    lines.push(format!("#contact: {}", REPOSITORY));
+    lines.push(format!("#date: {}", get_date()));
+    lines
+}
+
+/// A header line must be a single `#` comment.
+pub fn parse_header_line(s: &str) -> Result<String, String> {
+    let line = s.trim_end_matches(['\r', '\n']);
+    if !line.starts_with('#') {
+        return Err(format!("header lines must start with '#', got {:?}", s));
+    }
+    if line.contains(['\r', '\n']) {
+        return Err(format!("header lines cannot span several lines: {:?}", s));
+    }
+    Ok(line.to_string())
+}
+
+/// A value written into a header line must be one non-empty word.
+pub fn parse_header_value(s: &str) -> Result<String, String> {
+    if s.is_empty() || s.contains(char::is_whitespace) {
+        return Err(format!("expected a single word, got {:?}", s));
+    }
+    Ok(s.to_string())
+}
+
+/// Reads `--header-file`: every non-empty line, each a `#` comment.
+pub fn read_header_file(path: &Path) -> Result<Vec<String>, String> {
+    raw(path)
+        .map_err(|e| format!("Error reading header file {}: {}", path.display(), e))?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(parse_header_line)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    #[test]
+    fn reads_all_gzip_members() {
+        let line = |name: &str| {
+            format!(
+                "chr1\t1000\t5000\t{}\t0\t+\t1200\t4500\t0\t2\t500,600,\t0,3400,\n",
+                name
+            )
+        };
+        let mut data = Vec::new();
+        for name in ["A", "B"] {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(line(name).as_bytes()).unwrap();
+            data.extend(encoder.finish().unwrap());
+        }
+        let path =
+            std::env::temp_dir().join(format!("bed2gtf-members-{}.bed.gz", std::process::id()));
+        std::fs::write(&path, data).unwrap();
+
+        assert_eq!(with_gz(&path).unwrap(), line("A") + &line("B"));
+        let names = parse_gz(&path, false, None)
+            .unwrap()
+            .0
+            .into_iter()
+            .map(|r| r.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["A", "B"]);
+        // no extension to go by, as for `<(cat a.bed.gz)`
+        assert_eq!(parse_plain(&path, false, None).unwrap().0.len(), 2);
+        // work units larger than the input keep the records and their order
+        let chunked = parse_gz(&path, false, Some(1000)).unwrap().0;
+        assert_eq!(
+            chunked.iter().map(|r| &r.name).collect::<Vec<_>>(),
+            ["A", "B"]
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reads_windows_text_files() {
+        let dir = std::env::temp_dir();
+        let bed = dir.join(format!("bed2gtf-crlf-{}.bed", std::process::id()));
+        let isoforms = dir.join(format!("bed2gtf-crlf-{}.txt", std::process::id()));
+        std::fs::write(
+            &bed,
+            "\u{feff}chr1\t1000\t5000\tA\t0\t+\t1200\t4500\t0\t2\t500,600,\t0,3400,\r\n\
+             chr1\t1000\t5000\tB\t0\t+\t1200\t4500\t0\t2\t500,600,\t0,3400\r\n",
+        )
+        .unwrap();
+        std::fs::write(&isoforms, "\u{feff}G1\tA\r\nG1\tB\r\n").unwrap();
+
+        let records = parallel_parse(&raw(&bed).unwrap()).unwrap();
+        assert_eq!(records[0].chrom, "chr1");
+        assert_eq!(records[1].exon_start, vec![1000, 4400]);
+
+        let contents = reader(&isoforms).unwrap();
+        let names = records.iter().map(|r| r.name.as_str()).collect();
+        let (map, _) = crate::isoforms::read_isoforms(
+            &contents,
+            &names,
+            crate::isoforms::IsoformsOrder::GeneTranscript,
+            false,
+            crate::isoforms::OnConflict::Error,
+        )
+        .unwrap();
+        assert_eq!(map["A"], "G1");
+        assert_eq!(map["B"], "G1");
+        assert_eq!(get_isoforms(&contents).unwrap()["B"], "G1");
+        assert!(matches!(get_isoforms("\n"), Err(Bed2GtfError::Isoform(_))));
+
+        std::fs::remove_file(&bed).unwrap();
+        std::fs::remove_file(&isoforms).unwrap();
+    }
+
+    #[test]
+    fn header_lines_come_first() {
+        let extra = vec![parse_header_line("#!genome-build GRCh38.p14").unwrap()];
+        assert!(parse_header_line("genome-build GRCh38").is_err());
+        assert!(parse_header_line("#a\n#b").is_err());
+        assert!(parse_header_value("GRCh38 p14").is_err());
+
+        let mut out = Vec::new();
+        comments(&mut out, &extra);
+        let header = String::from_utf8(out).unwrap();
+        assert!(header.starts_with("#!genome-build GRCh38.p14\n#provider: bed2gtf\n"));
+    }
 }
@@ -0,0 +1,250 @@
+use crate::convert::{convert, ConversionJob};
+use crate::deps::{cache_dir, fetch_tool, find_tool, FetchOptions};
+use crate::exec::exec;
+use crate::utils::{create_output, get_isoforms, reader};
+
+use natord::compare;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Features written by both bed2gtf and genePredToGtf.
+const COMPARED: [&str; 4] = ["exon", "CDS", "start_codon", "stop_codon"];
+
+/// Which converter produced a line missing from the other output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Side {
+    Bed2gtf,
+    Ucsc,
+}
+
+/// A normalized GTF line: coordinates, frame and ids, without source,
+/// score or any other attribute.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NormLine {
+    pub chrom: String,
+    pub feature: String,
+    pub start: u32,
+    pub end: u32,
+    pub strand: String,
+    pub frame: String,
+    pub gene_id: String,
+    pub transcript_id: String,
+}
+
+/// Outcome of [`verify_against_ucsc`].
+#[derive(Debug, Default)]
+pub struct Verification {
+    /// Lines found in both outputs.
+    pub shared: usize,
+    /// Lines found in only one output, sorted by position.
+    pub differences: Vec<(Side, NormLine)>,
+}
+
+impl Verification {
+    /// Transcripts with at least one differing line.
+    pub fn transcripts(&self) -> usize {
+        let mut ids = self
+            .differences
+            .iter()
+            .map(|(_, l)| l.transcript_id.as_str())
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids.dedup();
+        ids.len()
+    }
+}
+
+/// Converts `bed` with bed2gtf and with UCSC bedToGenePred +
+/// genePredToGtf and diffs both GTFs after normalization. UCSC tools
+/// are taken from `$PATH` or the cache, and downloaded when missing, as
+/// `fetch` says (a pinned release is never taken from `$PATH`); each is
+/// killed after `timeout`. Both GTFs are written under `tmpdir`.
+/// UCSC output has no gene model, so its gene_id is mapped through the
+/// same isoforms file.
+pub fn verify_against_ucsc(
+    bed: &Path,
+    isoforms: Option<&Path>,
+    cache: Option<&Path>,
+    fetch: &FetchOptions,
+    tmpdir: &Path,
+    timeout: Option<Duration>,
+) -> Result<Verification, Box<dyn Error>> {
+    let cache = cache_dir(cache);
+    let tmp = tmpdir.join(format!("bed2gtf-verify-{}", std::process::id()));
+    fs::create_dir_all(&tmp)?;
+    let result = run_both(bed, isoforms, &cache, fetch, &tmp, timeout);
+    let _ = fs::remove_dir_all(&tmp);
+    let (ours, theirs) = result?;
+
+    let mut theirs = theirs;
+    if let Some(isoforms) = isoforms {
+        let genes = get_isoforms(&reader(&isoforms.to_path_buf())?)?;
+        for line in &mut theirs {
+            if let Some(gene) = genes.get(&line.transcript_id) {
+                line.gene_id = gene.clone();
+            }
+        }
+    }
+    Ok(diff(ours, theirs))
+}
+
+fn run_both(
+    bed: &Path,
+    isoforms: Option<&Path>,
+    cache: &Path,
+    fetch: &FetchOptions,
+    tmp: &Path,
+    timeout: Option<Duration>,
+) -> Result<(Vec<NormLine>, Vec<NormLine>), Box<dyn Error>> {
+    let ours = tmp.join("bed2gtf.gtf");
+    let job = ConversionJob::simple(bed, isoforms, &ours)?;
+    convert(job.args())?;
+
+    let tool = |name: &str| -> Result<PathBuf, Box<dyn Error>> {
+        let found = match fetch.release {
+            Some(_) => None,
+            None => find_tool(name, cache),
+        };
+        match found {
+            Some(path) => Ok(path),
+            None => {
+                log::info!("{} not found, fetching it", name);
+                fetch_tool(name, cache, None, fetch)
+            }
+        }
+    };
+    let gp = tmp.join("ucsc.gp");
+    let theirs = tmp.join("ucsc.gtf");
+    exec(&tool("bedToGenePred")?, &[bed, &gp], timeout)?;
+    exec(
+        &tool("genePredToGtf")?,
+        &[Path::new("file"), &gp, &theirs],
+        timeout,
+    )?;
+
+    Ok((
+        normalize(&fs::read_to_string(&ours)?),
+        normalize(&fs::read_to_string(&theirs)?),
+    ))
+}
+
+/// Keeps the [`COMPARED`] features of a GTF, reduced to [`NormLine`]s.
+pub fn normalize(gtf: &str) -> Vec<NormLine> {
+    gtf.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields = line.split('\t').collect::<Vec<_>>();
+            if fields.len() < 9 || !COMPARED.contains(&fields[2]) {
+                return None;
+            }
+            let attr = |key: &str| {
+                fields[8]
+                    .split(';')
+                    .filter_map(|pair| pair.trim().split_once(' '))
+                    .find(|(k, _)| *k == key)
+                    .map(|(_, v)| v.trim().trim_matches('"').to_string())
+                    .unwrap_or_default()
+            };
+            Some(NormLine {
+                chrom: fields[0].to_string(),
+                feature: fields[2].to_string(),
+                start: fields[3].parse().ok()?,
+                end: fields[4].parse().ok()?,
+                strand: fields[6].to_string(),
+                frame: fields[7].to_string(),
+                gene_id: attr("gene_id"),
+                transcript_id: attr("transcript_id"),
+            })
+        })
+        .collect()
+}
+
+/// Multiset difference of two normalized GTFs.
+pub fn diff(ours: Vec<NormLine>, theirs: Vec<NormLine>) -> Verification {
+    let mut counts: HashMap<NormLine, i64> = HashMap::new();
+    for line in ours {
+        *counts.entry(line).or_default() += 1;
+    }
+    let mut shared = 0;
+    let mut differences = Vec::new();
+    for line in theirs {
+        match counts.get_mut(&line) {
+            Some(n) if *n > 0 => {
+                *n -= 1;
+                shared += 1;
+            }
+            _ => differences.push((Side::Ucsc, line)),
+        }
+    }
+    for (line, n) in counts {
+        for _ in 0..n {
+            differences.push((Side::Bed2gtf, line.clone()));
+        }
+    }
+
+    differences.sort_by(|(sa, a), (sb, b)| {
+        compare(&a.chrom, &b.chrom)
+            .then(a.start.cmp(&b.start))
+            .then(a.transcript_id.cmp(&b.transcript_id))
+            .then(a.feature.cmp(&b.feature))
+            .then(sa.cmp(sb))
+    });
+    Verification {
+        shared,
+        differences,
+    }
+}
+
+/// Writes the differences as a TSV, `<` for bed2gtf-only lines and `>`
+/// for UCSC-only lines.
+pub fn write_differences(report: &Verification, path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(create_output(path)?);
+    writeln!(
+        writer,
+        "side\tchrom\tfeature\tstart\tend\tstrand\tframe\tgene_id\ttranscript_id"
+    )?;
+    for (side, l) in &report.differences {
+        let side = match side {
+            Side::Bed2gtf => "<",
+            Side::Ucsc => ">",
+        };
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            side, l.chrom, l.feature, l.start, l.end, l.strand, l.frame, l.gene_id, l.transcript_id
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_ignores_source_and_extra_attributes() {
+        let ours = normalize(
+            "#provider: bed2gtf\n\
+             chr1\tbed2gtf\ttranscript\t1\t100\t.\t+\t.\tgene_id \"G\"; transcript_id \"A\";\n\
+             chr1\tbed2gtf\texon\t1\t100\t.\t+\t.\tgene_id \"G\"; transcript_id \"A\"; exon_number \"1\";\n\
+             chr1\tbed2gtf\tCDS\t11\t100\t.\t+\t0\tgene_id \"G\"; transcript_id \"A\";\n",
+        );
+        let theirs = normalize(
+            "chr1\tfile\texon\t1\t100\t.\t+\t.\tgene_id \"G\"; transcript_id \"A\";\n\
+             chr1\tfile\tCDS\t11\t100\t.\t+\t1\tgene_id \"G\"; transcript_id \"A\";\n",
+        );
+
+        let report = diff(ours, theirs);
+        assert_eq!(report.shared, 1);
+        assert_eq!(report.differences.len(), 2);
+        assert_eq!(report.differences[0].0, Side::Bed2gtf);
+        assert_eq!(report.differences[1].1.frame, "1");
+        assert_eq!(report.transcripts(), 1);
+    }
+}
@@ -0,0 +1,72 @@
+use crate::cli::Cli;
+use crate::error::Bed2GtfError;
+use crate::utils::{header_lines, read_header_file};
+
+use serde::Serialize;
+
+/// Optional cargo features this binary was built with.
+const FEATURES: &[(&str, bool)] = &[
+    ("cloud", cfg!(feature = "cloud")),
+    ("parallel", cfg!(feature = "parallel")),
+];
+
+/// Build provenance printed by `--version-json`, so workflows can record
+/// it without parsing the banner.
+#[derive(Debug, Serialize)]
+pub struct VersionInfo {
+    pub name: &'static str,
+    pub version: &'static str,
+    /// Short commit of the build, or "unknown" outside a git checkout.
+    pub git_commit: &'static str,
+    pub features: Vec<&'static str>,
+    /// Comment lines stamped at the top of outputs, with today's date.
+    pub header: Vec<String>,
+}
+
+impl VersionInfo {
+    pub fn new(extra_header: &[String]) -> VersionInfo {
+        VersionInfo {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            git_commit: env!("BED2GTF_GIT_COMMIT"),
+            features: FEATURES
+                .iter()
+                .filter(|(_, on)| *on)
+                .map(|(name, _)| *name)
+                .collect(),
+            header: header_lines(extra_header),
+        }
+    }
+}
+
+/// `--version-json`: prints [`VersionInfo`] to stdout, with the
+/// `--header-line`/`--header-file` lines of this command line.
+pub fn print_version_json(args: &Cli) -> Result<(), Bed2GtfError> {
+    let mut extra = args.provenance_header();
+    extra.extend(args.header_line.iter().cloned());
+    if let Some(path) = &args.header_file {
+        extra.extend(read_header_file(path)?);
+    }
+    let json = serde_json::to_string_pretty(&VersionInfo::new(&extra))
+        .map_err(|e| Bed2GtfError::Validation(e.to_string()))?;
+    println!("{}", json);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_json_matches_header() {
+        let extra = vec!["#!genome-build GRCh38".to_string()];
+        let json = serde_json::to_value(VersionInfo::new(&extra)).unwrap();
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(json["header"][0], "#!genome-build GRCh38");
+        assert_eq!(
+            json["header"][2],
+            format!("#version: {}", env!("CARGO_PKG_VERSION"))
+        );
+        assert!(!json["git_commit"].as_str().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,209 @@
+use crate::bed::BedRecord;
+use crate::utils::create_output;
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// One non-fatal issue found during a conversion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    /// 1-based BED line of the transcript, when the issue has one.
+    pub line: Option<usize>,
+    /// Transcript (or gene) the issue is about.
+    pub id: String,
+    /// Stable, machine-readable issue code, e.g. `invalid_stop_codon`.
+    pub code: &'static str,
+    /// What bed2gtf did about it.
+    pub action: String,
+}
+
+/// Thread-safe collector of [`Issue`]s, written as a TSV at the end of a
+/// run so batch pipelines keep a diffable record of what was fixed up.
+#[derive(Debug, Default)]
+pub struct Warnings {
+    lines: HashMap<String, usize>,
+    /// Chromosome of every BED line, as an index into `chroms`.
+    line_chroms: Vec<u32>,
+    chroms: Vec<String>,
+    issues: Mutex<Vec<Issue>>,
+    /// Skipped input lines with the reason, for `--rejects`.
+    rejects: Mutex<Vec<String>>,
+}
+
+impl Warnings {
+    /// Remembers the input line of every transcript in `records`, which
+    /// must still be in BED order.
+    pub fn new(records: &[BedRecord]) -> Warnings {
+        let mut lines = HashMap::with_capacity(records.len());
+        let mut line_chroms = Vec::with_capacity(records.len());
+        let mut chroms: HashMap<&str, u32> = HashMap::new();
+        for (i, record) in records.iter().enumerate() {
+            lines.entry(record.name.clone()).or_insert(i + 1);
+            let next = chroms.len() as u32;
+            line_chroms.push(*chroms.entry(&record.chrom).or_insert(next));
+        }
+        let mut names = vec![String::new(); chroms.len()];
+        for (chrom, i) in chroms {
+            names[i as usize] = chrom.to_string();
+        }
+        Warnings {
+            lines,
+            line_chroms,
+            chroms: names,
+            issues: Mutex::new(Vec::new()),
+            rejects: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn push<S: Into<String>>(&self, id: &str, code: &'static str, action: S) {
+        let issue = Issue {
+            line: self.lines.get(id).copied(),
+            id: id.to_string(),
+            code,
+            action: action.into(),
+        };
+        self.issues.lock().unwrap().push(issue);
+    }
+
+    /// Sets aside an input `line` the run skipped, with the `reason`.
+    pub fn reject<S: fmt::Display>(&self, line: &str, reason: S) {
+        let reason = reason.to_string().replace(['\t', '\n'], " ");
+        self.rejects
+            .lock()
+            .unwrap()
+            .push(format!("{}\t{}", line, reason));
+    }
+
+    /// [`Warnings::reject`] for a record skipped after parsing, written
+    /// back as BED12 (with any extra columns) under its original name.
+    pub fn reject_record<S: fmt::Display>(&self, record: &BedRecord, reason: S) {
+        let record = BedRecord {
+            name: record.original_name().to_string(),
+            ..record.clone()
+        };
+        self.reject(&record.to_string(), reason)
+    }
+
+    pub fn rejects_len(&self) -> usize {
+        self.rejects.lock().unwrap().len()
+    }
+
+    /// Writes the skipped lines, each followed by a tab and the reason.
+    pub fn write_rejects<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(create_output(path)?);
+        for line in self.rejects.lock().unwrap().iter() {
+            writeln!(writer, "{}", line)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.issues.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of issues per code.
+    pub fn counts(&self) -> BTreeMap<&'static str, usize> {
+        let mut counts = BTreeMap::new();
+        for issue in self.issues.lock().unwrap().iter() {
+            *counts.entry(issue.code).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Number of issues per chromosome, for issues about a BED line.
+    pub fn counts_by_chrom(&self) -> HashMap<&str, usize> {
+        let mut counts = HashMap::new();
+        for issue in self.issues.lock().unwrap().iter() {
+            let chrom = issue
+                .line
+                .and_then(|line| self.line_chroms.get(line - 1))
+                .map(|&i| self.chroms[i as usize].as_str());
+            if let Some(chrom) = chrom {
+                *counts.entry(chrom).or_insert(0) += 1;
+            }
+        }
+        counts
+    }
+
+    /// Issues sorted by line, then id and code; issues without a line go
+    /// last.
+    pub fn issues(&self) -> Vec<Issue> {
+        let mut issues = self.issues.lock().unwrap().clone();
+        issues.sort_by(|a, b| {
+            (a.line.is_none(), a.line, &a.id, a.code).cmp(&(
+                b.line.is_none(),
+                b.line,
+                &b.id,
+                b.code,
+            ))
+        });
+        issues
+    }
+
+    /// Writes `line, id, code, action` rows; `.` marks a missing line.
+    pub fn write_tsv<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(create_output(path)?);
+        writeln!(writer, "line\tid\tcode\taction")?;
+        for issue in self.issues() {
+            let line = issue.line.map_or(".".to_string(), |l| l.to_string());
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}",
+                line, issue.id, issue.code, issue.action
+            )?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn issues_carry_bed_lines() {
+        let records = ["A", "B"]
+            .iter()
+            .map(|name| {
+                BedRecord::parse(&format!(
+                    "chr1\t0\t100\t{}\t0\t+\t0\t0\t0\t1\t100,\t0,",
+                    name
+                ))
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let warnings = Warnings::new(&records);
+        warnings.push("G", "gene_split", "split into 2 loci");
+        warnings.push("B", "invalid_stop_codon", "tagged");
+        warnings.push("B", "duplicate_merged", "merged into A");
+
+        let issues = warnings.issues();
+        assert_eq!(issues[0].line, Some(2));
+        assert_eq!(issues[0].code, "duplicate_merged");
+        assert_eq!(issues[2].line, None);
+        assert_eq!(warnings.counts()["invalid_stop_codon"], 1);
+        assert_eq!(warnings.counts_by_chrom()["chr1"], 2);
+
+        let mut record = records[0].clone();
+        record.rename("T1".to_string());
+        warnings.reject("chr1\tbroken", "too few fields");
+        warnings.reject_record(&record, "no exons");
+        assert_eq!(
+            *warnings.rejects.lock().unwrap(),
+            [
+                "chr1\tbroken\ttoo few fields",
+                "chr1\t0\t100\tA\t0\t+\t0\t0\t0\t1\t100,\t0,\tno exons"
+            ]
+        );
+    }
+}